@@ -0,0 +1,127 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use simple_redis::{BulkString, Command, RespArray, RespDecode, RespEncode, RespFrame};
+
+const VALUE_SIZES: [usize; 3] = [64, 16 * 1024, 1024 * 1024];
+const PIPELINE: usize = 100;
+
+fn set_command(size: usize) -> RespFrame {
+    RespArray::with_vec(vec![
+        BulkString::new("set").into(),
+        BulkString::new("key").into(),
+        BulkString::new(vec![b'x'; size]).into(),
+    ])
+    .into()
+}
+
+fn pipeline(size: usize) -> BytesMut {
+    let frame = set_command(size);
+    let mut buf = BytesMut::new();
+    for _ in 0..PIPELINE {
+        frame.encode_to(&mut buf);
+    }
+    buf
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for size in VALUE_SIZES {
+        let input = pipeline(size);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("pipeline", size), &input, |b, input| {
+            b.iter(|| {
+                let mut data = input.clone();
+                while !data.is_empty() {
+                    black_box(RespFrame::decode(&mut data).unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// Decode and parse into commands, as a connection does.
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for size in VALUE_SIZES {
+        let input = pipeline(size);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        // every argument copied out of the read buffer, as before big ones
+        // were kept in it
+        group.bench_with_input(BenchmarkId::new("copy", size), &input, |b, input| {
+            b.iter_batched(
+                || input.clone(),
+                |mut data| {
+                    while !data.is_empty() {
+                        let frame = copy_args(RespFrame::decode(&mut data).unwrap());
+                        black_box(Command::try_from(frame).unwrap());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("command", size), &input, |b, input| {
+            b.iter_batched(
+                || input.clone(),
+                |mut data| {
+                    while !data.is_empty() {
+                        let frame = RespFrame::decode(&mut data).unwrap();
+                        black_box(Command::try_from(frame).unwrap());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn copy_args(frame: RespFrame) -> RespFrame {
+    let RespFrame::Array(args) = frame else {
+        return frame;
+    };
+    let args: Vec<RespFrame> = args
+        .iter()
+        .flatten()
+        .map(|arg| match arg.as_bytes() {
+            Some(v) => BulkString::new(Bytes::copy_from_slice(v)).into(),
+            None => arg.clone(),
+        })
+        .collect();
+    RespArray::with_vec(args).into()
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in VALUE_SIZES {
+        let frame = set_command(size);
+        group.throughput(Throughput::Bytes((frame.encode().len() * PIPELINE) as u64));
+        // the previous encoder built one vector per frame before copying it out
+        group.bench_with_input(BenchmarkId::new("vec", size), &frame, |b, frame| {
+            let mut buf = BytesMut::new();
+            b.iter(|| {
+                buf.clear();
+                for _ in 0..PIPELINE {
+                    buf.extend_from_slice(&frame.encode());
+                }
+                black_box(&buf);
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("buffer", size), &frame, |b, frame| {
+            let mut buf = BytesMut::new();
+            b.iter(|| {
+                buf.clear();
+                for _ in 0..PIPELINE {
+                    frame.encode_to(&mut buf);
+                }
+                black_box(&buf);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, parse, encode);
+criterion_main!(benches);
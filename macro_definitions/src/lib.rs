@@ -1,6 +1,10 @@
 mod auto_deref;
+mod redis_command;
+mod resp_codec;
 use auto_deref::process_auto_deref;
 use proc_macro::TokenStream;
+use redis_command::process_redis_command;
+use resp_codec::{process_resp_decode, process_resp_encode};
 use syn::DeriveInput;
 
 #[proc_macro_derive(AutoDeref, attributes(deref))]
@@ -9,3 +13,28 @@ pub fn derive_auto_deref(input: TokenStream) -> TokenStream {
 
     process_auto_deref(input).into()
 }
+
+// Generate `TryFrom<RespArray>` for a command from the `#[arg(...)]` of its fields.
+#[proc_macro_derive(RedisCommand, attributes(command, arg))]
+pub fn derive_redis_command(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    process_redis_command(input).into()
+}
+
+// Encode a struct as a map of its fields, or an array with `#[resp(array)]`,
+// and an enum as the name of a unit variant or a map of the variant to its value.
+#[proc_macro_derive(RespEncode, attributes(resp))]
+pub fn derive_resp_encode(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    process_resp_encode(input).into()
+}
+
+// Decode what `#[derive(RespEncode)]` encodes, maps may come as RESP2 flat arrays.
+#[proc_macro_derive(RespDecode, attributes(resp))]
+pub fn derive_resp_decode(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    process_resp_decode(input).into()
+}
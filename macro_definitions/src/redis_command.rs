@@ -0,0 +1,301 @@
+use darling::ast::{Data, Style};
+use darling::util::{Flag, Ignored};
+use darling::{FromDeriveInput, FromField};
+use proc_macro2::TokenStream;
+use syn::__private::quote::quote;
+use syn::spanned::Spanned;
+use syn::DeriveInput;
+
+#[derive(Debug, FromDeriveInput)]
+#[darling(attributes(command), supports(struct_named, struct_unit))]
+struct RedisCommand {
+    ident: syn::Ident,
+    data: Data<Ignored, CommandField>,
+    // the full name in the command table, `container|subcommand` for a subcommand
+    name: String,
+}
+
+#[derive(Debug, FromField)]
+#[darling(attributes(arg))]
+struct CommandField {
+    ident: Option<syn::Ident>,
+    ty: syn::Type,
+    // the kind of value
+    key: Flag,
+    bytes: Flag,
+    string: Flag,
+    integer: Flag,
+    float: Flag,
+    frame: Flag,
+    // how the value is given
+    optional: Flag,
+    variadic: Flag,
+    flag: Option<String>,
+    option: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Bytes,
+    String,
+    Integer,
+    Float,
+    Frame,
+}
+
+enum Arg {
+    Required(Kind),
+    Optional(Kind),
+    Variadic(Kind),
+    // a keyword alone, e.g. NX
+    Flag(String),
+    // a keyword followed by a value, e.g. EX <secs>
+    Option(String, Kind),
+}
+
+pub(crate) fn process_redis_command(input: DeriveInput) -> TokenStream {
+    let command = match RedisCommand::from_derive_input(&input) {
+        Ok(command) => command,
+        Err(e) => return e.write_errors(),
+    };
+    match expand(command) {
+        Ok(code) => code,
+        Err(e) => e.to_compile_error(),
+    }
+}
+
+fn expand(command: RedisCommand) -> syn::Result<TokenStream> {
+    let RedisCommand { ident, data, name } = command;
+    let fields = match data {
+        Data::Struct(fields) => fields,
+        Data::Enum(_) => unreachable!("darling only accepts structs"),
+    };
+
+    let mut args = Vec::new();
+    for field in &fields.fields {
+        args.push(field_arg(field)?);
+    }
+    check_order(&fields.fields, &args)?;
+
+    let keywords: Vec<&String> = args
+        .iter()
+        .filter_map(|arg| match arg {
+            Arg::Flag(k) | Arg::Option(k, _) => Some(k),
+            _ => None,
+        })
+        .collect();
+
+    let mut parse = Vec::new();
+    let mut options = Vec::new();
+    for (field, arg) in fields.fields.iter().zip(&args) {
+        let ident = &field.ident;
+        match arg {
+            Arg::Required(kind) => {
+                let next = next_fn(*kind);
+                parse.push(quote! { let #ident = __args.#next()?; });
+            }
+            Arg::Optional(kind) => {
+                let next = next_fn(*kind);
+                parse.push(quote! {
+                    let #ident = match __args.has_positional(&[#(#keywords),*]) {
+                        true => Some(__args.#next()?),
+                        false => None,
+                    };
+                });
+            }
+            Arg::Variadic(kind) => {
+                let next = next_fn(*kind);
+                parse.push(quote! {
+                    let mut #ident = Vec::new();
+                    while !__args.is_empty() {
+                        #ident.push(__args.#next()?);
+                    }
+                });
+            }
+            Arg::Flag(keyword) => {
+                parse.push(quote! { let mut #ident = false; });
+                options.push(quote! {
+                    if __args.next_keyword(#keyword) {
+                        #ident = true;
+                        continue;
+                    }
+                });
+            }
+            Arg::Option(keyword, kind) => {
+                let next = next_fn(*kind);
+                parse.push(quote! { let mut #ident = None; });
+                options.push(quote! {
+                    if __args.next_keyword(#keyword) {
+                        if __args.is_empty() {
+                            return Err(::simple_redis::CommandError::SyntaxError);
+                        }
+                        #ident = Some(__args.#next()?);
+                        continue;
+                    }
+                });
+            }
+        }
+    }
+
+    let idents = fields.fields.iter().map(|f| &f.ident);
+    let build = match fields.style {
+        Style::Unit => quote! { #ident },
+        _ => quote! { #ident { #(#idents),* } },
+    };
+
+    // without keywords any argument left is a syntax error
+    let rest = match options.is_empty() {
+        true => quote! { __args.finish()?; },
+        false => quote! {
+            while !__args.is_empty() {
+                #(#options)*
+                return Err(::simple_redis::CommandError::SyntaxError);
+            }
+        },
+    };
+
+    Ok(quote! {
+        impl ::std::convert::TryFrom<::simple_redis::RespArray> for #ident {
+            type Error = ::simple_redis::CommandError;
+
+            fn try_from(value: ::simple_redis::RespArray) -> ::std::result::Result<Self, Self::Error> {
+                let mut __args = ::simple_redis::CommandArgs::new(value, #name)?;
+                #(#parse)*
+                #rest
+                Ok(#build)
+            }
+        }
+    })
+}
+
+fn field_arg(field: &CommandField) -> syn::Result<Arg> {
+    let kinds = [
+        (field.key.is_present(), Kind::Bytes),
+        (field.bytes.is_present(), Kind::Bytes),
+        (field.string.is_present(), Kind::String),
+        (field.integer.is_present(), Kind::Integer),
+        (field.float.is_present(), Kind::Float),
+        (field.frame.is_present(), Kind::Frame),
+    ];
+    let mut kinds = kinds
+        .iter()
+        .filter(|(present, _)| *present)
+        .map(|(_, k)| *k);
+    let kind = kinds.next();
+    if kinds.next().is_some() {
+        return Err(error(field, "an argument can only have one kind"));
+    }
+
+    let type_name = outer_type(&field.ty);
+    let expect_type = |expected: &str, what: &str| match type_name.as_deref() {
+        Some(name) if name == expected => Ok(()),
+        _ => Err(error(
+            field,
+            &format!("{} argument must be a {}", what, expected),
+        )),
+    };
+    let value_kind = || {
+        kind.ok_or_else(|| {
+            error(
+                field,
+                "expected the kind of argument: key, bytes, string, integer, float or frame",
+            )
+        })
+    };
+
+    let modes = [
+        field.optional.is_present(),
+        field.variadic.is_present(),
+        field.flag.is_some(),
+        field.option.is_some(),
+    ];
+    if modes.iter().filter(|m| **m).count() > 1 {
+        return Err(error(
+            field,
+            "optional, variadic, flag and option can't be combined",
+        ));
+    }
+
+    if let Some(keyword) = &field.flag {
+        if kind.is_some() {
+            return Err(error(field, "a flag argument takes no value"));
+        }
+        expect_type("bool", "a flag")?;
+        return Ok(Arg::Flag(keyword.to_uppercase()));
+    }
+    if let Some(keyword) = &field.option {
+        expect_type("Option", "an option")?;
+        return Ok(Arg::Option(keyword.to_uppercase(), value_kind()?));
+    }
+    if field.optional.is_present() {
+        expect_type("Option", "an optional")?;
+        return Ok(Arg::Optional(value_kind()?));
+    }
+    if field.variadic.is_present() {
+        expect_type("Vec", "a variadic")?;
+        return Ok(Arg::Variadic(value_kind()?));
+    }
+    Ok(Arg::Required(value_kind()?))
+}
+
+// Positional arguments are parsed in the order of the fields, so required
+// arguments come first, then optional ones, and a variadic argument last.
+fn check_order(fields: &[CommandField], args: &[Arg]) -> syn::Result<()> {
+    let mut optional = false;
+    let mut variadic = false;
+    let has_keywords = args
+        .iter()
+        .any(|a| matches!(a, Arg::Flag(_) | Arg::Option(..)));
+    for (field, arg) in fields.iter().zip(args) {
+        match arg {
+            Arg::Required(_) | Arg::Optional(_) if variadic => {
+                return Err(error(field, "a variadic argument must be the last one"))
+            }
+            Arg::Required(_) if optional => {
+                return Err(error(
+                    field,
+                    "a required argument can't follow an optional one",
+                ))
+            }
+            Arg::Optional(_) => optional = true,
+            Arg::Variadic(_) if variadic => {
+                return Err(error(field, "only one argument can be variadic"))
+            }
+            Arg::Variadic(_) if has_keywords => {
+                return Err(error(
+                    field,
+                    "a variadic argument can't be combined with flags or options",
+                ))
+            }
+            Arg::Variadic(_) => variadic = true,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn next_fn(kind: Kind) -> syn::Ident {
+    let name = match kind {
+        Kind::Bytes => "next_bytes",
+        Kind::String => "next_string",
+        Kind::Integer => "next_integer",
+        Kind::Float => "next_float",
+        Kind::Frame => "next_frame",
+    };
+    syn::Ident::new(name, proc_macro2::Span::call_site())
+}
+
+// the name of the outer type, e.g. `Option` for `Option<i64>`
+fn outer_type(ty: &syn::Type) -> Option<String> {
+    match ty {
+        syn::Type::Path(p) => p.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn error(field: &CommandField, msg: &str) -> syn::Error {
+    match &field.ident {
+        Some(ident) => syn::Error::new(ident.span(), msg),
+        None => syn::Error::new(field.ty.span(), msg),
+    }
+}
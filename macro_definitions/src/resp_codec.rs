@@ -0,0 +1,425 @@
+use darling::ast::{Data, Fields, Style};
+use darling::util::Flag;
+use darling::{FromDeriveInput, FromField, FromVariant};
+use proc_macro2::TokenStream;
+use syn::__private::quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::DeriveInput;
+
+#[derive(Debug, FromDeriveInput)]
+#[darling(attributes(resp), supports(struct_any, enum_any))]
+struct RespType {
+    ident: syn::Ident,
+    generics: syn::Generics,
+    data: Data<RespVariant, RespField>,
+    // named fields become an array in the order of the fields instead of a map
+    array: Flag,
+}
+
+#[derive(Debug, FromVariant)]
+#[darling(attributes(resp))]
+struct RespVariant {
+    ident: syn::Ident,
+    fields: Fields<RespField>,
+    rename: Option<String>,
+}
+
+#[derive(Debug, FromField)]
+#[darling(attributes(resp))]
+struct RespField {
+    ident: Option<syn::Ident>,
+    ty: syn::Type,
+    rename: Option<String>,
+    // left out of the frame, decoded as `Default::default()`
+    skip: Flag,
+    // the entries of the field's own map are merged into the map of the struct
+    flatten: Flag,
+}
+
+// how the fields of a struct or a variant map to a frame
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    Unit,
+    // a single unnamed field is the frame of its value
+    Newtype,
+    Array,
+    Map,
+}
+
+// A field to encode or decode, `binding` names its value in the generated code.
+struct FieldInfo<'a> {
+    field: &'a RespField,
+    binding: syn::Ident,
+    key: String,
+}
+
+pub(crate) fn process_resp_encode(input: DeriveInput) -> TokenStream {
+    match parse(&input).and_then(|t| expand_encode(&t)) {
+        Ok(code) => code,
+        Err(e) => e.write_errors(),
+    }
+}
+
+pub(crate) fn process_resp_decode(input: DeriveInput) -> TokenStream {
+    match parse(&input).and_then(|t| expand_decode(&t)) {
+        Ok(code) => code,
+        Err(e) => e.write_errors(),
+    }
+}
+
+fn parse(input: &DeriveInput) -> darling::Result<RespType> {
+    // darling panics on a union instead of reporting it
+    if let syn::Data::Union(u) = &input.data {
+        return Err(
+            darling::Error::custom("a union can't be converted to a frame")
+                .with_span(&u.union_token),
+        );
+    }
+    let resp = RespType::from_derive_input(input)?;
+    let mut errors = darling::Error::accumulator();
+    match &resp.data {
+        Data::Struct(fields) => {
+            errors.handle(check_fields(fields, resp.array.is_present()));
+        }
+        Data::Enum(variants) => {
+            for variant in variants {
+                errors.handle(check_fields(&variant.fields, resp.array.is_present()));
+            }
+        }
+    }
+    errors.finish_with(resp)
+}
+
+fn check_fields(fields: &Fields<RespField>, array: bool) -> darling::Result<()> {
+    let shape = shape(fields, array);
+    let mut errors = darling::Error::accumulator();
+    for field in fields.iter() {
+        let span = field_span(field);
+        if field.skip.is_present() && (field.flatten.is_present() || field.rename.is_some()) {
+            errors.push(
+                darling::Error::custom("a skipped field can't be renamed or flattened")
+                    .with_span(&span),
+            );
+        }
+        if field.flatten.is_present() && field.rename.is_some() {
+            errors.push(
+                darling::Error::custom("a flattened field has no name of its own").with_span(&span),
+            );
+        }
+        if shape != Shape::Map && (field.flatten.is_present() || field.rename.is_some()) {
+            errors.push(
+                darling::Error::custom("rename and flatten only apply to fields of a map")
+                    .with_span(&span),
+            );
+        }
+    }
+    errors.finish()
+}
+
+fn shape(fields: &Fields<RespField>, array: bool) -> Shape {
+    match fields.style {
+        Style::Unit => Shape::Unit,
+        Style::Tuple if fields.len() == 1 => Shape::Newtype,
+        Style::Tuple => Shape::Array,
+        Style::Struct if array => Shape::Array,
+        Style::Struct => Shape::Map,
+    }
+}
+
+fn field_span(field: &RespField) -> proc_macro2::Span {
+    match &field.ident {
+        Some(ident) => ident.span(),
+        None => field.ty.span(),
+    }
+}
+
+fn field_infos(fields: &Fields<RespField>) -> Vec<FieldInfo<'_>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let key = match (&field.rename, &field.ident) {
+                (Some(rename), _) => rename.clone(),
+                (None, Some(ident)) => ident.to_string(),
+                (None, None) => i.to_string(),
+            };
+            FieldInfo {
+                field,
+                binding: format_ident!("__field{}", i),
+                key,
+            }
+        })
+        .collect()
+}
+
+// the pattern binding every field that goes into the frame, e.g. `{ a: __field0, .. }`
+fn bind_fields(fields: &Fields<RespField>, infos: &[FieldInfo]) -> TokenStream {
+    let binds = infos.iter().map(|info| {
+        let binding = &info.binding;
+        match (&info.field.ident, info.field.skip.is_present()) {
+            (Some(ident), false) => quote! { #ident: #binding },
+            (Some(ident), true) => quote! { #ident: _ },
+            (None, false) => quote! { #binding },
+            (None, true) => quote! { _ },
+        }
+    });
+    match fields.style {
+        Style::Unit => quote! {},
+        Style::Tuple => quote! { ( #(#binds),* ) },
+        Style::Struct => quote! { { #(#binds),* } },
+    }
+}
+
+// Build the frame of the fields, bound by reference under their binding names.
+fn encode_fields(fields: &Fields<RespField>, shape: Shape) -> TokenStream {
+    let infos = field_infos(fields);
+    let infos: Vec<_> = infos
+        .iter()
+        .filter(|i| !i.field.skip.is_present())
+        .collect();
+    match shape {
+        Shape::Unit => quote! { ::simple_redis::RespNull::new().into() },
+        Shape::Newtype => match infos.first() {
+            Some(info) => {
+                let binding = &info.binding;
+                quote! { ::simple_redis::ToFrame::to_frame(#binding) }
+            }
+            None => quote! { ::simple_redis::RespNull::new().into() },
+        },
+        Shape::Array => {
+            let bindings = infos.iter().map(|i| &i.binding);
+            quote! {
+                ::simple_redis::RespArray::with_vec(::std::vec![
+                    #(::simple_redis::ToFrame::to_frame(#bindings)),*
+                ]).into()
+            }
+        }
+        Shape::Map => {
+            let inserts = infos.iter().map(|info| {
+                let binding = &info.binding;
+                let key = &info.key;
+                match info.field.flatten.is_present() {
+                    true => quote! {
+                        ::simple_redis::flatten_frame(&mut __map, ::simple_redis::ToFrame::to_frame(#binding));
+                    },
+                    false => quote! {
+                        __map.insert(#key, ::simple_redis::ToFrame::to_frame(#binding));
+                    },
+                }
+            });
+            quote! {{
+                let mut __map = ::simple_redis::RespMap::new();
+                #(#inserts)*
+                __map.into()
+            }}
+        }
+    }
+}
+
+// Build `constructor` out of the frame `__frame`, fields are decoded in order
+// except the flattened ones, which get the entries left by the others.
+fn decode_fields(
+    fields: &Fields<RespField>,
+    shape: Shape,
+    constructor: TokenStream,
+    typ: &str,
+) -> TokenStream {
+    let infos = field_infos(fields);
+    let mut decode = Vec::new();
+    let mut flatten = Vec::new();
+    for info in &infos {
+        let binding = &info.binding;
+        let key = &info.key;
+        let field = info.field;
+        if field.skip.is_present() {
+            decode.push(quote! { let #binding = ::std::default::Default::default(); });
+            continue;
+        }
+        match shape {
+            Shape::Unit => {}
+            Shape::Newtype => decode.push(quote! {
+                let #binding = ::simple_redis::FromFrame::from_frame(__frame)?;
+            }),
+            // missing trailing items are decoded from a null, as missing map entries are
+            Shape::Array => decode.push(quote! {
+                let #binding = ::simple_redis::FromFrame::from_frame(
+                    __items.next().unwrap_or_else(|| ::simple_redis::RespNull::new().into()),
+                )?;
+            }),
+            Shape::Map if field.flatten.is_present() => flatten.push(quote! {
+                let #binding = ::simple_redis::FromFrame::from_frame(__fields.rest())?;
+            }),
+            Shape::Map => decode.push(quote! { let #binding = __fields.take(#key)?; }),
+        }
+    }
+
+    let prepare = match shape {
+        Shape::Unit => quote! { let _ = __frame; },
+        Shape::Newtype => quote! {},
+        Shape::Array => quote! {
+            let mut __items = ::simple_redis::frame_items(__frame, #typ)?.into_iter();
+        },
+        Shape::Map => quote! {
+            let mut __fields = ::simple_redis::FrameFields::new(__frame, #typ)?;
+        },
+    };
+    let bindings = infos.iter().map(|i| &i.binding);
+    let build = match fields.style {
+        Style::Unit => quote! { #constructor },
+        Style::Tuple => quote! { #constructor ( #(#bindings),* ) },
+        Style::Struct => {
+            let idents = infos.iter().map(|i| &i.field.ident);
+            quote! { #constructor { #(#idents: #bindings),* } }
+        }
+    };
+    quote! {{
+        #prepare
+        #(#decode)*
+        #(#flatten)*
+        #build
+    }}
+}
+
+fn variant_tag(variant: &RespVariant) -> String {
+    match &variant.rename {
+        Some(rename) => rename.clone(),
+        None => variant.ident.to_string(),
+    }
+}
+
+// every type parameter must convert as well
+fn bounded_generics(generics: &syn::Generics, bound: TokenStream) -> syn::Generics {
+    let mut generics = generics.clone();
+    let params: Vec<_> = generics.type_params().map(|p| p.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote! { #param: #bound });
+    }
+    generics
+}
+
+fn expand_encode(resp: &RespType) -> darling::Result<TokenStream> {
+    let ident = &resp.ident;
+    let array = resp.array.is_present();
+    let body = match &resp.data {
+        Data::Struct(fields) => {
+            let infos = field_infos(fields);
+            let bind = bind_fields(fields, &infos);
+            let encode = encode_fields(fields, shape(fields, array));
+            quote! {
+                let #ident #bind = self;
+                #encode
+            }
+        }
+        Data::Enum(variants) => {
+            let arms = variants.iter().map(|variant| {
+                let name = &variant.ident;
+                let tag = variant_tag(variant);
+                let infos = field_infos(&variant.fields);
+                let bind = bind_fields(&variant.fields, &infos);
+                match variant.fields.style {
+                    Style::Unit => quote! {
+                        #ident::#name => ::simple_redis::SimpleString::new(#tag).into(),
+                    },
+                    _ => {
+                        let encode = encode_fields(&variant.fields, shape(&variant.fields, array));
+                        quote! {
+                            #ident::#name #bind => {
+                                let __value: ::simple_redis::RespFrame = #encode;
+                                let mut __map = ::simple_redis::RespMap::new();
+                                __map.insert(#tag, __value);
+                                __map.into()
+                            }
+                        }
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    };
+
+    let generics = bounded_generics(&resp.generics, quote! { ::simple_redis::ToFrame });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::simple_redis::ToFrame for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn to_frame(&self) -> ::simple_redis::RespFrame {
+                #body
+            }
+        }
+
+        impl #impl_generics ::simple_redis::RespEncode for #ident #ty_generics #where_clause {
+            fn encode_to(&self, buf: &mut ::simple_redis::bytes::BytesMut) {
+                ::simple_redis::RespEncode::encode_to(&::simple_redis::ToFrame::to_frame(self), buf)
+            }
+        }
+    })
+}
+
+fn expand_decode(resp: &RespType) -> darling::Result<TokenStream> {
+    let ident = &resp.ident;
+    let typ = ident.to_string();
+    let array = resp.array.is_present();
+    let body = match &resp.data {
+        Data::Struct(fields) => {
+            let decode = decode_fields(fields, shape(fields, array), quote! { #ident }, &typ);
+            quote! { Ok(#decode) }
+        }
+        Data::Enum(variants) => {
+            let arms = variants.iter().map(|variant| {
+                let name = &variant.ident;
+                let tag = variant_tag(variant);
+                match variant.fields.style {
+                    Style::Unit => quote! {
+                        (#tag, None) => Ok(#ident::#name),
+                    },
+                    _ => {
+                        let shape = shape(&variant.fields, array);
+                        let decode =
+                            decode_fields(&variant.fields, shape, quote! { #ident::#name }, &typ);
+                        quote! {
+                            (#tag, Some(__frame)) => Ok(#decode),
+                        }
+                    }
+                }
+            });
+            quote! {
+                let (__tag, __value) = ::simple_redis::frame_variant(__frame, #typ)?;
+                match (__tag.as_str(), __value) {
+                    #(#arms)*
+                    _ => Err(::simple_redis::RespError::RespInvalid(
+                        ::std::format!("unknown variant {} of {}", __tag, #typ),
+                    )),
+                }
+            }
+        }
+    };
+
+    let generics = bounded_generics(&resp.generics, quote! { ::simple_redis::FromFrame });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::simple_redis::FromFrame for #ident #ty_generics #where_clause {
+            fn from_frame(
+                __frame: ::simple_redis::RespFrame,
+            ) -> ::std::result::Result<Self, ::simple_redis::RespError> {
+                #body
+            }
+        }
+
+        // decoded from whatever frame comes next, like `RespFrame`
+        impl #impl_generics ::simple_redis::RespDecode for #ident #ty_generics #where_clause {
+            const PREFIX: &'static str = "";
+
+            fn parse(data: &mut ::simple_redis::bytes::BytesMut) -> ::std::result::Result<Self, ::simple_redis::RespError> {
+                let frame = <::simple_redis::RespFrame as ::simple_redis::RespDecode>::parse(data)?;
+                <Self as ::simple_redis::FromFrame>::from_frame(frame)
+            }
+        }
+    })
+}
@@ -0,0 +1,166 @@
+use super::AofError;
+use std::fmt;
+use std::str::FromStr;
+
+// The manifest lists the files the AOF is made of, like Redis 7 does:
+// an optional base file, a snapshot of the dataset in RDB or AOF format,
+// and the incremental files which hold the commands that followed it.
+//
+// file appendonly.aof.2.base.rdb seq 2 type b
+// file appendonly.aof.3.incr.aof seq 3 type i
+// file appendonly.aof.4.incr.aof seq 4 type i
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FileType {
+    Base,
+    Incr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AofFileInfo {
+    pub(crate) name: String,
+    pub(crate) seq: u64,
+    pub(crate) typ: FileType,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Manifest {
+    pub(crate) base: Option<AofFileInfo>,
+    // in the order they are replayed
+    pub(crate) incrs: Vec<AofFileInfo>,
+}
+
+impl AofFileInfo {
+    pub(crate) fn base(prefix: &str, seq: u64, format: &str) -> Self {
+        AofFileInfo {
+            name: format!("{}.{}.base.{}", prefix, seq, format),
+            seq,
+            typ: FileType::Base,
+        }
+    }
+
+    pub(crate) fn incr(prefix: &str, seq: u64) -> Self {
+        AofFileInfo {
+            name: format!("{}.{}.incr.aof", prefix, seq),
+            seq,
+            typ: FileType::Incr,
+        }
+    }
+
+    // a base file is in RDB format when its name says so
+    pub(crate) fn is_rdb(&self) -> bool {
+        self.name.ends_with(".rdb")
+    }
+}
+
+impl Manifest {
+    // every file, base first
+    pub(crate) fn files(&self) -> impl Iterator<Item = &AofFileInfo> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    pub(crate) fn next_base_seq(&self) -> u64 {
+        self.base.as_ref().map_or(1, |b| b.seq + 1)
+    }
+
+    pub(crate) fn next_incr_seq(&self) -> u64 {
+        self.incrs.last().map_or(1, |i| i.seq + 1)
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in self.files() {
+            let typ = match file.typ {
+                FileType::Base => "b",
+                FileType::Incr => "i",
+            };
+            writeln!(f, "file {} seq {} type {}", file.name, file.seq, typ)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = AofError;
+
+    fn from_str(s: &str) -> Result<Self, AofError> {
+        let invalid = |line: &str| AofError::Manifest(format!("invalid line '{}'", line));
+        let mut manifest = Manifest::default();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (mut name, mut seq, mut typ) = (None, None, None);
+            for pair in words.chunks(2) {
+                match pair {
+                    ["file", v] => name = Some(v.to_string()),
+                    ["seq", v] => seq = Some(v.parse::<u64>().map_err(|_| invalid(line))?),
+                    ["type", "b"] => typ = Some(FileType::Base),
+                    ["type", "i"] => typ = Some(FileType::Incr),
+                    // history files wait to be deleted, they aren't loaded
+                    ["type", "h"] => typ = None,
+                    // unknown keys are skipped, as Redis does for forward compatibility
+                    [_, _] => {}
+                    _ => return Err(invalid(line)),
+                }
+            }
+            let (Some(name), Some(seq)) = (name, seq) else {
+                return Err(invalid(line));
+            };
+            let info = |typ| AofFileInfo { name, seq, typ };
+            match typ {
+                Some(FileType::Base) if manifest.base.is_some() => {
+                    return Err(AofError::Manifest("more than one base file".to_string()))
+                }
+                Some(FileType::Base) => manifest.base = Some(info(FileType::Base)),
+                Some(FileType::Incr) => manifest.incrs.push(info(FileType::Incr)),
+                None => {}
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest() -> Result<(), AofError> {
+        let manifest = Manifest {
+            base: Some(AofFileInfo::base("appendonly.aof", 2, "rdb")),
+            incrs: vec![
+                AofFileInfo::incr("appendonly.aof", 3),
+                AofFileInfo::incr("appendonly.aof", 4),
+            ],
+        };
+        let text = manifest.to_string();
+        assert_eq!(
+            text,
+            "file appendonly.aof.2.base.rdb seq 2 type b\n\
+             file appendonly.aof.3.incr.aof seq 3 type i\n\
+             file appendonly.aof.4.incr.aof seq 4 type i\n"
+        );
+        assert_eq!(text.parse::<Manifest>()?, manifest);
+        assert_eq!(manifest.next_base_seq(), 3);
+        assert_eq!(manifest.next_incr_seq(), 5);
+        assert!(manifest.base.as_ref().unwrap().is_rdb());
+
+        // written by Redis, with a history file
+        let manifest: Manifest = "file appendonly.aof.1.base.aof seq 1 type h\n\
+             file appendonly.aof.2.base.aof seq 2 type b\n\
+             file appendonly.aof.5.incr.aof seq 5 type i startoffset 0\n"
+            .parse()?;
+        assert_eq!(manifest.base.unwrap().name, "appendonly.aof.2.base.aof");
+        assert_eq!(manifest.incrs.len(), 1);
+
+        assert!("file a seq x type i".parse::<Manifest>().is_err());
+        assert!("file a type i".parse::<Manifest>().is_err());
+        assert!("file a seq 1 type b\nfile b seq 2 type b"
+            .parse::<Manifest>()
+            .is_err());
+        Ok(())
+    }
+}
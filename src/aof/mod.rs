@@ -0,0 +1,1175 @@
+mod manifest;
+mod rewrite;
+
+use crate::replication::Replication;
+use crate::snapshot::write_file;
+use crate::{
+    rdb, AppendFsync, Backend, BulkString, Command, CommandExecutor, CommandFlag, Config,
+    RespArray, RespDecode, RespEncode, RespError, RespFrame, SimpleError,
+};
+use bytes::BytesMut;
+use manifest::{AofFileInfo, FileType, Manifest};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{info, warn};
+
+pub(crate) use rewrite::bgrewriteaof;
+
+// Append only file.
+//
+// Every command which modified the dataset is appended as the RESP array the
+// client sent, a transaction between MULTI and EXEC. On boot the files are
+// replayed through the same commands. A crash may cut the last command short,
+// the part after the last complete command is then dropped.
+//
+// The AOF is made of several files listed by a manifest in `appenddirname`:
+// a base file with the dataset as of the last rewrite, and the incr files
+// written to since. A rewrite switches to a new incr file, then writes a new
+// base in the background and only then drops the old files from the manifest.
+
+const CRON_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum AofError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("Bad file format reading the append only file {0} after {1} bytes ({2}), make a backup and run simple-redis-check-aof --fix")]
+    Corrupt(String, usize, String),
+    #[error("Unknown command in the append only file ({0})")]
+    BadCommand(String),
+    #[error("Bad AOF manifest ({0})")]
+    Manifest(String),
+    #[error("Bad AOF base file {0} ({1})")]
+    BadBase(String, String),
+    #[error("Background append only file rewriting already in progress")]
+    RewriteInProgress,
+    #[error("Append only file is disabled")]
+    Disabled,
+}
+
+// The files commands are appended to, None until they are opened after loading them.
+#[derive(Debug, Default)]
+pub(crate) struct Aof {
+    state: Mutex<Option<AofState>>,
+    // set once the files are opened, they are never closed
+    enabled: AtomicBool,
+    // held instead of the files when the AOF is disabled, so that commands
+    // are still fed to the replicas in the order they ran
+    order: Mutex<()>,
+    rewriting: AtomicBool,
+    // the size of the AOF after the last rewrite or load, auto rewrite measures the growth from it
+    base_size: AtomicU64,
+    // the offset of the replication stream fsynced to disk, for WAITAOF
+    fsynced_offset: AtomicU64,
+    // why the last write or fsync failed, writes are refused until one succeeds
+    error: Mutex<Option<String>>,
+}
+
+enum AofGuard<'a> {
+    Files(MutexGuard<'a, Option<AofState>>),
+    // only held
+    Order { _guard: MutexGuard<'a, ()> },
+}
+
+impl AofGuard<'_> {
+    fn state(&mut self) -> Option<&mut AofState> {
+        match self {
+            AofGuard::Files(state) => state.as_mut(),
+            AofGuard::Order { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AofState {
+    dir: PathBuf,
+    // appendfilename, the files are named after it
+    prefix: String,
+    manifest: Manifest,
+    // the last incr file of the manifest
+    file: File,
+    fsync: AppendFsync,
+    // written since the last fsync
+    unsynced: bool,
+    // what couldn't be written yet, written before anything else
+    pending: Vec<u8>,
+    // of all the files of the manifest
+    size: u64,
+    // the offset of the replication stream after the last write
+    written_offset: u64,
+}
+
+impl Aof {
+    // Start appending to the last incr file of the manifest, a first one is
+    // created when there is none.
+    fn open(
+        &self,
+        dir: PathBuf,
+        prefix: String,
+        mut manifest: Manifest,
+        fsync: AppendFsync,
+    ) -> io::Result<()> {
+        let file = match manifest.incrs.last() {
+            Some(incr) => open_append(&dir.join(&incr.name))?,
+            None => {
+                let incr = AofFileInfo::incr(&prefix, manifest.next_incr_seq());
+                let file = open_append(&dir.join(&incr.name))?;
+                manifest.incrs.push(incr);
+                persist_manifest(&dir, &prefix, &manifest)?;
+                file
+            }
+        };
+        let state = AofState {
+            size: files_size(&dir, &manifest),
+            dir,
+            prefix,
+            manifest,
+            file,
+            fsync,
+            unsynced: false,
+            pending: Vec::new(),
+            written_offset: 0,
+        };
+        self.base_size.store(state.size, Ordering::Release);
+        *self.state.lock().unwrap() = Some(state);
+        self.enabled.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // Run a write command and append it if it succeeded, it's fed to the
+    // replicas as well. `f` returns the reply and the frame to append. The
+    // file is held meanwhile, so that commands are appended and fed in the
+    // order they ran. With appendfsync always this waits for the disk, see
+    // `run_blocking`. Returns the reply, MISCONF when it couldn't be
+    // written, and the offset of the stream after the command if it was fed.
+    pub(crate) fn execute(
+        &self,
+        repl: Option<&Replication>,
+        f: impl FnOnce() -> (RespFrame, RespFrame),
+    ) -> (RespFrame, Option<u64>) {
+        let mut guard = self.lock();
+        let (res, frame) = f();
+        if is_error(&res) {
+            return (res, None);
+        }
+        let buf = frame.encode();
+        match self.write(guard.state(), &buf, repl.map(|r| (r, &buf[..]))) {
+            Ok(offset) => (res, offset),
+            Err((e, offset)) => (e, offset),
+        }
+    }
+
+    // Append commands which ran under the write side of the gate, returns
+    // the offset of the stream after them if they were fed, and MISCONF
+    // when they couldn't be written.
+    pub(crate) fn append(
+        &self,
+        repl: Option<&Replication>,
+        frames: Vec<RespFrame>,
+    ) -> (Option<RespFrame>, Option<u64>) {
+        let buf = encode_frames(frames);
+        let mut guard = self.lock();
+        match self.write(guard.state(), &buf, repl.map(|r| (r, &buf[..]))) {
+            Ok(offset) => (None, offset),
+            Err((e, offset)) => (Some(e), offset),
+        }
+    }
+
+    // Append commands a replica got from its master. `raw` is what they
+    // came in, it's fed to the replicas of this server unchanged.
+    pub(crate) fn append_replicated(&self, repl: &Replication, frames: Vec<RespFrame>, raw: &[u8]) {
+        let buf = encode_frames(frames);
+        let mut guard = self.lock();
+        let _ = self.write(guard.state(), &buf, Some((repl, raw)));
+    }
+
+    // The error write commands are refused with while the AOF can't be written.
+    pub(crate) fn misconf(&self) -> Option<RespFrame> {
+        let error = self.error.lock().unwrap();
+        error.as_ref().map(|e| {
+            SimpleError::new(format!("MISCONF Errors writing to the AOF file: {}", e)).into()
+        })
+    }
+
+    fn set_error(&self, result: &io::Result<()>) {
+        let mut error = self.error.lock().unwrap();
+        match result {
+            Ok(_) if error.take().is_some() => {
+                info!("AOF write error looks solved, can write again")
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Error writing to the append only file: {}", e);
+                *error = Some(e.to_string());
+            }
+        }
+    }
+
+    // Write what a failed write left pending, for the cron. Writes are
+    // accepted again once it's on disk.
+    fn retry(&self) {
+        if self.error.lock().unwrap().is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+        let mut result = state.flush();
+        if result.is_ok() && state.unsynced {
+            result = state.file.sync_data();
+            state.unsynced = result.is_err();
+        }
+        if result.is_ok() {
+            self.fsynced_offset
+                .fetch_max(state.written_offset, Ordering::AcqRel);
+        }
+        self.set_error(&result);
+    }
+
+    // the files, or only the order of the stream when the AOF is disabled
+    fn lock(&self) -> AofGuard<'_> {
+        match self.enabled() {
+            true => AofGuard::Files(self.state.lock().unwrap()),
+            false => AofGuard::Order {
+                _guard: self.order.lock().unwrap(),
+            },
+        }
+    }
+
+    // The caller holds the file. Returns the offset of the stream after
+    // what was fed, if anything was, with MISCONF when it wasn't written.
+    fn write(
+        &self,
+        state: Option<&mut AofState>,
+        buf: &[u8],
+        feed: Option<(&Replication, &[u8])>,
+    ) -> Result<Option<u64>, (RespFrame, Option<u64>)> {
+        let offset = feed
+            .filter(|(_, data)| !data.is_empty())
+            .map(|(repl, data)| repl.feed(data));
+        let Some(state) = state.filter(|_| !buf.is_empty()) else {
+            return Ok(offset);
+        };
+        let result = state.append(buf);
+        if let Some(offset) = offset {
+            state.written_offset = offset;
+            if result.is_ok() && state.fsync == AppendFsync::Always {
+                self.fsynced_offset.fetch_max(offset, Ordering::AcqRel);
+            }
+        }
+        self.set_error(&result);
+        match self.misconf() {
+            Some(e) => Err((e, offset)),
+            None => Ok(offset),
+        }
+    }
+
+    // The offset of the replication stream the AOF is fsynced up to. When
+    // all that was written is, the stream after it isn't for the AOF and
+    // counts as well. With appendfsync no it's only what a rewrite wrote.
+    pub(crate) fn fsynced_offset(&self, repl: &Replication) -> u64 {
+        let state = self.state.lock().unwrap();
+        let fsynced = self.fsynced_offset.load(Ordering::Acquire);
+        match state.as_ref() {
+            Some(state) if state.written_offset <= fsynced => repl.offset(),
+            _ => fsynced,
+        }
+    }
+
+    // A replica loaded the snapshot of its master at `offset`, which is on
+    // disk once the rewrite which follows is done.
+    pub(crate) fn reset_offsets(&self, offset: u64) {
+        if let Some(state) = self.state.lock().unwrap().as_mut() {
+            state.written_offset = offset;
+            self.fsynced_offset.store(0, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn rewriting(&self) -> bool {
+        self.rewriting.load(Ordering::Acquire)
+    }
+
+    // fsync what was written since the last time, for the everysec policy
+    fn fsync(&self) {
+        let (file, offset) = match self.state.lock().unwrap().as_mut() {
+            Some(state) if state.unsynced => {
+                state.unsynced = false;
+                (state.file.try_clone(), state.written_offset)
+            }
+            _ => return,
+        };
+        // the file isn't held while waiting for the disk
+        let result = file.and_then(|f| f.sync_data());
+        match &result {
+            Ok(_) => {
+                self.fsynced_offset.fetch_max(offset, Ordering::AcqRel);
+            }
+            // fsynced again by the next retry
+            Err(_) => {
+                if let Some(state) = self.state.lock().unwrap().as_mut() {
+                    state.unsynced = true;
+                }
+            }
+        }
+        if result.is_err() {
+            self.set_error(&result);
+        }
+    }
+
+    // Whether the AOF grew enough since the last rewrite to rewrite it again.
+    fn should_rewrite(&self, config: &Config) -> bool {
+        if config.auto_aof_rewrite_percentage == 0 || self.rewriting() {
+            return false;
+        }
+        let Some(size) = self.state.lock().unwrap().as_ref().map(|s| s.size) else {
+            return false;
+        };
+        let base = self.base_size.load(Ordering::Acquire).max(1);
+        size >= config.auto_aof_rewrite_min_size
+            && size.saturating_sub(base) * 100 / base >= config.auto_aof_rewrite_percentage
+    }
+}
+
+impl AofState {
+    // Write after what's pending, and fsync with the always policy.
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(buf);
+        self.flush()
+    }
+
+    // Write what's pending. A failed write is cut off the file again, the
+    // file never ends in the middle of a command, and stays pending.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            if let Err(e) = self.file.write_all(&self.pending) {
+                let _ = self.file.set_len(self.size);
+                return Err(e);
+            }
+            self.size += self.pending.len() as u64;
+            self.pending.clear();
+        }
+        match self.fsync {
+            AppendFsync::Always => return self.file.sync_data(),
+            AppendFsync::EverySec => self.unsynced = true,
+            AppendFsync::No => {}
+        }
+        Ok(())
+    }
+
+    // Append to a new incr file from now on, returns its sequence number.
+    // The file exists before the manifest names it.
+    fn open_incr(&mut self) -> io::Result<u64> {
+        // no rewrite until what's pending is written to the old file
+        self.flush()?;
+        let incr = AofFileInfo::incr(&self.prefix, self.manifest.next_incr_seq());
+        let file = open_append(&self.dir.join(&incr.name))?;
+        let seq = incr.seq;
+        self.manifest.incrs.push(incr);
+        if let Err(e) = persist_manifest(&self.dir, &self.prefix, &self.manifest) {
+            self.manifest.incrs.pop();
+            return Err(e);
+        }
+        if self.unsynced {
+            let _ = self.file.sync_data();
+            self.unsynced = false;
+        }
+        self.file = file;
+        Ok(seq)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn manifest_path(dir: &Path, prefix: &str) -> PathBuf {
+    dir.join(format!("{}.manifest", prefix))
+}
+
+// The manifest is replaced as a whole, a crash leaves either the old or the new one.
+fn persist_manifest(dir: &Path, prefix: &str, manifest: &Manifest) -> io::Result<()> {
+    write_file(&manifest_path(dir, prefix), manifest.to_string().as_bytes())
+}
+
+fn files_size(dir: &Path, manifest: &Manifest) -> u64 {
+    manifest
+        .files()
+        .filter_map(|f| fs::metadata(dir.join(&f.name)).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn is_error(frame: &RespFrame) -> bool {
+    matches!(frame, RespFrame::Error(_) | RespFrame::BulkError(_))
+}
+
+fn command_frame(args: &[&str]) -> RespFrame {
+    let args: Vec<RespFrame> = args.iter().map(|a| BulkString::new(*a).into()).collect();
+    RespArray::with_vec(args).into()
+}
+
+// More than one command are wrapped in MULTI and EXEC.
+fn encode_frames(mut frames: Vec<RespFrame>) -> Vec<u8> {
+    if frames.len() > 1 {
+        frames.insert(0, command_frame(&["MULTI"]));
+        frames.push(command_frame(&["EXEC"]));
+    }
+    let mut buf = Vec::new();
+    for frame in frames {
+        buf.extend_from_slice(&frame.encode());
+    }
+    buf
+}
+
+fn command_name(frame: &RespFrame) -> Option<&[u8]> {
+    match frame {
+        RespFrame::Array(RespArray(Some(args))) => args.first()?.as_bytes(),
+        _ => None,
+    }
+}
+
+// Run `f`, which appends to the AOF, on the blocking pool: with appendfsync
+// always every append waits for the disk, which must not stall the other
+// connections of a runtime thread. With the AOF disabled it runs in place.
+pub(crate) async fn run_blocking<T, F>(backend: &Backend, f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(&Backend) -> T + Send + 'static,
+{
+    if !backend.aof.enabled() {
+        return f(backend);
+    }
+    let backend = backend.clone();
+    match tokio::task::spawn_blocking(move || f(&backend)).await {
+        Ok(res) => res,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// Whether a command which ran under the write side of the gate is appended:
+// a write command which succeeded, or a script which modified the dataset.
+pub(crate) fn must_append(cmd: &Command, res: &RespFrame, dirty: bool) -> bool {
+    dirty || (cmd.spec().has_flag(CommandFlag::Write) && !is_error(res))
+}
+
+// The frame appended for a command, once it ran. EVALSHA is appended as EVAL
+// since the script cache is empty after a restart. RESTORE with a TTL is
+// appended with the time the key expires at and ABSTTL, like Redis does, so
+// that it isn't pushed back when the AOF is loaded or a replica applies it.
+pub(crate) fn logged_frame(cmd: &Command, frame: RespFrame, backend: &Backend) -> RespFrame {
+    let RespFrame::Array(RespArray(Some(mut args))) = frame else {
+        return frame;
+    };
+    match cmd {
+        Command::EvalSha(evalsha) if args.len() > 1 => {
+            if let Some(script) = backend.scripts.get(&evalsha.sha1.to_lowercase()) {
+                args[0] = BulkString::new("EVAL").into();
+                args[1] = BulkString::new(script.as_str()).into();
+            }
+        }
+        Command::Restore(restore) if restore.ttl > 0 && !restore.absttl && args.len() > 2 => {
+            // expired on arrival when there's no expiry left, any past time does
+            let at = backend.expires.get(&restore.key).map_or(1, |at| *at);
+            args[2] = BulkString::new(at.to_string()).into();
+            args.push(BulkString::new("ABSTTL").into());
+        }
+        _ => {}
+    }
+    RespArray::with_vec(args).into()
+}
+
+// How the content of an AOF after its last complete command looks.
+#[derive(Debug, PartialEq)]
+pub enum AofTail {
+    Clean,
+    // cut short in the middle of a command, or of a transaction
+    Truncated,
+    // something which isn't a command, the reason
+    Corrupt(String),
+}
+
+// The complete commands at the start of an AOF, and what follows them.
+#[derive(Debug)]
+pub struct AofScan {
+    pub commands: Vec<RespFrame>,
+    // the length of those commands, a transaction without its EXEC isn't complete
+    pub valid_len: usize,
+    pub tail: AofTail,
+}
+
+pub fn scan_aof(data: &[u8]) -> AofScan {
+    let mut buf = BytesMut::from(data);
+    let mut commands = Vec::new();
+    let mut complete = 0;
+    let mut valid_len = 0;
+    let mut in_multi = false;
+    let tail = loop {
+        if buf.is_empty() {
+            break if in_multi {
+                AofTail::Truncated
+            } else {
+                AofTail::Clean
+            };
+        }
+        let frame = match RespFrame::decode(&mut buf) {
+            Ok(frame) => frame,
+            Err(RespError::RespNotComplete) => break AofTail::Truncated,
+            Err(e) => break AofTail::Corrupt(e.to_string()),
+        };
+        match command_name(&frame) {
+            Some(name) if name.eq_ignore_ascii_case(b"multi") => in_multi = true,
+            Some(name) if name.eq_ignore_ascii_case(b"exec") => in_multi = false,
+            Some(_) => {}
+            None => break AofTail::Corrupt("expected a command".to_string()),
+        }
+        commands.push(frame);
+        if !in_multi {
+            complete = commands.len();
+            valid_len = data.len() - buf.len();
+        }
+    };
+    commands.truncate(complete);
+    AofScan {
+        commands,
+        valid_len,
+        tail,
+    }
+}
+
+// A file of the AOF, as listed by its manifest.
+#[derive(Debug, PartialEq)]
+pub struct AofPart {
+    pub path: PathBuf,
+    pub base: bool,
+    // a base file in RDB format
+    pub rdb: bool,
+}
+
+// The files of the AOF whose manifest is `path`, or whose manifest is the only
+// one in the directory `path`, base first.
+pub fn aof_parts(path: &Path) -> Result<Vec<AofPart>, AofError> {
+    let manifest_path = match path.is_dir() {
+        true => {
+            let mut manifests = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|e| e == "manifest") {
+                    manifests.push(entry);
+                }
+            }
+            match manifests.as_slice() {
+                [manifest] => manifest.clone(),
+                [] => return Err(AofError::Manifest(format!("none in {}", path.display()))),
+                _ => {
+                    let reason = format!("more than one in {}", path.display());
+                    return Err(AofError::Manifest(reason));
+                }
+            }
+        }
+        false => path.to_path_buf(),
+    };
+    let manifest: Manifest = fs::read_to_string(&manifest_path)?.parse()?;
+    let dir = manifest_path.parent().unwrap_or(Path::new(""));
+    let parts = manifest.files().map(|info| AofPart {
+        path: dir.join(&info.name),
+        base: info.typ == FileType::Base,
+        rdb: info.is_rdb(),
+    });
+    Ok(parts.collect())
+}
+
+// Check a base file in RDB format, or with an RDB preamble.
+pub fn check_rdb(data: &[u8]) -> Result<(), String> {
+    rdb::read_rdb(data).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn replay(backend: &Backend, commands: Vec<RespFrame>) -> Result<(), AofError> {
+    let mut queued: Option<Vec<Command>> = None;
+    for frame in commands {
+        let cmd = Command::try_from(frame).map_err(|e| AofError::BadCommand(e.to_string()))?;
+        match (cmd, queued.as_mut()) {
+            (Command::Multi(_), None) => queued = Some(Vec::new()),
+            (Command::Exec(_), Some(_)) => {
+                for cmd in queued.take().unwrap_or_default() {
+                    cmd.execute(backend);
+                }
+            }
+            (cmd, Some(queued)) => queued.push(cmd),
+            (cmd, None) => {
+                cmd.execute(backend);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Replay the AOF at boot, then open it to append to, returns false when there
+// was none yet. A last command cut short by a crash is removed from the file.
+pub fn load_aof(backend: &Backend) -> Result<bool, AofError> {
+    let config = &backend.config;
+    let dir = config.aof_dir();
+    fs::create_dir_all(&dir)?;
+    let prefix = &config.appendfilename;
+    let manifest: Manifest = match fs::read_to_string(manifest_path(&dir, prefix)) {
+        Ok(text) => text.parse()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => upgrade(config, &dir)?,
+        Err(e) => return Err(e.into()),
+    };
+    let count = manifest.files().count();
+    for (i, info) in manifest.files().enumerate() {
+        load_file(backend, &dir, info, i + 1 == count)?;
+    }
+    if count > 0 {
+        // what was loaded is on disk already
+        backend.save_state.saved(backend.save_state.dirty());
+    }
+    backend
+        .aof
+        .open(dir, prefix.clone(), manifest, config.appendfsync)?;
+    Ok(count > 0)
+}
+
+// The AOF of older versions is a single file in `dir`, it becomes the base
+// file of a new manifest.
+fn upgrade(config: &Config, dir: &Path) -> Result<Manifest, AofError> {
+    let legacy = config.aof_path();
+    if !legacy.is_file() {
+        return Ok(Manifest::default());
+    }
+    let base = AofFileInfo::base(&config.appendfilename, 1, "aof");
+    let path = dir.join(&base.name);
+    // linked rather than moved, so that the old file is still there if we
+    // crash before the manifest is written
+    let _ = fs::remove_file(&path);
+    fs::hard_link(&legacy, &path)?;
+    let manifest = Manifest {
+        base: Some(base),
+        incrs: Vec::new(),
+    };
+    persist_manifest(dir, &config.appendfilename, &manifest)?;
+    fs::remove_file(&legacy)?;
+    info!("The append only file was moved to {}", dir.display());
+    Ok(manifest)
+}
+
+// Only the last file may be cut short, it's the one which was being written.
+fn load_file(
+    backend: &Backend,
+    dir: &Path,
+    info: &AofFileInfo,
+    last: bool,
+) -> Result<(), AofError> {
+    let path = dir.join(&info.name);
+    let data = fs::read(&path)?;
+    if info.is_rdb() || data.starts_with(rdb::RDB_MAGIC) {
+        let bad_base = |e: String| AofError::BadBase(info.name.clone(), e);
+        let snapshot = rdb::read_rdb(&data).map_err(|e| bad_base(e.to_string()))?;
+        return snapshot
+            .restore(backend)
+            .map_err(|e| bad_base(e.to_string()));
+    }
+    let scan = scan_aof(&data);
+    match scan.tail {
+        AofTail::Clean => {}
+        AofTail::Truncated if last => {
+            warn!(
+                "The append only file {} is truncated, {} bytes after the last complete command are removed",
+                info.name,
+                data.len() - scan.valid_len
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(scan.valid_len as u64)?;
+        }
+        AofTail::Truncated => {
+            let reason = "unexpected end of file".to_string();
+            return Err(AofError::Corrupt(info.name.clone(), scan.valid_len, reason));
+        }
+        AofTail::Corrupt(e) => return Err(AofError::Corrupt(info.name.clone(), scan.valid_len, e)),
+    }
+    replay(backend, scan.commands)
+}
+
+// Once a second: fsync the AOF under the everysec policy, and rewrite it when
+// it grew past the auto rewrite thresholds. Runs as long as the server does.
+pub async fn run_aof_cron(backend: Backend) {
+    loop {
+        tokio::time::sleep(CRON_INTERVAL).await;
+        if backend.aof.misconf().is_some() {
+            let cloned = backend.clone();
+            let _ = tokio::task::spawn_blocking(move || cloned.aof.retry()).await;
+        }
+        if backend.config.appendfsync == AppendFsync::EverySec {
+            let cloned = backend.clone();
+            let _ = tokio::task::spawn_blocking(move || cloned.aof.fsync()).await;
+            backend.repl.notify_acks();
+        }
+        if backend.aof.should_rewrite(&backend.config) {
+            let _guard = backend.gate.write().await;
+            info!("Starting automatic rewriting of AOF");
+            if let Err(e) = bgrewriteaof(&backend) {
+                warn!("Can't rewrite the append only file: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::script::Caller;
+    use crate::RESP_OK;
+    use bytes::Bytes;
+
+    fn backend_in(dir: &Path, appendfsync: AppendFsync) -> Backend {
+        Backend::with_config(Config {
+            dir: dir.to_path_buf(),
+            appendonly: true,
+            appendfsync,
+            ..Default::default()
+        })
+    }
+
+    fn set(key: &str, value: &str) -> RespFrame {
+        command_frame(&["SET", key, value])
+    }
+
+    fn encode(frames: &[RespFrame]) -> Vec<u8> {
+        frames.iter().flat_map(|f| f.clone().encode()).collect()
+    }
+
+    fn manifest(backend: &Backend) -> Manifest {
+        let state = backend.aof.state.lock().unwrap();
+        state.as_ref().unwrap().manifest.clone()
+    }
+
+    fn incr_path(dir: &Path, seq: u64) -> PathBuf {
+        dir.join(format!("appendonlydir/appendonly.aof.{}.incr.aof", seq))
+    }
+
+    #[test]
+    fn test_scan() {
+        let frames = [
+            set("a", "1"),
+            command_frame(&["MULTI"]),
+            set("b", "2"),
+            command_frame(&["EXEC"]),
+        ];
+        let data = encode(&frames);
+        let scan = scan_aof(&data);
+        assert_eq!(scan.tail, AofTail::Clean);
+        assert_eq!(scan.commands, frames);
+        assert_eq!(scan.valid_len, data.len());
+
+        // a transaction without its EXEC is dropped as a whole
+        let first = set("a", "1").encode().len();
+        let scan = scan_aof(&data[..data.len() - 3]);
+        assert_eq!(scan.tail, AofTail::Truncated);
+        assert_eq!(scan.commands, frames[..1]);
+        assert_eq!(scan.valid_len, first);
+
+        let mut corrupt = data[..first].to_vec();
+        corrupt.extend_from_slice(b"+OK\r\n");
+        corrupt.extend_from_slice(&data[first..]);
+        let scan = scan_aof(&corrupt);
+        assert!(matches!(scan.tail, AofTail::Corrupt(_)));
+        assert_eq!(scan.valid_len, first);
+    }
+
+    #[test]
+    fn test_load() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::Always);
+        assert!(!load_aof(&backend)?);
+        backend.aof.execute(None, || {
+            backend.set(Bytes::from_static(b"a"), BulkString::new("1").into());
+            (RespFrame::Integer(1), set("a", "1"))
+        });
+        // failed commands are not appended
+        backend.aof.execute(None, || {
+            (crate::SimpleError::new("ERR").into(), set("x", "1"))
+        });
+        backend.aof.append(
+            None,
+            vec![
+                command_frame(&["HSET", "h", "f", "v"]),
+                command_frame(&["SADD", "s", "m"]),
+            ],
+        );
+
+        let loaded = backend_in(dir.path(), AppendFsync::Always);
+        assert!(load_aof(&loaded)?);
+        assert_eq!(loaded.get(b"a"), Some(BulkString::new("1").into()));
+        assert_eq!(loaded.get(b"x"), None);
+        assert_eq!(loaded.hget(b"h", b"f"), Some(BulkString::new("v").into()));
+        assert!(loaded.sismember(b"s", b"m"));
+        assert_eq!(loaded.save_state.dirty(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_truncated() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        load_aof(&backend_in(dir.path(), AppendFsync::No))?;
+        let path = incr_path(dir.path(), 1);
+        let complete = encode(&[set("a", "1")]);
+        let mut data = complete.clone();
+        data.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nb");
+        fs::write(&path, &data)?;
+
+        let backend = backend_in(dir.path(), AppendFsync::No);
+        assert!(load_aof(&backend)?);
+        assert_eq!(backend.get(b"a"), Some(BulkString::new("1").into()));
+        assert_eq!(backend.get(b"b"), None);
+        assert_eq!(fs::read(&path)?, complete);
+
+        // appending goes on after the last complete command
+        backend.aof.append(None, vec![set("c", "3")]);
+        assert_eq!(fs::read(&path)?, encode(&[set("a", "1"), set("c", "3")]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_errors() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        load_aof(&backend_in(dir.path(), AppendFsync::No))?;
+        let path = incr_path(dir.path(), 1);
+        let mut data = encode(&[set("a", "1")]);
+        data.extend_from_slice(b"garbage\r\n");
+        fs::write(&path, &data)?;
+        let backend = backend_in(dir.path(), AppendFsync::No);
+        let valid = set("a", "1").encode().len();
+        assert!(matches!(load_aof(&backend), Err(AofError::Corrupt(_, n, _)) if n == valid));
+        // the file is left alone
+        assert_eq!(fs::read(&path)?, data);
+
+        fs::write(&path, encode(&[command_frame(&["NOSUCH"])]))?;
+        assert!(matches!(load_aof(&backend), Err(AofError::BadCommand(_))));
+
+        // only the last file may be cut short
+        fs::write(&path, b"*3\r\n$3\r\nSET\r\n$1\r\nb")?;
+        fs::write(incr_path(dir.path(), 2), encode(&[set("a", "1")]))?;
+        let manifest = "file appendonly.aof.1.incr.aof seq 1 type i\n\
+                        file appendonly.aof.2.incr.aof seq 2 type i\n";
+        let manifest_path = dir.path().join("appendonlydir/appendonly.aof.manifest");
+        fs::write(&manifest_path, manifest)?;
+        assert!(
+            matches!(load_aof(&backend), Err(AofError::Corrupt(name, 0, _)) if name == "appendonly.aof.1.incr.aof")
+        );
+
+        fs::write(&manifest_path, "file x seq y type i\n")?;
+        assert!(matches!(load_aof(&backend), Err(AofError::Manifest(_))));
+        Ok(())
+    }
+
+    fn write(backend: &Backend, key: &str, value: &str) {
+        backend.aof.execute(None, || {
+            backend.set(
+                Bytes::copy_from_slice(key.as_bytes()),
+                BulkString::new(value).into(),
+            );
+            (RESP_OK.clone(), set(key, value))
+        });
+    }
+
+    #[test]
+    fn test_upgrade() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let legacy = dir.path().join("appendonly.aof");
+        fs::write(&legacy, encode(&[set("a", "1")]))?;
+        let backend = backend_in(dir.path(), AppendFsync::No);
+        assert!(load_aof(&backend)?);
+        assert_eq!(backend.get(b"a"), Some(BulkString::new("1").into()));
+        assert!(!legacy.exists());
+        assert_eq!(
+            manifest(&backend).to_string(),
+            "file appendonly.aof.1.base.aof seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type i\n"
+        );
+
+        write(&backend, "b", "2");
+        let loaded = backend_in(dir.path(), AppendFsync::No);
+        assert!(load_aof(&loaded)?);
+        assert_eq!(loaded.get(b"a"), Some(BulkString::new("1").into()));
+        assert_eq!(loaded.get(b"b"), Some(BulkString::new("2").into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rewrite() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::Always);
+        assert!(matches!(bgrewriteaof(&backend), Err(AofError::Disabled)));
+        load_aof(&backend)?;
+        write(&backend, "a", "1");
+        write(&backend, "a", "2");
+        bgrewriteaof(&backend)?;
+        // writes during the rewrite go to the new incr file
+        write(&backend, "b", "3");
+        if backend.aof.rewriting() {
+            assert!(matches!(
+                bgrewriteaof(&backend),
+                Err(AofError::RewriteInProgress)
+            ));
+        }
+        while backend.aof.rewriting() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            manifest(&backend).to_string(),
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+        assert!(!incr_path(dir.path(), 1).exists());
+        assert_eq!(
+            fs::read(incr_path(dir.path(), 2))?,
+            encode(&[set("b", "3")])
+        );
+
+        let loaded = backend_in(dir.path(), AppendFsync::Always);
+        assert!(load_aof(&loaded)?);
+        assert_eq!(loaded.get(b"a"), Some(BulkString::new("2").into()));
+        assert_eq!(loaded.get(b"b"), Some(BulkString::new("3").into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_during_rewrite() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::Always);
+        load_aof(&backend)?;
+        write(&backend, "a", "1");
+        // the rewrite switched to a new incr file and wrote its base, then
+        // the process died before the manifest was updated
+        backend
+            .aof
+            .state
+            .lock()
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .open_incr()?;
+        write(&backend, "b", "2");
+        let orphan = dir.path().join("appendonlydir/appendonly.aof.1.base.rdb");
+        fs::write(&orphan, b"REDIS0011")?;
+
+        let loaded = backend_in(dir.path(), AppendFsync::Always);
+        assert!(load_aof(&loaded)?);
+        assert_eq!(loaded.get(b"a"), Some(BulkString::new("1").into()));
+        assert_eq!(loaded.get(b"b"), Some(BulkString::new("2").into()));
+        assert_eq!(manifest(&loaded).base, None);
+        assert_eq!(manifest(&loaded).incrs.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_rewrite() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let mut config = Config {
+            dir: dir.path().to_path_buf(),
+            appendonly: true,
+            auto_aof_rewrite_min_size: 100,
+            ..Default::default()
+        };
+        let backend = Backend::with_config(config.clone());
+        load_aof(&backend)?;
+        write(&backend, "a", "1");
+        assert!(!backend.aof.should_rewrite(&config));
+        for i in 0..5 {
+            write(&backend, "a", &i.to_string());
+        }
+        assert!(backend.aof.should_rewrite(&config));
+        config.auto_aof_rewrite_percentage = 0;
+        assert!(!backend.aof.should_rewrite(&config));
+
+        // measured from the size after the last rewrite
+        config.auto_aof_rewrite_percentage = 100;
+        let size = backend.aof.state.lock().unwrap().as_ref().unwrap().size;
+        backend.aof.base_size.store(size, Ordering::Release);
+        assert!(!backend.aof.should_rewrite(&config));
+        for i in 0..6 {
+            write(&backend, "a", &i.to_string());
+        }
+        assert!(backend.aof.should_rewrite(&config));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_error() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::Always);
+        load_aof(&backend)?;
+        backend.aof.append(None, vec![set("a", "1")]);
+        // the file can't be written to anymore
+        let path = {
+            let mut state = backend.aof.state.lock().unwrap();
+            let state = state.as_mut().unwrap();
+            let path = state.dir.join(&state.manifest.incrs.last().unwrap().name);
+            state.file = File::open(&path)?;
+            path
+        };
+        let (res, _) = backend
+            .aof
+            .execute(None, || (RESP_OK.clone(), set("b", "2")));
+        let RespFrame::Error(e) = res else {
+            panic!("the failed write should be replied with an error");
+        };
+        assert!(e.0.starts_with("MISCONF Errors writing to the AOF file"));
+        assert!(backend.aof.misconf().is_some());
+
+        // once it can, what failed is written and writes are accepted again
+        backend.aof.retry();
+        assert!(backend.aof.misconf().is_some());
+        backend.aof.state.lock().unwrap().as_mut().unwrap().file = open_append(&path)?;
+        backend.aof.retry();
+        assert_eq!(backend.aof.misconf(), None);
+        let loaded = backend_in(dir.path(), AppendFsync::Always);
+        load_aof(&loaded)?;
+        assert_eq!(loaded.get(b"a"), Some(BulkString::new("1").into()));
+        assert_eq!(loaded.get(b"b"), Some(BulkString::new("2").into()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_logged_restore() {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"k"), BulkString::new("v").into());
+        let payload = rdb::dump(&backend, b"k").unwrap();
+        let restore = |args: &[&str]| -> RespFrame {
+            let mut args: Vec<RespFrame> =
+                args.iter().map(|a| BulkString::new(*a).into()).collect();
+            args.insert(3, BulkString::new(payload.clone()).into());
+            RespArray::with_vec(args).into()
+        };
+        let run = |frame: RespFrame| {
+            let cmd = Command::try_from(frame.clone()).unwrap();
+            crate::network::run_logged(cmd, frame, &backend, Caller::Client)
+                .1
+                .unwrap()
+        };
+
+        let logged = run(restore(&["RESTORE", "a", "100000", "REPLACE"]));
+        let at = *backend.expires.get(&b"a"[..]).unwrap();
+        assert_eq!(
+            logged,
+            restore(&["RESTORE", "a", &at.to_string(), "REPLACE", "ABSTTL"])
+        );
+        // replayed later, the key still expires at the same time
+        let replayed = Backend::new();
+        let cmd = Command::try_from(logged.clone()).unwrap();
+        crate::network::run_logged(cmd, logged, &replayed, Caller::Master);
+        assert_eq!(replayed.expires.get(&b"a"[..]).map(|v| *v), Some(at));
+
+        // without a TTL, or with ABSTTL already, it's appended as it came
+        let frame = restore(&["RESTORE", "b", "0"]);
+        assert_eq!(run(frame.clone()), frame);
+        let frame = restore(&["RESTORE", "c", "4102444800000", "ABSTTL"]);
+        assert_eq!(run(frame.clone()), frame);
+    }
+
+    #[test]
+    fn test_logged_evalsha() {
+        let backend = Backend::new();
+        let script = "return redis.call('set', KEYS[1], ARGV[1])";
+        let sha1 = crate::script::sha1_hex(script);
+        backend.scripts.insert(sha1.clone(), script.to_string());
+        let frame = |args: &[&str]| -> RespFrame {
+            let args: Vec<RespFrame> = args.iter().map(|a| BulkString::new(*a).into()).collect();
+            RespArray::with_vec(args).into()
+        };
+        // the SHA is matched whatever its case, as when it runs
+        for sha1 in [sha1.clone(), sha1.to_uppercase()] {
+            let evalsha = frame(&["EVALSHA", &sha1, "1", "k", "v"]);
+            let cmd = Command::try_from(evalsha.clone()).unwrap();
+            assert_eq!(
+                logged_frame(&cmd, evalsha, &backend),
+                frame(&["EVAL", script, "1", "k", "v"])
+            );
+        }
+    }
+
+    #[test]
+    fn test_disabled_skips_the_files() {
+        let backend = Backend::new();
+        // held by a rewrite or an fsync, a write doesn't wait for it
+        let _state = backend.aof.state.lock().unwrap();
+        let res = backend.aof.execute(Some(&backend.repl), || {
+            backend.set(Bytes::from_static(b"a"), BulkString::new("1").into());
+            (RESP_OK.clone(), set("a", "1"))
+        });
+        let offset = set("a", "1").encode().len() as u64;
+        assert_eq!(res, (RESP_OK.clone(), Some(offset)));
+        assert_eq!(backend.repl.offset(), offset);
+        // nothing to feed, no offset
+        assert_eq!(
+            backend.aof.append(Some(&backend.repl), vec![]),
+            (None, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_blocking() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::Always);
+        load_aof(&backend)?;
+        let main = std::thread::current().id();
+        let thread = run_blocking(&backend, move |b| {
+            b.aof.append(None, vec![set("a", "1")]);
+            std::thread::current().id()
+        })
+        .await;
+        // appended and fsynced off the runtime thread
+        assert_ne!(thread, main);
+        assert!(backend.aof.state.lock().unwrap().as_ref().unwrap().size > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_everysec() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::EverySec);
+        load_aof(&backend)?;
+        backend.aof.append(None, vec![set("a", "1")]);
+        assert!(backend.aof.state.lock().unwrap().as_ref().unwrap().unsynced);
+        backend.aof.fsync();
+        assert!(!backend.aof.state.lock().unwrap().as_ref().unwrap().unsynced);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fsynced_offset() -> Result<(), AofError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::EverySec);
+        let repl = &backend.repl;
+        assert_eq!(backend.aof.fsynced_offset(repl), 0);
+        load_aof(&backend)?;
+        backend.aof.append(Some(repl), vec![set("a", "1")]);
+        let offset = repl.offset();
+        assert!(offset > 0);
+        assert_eq!(backend.aof.fsynced_offset(repl), 0);
+        backend.aof.fsync();
+        assert_eq!(backend.aof.fsynced_offset(repl), offset);
+        // what is fed without being appended doesn't wait for an fsync
+        repl.feed(b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(backend.aof.fsynced_offset(repl), repl.offset());
+
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path(), AppendFsync::Always);
+        load_aof(&backend)?;
+        backend.aof.append(Some(&backend.repl), vec![set("a", "1")]);
+        let offset = backend.repl.offset();
+        assert_eq!(backend.aof.fsynced_offset(&backend.repl), offset);
+        Ok(())
+    }
+}
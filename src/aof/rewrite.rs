@@ -0,0 +1,89 @@
+use super::manifest::{AofFileInfo, Manifest};
+use super::{files_size, persist_manifest, Aof, AofError};
+use crate::rdb::write_rdb;
+use crate::snapshot::{write_file, Snapshot};
+use crate::Backend;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use tracing::{info, warn};
+
+// BGREWRITEAOF: the caller holds the write side of the gate.
+//
+// Writes go to a new incr file from now on, and the dataset is copied. A
+// thread writes the copy as the new base file, then the manifest drops the
+// old base and incr files. Until then the manifest still lists them, so a
+// crash at any point loads everything.
+pub(crate) fn bgrewriteaof(backend: &Backend) -> Result<(), AofError> {
+    let aof = &backend.aof;
+    let mut state = aof.state.lock().unwrap();
+    let state = state.as_mut().ok_or(AofError::Disabled)?;
+    if aof.rewriting.swap(true, Ordering::AcqRel) {
+        return Err(AofError::RewriteInProgress);
+    }
+    let incr_seq = match state.open_incr() {
+        Ok(seq) => seq,
+        Err(e) => {
+            aof.rewriting.store(false, Ordering::Release);
+            return Err(e.into());
+        }
+    };
+    let base = AofFileInfo::base(&state.prefix, state.manifest.next_base_seq(), "rdb");
+    let dir = state.dir.clone();
+    // the new base holds the stream up to there
+    let offset = state.written_offset;
+    let snapshot = Snapshot::capture(backend);
+    let backend = backend.clone();
+    std::thread::spawn(move || {
+        let aof = &backend.aof;
+        let result = write_file(&dir.join(&base.name), &write_rdb(&snapshot))
+            .map_err(AofError::from)
+            .and_then(|_| aof.finish_rewrite(base, incr_seq, offset));
+        match result {
+            Ok(_) => info!("Background AOF rewrite finished successfully"),
+            Err(e) => warn!("Background AOF rewrite error: {}", e),
+        }
+        aof.rewriting.store(false, Ordering::Release);
+    });
+    Ok(())
+}
+
+impl Aof {
+    // The new base is on disk: it replaces the old base and the incr files
+    // before `incr_seq`, which are deleted.
+    fn finish_rewrite(
+        &self,
+        base: AofFileInfo,
+        incr_seq: u64,
+        offset: u64,
+    ) -> Result<(), AofError> {
+        let mut state = self.state.lock().unwrap();
+        let Some(state) = state.as_mut() else {
+            return Ok(());
+        };
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: (state.manifest.incrs.iter())
+                .filter(|incr| incr.seq >= incr_seq)
+                .cloned()
+                .collect(),
+        };
+        persist_manifest(&state.dir, &state.prefix, &manifest)?;
+        let old = std::mem::replace(&mut state.manifest, manifest);
+        for info in old.files() {
+            if !state.manifest.files().any(|f| f.name == info.name) {
+                remove_file(&state.dir, &info.name);
+            }
+        }
+        state.size = files_size(&state.dir, &state.manifest);
+        self.base_size.store(state.size, Ordering::Release);
+        self.fsynced_offset.fetch_max(offset, Ordering::AcqRel);
+        Ok(())
+    }
+}
+
+fn remove_file(dir: &Path, name: &str) {
+    if let Err(e) = fs::remove_file(dir.join(name)) {
+        warn!("Can't remove the old append only file {}: {}", name, e);
+    }
+}
@@ -1,16 +1,117 @@
-use crate::RespFrame;
+use crate::aof::Aof;
+use crate::cluster::{key_slot, Cluster, MigratePool};
+use crate::replication::Replication;
+use crate::script::{FunctionRegistry, ScriptState};
+use crate::snapshot::{unix_time_ms, SaveState};
+use crate::{Config, RespFrame};
+use bytes::Bytes;
+use dashmap::mapref::entry::Entry;
 use dashmap::{DashMap, DashSet};
 use macro_definitions::AutoDeref;
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+// number of locks the keys are spread over, see `Backend::lock_key`
+const KEY_LOCKS: usize = 1024;
+// how often keys which are never accessed again are looked for expiring
+const EXPIRE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+// most keys deleted by one cycle, so that it doesn't stall the clients
+const EXPIRE_CYCLE_KEYS: usize = 200;
 
 #[derive(Debug, Clone, AutoDeref)]
 pub struct Backend(Arc<BackendInner>);
 
+// the kind of value a key holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    String,
+    Hash,
+    Set,
+    List,
+    ZSet,
+}
+
 #[derive(Debug)]
 pub struct BackendInner {
-    pub(crate) map: DashMap<String, RespFrame>,
-    pub(crate) hmap: DashMap<String, DashMap<String, RespFrame>>,
-    pub(crate) set: DashMap<String, DashSet<String>>,
+    pub(crate) config: Config,
+    // keys, hash fields and set members are binary safe
+    pub(crate) map: DashMap<Bytes, RespFrame>,
+    pub(crate) hmap: DashMap<Bytes, DashMap<Bytes, RespFrame>>,
+    pub(crate) set: DashMap<Bytes, DashSet<Bytes>>,
+    // lists and sorted sets only come from RDB files and RESTORE for now
+    pub(crate) list: DashMap<Bytes, VecDeque<Bytes>>,
+    pub(crate) zset: DashMap<Bytes, DashMap<Bytes, f64>>,
+    // unix time in milliseconds at which a key expires
+    pub(crate) expires: DashMap<Bytes, u64>,
+    // version counter of the watched keys, only kept while someone watches them
+    pub(crate) versions: DashMap<Bytes, KeyVersion>,
+    // a command which checks the type of a key before writing it holds the
+    // lock of the key, so that no other type is stored in between
+    key_locks: Box<[Mutex<()>]>,
+    // normal commands hold the read side, EXEC holds the write side
+    // so that a transaction runs atomically with respect to other connections
+    pub(crate) gate: RwLock<()>,
+    // scripts cached by their SHA1 digest
+    pub(crate) scripts: DashMap<String, String>,
+    pub(crate) script_state: ScriptState,
+    pub(crate) functions: FunctionRegistry,
+    pub(crate) save_state: SaveState,
+    pub(crate) aof: Aof,
+    pub(crate) repl: Replication,
+    // None unless cluster mode is enabled
+    pub(crate) cluster: Option<Cluster>,
+    // connections kept to the targets of MIGRATE
+    pub(crate) migrate_pool: MigratePool,
+    next_client_id: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct KeyVersion {
+    version: u64,
+    watchers: usize,
+}
+
+// A watched key, it's not watched anymore once dropped.
+pub(crate) struct WatchedKey {
+    backend: Backend,
+    key: Bytes,
+    version: u64,
+}
+
+impl WatchedKey {
+    pub(crate) fn key(&self) -> &Bytes {
+        &self.key
+    }
+
+    // whether the key was modified since it was watched
+    pub(crate) fn changed(&self) -> bool {
+        self.backend.version(&self.key) != self.version
+    }
+}
+
+impl Drop for WatchedKey {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut entry) = self.backend.versions.entry(self.key.clone()) {
+            entry.get_mut().watchers -= 1;
+            if entry.get().watchers == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+// the backend is left out
+impl fmt::Debug for WatchedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchedKey")
+            .field("key", &self.key)
+            .field("version", &self.version)
+            .finish()
+    }
 }
 
 impl Default for Backend {
@@ -22,9 +123,25 @@ impl Default for Backend {
 impl Default for BackendInner {
     fn default() -> Self {
         Self {
+            config: Config::default(),
             map: DashMap::new(),
             hmap: DashMap::new(),
             set: DashMap::new(),
+            list: DashMap::new(),
+            zset: DashMap::new(),
+            expires: DashMap::new(),
+            versions: DashMap::new(),
+            key_locks: (0..KEY_LOCKS).map(|_| Mutex::new(())).collect(),
+            gate: RwLock::new(()),
+            scripts: DashMap::new(),
+            script_state: ScriptState::default(),
+            functions: FunctionRegistry::default(),
+            save_state: SaveState::default(),
+            aof: Aof::default(),
+            repl: Replication::default(),
+            cluster: None,
+            migrate_pool: MigratePool::default(),
+            next_client_id: AtomicU64::new(1),
         }
     }
 }
@@ -34,38 +151,325 @@ impl Backend {
         Self::default()
     }
 
-    pub fn get(&self, key: &str) -> Option<RespFrame> {
+    pub fn with_config(config: Config) -> Self {
+        Self(Arc::new(BackendInner {
+            repl: Replication::new(
+                config.repl_backlog_size,
+                config.client_output_buffer_limit_replica,
+            ),
+            cluster: config.cluster_enabled.then(|| Cluster::new(&config)),
+            config,
+            ..Default::default()
+        }))
+    }
+
+    pub fn next_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Lock the key against the commands which change its type. Keys share
+    // locks, so at most one key is locked at a time.
+    pub(crate) fn lock_key(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        let lock = &self.key_locks[key_slot(key) as usize % KEY_LOCKS];
+        lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Delete the key if its time to live is over, returns true if it was.
+    // Every lookup calls it first, so an expired key is never seen. The
+    // entry of `expires` is held while the key is deleted: a write which
+    // replaces the key drops its expiry first, so it waits and isn't undone.
+    pub(crate) fn expire_if_needed(&self, key: &[u8]) -> bool {
+        // most keys have no expiry, look before taking the entry
+        if self.expires.get(key).is_none_or(|at| *at > unix_time_ms()) {
+            return false;
+        }
+        let Entry::Occupied(entry) = self.expires.entry(Bytes::copy_from_slice(key)) else {
+            return false;
+        };
+        if *entry.get() > unix_time_ms() {
+            return false;
+        }
+        self.map.remove(key);
+        self.hmap.remove(key);
+        self.set.remove(key);
+        self.list.remove(key);
+        self.zset.remove(key);
+        let (key, _) = entry.remove_entry();
+        self.touch(&key);
+        true
+    }
+
+    pub fn key_type(&self, key: &[u8]) -> Option<KeyType> {
+        self.expire_if_needed(key);
+        if self.map.contains_key(key) {
+            Some(KeyType::String)
+        } else if self.hmap.contains_key(key) {
+            Some(KeyType::Hash)
+        } else if self.set.contains_key(key) {
+            Some(KeyType::Set)
+        } else if self.list.contains_key(key) {
+            Some(KeyType::List)
+        } else if self.zset.contains_key(key) {
+            Some(KeyType::ZSet)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<RespFrame> {
+        self.expire_if_needed(key);
         self.map.get(key).map(|v| v.value().clone())
     }
 
-    pub fn set(&self, key: String, value: RespFrame) {
+    // SET overwrites the key whatever it held before, and drops its expiry
+    pub fn set(&self, key: Bytes, value: RespFrame) {
+        let _lock = self.lock_key(&key);
+        self.touch(&key);
+        self.hmap.remove(&key);
+        self.set.remove(&key);
+        self.list.remove(&key);
+        self.zset.remove(&key);
+        self.expires.remove(&key);
         self.map.insert(key, value);
     }
 
-    pub fn hget(&self, key: &str, field: &str) -> Option<RespFrame> {
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Option<RespFrame> {
+        self.expire_if_needed(key);
         self.hmap
             .get(key)
             .and_then(|v| v.get(field).map(|v| v.value().clone()))
     }
 
-    pub fn hset(&self, key: String, field: String, value: RespFrame) {
+    // the caller holds the lock of the key if it checked the type
+    pub fn hset(&self, key: Bytes, field: Bytes, value: RespFrame) {
+        self.touch(&key);
         let hmap = self.hmap.entry(key).or_default();
         hmap.insert(field, value);
     }
 
-    pub fn hgetall(&self, key: &str) -> Option<DashMap<String, RespFrame>> {
+    pub fn hgetall(&self, key: &[u8]) -> Option<DashMap<Bytes, RespFrame>> {
+        self.expire_if_needed(key);
         self.hmap.get(key).map(|v| v.clone())
     }
 
-    pub fn sadd(&self, key: String, field: String) -> bool {
-        let set = self.set.entry(key).or_default();
-        set.insert(field)
+    // the caller holds the lock of the key if it checked the type
+    pub fn sadd(&self, key: Bytes, member: Bytes) -> bool {
+        let set = self.set.entry(key.clone()).or_default();
+        let inserted = set.insert(member);
+        if inserted {
+            self.touch(&key);
+        }
+        inserted
     }
 
-    pub fn sismember(&self, key: &str, member: &str) -> bool {
+    pub fn sismember(&self, key: &[u8], member: &[u8]) -> bool {
+        self.expire_if_needed(key);
         match self.set.get(key) {
             None => false,
             Some(v) => v.contains(member),
         }
     }
+
+    // Watch the key for modifications, until the returned key is dropped.
+    pub(crate) fn watch(&self, key: Bytes) -> WatchedKey {
+        let mut entry = self.versions.entry(key.clone()).or_default();
+        entry.watchers += 1;
+        let version = entry.version;
+        drop(entry);
+        WatchedKey {
+            backend: self.clone(),
+            key,
+            version,
+        }
+    }
+
+    // the version of a watched key, modifications of unwatched keys aren't counted
+    fn version(&self, key: &[u8]) -> u64 {
+        self.versions.get(key).map_or(0, |v| v.version)
+    }
+
+    // Remove the key whatever it holds, returns false if it didn't exist.
+    // The caller touches the key.
+    pub(crate) fn remove(&self, key: &[u8]) -> bool {
+        if self.expire_if_needed(key) {
+            return false;
+        }
+        self.expires.remove(key);
+        let removed = [
+            self.map.remove(key).is_some(),
+            self.hmap.remove(key).is_some(),
+            self.set.remove(key).is_some(),
+            self.list.remove(key).is_some(),
+            self.zset.remove(key).is_some(),
+        ];
+        removed.contains(&true)
+    }
+
+    // every key, whatever it holds
+    pub(crate) fn keys(&self) -> Vec<Bytes> {
+        let mut keys: Vec<Bytes> = self.map.iter().map(|e| e.key().clone()).collect();
+        keys.extend(self.hmap.iter().map(|e| e.key().clone()));
+        keys.extend(self.set.iter().map(|e| e.key().clone()));
+        keys.extend(self.list.iter().map(|e| e.key().clone()));
+        keys.extend(self.zset.iter().map(|e| e.key().clone()));
+        keys.retain(|k| !self.expire_if_needed(k));
+        keys
+    }
+
+    pub(crate) fn touch(&self, key: &Bytes) {
+        if let Some(mut watched) = self.versions.get_mut(key) {
+            watched.version += 1;
+        }
+        self.save_state.incr_dirty();
+    }
+}
+
+// Delete the expired keys nobody accesses, runs as long as the server does.
+// Replicas and the AOF hold the absolute time a key expires at, so every
+// node deletes it by itself instead of waiting for a DEL.
+pub async fn run_expire_cycle(backend: Backend) {
+    loop {
+        tokio::time::sleep(EXPIRE_CYCLE_INTERVAL).await;
+        // not in the middle of a transaction
+        let _guard = backend.gate.read().await;
+        let now = unix_time_ms();
+        let expired: Vec<Bytes> = backend
+            .expires
+            .iter()
+            .filter(|e| *e.value() <= now)
+            .map(|e| e.key().clone())
+            .take(EXPIRE_CYCLE_KEYS)
+            .collect();
+        for key in expired {
+            backend.expire_if_needed(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BulkString;
+
+    #[test]
+    fn test_set_replaces_other_types() {
+        let backend = Backend::new();
+        backend.hset(
+            Bytes::from_static(b"h"),
+            Bytes::from_static(b"f"),
+            BulkString::new("v").into(),
+        );
+        backend.sadd(Bytes::from_static(b"s"), Bytes::from_static(b"m"));
+        backend.set(Bytes::from_static(b"h"), BulkString::new("1").into());
+        backend.set(Bytes::from_static(b"s"), BulkString::new("2").into());
+        assert!(backend.hmap.is_empty());
+        assert!(backend.set.is_empty());
+        assert_eq!(backend.key_type(b"h"), Some(KeyType::String));
+        assert_eq!(backend.key_type(b"s"), Some(KeyType::String));
+    }
+
+    #[test]
+    fn test_expire_on_access() {
+        let backend = Backend::new();
+        let past = unix_time_ms() - 1;
+        backend.set(Bytes::from_static(b"s"), BulkString::new("v").into());
+        backend.hset(
+            Bytes::from_static(b"h"),
+            Bytes::from_static(b"f"),
+            BulkString::new("v").into(),
+        );
+        backend.sadd(Bytes::from_static(b"set"), Bytes::from_static(b"m"));
+        backend.sadd(Bytes::from_static(b"live"), Bytes::from_static(b"m"));
+        for key in ["s", "h", "set"] {
+            backend.expires.insert(Bytes::from(key), past);
+        }
+        backend
+            .expires
+            .insert(Bytes::from_static(b"live"), past + 100_000);
+        let watched = backend.watch(Bytes::from_static(b"s"));
+
+        assert_eq!(backend.get(b"s"), None);
+        assert!(watched.changed());
+        assert_eq!(backend.hget(b"h", b"f"), None);
+        assert!(!backend.sismember(b"set", b"m"));
+        assert!(backend.sismember(b"live", b"m"));
+        assert_eq!(backend.key_type(b"live"), Some(KeyType::Set));
+        assert!(backend.map.is_empty() && backend.hmap.is_empty());
+        assert_eq!(backend.expires.len(), 1);
+    }
+
+    #[test]
+    fn test_versions_only_kept_while_watched() {
+        let backend = Backend::new();
+        let key = Bytes::from_static(b"k");
+        backend.set(key.clone(), BulkString::new("1").into());
+        assert!(backend.versions.is_empty());
+
+        let first = backend.watch(key.clone());
+        let second = backend.watch(key.clone());
+        assert!(!first.changed());
+        backend.set(key.clone(), BulkString::new("2").into());
+        assert!(first.changed() && second.changed());
+        drop(first);
+        assert!(second.changed());
+        drop(second);
+        assert!(backend.versions.is_empty());
+        // watched again, only what follows counts
+        let watched = backend.watch(key.clone());
+        assert!(!watched.changed());
+    }
+
+    #[test]
+    fn test_expired_keys_are_not_listed_or_removed() {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"a"), BulkString::new("1").into());
+        backend.set(Bytes::from_static(b"b"), BulkString::new("2").into());
+        backend
+            .expires
+            .insert(Bytes::from_static(b"a"), unix_time_ms() - 1);
+        assert_eq!(backend.keys(), vec![Bytes::from_static(b"b")]);
+        backend
+            .expires
+            .insert(Bytes::from_static(b"b"), unix_time_ms() - 1);
+        // DEL counts a key which had expired as missing
+        assert!(!backend.remove(b"b"));
+        assert!(backend.map.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expire_cycle() {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"k"), BulkString::new("v").into());
+        backend
+            .expires
+            .insert(Bytes::from_static(b"k"), unix_time_ms() + 50);
+        let cycle = tokio::spawn(run_expire_cycle(backend.clone()));
+        tokio::time::sleep(EXPIRE_CYCLE_INTERVAL * 3).await;
+        cycle.abort();
+        // deleted without being accessed
+        assert!(backend.map.is_empty());
+        assert!(backend.expires.is_empty());
+    }
+
+    #[test]
+    fn test_lock_key_serializes_type_changes() {
+        let backend = Backend::new();
+        let lock = backend.lock_key(b"k");
+        let handle = std::thread::spawn({
+            let backend = backend.clone();
+            move || backend.set(Bytes::from_static(b"k"), BulkString::new("v").into())
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        // SET waits for the lock before replacing the hash
+        assert_eq!(backend.key_type(b"k"), None);
+        backend.hset(
+            Bytes::from_static(b"k"),
+            Bytes::from_static(b"f"),
+            BulkString::new("v").into(),
+        );
+        drop(lock);
+        handle.join().unwrap();
+        assert_eq!(backend.key_type(b"k"), Some(KeyType::String));
+        assert!(backend.hmap.is_empty());
+    }
 }
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use simple_redis::{aof_parts, check_rdb, scan_aof, AofTail};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::process::ExitCode;
+
+// Check an append only file the way redis-check-aof does, and with --fix
+// truncate it after its last complete command. Given the manifest of a multi
+// part AOF or the directory holding it, every file it lists is checked, and
+// only the last one can be fixed.
+//
+// Usage: simple-redis-check-aof [--fix] <file.manifest|file.aof|dir>
+fn main() -> Result<ExitCode> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (fix, path) = match args.as_slice() {
+        [path] => (false, Path::new(path)),
+        [flag, path] if flag == "--fix" => (true, Path::new(path)),
+        _ => {
+            eprintln!("Usage: simple-redis-check-aof [--fix] <file.manifest|file.aof|dir>");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    if !path.is_dir() && path.extension().is_none_or(|e| e != "manifest") {
+        return check_file(path, fix, true);
+    }
+
+    println!("Start checking Multi Part AOF");
+    let parts = aof_parts(path)?;
+    for (i, part) in parts.iter().enumerate() {
+        let kind = if part.base { "BASE" } else { "INCR" };
+        let data = fs::read(&part.path)?;
+        if part.rdb || data.starts_with(b"REDIS") {
+            println!("Start to check {} AOF (RDB format)", kind);
+            if let Err(e) = check_rdb(&data) {
+                println!("{} AOF {} is not valid: {}", kind, part.path.display(), e);
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("{} AOF {} is valid", kind, part.path.display());
+            continue;
+        }
+        println!("Start to check {} AOF (AOF format)", kind);
+        let last = i + 1 == parts.len();
+        if check_file(&part.path, fix, last)? != ExitCode::SUCCESS {
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    println!("All AOF files and manifest are valid");
+    Ok(ExitCode::SUCCESS)
+}
+
+// Only the last file of an AOF is written to, the others can't be cut short
+// by a crash and aren't fixed.
+fn check_file(path: &Path, fix: bool, last: bool) -> Result<ExitCode> {
+    let data = fs::read(path)?;
+    let scan = scan_aof(&data);
+    let diff = data.len() - scan.valid_len;
+    println!(
+        "AOF analyzed: filename={}, size={}, ok_up_to={}, diff={}",
+        path.display(),
+        data.len(),
+        scan.valid_len,
+        diff
+    );
+    match scan.tail {
+        AofTail::Clean => {
+            println!("AOF is valid");
+            return Ok(ExitCode::SUCCESS);
+        }
+        AofTail::Truncated => println!("The last command or transaction is incomplete"),
+        AofTail::Corrupt(e) => println!("Bad file format after {} bytes: {}", scan.valid_len, e),
+    }
+    if !last {
+        println!("AOF is not valid. Only the last file of a multi part AOF can be fixed.");
+        return Ok(ExitCode::FAILURE);
+    }
+    if !fix {
+        println!("AOF is not valid. Use the --fix option to try fixing it.");
+        return Ok(ExitCode::FAILURE);
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(scan.valid_len as u64)?;
+    println!("Successfully truncated AOF, {} bytes removed", diff);
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,564 @@
+use super::{Address, Cluster, ClusterState, Node, CLUSTER_SLOTS};
+use crate::snapshot::unix_time_ms;
+use crate::{Backend, BulkString, Config, RespArray, RespDecoder, RespEncode, RespFrame};
+use anyhow::{anyhow, bail, Result};
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+// how often links are started to the nodes which have none
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+// how often every node is pinged
+const PING_INTERVAL: Duration = Duration::from_millis(100);
+
+// The cluster bus.
+//
+// Every node keeps a link to every other node it knows, on which it sends a
+// PING and waits for the PONG in turn. Both carry the slots the sender
+// serves and the nodes it knows about, so that CLUSTER MEET with a single
+// node is enough for all the nodes to find each other. When two nodes claim
+// a slot, the one with the greater config epoch wins.
+pub async fn run_cluster_bus(listener: TcpListener, backend: Backend) {
+    if backend.cluster.is_none() {
+        return;
+    }
+    tokio::spawn(run_cron(backend.clone()));
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let backend = backend.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_node(stream, &backend).await {
+                        debug!("Cluster bus connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Cluster bus accept failed: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageType {
+    Meet,
+    Ping,
+    Pong,
+}
+
+#[derive(Debug, PartialEq)]
+struct Message {
+    typ: MessageType,
+    sender: String,
+    port: u16,
+    bus_port: u16,
+    current_epoch: u64,
+    config_epoch: u64,
+    // a bit for every slot the sender serves
+    slots: Vec<u8>,
+    // the other nodes the sender knows
+    gossip: Vec<(String, Address)>,
+}
+
+// Reads the messages of a link with the limits of client requests, a frame
+// received in many pieces is only scanned once.
+struct MessageReader {
+    buf: BytesMut,
+    decoder: RespDecoder,
+    buffer_limit: usize,
+}
+
+// Where a link goes: a known node, or an address given to CLUSTER MEET.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    Node(String),
+    Meet(Address),
+}
+
+async fn run_cron(backend: Backend) {
+    let mut links: HashMap<Target, JoinHandle<()>> = HashMap::new();
+    loop {
+        tokio::time::sleep(CRON_INTERVAL).await;
+        let Some(cluster) = &backend.cluster else {
+            return;
+        };
+        let timeout = Duration::from_millis(backend.config.cluster_node_timeout);
+        let targets = cluster.targets(timeout);
+        links.retain(|target, link| targets.contains(target) || !link.is_finished());
+        for target in targets {
+            if links.get(&target).is_none_or(|link| link.is_finished()) {
+                let link = tokio::spawn(run_link(backend.clone(), target.clone()));
+                links.insert(target, link);
+            }
+        }
+    }
+}
+
+async fn run_link(backend: Backend, target: Target) {
+    let Some(cluster) = &backend.cluster else {
+        return;
+    };
+    if let Err(e) = ping_node(cluster, &target, &backend.config).await {
+        debug!("Cluster bus link to {:?} lost: {}", target, e);
+    }
+    if let Target::Node(id) = &target {
+        cluster.set_connected(id, false);
+    }
+}
+
+// Ping the node until it fails to reply in time. A MEET only waits for the
+// first reply, the node is linked to as any other afterwards.
+async fn ping_node(cluster: &Cluster, target: &Target, config: &Config) -> Result<()> {
+    let timeout = Duration::from_millis(config.cluster_node_timeout);
+    let addr = match target {
+        Target::Node(id) => cluster.addr(id).ok_or_else(|| anyhow!("unknown node"))?,
+        Target::Meet(addr) => addr.clone(),
+    };
+    let connect = TcpStream::connect((addr.ip.as_str(), addr.bus_port));
+    let mut stream = tokio::time::timeout(timeout, connect).await??;
+    let local_ip = stream.local_addr()?.ip().to_string();
+    let mut reader = MessageReader::new(config);
+    loop {
+        let (typ, to) = match target {
+            Target::Node(id) => (MessageType::Ping, Some(id.as_str())),
+            Target::Meet(_) => (MessageType::Meet, None),
+        };
+        let ping = cluster.message(typ, to);
+        stream.write_all(&RespFrame::from(ping).encode()).await?;
+        cluster.ping_sent(to);
+        let pong = tokio::time::timeout(timeout, reader.read(&mut stream)).await??;
+        if let Target::Node(id) = target {
+            if pong.sender != *id {
+                bail!("the node is now {}", pong.sender);
+            }
+        }
+        let meet = typ == MessageType::Meet;
+        if !cluster.process(&pong, &addr.ip, &local_ip, meet) {
+            bail!("unexpected PONG from {}", pong.sender);
+        }
+        if let Target::Meet(addr) = target {
+            info!(
+                "Cluster node {} met at {}:{}",
+                pong.sender, addr.ip, addr.port
+            );
+            cluster.handshake_done(addr);
+            return Ok(());
+        }
+        tokio::time::sleep(PING_INTERVAL).await;
+    }
+}
+
+// A link from another node, every PING or MEET gets a PONG.
+async fn serve_node(mut stream: TcpStream, backend: &Backend) -> Result<()> {
+    let cluster = backend
+        .cluster
+        .as_ref()
+        .ok_or_else(|| anyhow!("not a cluster"))?;
+    let peer_ip = stream.peer_addr()?.ip().to_string();
+    let local_ip = stream.local_addr()?.ip().to_string();
+    let mut reader = MessageReader::new(&backend.config);
+    loop {
+        let ping = reader.read(&mut stream).await?;
+        let meet = ping.typ == MessageType::Meet;
+        if !cluster.process(&ping, &peer_ip, &local_ip, meet) {
+            bail!("PING from unknown node {}", ping.sender);
+        }
+        let pong = cluster.message(MessageType::Pong, Some(&ping.sender));
+        stream.write_all(&RespFrame::from(pong).encode()).await?;
+    }
+}
+
+impl MessageReader {
+    fn new(config: &Config) -> Self {
+        MessageReader {
+            buf: BytesMut::new(),
+            decoder: RespDecoder::with_limits(config.resp_limits()),
+            buffer_limit: config.client_query_buffer_limit,
+        }
+    }
+
+    async fn read(&mut self, stream: &mut (impl AsyncRead + Unpin)) -> Result<Message> {
+        loop {
+            if let Some(frame) = self.decoder.decode(&mut self.buf)? {
+                return Message::try_from(frame);
+            }
+            if self.buf.len() > self.buffer_limit {
+                bail!("message buffer limit exceeded");
+            }
+            if stream.read_buf(&mut self.buf).await? == 0 {
+                bail!("connection closed");
+            }
+        }
+    }
+}
+
+impl Cluster {
+    // the nodes to link to, and the addresses to meet
+    fn targets(&self, timeout: Duration) -> Vec<Target> {
+        let mut state = self.state.lock().unwrap();
+        // nodes which never replied to MEET are given up on
+        state
+            .handshakes
+            .retain(|(_, since)| since.elapsed() < timeout);
+        let nodes = state.nodes.keys().filter(|id| **id != state.myself);
+        let mut targets: Vec<Target> = nodes.map(|id| Target::Node(id.clone())).collect();
+        let meets = state.handshakes.iter();
+        targets.extend(meets.map(|(addr, _)| Target::Meet(addr.clone())));
+        targets
+    }
+
+    fn addr(&self, id: &str) -> Option<Address> {
+        let state = self.state.lock().unwrap();
+        state.nodes.get(id).map(|node| node.addr.clone())
+    }
+
+    fn set_connected(&self, id: &str, connected: bool) {
+        if let Some(node) = self.state.lock().unwrap().nodes.get_mut(id) {
+            node.connected = connected;
+        }
+    }
+
+    fn ping_sent(&self, id: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(node) = id.and_then(|id| state.nodes.get_mut(id)) {
+            node.ping_sent = unix_time_ms();
+        }
+    }
+
+    fn handshake_done(&self, addr: &Address) {
+        let mut state = self.state.lock().unwrap();
+        state.handshakes.retain(|(a, _)| a != addr);
+    }
+
+    // a message from this node to `to`, which isn't gossiped about
+    fn message(&self, typ: MessageType, to: Option<&str>) -> Message {
+        let state = self.state.lock().unwrap();
+        let myself = &state.nodes[&state.myself];
+        let mut slots = vec![0u8; CLUSTER_SLOTS / 8];
+        for (slot, owner) in state.owners.iter().enumerate() {
+            if owner.as_ref() == Some(&myself.id) {
+                slots[slot / 8] |= 1 << (slot % 8);
+            }
+        }
+        let gossip = state
+            .nodes
+            .values()
+            .filter(|n| n.id != myself.id && Some(n.id.as_str()) != to)
+            .map(|n| (n.id.clone(), n.addr.clone()))
+            .collect();
+        Message {
+            typ,
+            sender: myself.id.clone(),
+            port: myself.addr.port,
+            bus_port: myself.addr.bus_port,
+            current_epoch: state.current_epoch,
+            config_epoch: myself.config_epoch,
+            slots,
+            gossip,
+        }
+    }
+
+    // Update what this node knows with a message received from `peer_ip`
+    // on a connection to `local_ip`. Unknown senders are only added when
+    // `meet`, returns false when the message was ignored.
+    fn process(&self, msg: &Message, peer_ip: &str, local_ip: &str, meet: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let myself = state.myself.clone();
+        if msg.sender == myself || !(meet || state.nodes.contains_key(&msg.sender)) {
+            return false;
+        }
+        // the other nodes connect to this IP, unless it's announced
+        let me = state.nodes.get_mut(&myself).unwrap();
+        if me.addr.ip.is_empty() {
+            me.addr.ip = local_ip.to_string();
+        }
+        let addr = Address {
+            ip: peer_ip.to_string(),
+            port: msg.port,
+            bus_port: msg.bus_port,
+        };
+        let node = (state.nodes)
+            .entry(msg.sender.clone())
+            .or_insert_with(|| Node::new(msg.sender.clone(), addr.clone()));
+        node.addr = addr;
+        node.config_epoch = msg.config_epoch;
+        node.connected = true;
+        if msg.typ == MessageType::Pong {
+            node.pong_received = unix_time_ms();
+        }
+        state.current_epoch = state.current_epoch.max(msg.current_epoch);
+        state.resolve_epoch_collision(&msg.sender);
+        state.update_slots(&msg.sender, &msg.slots);
+        for (id, addr) in &msg.gossip {
+            if *id != myself && !state.nodes.contains_key(id) {
+                debug!("Cluster node {} learned from {}", id, msg.sender);
+                let node = Node::new(id.clone(), addr.clone());
+                state.nodes.insert(id.clone(), node);
+            }
+        }
+        true
+    }
+}
+
+impl ClusterState {
+    // Two nodes with the same config epoch can't both win the slots they
+    // claim, the one with the smaller id takes a new epoch.
+    fn resolve_epoch_collision(&mut self, sender: &str) {
+        let epoch = self.nodes[&self.myself].config_epoch;
+        if self.nodes[sender].config_epoch != epoch || sender <= self.myself.as_str() {
+            return;
+        }
+        self.current_epoch += 1;
+        let epoch = self.current_epoch;
+        self.nodes.get_mut(&self.myself).unwrap().config_epoch = epoch;
+    }
+
+    // the slots the sender claims are its own unless a node with a greater
+    // config epoch serves them
+    fn update_slots(&mut self, sender: &str, slots: &[u8]) {
+        let epoch = self.nodes[sender].config_epoch;
+        for slot in 0..CLUSTER_SLOTS {
+            if slots
+                .get(slot / 8)
+                .is_none_or(|b| b & (1 << (slot % 8)) == 0)
+            {
+                continue;
+            }
+            let owner = self.owners[slot].as_deref();
+            if owner == Some(sender) {
+                continue;
+            }
+            if let Some(owner) = owner.and_then(|id| self.nodes.get(id)) {
+                if owner.config_epoch >= epoch {
+                    continue;
+                }
+            }
+            if owner == Some(self.myself.as_str()) {
+                self.migrating.remove(&(slot as u16));
+            }
+            self.owners[slot] = Some(sender.to_string());
+        }
+    }
+}
+
+impl From<Message> for RespFrame {
+    fn from(msg: Message) -> Self {
+        let bulk = |s: &str| -> RespFrame { BulkString::new(s).into() };
+        let gossip = msg.gossip.iter().map(|(id, addr)| -> RespFrame {
+            RespArray::with_vec(vec![
+                bulk(id),
+                bulk(&addr.ip),
+                RespFrame::Integer(addr.port as i64),
+                RespFrame::Integer(addr.bus_port as i64),
+            ])
+            .into()
+        });
+        let typ = match msg.typ {
+            MessageType::Meet => "meet",
+            MessageType::Ping => "ping",
+            MessageType::Pong => "pong",
+        };
+        RespArray::with_vec(vec![
+            bulk(typ),
+            bulk(&msg.sender),
+            RespFrame::Integer(msg.port as i64),
+            RespFrame::Integer(msg.bus_port as i64),
+            RespFrame::Integer(msg.current_epoch as i64),
+            RespFrame::Integer(msg.config_epoch as i64),
+            BulkString::new(msg.slots).into(),
+            RespArray::with_vec(gossip.collect::<Vec<_>>()).into(),
+        ])
+        .into()
+    }
+}
+
+impl TryFrom<RespFrame> for Message {
+    type Error = anyhow::Error;
+
+    fn try_from(frame: RespFrame) -> Result<Self> {
+        let RespFrame::Array(RespArray(Some(fields))) = frame else {
+            bail!("not a cluster bus message");
+        };
+        let [typ, sender, port, bus_port, current_epoch, config_epoch, slots, gossip] =
+            <[RespFrame; 8]>::try_from(fields).map_err(|_| anyhow!("bad message length"))?;
+        let typ = match string(&typ)?.as_str() {
+            "meet" => MessageType::Meet,
+            "ping" => MessageType::Ping,
+            "pong" => MessageType::Pong,
+            typ => bail!("unknown message type {}", typ),
+        };
+        let RespFrame::Array(RespArray(Some(gossip))) = gossip else {
+            bail!("bad gossip section");
+        };
+        let gossip = gossip
+            .into_iter()
+            .map(|node| {
+                let RespFrame::Array(RespArray(Some(node))) = node else {
+                    bail!("bad gossip entry");
+                };
+                let [id, ip, port, bus_port] =
+                    <[RespFrame; 4]>::try_from(node).map_err(|_| anyhow!("bad gossip entry"))?;
+                let addr = Address {
+                    ip: string(&ip)?,
+                    port: integer(&port)?,
+                    bus_port: integer(&bus_port)?,
+                };
+                Ok((string(&id)?, addr))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Message {
+            typ,
+            sender: string(&sender)?,
+            port: integer(&port)?,
+            bus_port: integer(&bus_port)?,
+            current_epoch: integer(&current_epoch)?,
+            config_epoch: integer(&config_epoch)?,
+            slots: slots
+                .as_bytes()
+                .ok_or_else(|| anyhow!("bad slots"))?
+                .to_vec(),
+            gossip,
+        })
+    }
+}
+
+fn string(frame: &RespFrame) -> Result<String> {
+    let bytes = frame
+        .as_bytes()
+        .ok_or_else(|| anyhow!("expected a string"))?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+fn integer<T: TryFrom<i64>>(frame: &RespFrame) -> Result<T> {
+    match frame {
+        RespFrame::Integer(n) => T::try_from(*n).map_err(|_| anyhow!("integer out of range")),
+        _ => bail!("expected an integer"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Config, RespDecode};
+
+    fn cluster(port: u16) -> Cluster {
+        Cluster::new(&Config {
+            cluster_enabled: true,
+            port,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let cluster = cluster(7000);
+        cluster.add_slots(&[0, 9, 16383]).unwrap();
+        let msg = cluster.message(MessageType::Ping, None);
+        assert_eq!(msg.slots[0], 1);
+        assert_eq!(msg.slots[1], 2);
+        assert_eq!(msg.slots[2047], 0x80);
+        let mut buf = BytesMut::from(&RespFrame::from(msg).encode()[..]);
+        let decoded = Message::try_from(RespFrame::decode(&mut buf).unwrap()).unwrap();
+        assert_eq!(decoded, cluster.message(MessageType::Ping, None));
+    }
+
+    #[tokio::test]
+    async fn test_reader_limits() {
+        let encoded = RespFrame::from(cluster(7000).message(MessageType::Pong, None)).encode();
+        let mut reader = MessageReader::new(&Config::default());
+        let mut stream = &encoded[..];
+        assert_eq!(
+            reader.read(&mut stream).await.unwrap().typ,
+            MessageType::Pong
+        );
+        assert!(reader.read(&mut stream).await.is_err());
+
+        // the slots are a bulk string of 2048 bytes
+        let config = Config {
+            proto_max_bulk_len: 1024,
+            ..Default::default()
+        };
+        let mut reader = MessageReader::new(&config);
+        let error = reader.read(&mut &encoded[..]).await.unwrap_err();
+        assert_eq!(error.to_string(), "Protocol error: invalid bulk length");
+
+        let config = Config {
+            client_query_buffer_limit: 1024,
+            ..Default::default()
+        };
+        let mut reader = MessageReader::new(&config);
+        let error = reader.read(&mut &encoded[..]).await.unwrap_err();
+        assert_eq!(error.to_string(), "message buffer limit exceeded");
+    }
+
+    #[test]
+    fn test_process() {
+        let (a, b, c) = (cluster(7000), cluster(7001), cluster(7002));
+        a.add_slots(&[1]).unwrap();
+        b.add_slots(&[2]).unwrap();
+
+        // a PING from a node which wasn't met is ignored
+        let ping = b.message(MessageType::Ping, None);
+        assert!(!a.process(&ping, "127.0.0.1", "127.0.0.1", false));
+        let meet = b.message(MessageType::Meet, None);
+        assert!(a.process(&meet, "127.0.0.1", "127.0.0.1", true));
+        assert_eq!(a.shards()[1].1, vec![(2, 2)]);
+        let pong = a.message(MessageType::Pong, Some(&b.myself()));
+        assert!(pong.gossip.is_empty());
+        assert!(b.process(&pong, "127.0.0.1", "127.0.0.1", true));
+
+        // c learns about b from a
+        let meet = c.message(MessageType::Meet, None);
+        assert!(a.process(&meet, "127.0.0.1", "127.0.0.1", true));
+        let pong = a.message(MessageType::Pong, Some(&c.myself()));
+        assert!(c.process(&pong, "127.0.0.1", "127.0.0.1", true));
+        assert_eq!(c.addr(&b.myself()).unwrap().port, 7001);
+        assert_eq!(c.shards().len(), 3);
+
+        // a slot claimed twice goes to the greater config epoch, the ids are
+        // random so b may have won an epoch collision already
+        let (a_id, b_id, c_id) = (a.myself(), b.myself(), c.myself());
+        let mut state = c.state.lock().unwrap();
+        state.owners[2] = Some(c_id.clone());
+        state.migrating.insert(2, a_id);
+        state.current_epoch = 2;
+        state.nodes.get_mut(&c_id).unwrap().config_epoch = 2;
+        drop(state);
+        let ping = b.message(MessageType::Ping, None);
+        assert!(c.process(&ping, "127.0.0.1", "127.0.0.1", false));
+        assert_eq!(c.state.lock().unwrap().owners[2], Some(c_id));
+        let mut state = b.state.lock().unwrap();
+        state.current_epoch = 5;
+        state.nodes.get_mut(&b_id).unwrap().config_epoch = 5;
+        drop(state);
+        let ping = b.message(MessageType::Ping, None);
+        assert!(c.process(&ping, "127.0.0.1", "127.0.0.1", false));
+        let state = c.state.lock().unwrap();
+        assert_eq!(state.owners[2], Some(b_id));
+        assert!(state.migrating.is_empty());
+        assert_eq!(state.current_epoch, 5);
+    }
+
+    #[test]
+    fn test_epoch_collision() {
+        let (a, b) = (cluster(7000), cluster(7001));
+        let (small, large) = match a.myself() < b.myself() {
+            true => (a, b),
+            false => (b, a),
+        };
+        let ping = large.message(MessageType::Ping, None);
+        assert!(small.process(&ping, "127.0.0.1", "127.0.0.1", true));
+        let ping = small.message(MessageType::Ping, None);
+        assert!(large.process(&ping, "127.0.0.1", "127.0.0.1", true));
+        let epoch = |c: &Cluster| {
+            let state = c.state.lock().unwrap();
+            (state.current_epoch, state.nodes[&state.myself].config_epoch)
+        };
+        assert_eq!(epoch(&small), (1, 1));
+        // the other node only learns the current epoch
+        assert_eq!(epoch(&large), (1, 0));
+    }
+}
@@ -0,0 +1,422 @@
+use crate::aof::run_blocking;
+use crate::rdb;
+use crate::snapshot::unix_time_ms;
+use crate::{
+    Backend, BulkString, Migrate, RespArray, RespDecode, RespEncode, RespError, RespFrame,
+    SimpleError, SimpleString, RESP_OK,
+};
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// the timeout of MIGRATE when it's given as 0
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+// how long a connection to a target is kept once idle
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connections MIGRATE opened to its targets, reused by the next MIGRATE to
+// the same target unless they were idle for too long.
+#[derive(Debug, Default)]
+pub(crate) struct MigratePool {
+    conns: Mutex<HashMap<Target, Vec<Conn>>>,
+}
+
+// A connection keeps the database it selected and the user it authenticated
+// as, so it's only reused for the same ones.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Target {
+    host: String,
+    port: u16,
+    db: i64,
+    auth: Option<(Option<String>, String)>,
+}
+
+impl Target {
+    fn new(migrate: &Migrate) -> Self {
+        Target {
+            host: migrate.host.clone(),
+            port: migrate.port,
+            db: migrate.db,
+            auth: migrate.auth.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let auth = self.auth.as_ref().map(|(user, _)| (user, "(redacted)"));
+        f.debug_struct("Target")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("db", &self.db)
+            .field("auth", &auth)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Conn {
+    stream: TcpStream,
+    buf: BytesMut,
+    idle_since: Instant,
+}
+
+impl MigratePool {
+    fn take(&self, target: &Target) -> Option<Conn> {
+        let mut conns = self.conns.lock().unwrap();
+        let idle = conns.get_mut(target)?;
+        idle.retain(|conn| conn.idle_since.elapsed() < IDLE_TIMEOUT);
+        idle.pop()
+    }
+
+    fn put(&self, target: Target, mut conn: Conn) {
+        conn.idle_since = Instant::now();
+        let mut conns = self.conns.lock().unwrap();
+        let idle = conns.entry(target).or_default();
+        idle.push(conn);
+    }
+
+    // the number of connections kept to a target
+    #[cfg(test)]
+    fn idle(&self, target: &Target) -> usize {
+        let conns = self.conns.lock().unwrap();
+        conns.get(target).map_or(0, Vec::len)
+    }
+}
+
+impl Conn {
+    async fn connect(host: &str, port: u16, timeout: Duration) -> Option<Conn> {
+        let connect = TcpStream::connect((host, port));
+        let stream = tokio::time::timeout(timeout, connect).await.ok()?.ok()?;
+        Some(Conn {
+            stream,
+            buf: BytesMut::new(),
+            idle_since: Instant::now(),
+        })
+    }
+
+    // Send the pipelined commands and read a reply for each into `replies`.
+    async fn pipeline(
+        &mut self,
+        request: &[u8],
+        count: usize,
+        replies: &mut Vec<RespFrame>,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        tokio::time::timeout(timeout, self.stream.write_all(request)).await??;
+        while replies.len() < count {
+            match RespFrame::decode(&mut self.buf) {
+                Ok(frame) => {
+                    replies.push(frame);
+                    continue;
+                }
+                Err(RespError::RespNotComplete) => {}
+                Err(e) => return Err(e.into()),
+            }
+            let read = self.stream.read_buf(&mut self.buf);
+            if tokio::time::timeout(timeout, read).await?? == 0 {
+                anyhow::bail!("connection closed by the target");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn command(args: Vec<RespFrame>) -> RespFrame {
+    RespArray::with_vec(args).into()
+}
+
+fn bulk(s: impl Into<Vec<u8>>) -> RespFrame {
+    BulkString::new(s).into()
+}
+
+// MIGRATE sends the keys to the target as RESTORE, after ASKING in cluster
+// mode so that the target takes them while it imports their slot, then
+// removes them unless COPY. The keys are dumped and removed under the write
+// side of the gate, which isn't held while talking to the target: a key
+// modified meanwhile is kept rather than losing the modification.
+// `woff` is set to the offset of the replication stream after the DEL.
+pub(crate) async fn migrate(backend: &Backend, migrate: &Migrate, woff: &mut u64) -> RespFrame {
+    let timeout = match migrate.timeout {
+        t if t <= 0 => DEFAULT_TIMEOUT,
+        t => Duration::from_millis(t as u64),
+    };
+    let guard = backend.gate.write().await;
+    let mut request = Vec::new();
+    if let Some((username, password)) = &migrate.auth {
+        let mut auth = vec![bulk("AUTH")];
+        auth.extend(username.iter().map(|u| bulk(u.as_str())));
+        auth.push(bulk(password.as_str()));
+        request.push(command(auth));
+    }
+    if migrate.db != 0 {
+        request.push(command(vec![bulk("SELECT"), bulk(migrate.db.to_string())]));
+    }
+    let setup = request.len();
+    let asking = backend.cluster.is_some();
+    let now = unix_time_ms();
+    let mut keys = Vec::new();
+    for key in &migrate.keys {
+        let watched = backend.watch(key.clone());
+        let Some(payload) = rdb::dump(backend, key) else {
+            continue;
+        };
+        let ttl = backend
+            .expires
+            .get(key)
+            .map_or(0, |at| at.saturating_sub(now).max(1));
+        if asking {
+            request.push(command(vec![bulk("ASKING")]));
+        }
+        let mut restore = vec![bulk("RESTORE"), bulk(key.to_vec()), bulk(ttl.to_string())];
+        restore.push(bulk(payload));
+        if migrate.replace {
+            restore.push(bulk("REPLACE"));
+        }
+        request.push(command(restore));
+        keys.push(watched);
+    }
+    drop(guard);
+    if keys.is_empty() {
+        return SimpleString::new("NOKEY").into();
+    }
+    let count = request.len();
+    let request: Vec<u8> = request.into_iter().flat_map(|c| c.encode()).collect();
+
+    // a pooled connection may have been closed by the target meanwhile,
+    // the commands are sent again on a new one unless they got a reply
+    let target = Target::new(migrate);
+    let mut replies = Vec::with_capacity(count);
+    let mut conn = None;
+    if let Some(mut pooled) = backend.migrate_pool.take(&target) {
+        match pooled
+            .pipeline(&request, count, &mut replies, timeout)
+            .await
+        {
+            Ok(()) => conn = Some(pooled),
+            Err(_) if !replies.is_empty() => return io_error(),
+            Err(_) => {}
+        }
+    }
+    let conn = match conn {
+        Some(conn) => conn,
+        None => {
+            let Some(mut conn) = Conn::connect(&target.host, target.port, timeout).await else {
+                return SimpleError::new("IOERR error or timeout connecting to the client").into();
+            };
+            if conn
+                .pipeline(&request, count, &mut replies, timeout)
+                .await
+                .is_err()
+            {
+                return io_error();
+            }
+            conn
+        }
+    };
+    // a connection whose AUTH or SELECT failed isn't kept
+    let mut replies = replies.into_iter();
+    if let Some(e) = replies.by_ref().take(setup).find_map(|r| error(&r)) {
+        return target_error(&e);
+    }
+    backend.migrate_pool.put(target, conn);
+    let _guard = backend.gate.write().await;
+    let mut failed = None;
+    let mut changed = false;
+    let mut removed = Vec::new();
+    for watched in keys {
+        if asking {
+            replies.next();
+        }
+        match replies.next().and_then(|r| error(&r)) {
+            Some(e) => {
+                failed.get_or_insert(e);
+            }
+            None if !migrate.copy && watched.changed() => changed = true,
+            None if !migrate.copy => {
+                let key = watched.key().clone();
+                backend.remove(&key);
+                backend.touch(&key);
+                removed.push(key);
+            }
+            None => {}
+        }
+    }
+    // the keys are gone for the AOF and the replicas too
+    if !removed.is_empty() {
+        let mut del = vec![bulk("DEL")];
+        del.extend(removed.iter().map(|k: &Bytes| bulk(k.to_vec())));
+        let del = vec![command(del)];
+        let (error, offset) = run_blocking(backend, |b| b.aof.append(Some(&b.repl), del)).await;
+        if let Some(offset) = offset {
+            *woff = offset;
+        }
+        if let Some(e) = error {
+            return e;
+        }
+    }
+    match failed {
+        Some(e) => target_error(&e),
+        None if changed => {
+            SimpleError::new("ERR keys modified during MIGRATE were kept on this instance").into()
+        }
+        None => RESP_OK.clone(),
+    }
+}
+
+fn error(reply: &RespFrame) -> Option<String> {
+    match reply {
+        RespFrame::Error(e) => Some(e.0.clone()),
+        _ => None,
+    }
+}
+
+fn target_error(e: &str) -> RespFrame {
+    SimpleError::new(format!("ERR Target instance replied with error: {}", e)).into()
+}
+
+fn io_error() -> RespFrame {
+    SimpleError::new("IOERR error or timeout reading to target instance").into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stream_handler;
+    use tokio::net::TcpListener;
+
+    // a server for the keys to be migrated to
+    async fn target() -> (Backend, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let backend = Backend::new();
+        let cloned = backend.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(stream_handler(stream, cloned.clone()));
+            }
+        });
+        (backend, port)
+    }
+
+    fn request(port: u16, keys: &[&str], copy: bool, replace: bool) -> Migrate {
+        Migrate {
+            host: "127.0.0.1".to_string(),
+            port,
+            keys: keys.iter().map(|k| Bytes::from(k.to_string())).collect(),
+            db: 0,
+            timeout: 1000,
+            copy,
+            replace,
+            auth: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let (target, port) = target().await;
+        let backend = Backend::new();
+        backend.set(Bytes::from("a"), bulk("1"));
+        backend.set(Bytes::from("b"), bulk("2"));
+        backend
+            .expires
+            .insert(Bytes::from("b"), unix_time_ms() + 100_000);
+
+        let nokey: RespFrame = SimpleString::new("NOKEY").into();
+        assert_eq!(
+            migrate(&backend, &request(port, &["x"], false, false), &mut 0).await,
+            nokey
+        );
+        let copy = request(port, &["a", "x"], true, false);
+        assert_eq!(migrate(&backend, &copy, &mut 0).await, RESP_OK.clone());
+        assert_eq!(target.get(b"a"), Some(bulk("1")));
+        assert_eq!(backend.get(b"a"), Some(bulk("1")));
+
+        // the key exists on the target, only b is moved
+        let both = request(port, &["a", "b"], false, false);
+        assert_eq!(
+            migrate(&backend, &both, &mut 0).await,
+            target_error("BUSYKEY Target key name already exists.")
+        );
+        assert_eq!(backend.get(b"a"), Some(bulk("1")));
+        assert_eq!(backend.get(b"b"), None);
+        assert_eq!(target.get(b"b"), Some(bulk("2")));
+        assert!(target.expires.get(b"b".as_slice()).is_some());
+
+        backend.set(Bytes::from("a"), bulk("3"));
+        let replace = request(port, &["a"], false, true);
+        assert_eq!(migrate(&backend, &replace, &mut 0).await, RESP_OK.clone());
+        assert_eq!(target.get(b"a"), Some(bulk("3")));
+        assert_eq!(backend.key_type(b"a"), None);
+        // every MIGRATE went over the same connection
+        assert_eq!(backend.migrate_pool.idle(&Target::new(&replace)), 1);
+
+        // another database or user doesn't reuse it, and a connection whose
+        // SELECT or AUTH failed isn't kept
+        backend.set(Bytes::from("d"), bulk("5"));
+        let mut other = request(port, &["d"], false, false);
+        other.db = 1;
+        assert!(matches!(
+            migrate(&backend, &other, &mut 0).await,
+            RespFrame::Error(_)
+        ));
+        assert_eq!(backend.migrate_pool.idle(&Target::new(&other)), 0);
+        let mut auth = request(port, &["d"], false, false);
+        auth.auth = Some((None, "secret".to_string()));
+        assert!(matches!(
+            migrate(&backend, &auth, &mut 0).await,
+            RespFrame::Error(_)
+        ));
+        assert_eq!(backend.migrate_pool.idle(&Target::new(&auth)), 0);
+        assert_eq!(backend.migrate_pool.idle(&Target::new(&replace)), 1);
+        assert_eq!(backend.get(b"d"), Some(bulk("5")));
+        assert!(!format!("{:?}", Target::new(&auth)).contains("secret"));
+
+        let closed = request(1, &["x"], false, false);
+        assert_eq!(migrate(&backend, &closed, &mut 0).await, nokey);
+        backend.set(Bytes::from("c"), bulk("4"));
+        assert_eq!(
+            migrate(&backend, &request(1, &["c"], false, false), &mut 0).await,
+            SimpleError::new("IOERR error or timeout connecting to the client").into()
+        );
+        assert_eq!(backend.get(b"c"), Some(bulk("4")));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_keeps_keys_modified_meanwhile() {
+        let backend = Backend::new();
+        backend.set(Bytes::from("a"), bulk("1"));
+        backend.set(Bytes::from("b"), bulk("2"));
+
+        // the target modifies a before replying, which needs the gate
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let cloned = backend.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = BytesMut::new();
+            while !buf.windows(3).any(|w| w == b"\nb\r") {
+                stream.read_buf(&mut buf).await.unwrap();
+            }
+            let gate = cloned.gate.read();
+            let _guard = tokio::time::timeout(Duration::from_secs(1), gate)
+                .await
+                .unwrap();
+            cloned.set(Bytes::from("a"), bulk("3"));
+            stream.write_all(b"+OK\r\n+OK\r\n").await.unwrap();
+        });
+
+        let both = request(port, &["a", "b"], false, false);
+        assert_eq!(
+            migrate(&backend, &both, &mut 0).await,
+            SimpleError::new("ERR keys modified during MIGRATE were kept on this instance").into()
+        );
+        assert_eq!(backend.get(b"a"), Some(bulk("3")));
+        assert_eq!(backend.get(b"b"), None);
+        assert!(backend.versions.is_empty());
+    }
+}
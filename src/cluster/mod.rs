@@ -0,0 +1,599 @@
+mod bus;
+mod migrate;
+
+pub use bus::run_cluster_bus;
+pub(crate) use migrate::{migrate, MigratePool};
+
+use crate::replication::random_id;
+use crate::{Backend, Config, RespFrame, SimpleError};
+use bytes::Bytes;
+use crc::{Crc, CRC_16_XMODEM};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Cluster.
+//
+// The keyspace is split in 16384 hash slots, each served by one node. A node
+// redirects requests for keys of a slot it doesn't serve to the node which
+// does with MOVED, and with ASK for keys which already moved when the slot
+// is migrating to another node. Nodes learn about each other and about the
+// slots they serve from the messages they exchange on the cluster bus.
+
+pub const CLUSTER_SLOTS: usize = 16384;
+
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_XMODEM);
+
+// The slot of a key. Only the part between the first `{` and the next `}`
+// is hashed when it isn't empty, so that keys with the same hashtag are in
+// the same slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|&c| c == b'{').and_then(|start| {
+        let len = key[start + 1..].iter().position(|&c| c == b'}')?;
+        (len > 0).then(|| &key[start + 1..start + 1 + len])
+    });
+    CRC16.checksum(tag.unwrap_or(key)) & (CLUSTER_SLOTS as u16 - 1)
+}
+
+#[derive(Debug)]
+pub(crate) struct Cluster {
+    state: Mutex<ClusterState>,
+}
+
+#[derive(Debug)]
+struct ClusterState {
+    myself: String,
+    // every known node, this one included
+    nodes: HashMap<String, Node>,
+    // the node serving every slot
+    owners: Vec<Option<String>>,
+    // slots of this node moving to another node, and slots of another
+    // node moving to this one
+    migrating: BTreeMap<u16, String>,
+    importing: BTreeMap<u16, String>,
+    current_epoch: u64,
+    // addresses given to CLUSTER MEET, until the node there replies
+    handshakes: Vec<(Address, Instant)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Address {
+    pub(crate) ip: String,
+    pub(crate) port: u16,
+    pub(crate) bus_port: u16,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Node {
+    pub(crate) id: String,
+    pub(crate) addr: Address,
+    pub(crate) config_epoch: u64,
+    // unix time in milliseconds of the last PING sent and PONG received
+    ping_sent: u64,
+    pong_received: u64,
+    // the bus link to the node is up
+    connected: bool,
+}
+
+impl Node {
+    fn new(id: String, addr: Address) -> Self {
+        Node {
+            id,
+            addr,
+            config_epoch: 0,
+            ping_sent: 0,
+            pong_received: 0,
+            connected: false,
+        }
+    }
+}
+
+impl Cluster {
+    pub(crate) fn new(config: &Config) -> Self {
+        let myself = Node::new(
+            random_id(),
+            Address {
+                ip: config.cluster_announce_ip.clone().unwrap_or_default(),
+                port: config.port,
+                bus_port: config.cluster_bus_port(),
+            },
+        );
+        Cluster {
+            state: Mutex::new(ClusterState {
+                myself: myself.id.clone(),
+                nodes: HashMap::from([(myself.id.clone(), myself)]),
+                owners: vec![None; CLUSTER_SLOTS],
+                migrating: BTreeMap::new(),
+                importing: BTreeMap::new(),
+                current_epoch: 0,
+                handshakes: Vec::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn myself(&self) -> String {
+        self.state.lock().unwrap().myself.clone()
+    }
+
+    // Whether this node serves the keys of a request, or the redirection
+    // to reply with. `asking` is set by ASKING, and MIGRATE moves the keys
+    // of a slot which is migrating or importing whoever serves it.
+    pub(crate) fn check_keys(
+        &self,
+        backend: &Backend,
+        keys: &[Bytes],
+        asking: bool,
+        migrate: bool,
+    ) -> Result<(), RespFrame> {
+        let Some(first) = keys.first() else {
+            return Ok(());
+        };
+        let slot = key_slot(first);
+        if keys.iter().any(|k| key_slot(k) != slot) {
+            return Err(
+                SimpleError::new("CROSSSLOT Keys in request don't hash to the same slot").into(),
+            );
+        }
+        let state = self.state.lock().unwrap();
+        let redirect = |kind: &str, id: &str| -> RespFrame {
+            match state.nodes.get(id) {
+                Some(node) => SimpleError::new(format!(
+                    "{} {} {}:{}",
+                    kind, slot, node.addr.ip, node.addr.port
+                ))
+                .into(),
+                None => SimpleError::new("CLUSTERDOWN Hash slot not served").into(),
+            }
+        };
+        let migrating = state.migrating.get(&slot);
+        let importing = state.importing.contains_key(&slot);
+        if migrate && (migrating.is_some() || importing) {
+            return Ok(());
+        }
+        let missing = || {
+            keys.iter()
+                .filter(|k| backend.key_type(k).is_none())
+                .count()
+        };
+        let tryagain = || -> Result<(), RespFrame> {
+            Err(SimpleError::new("TRYAGAIN Multiple keys request during rehashing of slot").into())
+        };
+        match &state.owners[slot as usize] {
+            Some(owner) if *owner == state.myself => match migrating {
+                // the keys which aren't here moved already
+                Some(target) => match missing() {
+                    0 => Ok(()),
+                    n if n == keys.len() => Err(redirect("ASK", target)),
+                    _ => tryagain(),
+                },
+                None => Ok(()),
+            },
+            // the keys which moved already are served after ASKING
+            _ if importing && asking => match missing() {
+                n if n > 0 && keys.len() > 1 => tryagain(),
+                _ => Ok(()),
+            },
+            Some(owner) => Err(redirect("MOVED", owner)),
+            None => Err(SimpleError::new("CLUSTERDOWN Hash slot not served").into()),
+        }
+    }
+
+    // CLUSTER MEET, the node is added once it replies
+    pub(crate) fn meet(&self, addr: Address) {
+        let mut state = self.state.lock().unwrap();
+        let known = state.nodes.values().any(|n| n.addr == addr)
+            || state.handshakes.iter().any(|(a, _)| *a == addr);
+        if !known {
+            state.handshakes.push((addr, Instant::now()));
+        }
+    }
+
+    // CLUSTER ADDSLOTS, nothing changes unless all the slots are free
+    pub(crate) fn add_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(slot) = slots.iter().find(|&&s| state.owners[s as usize].is_some()) {
+            return Err(format!("Slot {} is already busy", slot));
+        }
+        for &slot in slots {
+            state.owners[slot as usize] = Some(state.myself.clone());
+        }
+        Ok(())
+    }
+
+    // CLUSTER DELSLOTS
+    pub(crate) fn del_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(slot) = slots.iter().find(|&&s| state.owners[s as usize].is_none()) {
+            return Err(format!("Slot {} is already unassigned", slot));
+        }
+        for &slot in slots {
+            state.owners[slot as usize] = None;
+            state.migrating.remove(&slot);
+            state.importing.remove(&slot);
+        }
+        Ok(())
+    }
+
+    // CLUSTER SETSLOT, the steps of moving a slot: the target imports it,
+    // the source migrates it, then both assign it to the target once the
+    // source has no keys left in it.
+    pub(crate) fn set_slot(
+        &self,
+        backend: &Backend,
+        slot: u16,
+        action: SetSlot,
+    ) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let myself = state.myself.clone();
+        let mine = state.owners[slot as usize].as_deref() == Some(myself.as_str());
+        let known = |id: &str| match state.nodes.contains_key(id) {
+            true => Ok(id.to_string()),
+            false => Err(format!("I don't know about node {}", id)),
+        };
+        match action {
+            SetSlot::Migrating(id) => {
+                if !mine {
+                    return Err(format!("I'm not the owner of hash slot {}", slot));
+                }
+                let id = known(&id)?;
+                state.migrating.insert(slot, id);
+            }
+            SetSlot::Importing(id) => {
+                if mine {
+                    return Err(format!("I'm already the owner of hash slot {}", slot));
+                }
+                let id = known(&id)?;
+                state.importing.insert(slot, id);
+            }
+            SetSlot::Stable => {
+                state.migrating.remove(&slot);
+                state.importing.remove(&slot);
+            }
+            SetSlot::Node(id) => {
+                let id = known(&id)?;
+                if id != myself {
+                    if mine && !keys_in_slot(backend, slot).is_empty() {
+                        return Err(format!(
+                            "Can't assign hashslot {} to a different node while I still hold keys for this hash slot.",
+                            slot
+                        ));
+                    }
+                    state.migrating.remove(&slot);
+                }
+                // the other nodes take the slot from the source since this
+                // node claims it with a greater config epoch
+                if id == myself && state.importing.remove(&slot).is_some() {
+                    state.current_epoch += 1;
+                    let epoch = state.current_epoch;
+                    state.nodes.get_mut(&myself).unwrap().config_epoch = epoch;
+                }
+                state.owners[slot as usize] = Some(id);
+            }
+        }
+        Ok(())
+    }
+
+    // The nodes with the ranges of slots they serve, this one first.
+    pub(crate) fn shards(&self) -> Vec<(Node, Vec<(u16, u16)>)> {
+        let state = self.state.lock().unwrap();
+        let mut nodes: Vec<&Node> = state.nodes.values().collect();
+        nodes.sort_by_key(|n| (n.id != state.myself, n.addr.port, &n.id));
+        nodes
+            .into_iter()
+            .map(|node| (node.clone(), state.ranges_of(&node.id)))
+            .collect()
+    }
+
+    // CLUSTER INFO
+    pub(crate) fn info(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let assigned = state.owners.iter().filter(|o| o.is_some()).count();
+        let size = state
+            .nodes
+            .keys()
+            .filter(|id| state.owners.iter().any(|o| o.as_ref() == Some(id)))
+            .count();
+        let mut info = String::new();
+        let mut line = |k: &str, v: &dyn std::fmt::Display| {
+            let _ = write!(info, "{}:{}\r\n", k, v);
+        };
+        let ok = assigned == CLUSTER_SLOTS;
+        line("cluster_state", &if ok { "ok" } else { "fail" });
+        line("cluster_slots_assigned", &assigned);
+        line("cluster_slots_ok", &assigned);
+        line("cluster_slots_pfail", &0);
+        line("cluster_slots_fail", &0);
+        line("cluster_known_nodes", &state.nodes.len());
+        line("cluster_size", &size);
+        line("cluster_current_epoch", &state.current_epoch);
+        line("cluster_my_epoch", &state.nodes[&state.myself].config_epoch);
+        info
+    }
+
+    // CLUSTER NODES, a line for every node
+    pub(crate) fn nodes(&self) -> String {
+        let myself = self.myself();
+        let state = self.state.lock().unwrap();
+        let (migrating, importing) = (state.migrating.clone(), state.importing.clone());
+        drop(state);
+        let mut nodes = String::new();
+        for (node, ranges) in self.shards() {
+            let flags = if node.id == myself {
+                "myself,master"
+            } else {
+                "master"
+            };
+            let link = match node.id == myself || node.connected {
+                true => "connected",
+                false => "disconnected",
+            };
+            let _ = write!(
+                nodes,
+                "{} {}:{}@{} {} - {} {} {} {}",
+                node.id,
+                node.addr.ip,
+                node.addr.port,
+                node.addr.bus_port,
+                flags,
+                node.ping_sent,
+                node.pong_received,
+                node.config_epoch,
+                link
+            );
+            for (start, end) in ranges {
+                match start == end {
+                    true => write!(nodes, " {}", start),
+                    false => write!(nodes, " {}-{}", start, end),
+                }
+                .unwrap_or_default();
+            }
+            if node.id == myself {
+                for (slot, target) in &migrating {
+                    let _ = write!(nodes, " [{}->-{}]", slot, target);
+                }
+                for (slot, source) in &importing {
+                    let _ = write!(nodes, " [{}-<-{}]", slot, source);
+                }
+            }
+            nodes.push('\n');
+        }
+        nodes
+    }
+}
+
+// The action of CLUSTER SETSLOT, with the id of the other node.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SetSlot {
+    Importing(String),
+    Migrating(String),
+    Stable,
+    Node(String),
+}
+
+pub(crate) fn keys_in_slot(backend: &Backend, slot: u16) -> Vec<Bytes> {
+    let keys = backend.keys().into_iter();
+    keys.filter(|k| key_slot(k) == slot).collect()
+}
+
+impl ClusterState {
+    fn ranges_of(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        let slots =
+            (0..CLUSTER_SLOTS as u16).filter(|&s| self.owners[s as usize].as_deref() == Some(id));
+        for slot in slots {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cluster() -> Cluster {
+        Cluster::new(&Config {
+            cluster_enabled: true,
+            port: 7000,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_key_slot() {
+        // the examples of the cluster specification
+        assert_eq!(key_slot(b"123456789"), 0x31c3 & 16383);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"{user1000}.followers"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{}{bar}"), key_slot(b"foo{}{bar}"));
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+        assert_eq!(key_slot(b"foo{bar}{zap}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"{"), key_slot(b"{"));
+    }
+
+    #[test]
+    fn test_check_keys() {
+        let backend = Backend::new();
+        let cluster = cluster();
+        let keys = |keys: &[&str]| -> Vec<Bytes> {
+            keys.iter()
+                .map(|k| Bytes::copy_from_slice(k.as_bytes()))
+                .collect()
+        };
+        let error = |s: &str| -> Result<(), RespFrame> { Err(SimpleError::new(s).into()) };
+        assert_eq!(cluster.check_keys(&backend, &[], false, false), Ok(()));
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["foo"]), false, false),
+            error("CLUSTERDOWN Hash slot not served")
+        );
+        cluster.add_slots(&[key_slot(b"foo")]).unwrap();
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["foo", "{foo}a"]), false, false),
+            Ok(())
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["foo", "bar"]), false, false),
+            error("CROSSSLOT Keys in request don't hash to the same slot")
+        );
+
+        let other = Node::new(
+            "b".repeat(40),
+            Address {
+                ip: "127.0.0.1".to_string(),
+                port: 7001,
+                bus_port: 17001,
+            },
+        );
+        let mut state = cluster.state.lock().unwrap();
+        let slot = key_slot(b"bar");
+        state.owners[slot as usize] = Some(other.id.clone());
+        state.nodes.insert(other.id.clone(), other.clone());
+        drop(state);
+        let migrating = SetSlot::Migrating(other.id.clone());
+        cluster
+            .set_slot(&backend, key_slot(b"foo"), migrating)
+            .unwrap();
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["bar"]), false, false),
+            error(&format!("MOVED {} 127.0.0.1:7001", slot))
+        );
+        // a migrating slot serves the keys which are still there
+        backend.set(
+            Bytes::from_static(b"foo"),
+            crate::BulkString::new("v").into(),
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["foo"]), false, false),
+            Ok(())
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["{foo}a"]), false, false),
+            error("ASK 12182 127.0.0.1:7001")
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["foo", "{foo}a"]), false, false),
+            error("TRYAGAIN Multiple keys request during rehashing of slot")
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["{foo}a"]), false, true),
+            Ok(())
+        );
+
+        // an importing slot only serves the clients which asked
+        let importing = SetSlot::Importing(other.id.clone());
+        cluster.set_slot(&backend, slot, importing).unwrap();
+        let moved = error(&format!("MOVED {} 127.0.0.1:7001", slot));
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["bar"]), false, false),
+            moved
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["bar"]), true, false),
+            Ok(())
+        );
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["bar", "{bar}a"]), true, false),
+            error("TRYAGAIN Multiple keys request during rehashing of slot")
+        );
+        cluster.set_slot(&backend, slot, SetSlot::Stable).unwrap();
+        assert_eq!(
+            cluster.check_keys(&backend, &keys(&["bar"]), true, false),
+            moved
+        );
+    }
+
+    #[test]
+    fn test_set_slot() {
+        let backend = Backend::new();
+        let cluster = cluster();
+        let myself = cluster.myself();
+        let other = Node::new(
+            "b".repeat(40),
+            Address {
+                ip: "127.0.0.1".to_string(),
+                port: 7001,
+                bus_port: 17001,
+            },
+        );
+        let other_id = other.id.clone();
+        cluster.state.lock().unwrap().owners[1] = Some(other_id.clone());
+        cluster
+            .state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(other_id.clone(), other);
+        cluster.add_slots(&[0]).unwrap();
+        let set_slot = |slot: u16, action: SetSlot| cluster.set_slot(&backend, slot, action);
+
+        let error = |s: &str| -> Result<(), String> { Err(s.to_string()) };
+        let unknown = SetSlot::Migrating("x".repeat(40));
+        let msg = format!("I don't know about node {}", "x".repeat(40));
+        assert_eq!(set_slot(0, unknown), Err(msg));
+        let migrating = SetSlot::Migrating(other_id.clone());
+        assert_eq!(
+            set_slot(1, migrating),
+            error("I'm not the owner of hash slot 1")
+        );
+        let importing = SetSlot::Importing(other_id.clone());
+        assert_eq!(
+            set_slot(0, importing),
+            error("I'm already the owner of hash slot 0")
+        );
+
+        // slot 0 moves to the other node once its keys are gone
+        let key = Bytes::from_static(b"{06S}");
+        assert_eq!(key_slot(&key), 0);
+        backend.set(key.clone(), crate::BulkString::new("v").into());
+        assert_eq!(set_slot(0, SetSlot::Migrating(other_id.clone())), Ok(()));
+        assert_eq!(
+            set_slot(0, SetSlot::Node(other_id.clone())),
+            error("Can't assign hashslot 0 to a different node while I still hold keys for this hash slot.")
+        );
+        backend.remove(&key);
+        assert_eq!(set_slot(0, SetSlot::Node(other_id.clone())), Ok(()));
+        assert!(cluster.state.lock().unwrap().migrating.is_empty());
+
+        // slot 1 comes from the other node, with a new config epoch
+        assert_eq!(set_slot(1, SetSlot::Importing(other_id.clone())), Ok(()));
+        let nodes = cluster.nodes();
+        assert!(nodes.contains(&format!(" [1-<-{}]", other_id)), "{}", nodes);
+        assert_eq!(set_slot(1, SetSlot::Node(myself.clone())), Ok(()));
+        let state = cluster.state.lock().unwrap();
+        assert_eq!(state.owners[0], Some(other_id));
+        assert_eq!(state.owners[1], Some(myself.clone()));
+        assert!(state.importing.is_empty());
+        assert_eq!(state.nodes[&myself].config_epoch, 1);
+    }
+
+    #[test]
+    fn test_slots() {
+        let cluster = cluster();
+        cluster.add_slots(&[0, 1, 2, 5]).unwrap();
+        assert_eq!(
+            cluster.add_slots(&[3, 5]),
+            Err("Slot 5 is already busy".to_string())
+        );
+        assert_eq!(cluster.shards()[0].1, vec![(0, 2), (5, 5)]);
+        assert_eq!(cluster.del_slots(&[5]), Ok(()));
+        assert_eq!(
+            cluster.del_slots(&[5]),
+            Err("Slot 5 is already unassigned".to_string())
+        );
+        let nodes = cluster.nodes();
+        let myself = cluster.myself();
+        assert_eq!(
+            nodes,
+            format!("{myself} :7000@17000 myself,master - 0 0 0 connected 0-2\n")
+        );
+        let info = cluster.info();
+        assert!(info.starts_with("cluster_state:fail\r\ncluster_slots_assigned:3\r\n"));
+        assert!(info.contains("cluster_known_nodes:1\r\ncluster_size:1\r\n"));
+    }
+}
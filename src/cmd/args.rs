@@ -0,0 +1,220 @@
+use super::*;
+use std::collections::VecDeque;
+
+// Bulk strings from this size on are kept in the read buffer rather than
+// copied, the same threshold as PROTO_MBULK_BIG_ARG of Redis. The buffer
+// then holds little besides them.
+const BIG_ARG: usize = 32 * 1024;
+
+// CommandArgs
+//
+// The arguments of a request after the command name, consumed from the front.
+// Parsers generated by `#[derive(RedisCommand)]` are written against it.
+#[derive(Debug)]
+pub struct CommandArgs {
+    name: &'static str,
+    args: VecDeque<RespFrame>,
+}
+
+impl CommandArgs {
+    // Take the arguments of the command, checking the arity of the command table.
+    pub fn new(value: RespArray, command: &'static str) -> Result<Self, CommandError> {
+        let args = get_args(value, command)?;
+        Ok(CommandArgs {
+            name: command,
+            args: args.into(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    // Small bulk strings are copied out of the connection's read buffer, so
+    // that a stored key or value does not keep the whole buffer allocated.
+    pub fn next_frame(&mut self) -> Result<RespFrame, CommandError> {
+        match self.args.pop_front() {
+            Some(RespFrame::BulkString(BulkString(Some(v)))) if v.len() < BIG_ARG => {
+                Ok(BulkString::new(Bytes::copy_from_slice(&v)).into())
+            }
+            Some(frame) => Ok(frame),
+            None => Err(CommandError::WrongArity(self.name.to_string())),
+        }
+    }
+
+    pub fn next_bytes(&mut self) -> Result<Bytes, CommandError> {
+        parse_bytes(self.next_frame()?)
+    }
+
+    pub fn next_string(&mut self) -> Result<String, CommandError> {
+        parse_string(self.next_frame()?)
+    }
+
+    pub fn next_integer(&mut self) -> Result<i64, CommandError> {
+        self.next_string()?.parse().map_err(|_| {
+            CommandError::InvalidArgument("value is not an integer or out of range".to_string())
+        })
+    }
+
+    pub fn next_float(&mut self) -> Result<f64, CommandError> {
+        match self.next_string()?.parse::<f64>() {
+            Ok(v) if !v.is_nan() => Ok(v),
+            _ => Err(CommandError::InvalidArgument(
+                "value is not a valid float".to_string(),
+            )),
+        }
+    }
+
+    // Consume the next argument if it is the keyword, case insensitive.
+    pub fn next_keyword(&mut self, keyword: &str) -> bool {
+        let matched = self
+            .args
+            .front()
+            .and_then(|v| v.as_bytes())
+            .is_some_and(|v| v.eq_ignore_ascii_case(keyword.as_bytes()));
+        if matched {
+            self.args.pop_front();
+        }
+        matched
+    }
+
+    // whether the next argument is a positional one rather than one of the keywords
+    pub fn has_positional(&self, keywords: &[&str]) -> bool {
+        match self.args.front().and_then(|v| v.as_bytes()) {
+            None => !self.args.is_empty(),
+            Some(v) => !keywords
+                .iter()
+                .any(|k| v.eq_ignore_ascii_case(k.as_bytes())),
+        }
+    }
+
+    // every argument must have been consumed
+    pub fn finish(&mut self) -> Result<(), CommandError> {
+        match self.args.is_empty() {
+            true => Ok(()),
+            false => Err(CommandError::SyntaxError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RespDecode;
+    use bytes::BytesMut;
+    use macro_definitions::RedisCommand;
+
+    // a command of every kind of argument, parsed as SADD so that the
+    // command table allows any number of them
+    #[derive(Debug, PartialEq, RedisCommand)]
+    #[command(name = "sadd")]
+    struct Example {
+        #[arg(key)]
+        key: Bytes,
+        #[arg(frame)]
+        value: RespFrame,
+        #[arg(integer, optional)]
+        count: Option<i64>,
+        #[arg(flag = "nx")]
+        nx: bool,
+        #[arg(option = "EX", integer)]
+        ex: Option<i64>,
+        #[arg(option = "SCORE", float)]
+        score: Option<f64>,
+        #[arg(option = "NAME", string)]
+        name: Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, RedisCommand)]
+    #[command(name = "sadd")]
+    struct Variadic {
+        #[arg(key)]
+        key: Bytes,
+        #[arg(bytes, variadic)]
+        members: Vec<Bytes>,
+    }
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::with_vec(
+            args.iter()
+                .map(|a| BulkString::from(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    #[test]
+    fn test_derive_redis_command() {
+        let res = Example::try_from(array(&["SADD", "k", "v"])).unwrap();
+        assert_eq!(
+            res,
+            Example {
+                key: Bytes::from_static(b"k"),
+                value: BulkString::from("v").into(),
+                count: None,
+                nx: false,
+                ex: None,
+                score: None,
+                name: None,
+            }
+        );
+
+        let res = Example::try_from(array(&[
+            "sadd", "k", "v", "3", "ex", "10", "NX", "score", "1.5", "name", "n",
+        ]))
+        .unwrap();
+        assert_eq!(res.count, Some(3));
+        assert!(res.nx);
+        assert_eq!(res.ex, Some(10));
+        assert_eq!(res.score, Some(1.5));
+        assert_eq!(res.name, Some("n".to_string()));
+
+        // a keyword is not taken as the optional argument
+        let res = Example::try_from(array(&["sadd", "k", "v", "nx"])).unwrap();
+        assert_eq!((res.count, res.nx), (None, true));
+
+        let res = Variadic::try_from(array(&["sadd", "k", "a", "b"])).unwrap();
+        assert_eq!(res.members, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_only_big_args_borrow_the_read_buffer() {
+        let big = "v".repeat(BIG_ARG);
+        let input = format!(
+            "*3\r\n$4\r\nsadd\r\n$1\r\nk\r\n${}\r\n{}\r\n",
+            big.len(),
+            big
+        );
+        let mut buf = BytesMut::from(input.as_str());
+        let start = buf.as_ptr() as usize;
+        let range = start..start + buf.len();
+        let RespFrame::Array(frame) = RespFrame::decode(&mut buf).unwrap() else {
+            panic!("expect an array");
+        };
+        let res = Example::try_from(frame).unwrap();
+        let RespFrame::BulkString(BulkString(Some(value))) = &res.value else {
+            panic!("expect a bulk string");
+        };
+        assert!(!range.contains(&(res.key.as_ptr() as usize)));
+        assert!(range.contains(&(value.as_ptr() as usize)));
+        assert_eq!(value.as_ref(), big.as_bytes());
+    }
+
+    #[test]
+    fn test_derive_redis_command_errors() {
+        let err = |args: &[&str]| Example::try_from(array(args)).unwrap_err().to_string();
+        assert_eq!(
+            err(&["sadd", "k"]),
+            "ERR wrong number of arguments for 'sadd' command"
+        );
+        assert_eq!(err(&["sadd", "k", "v", "1", "xx"]), "ERR syntax error");
+        assert_eq!(
+            err(&["sadd", "k", "v", "ex", "ten"]),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(
+            err(&["sadd", "k", "v", "score", "nan"]),
+            "ERR value is not a valid float"
+        );
+        assert_eq!(err(&["sadd", "k", "v", "ex"]), "ERR syntax error");
+    }
+}
@@ -0,0 +1,474 @@
+use super::*;
+use crate::cluster::{key_slot, keys_in_slot, Address, Cluster, SetSlot, CLUSTER_SLOTS};
+use crate::RespMap;
+
+// Run `f` with the cluster state, CLUSTER fails when cluster mode is disabled.
+fn with_cluster(backend: &Backend, f: impl FnOnce(&Cluster) -> RespFrame) -> RespFrame {
+    match &backend.cluster {
+        Some(cluster) => f(cluster),
+        None => SimpleError::new("ERR This instance has cluster support disabled").into(),
+    }
+}
+
+fn parse_slot(slot: &str) -> Result<u16, RespFrame> {
+    match slot.parse::<u16>() {
+        Ok(slot) if (slot as usize) < CLUSTER_SLOTS => Ok(slot),
+        _ => Err(SimpleError::new("ERR Invalid or out of range slot").into()),
+    }
+}
+
+// The slots of ADDSLOTS and DELSLOTS, each given once.
+fn parse_slots(slots: &[String]) -> Result<Vec<u16>, RespFrame> {
+    let slots = slots
+        .iter()
+        .map(|s| parse_slot(s))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (i, slot) in slots.iter().enumerate() {
+        if slots[..i].contains(slot) {
+            return Err(
+                SimpleError::new(format!("ERR Slot {} specified multiple times", slot)).into(),
+            );
+        }
+    }
+    Ok(slots)
+}
+
+// The slots of ADDSLOTSRANGE and DELSLOTSRANGE, given as start and end pairs.
+fn parse_ranges(command: &str, ranges: &[String]) -> Result<Vec<u16>, RespFrame> {
+    if !ranges.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity(command.to_string()).into());
+    }
+    let mut slots = Vec::new();
+    for range in ranges.chunks(2) {
+        let (start, end) = (parse_slot(&range[0])?, parse_slot(&range[1])?);
+        if start > end {
+            let msg = format!(
+                "ERR start slot number {} is greater than end slot number {}",
+                start, end
+            );
+            return Err(SimpleError::new(msg).into());
+        }
+        slots.extend(start..=end);
+    }
+    let mut unique = slots.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    match unique.len() == slots.len() {
+        true => Ok(slots),
+        false => Err(SimpleError::new("ERR Slot range specified multiple times").into()),
+    }
+}
+
+fn update_slots(
+    slots: Result<Vec<u16>, RespFrame>,
+    f: impl FnOnce(&[u16]) -> Result<(), String>,
+) -> RespFrame {
+    match slots.map(|slots| f(&slots)) {
+        Ok(Ok(())) => RESP_OK.clone(),
+        Ok(Err(e)) => SimpleError::new(format!("ERR {}", e)).into(),
+        Err(e) => e,
+    }
+}
+
+impl CommandExecutor for ClusterInfo {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| BulkString::new(cluster.info()).into())
+    }
+}
+
+impl CommandExecutor for ClusterMyId {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| BulkString::new(cluster.myself()).into())
+    }
+}
+
+impl CommandExecutor for ClusterMeet {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            let port = self.port.parse::<u16>();
+            let bus_port = match &self.bus_port {
+                Some(bus_port) => bus_port.parse::<u16>(),
+                None => port.clone().map(|port| port.saturating_add(10000)),
+            };
+            let (Ok(port), Ok(bus_port), Ok(_)) =
+                (port, bus_port, self.ip.parse::<std::net::IpAddr>())
+            else {
+                let msg = format!(
+                    "ERR Invalid node address specified: {}:{}",
+                    self.ip, self.port
+                );
+                return SimpleError::new(msg).into();
+            };
+            let ip = self.ip.clone();
+            cluster.meet(Address { ip, port, bus_port });
+            RESP_OK.clone()
+        })
+    }
+}
+
+impl CommandExecutor for ClusterAddSlots {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            update_slots(parse_slots(&self.slots), |s| cluster.add_slots(s))
+        })
+    }
+}
+
+impl CommandExecutor for ClusterAddSlotsRange {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            let slots = parse_ranges("cluster|addslotsrange", &self.ranges);
+            update_slots(slots, |s| cluster.add_slots(s))
+        })
+    }
+}
+
+impl CommandExecutor for ClusterDelSlots {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            update_slots(parse_slots(&self.slots), |s| cluster.del_slots(s))
+        })
+    }
+}
+
+impl CommandExecutor for ClusterDelSlotsRange {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            let slots = parse_ranges("cluster|delslotsrange", &self.ranges);
+            update_slots(slots, |s| cluster.del_slots(s))
+        })
+    }
+}
+
+// every range of slots with the node serving it, ordered by slot
+impl CommandExecutor for ClusterSlots {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            let mut ranges: Vec<_> = cluster
+                .shards()
+                .into_iter()
+                .flat_map(|(node, ranges)| ranges.into_iter().map(move |r| (r, node.clone())))
+                .collect();
+            ranges.sort_by_key(|(range, _)| *range);
+            let ranges = ranges.into_iter().map(|((start, end), node)| -> RespFrame {
+                let node = RespArray::with_vec(vec![
+                    BulkString::new(node.addr.ip).into(),
+                    RespFrame::Integer(node.addr.port as i64),
+                    BulkString::new(node.id).into(),
+                ]);
+                RespArray::with_vec(vec![
+                    RespFrame::Integer(start as i64),
+                    RespFrame::Integer(end as i64),
+                    node.into(),
+                ])
+                .into()
+            });
+            RespArray::with_vec(ranges.collect::<Vec<_>>()).into()
+        })
+    }
+}
+
+// a shard for every node serving slots, there are no replicas
+impl CommandExecutor for ClusterShards {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            let myself = cluster.myself();
+            let shards = cluster
+                .shards()
+                .into_iter()
+                .filter(|(_, ranges)| !ranges.is_empty());
+            let shards = shards.map(|(node, ranges)| -> RespFrame {
+                let slots = ranges
+                    .iter()
+                    .flat_map(|&(start, end)| [start, end])
+                    .map(|slot| RespFrame::Integer(slot as i64));
+                // the offsets of the other nodes aren't gossiped
+                let offset = if node.id == myself {
+                    backend.repl.offset()
+                } else {
+                    0
+                };
+                let mut info = RespMap::new();
+                info.insert("id", BulkString::new(node.id));
+                info.insert("port", RespFrame::Integer(node.addr.port as i64));
+                info.insert("ip", BulkString::new(node.addr.ip.clone()));
+                info.insert("endpoint", BulkString::new(node.addr.ip));
+                info.insert("role", BulkString::new("master"));
+                info.insert("replication-offset", RespFrame::Integer(offset as i64));
+                info.insert("health", BulkString::new("online"));
+                let mut shard = RespMap::new();
+                shard.insert("slots", RespArray::with_vec(slots.collect::<Vec<_>>()));
+                shard.insert("nodes", RespArray::with_vec(vec![info.into()]));
+                shard.into()
+            });
+            RespArray::with_vec(shards.collect::<Vec<_>>()).into()
+        })
+    }
+}
+
+impl CommandExecutor for ClusterNodes {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| BulkString::new(cluster.nodes()).into())
+    }
+}
+
+impl CommandExecutor for ClusterKeySlot {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |_| RespFrame::Integer(key_slot(&self.key) as i64))
+    }
+}
+
+impl CommandExecutor for ClusterCountKeysInSlot {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |_| {
+            if !(0..CLUSTER_SLOTS as i64).contains(&self.slot) {
+                return SimpleError::new("ERR Invalid slot").into();
+            }
+            let keys = keys_in_slot(backend, self.slot as u16);
+            RespFrame::Integer(keys.len() as i64)
+        })
+    }
+}
+
+impl CommandExecutor for ClusterGetKeysInSlot {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |_| {
+            if !(0..CLUSTER_SLOTS as i64).contains(&self.slot) || self.count < 0 {
+                return SimpleError::new("ERR Invalid slot or number of keys").into();
+            }
+            let mut keys = keys_in_slot(backend, self.slot as u16);
+            keys.sort();
+            let keys = keys.into_iter().take(self.count as usize);
+            let keys: Vec<RespFrame> = keys.map(|k| BulkString::new(k.to_vec()).into()).collect();
+            RespArray::with_vec(keys).into()
+        })
+    }
+}
+
+// ASKING sets a flag of the connection, so it's handled by the session in
+// `network.rs`. The executor only runs when ASKING is issued inside MULTI.
+impl CommandExecutor for Asking {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |_| RESP_OK.clone())
+    }
+}
+
+impl CommandExecutor for ClusterSetSlot {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        with_cluster(backend, |cluster| {
+            let slot = match parse_slot(&self.slot) {
+                Ok(slot) => slot,
+                Err(e) => return e,
+            };
+            let action = match (self.action.to_lowercase().as_str(), &self.node) {
+                ("importing", Some(node)) => SetSlot::Importing(node.clone()),
+                ("migrating", Some(node)) => SetSlot::Migrating(node.clone()),
+                ("node", Some(node)) => SetSlot::Node(node.clone()),
+                ("stable", None) => SetSlot::Stable,
+                _ => return SimpleError::new(
+                    "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                )
+                .into(),
+            };
+            match cluster.set_slot(backend, slot, action) {
+                Ok(()) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::{decode_inline, Config};
+    use bytes::BytesMut;
+
+    fn run(backend: &Backend, line: &str) -> RespFrame {
+        let mut data = BytesMut::from(format!("{line}\r\n").as_str());
+        let array = decode_inline(&mut data).unwrap().unwrap();
+        Command::try_from(array).unwrap().execute(backend)
+    }
+
+    fn cluster_backend() -> Backend {
+        Backend::with_config(Config {
+            cluster_enabled: true,
+            port: 7000,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_cluster_slots() {
+        let backend = cluster_backend();
+        assert_eq!(run(&backend, "cluster addslots 0 1 2 100"), RESP_OK.clone());
+        assert_eq!(
+            run(&backend, "cluster addslotsrange 5 10 20 30"),
+            RESP_OK.clone()
+        );
+        assert_eq!(run(&backend, "cluster delslots 100"), RESP_OK.clone());
+        assert_eq!(
+            run(&backend, "cluster delslotsrange 25 30"),
+            RESP_OK.clone()
+        );
+        let myself = run(&backend, "cluster myid");
+        let node = |myself: &RespFrame| -> RespFrame {
+            RespArray::with_vec(vec![
+                BulkString::new("").into(),
+                RespFrame::Integer(7000),
+                myself.clone(),
+            ])
+            .into()
+        };
+        let range = |start: i64, end: i64| -> RespFrame {
+            RespArray::with_vec(vec![
+                RespFrame::Integer(start),
+                RespFrame::Integer(end),
+                node(&myself),
+            ])
+            .into()
+        };
+        assert_eq!(
+            run(&backend, "cluster slots"),
+            RespArray::with_vec(vec![range(0, 2), range(5, 10), range(20, 24)]).into()
+        );
+        let nodes = run(&backend, "cluster nodes");
+        let nodes = String::from_utf8(nodes.as_bytes().unwrap().to_vec()).unwrap();
+        assert!(
+            nodes.ends_with(" myself,master - 0 0 0 connected 0-2 5-10 20-24\n"),
+            "{}",
+            nodes
+        );
+        let shards = run(&backend, "cluster shards");
+        let RespFrame::Array(RespArray(Some(shards))) = shards else {
+            panic!("not an array: {:?}", shards);
+        };
+        assert_eq!(shards.len(), 1);
+        let info = run(&backend, "cluster info");
+        assert!(info
+            .as_bytes()
+            .unwrap()
+            .starts_with(b"cluster_state:fail\r\ncluster_slots_assigned:14\r\n"));
+    }
+
+    #[test]
+    fn test_cluster_keys() {
+        let backend = cluster_backend();
+        assert_eq!(
+            run(&backend, "cluster keyslot foo"),
+            RespFrame::Integer(12182)
+        );
+        assert_eq!(
+            run(&backend, "cluster keyslot {foo}bar"),
+            RespFrame::Integer(12182)
+        );
+        backend.set(Bytes::from_static(b"foo"), BulkString::new("1").into());
+        backend.hset(
+            Bytes::from_static(b"{foo}h"),
+            Bytes::from_static(b"f"),
+            BulkString::new("v").into(),
+        );
+        backend.set(Bytes::from_static(b"bar"), BulkString::new("1").into());
+        assert_eq!(
+            run(&backend, "cluster countkeysinslot 12182"),
+            RespFrame::Integer(2)
+        );
+        assert_eq!(
+            run(&backend, "cluster countkeysinslot 0"),
+            RespFrame::Integer(0)
+        );
+        assert_eq!(
+            run(&backend, "cluster getkeysinslot 12182 10"),
+            RespArray::with_vec(vec![
+                BulkString::new("foo").into(),
+                BulkString::new("{foo}h").into()
+            ])
+            .into()
+        );
+        assert_eq!(
+            run(&backend, "cluster getkeysinslot 12182 1"),
+            RespArray::with_vec(vec![BulkString::new("foo").into()]).into()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[(
+                "cluster info",
+                "ERR This instance has cluster support disabled",
+            )],
+        );
+        let backend = cluster_backend();
+        assert_errors(
+            &backend,
+            &[
+                ("cluster addslots 16384", "ERR Invalid or out of range slot"),
+                ("cluster addslots x", "ERR Invalid or out of range slot"),
+                (
+                    "cluster addslots 1 1",
+                    "ERR Slot 1 specified multiple times",
+                ),
+                (
+                    "cluster addslotsrange 1",
+                    "ERR wrong number of arguments for 'cluster|addslotsrange' command",
+                ),
+                (
+                    "cluster addslotsrange 1 2 3",
+                    "ERR wrong number of arguments for 'cluster|addslotsrange' command",
+                ),
+                (
+                    "cluster addslotsrange 5 1",
+                    "ERR start slot number 5 is greater than end slot number 1",
+                ),
+                (
+                    "cluster addslotsrange 1 5 5 6",
+                    "ERR Slot range specified multiple times",
+                ),
+                ("cluster delslots 1", "ERR Slot 1 is already unassigned"),
+                ("cluster countkeysinslot 16384", "ERR Invalid slot"),
+                (
+                    "cluster getkeysinslot 1 -1",
+                    "ERR Invalid slot or number of keys",
+                ),
+                (
+                    "cluster meet nohost 7001",
+                    "ERR Invalid node address specified: nohost:7001",
+                ),
+                (
+                    "cluster foo",
+                    "ERR unknown subcommand 'foo'. Try CLUSTER HELP.",
+                ),
+                (
+                    "cluster setslot 1 stable x",
+                    "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                ),
+                (
+                    "cluster setslot 1 migrating",
+                    "ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                ),
+                (
+                    "cluster setslot 1 migrating x",
+                    "ERR I'm not the owner of hash slot 1",
+                ),
+            ],
+        );
+        assert_eq!(run(&backend, "cluster addslots 1"), RESP_OK.clone());
+        assert_errors(
+            &backend,
+            &[
+                ("cluster addslotsrange 0 1", "ERR Slot 1 is already busy"),
+                (
+                    "cluster setslot 1 importing x",
+                    "ERR I'm already the owner of hash slot 1",
+                ),
+                (
+                    "cluster setslot 1 migrating x",
+                    "ERR I don't know about node x",
+                ),
+            ],
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use super::*;
+use crate::{Backend, RespMap, RespNull, RespSet};
+
+// The COMMAND family describes the commands from the command table,
+// client libraries call it at startup to learn where the keys are.
+
+impl CommandExecutor for CommandInfo {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        let res: Vec<RespFrame> = match self.names.is_empty() {
+            true => command_table().map(command_info).collect(),
+            false => self
+                .names
+                .iter()
+                .map(|name| match find_command(name) {
+                    Some(spec) => command_info(spec),
+                    None => RespNull::new().into(),
+                })
+                .collect(),
+        };
+        RespArray::with_vec(res).into()
+    }
+}
+
+impl CommandExecutor for CommandCount {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        RespFrame::Integer(command_table().count() as i64)
+    }
+}
+
+impl CommandExecutor for CommandDocs {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        let mut res = RespMap::new();
+        let specs: Vec<&CommandSpec> = match self.names.is_empty() {
+            true => command_table().collect(),
+            // unknown names are left out
+            false => self.names.iter().filter_map(|n| find_command(n)).collect(),
+        };
+        for spec in specs {
+            res.insert(spec.name, command_doc(spec));
+        }
+        res.into()
+    }
+}
+
+impl CommandExecutor for CommandGetKeys {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        let spec = match table::resolve(&self.args) {
+            Ok(spec) => spec,
+            Err(CommandError::WrongArity(_)) => {
+                return SimpleError::new("ERR Invalid number of arguments specified for command")
+                    .into()
+            }
+            Err(_) => return SimpleError::new("ERR Invalid command specified").into(),
+        };
+        match spec.key_positions(&self.args) {
+            None => SimpleError::new("ERR Invalid arguments specified for command").into(),
+            Some(keys) if keys.is_empty() => {
+                SimpleError::new("ERR The command has no key arguments").into()
+            }
+            Some(keys) => RespArray::with_vec(
+                keys.into_iter()
+                    .map(|i| self.args[i].clone())
+                    .collect::<Vec<_>>(),
+            )
+            .into(),
+        }
+    }
+}
+
+impl CommandExecutor for CommandList {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        let res: Vec<RespFrame> = command_table()
+            .flat_map(|c| std::iter::once(c).chain(c.subcommands.iter()))
+            .filter(|spec| match &self.filter {
+                None => true,
+                // there are no modules
+                Some(CommandListFilter::Module(_)) => false,
+                Some(CommandListFilter::AclCat(cat)) => spec
+                    .acl_categories
+                    .iter()
+                    .any(|c| c.trim_start_matches('@').eq_ignore_ascii_case(cat)),
+                Some(CommandListFilter::Pattern(pattern)) => {
+                    glob_match(pattern.to_lowercase().as_bytes(), spec.name.as_bytes())
+                }
+            })
+            .map(|spec| BulkString::from(spec.name).into())
+            .collect();
+        RespArray::with_vec(res).into()
+    }
+}
+
+// name, arity, flags, first key, last key, step, ACL categories, tips,
+// key specifications and subcommands, the same layout as Redis 7
+fn command_info(spec: &CommandSpec) -> RespFrame {
+    let (first, last, step) = spec.key_range();
+    let flags: Vec<RespFrame> = spec
+        .flags
+        .iter()
+        .map(|f| SimpleString::new(f.as_str()).into())
+        .collect();
+    let categories: Vec<RespFrame> = spec
+        .acl_categories
+        .iter()
+        .map(|c| SimpleString::new(*c).into())
+        .collect();
+    let key_specs: Vec<RespFrame> = key_spec(spec).into_iter().collect();
+    let subcommands: Vec<RespFrame> = spec.subcommands.iter().map(command_info).collect();
+    RespArray::with_vec(vec![
+        BulkString::from(spec.name).into(),
+        RespFrame::Integer(spec.arity),
+        RespSet::with_vec(flags).into(),
+        RespFrame::Integer(first),
+        RespFrame::Integer(last),
+        RespFrame::Integer(step),
+        RespSet::with_vec(categories).into(),
+        RespArray::new().into(),
+        RespArray::with_vec(key_specs).into(),
+        RespArray::with_vec(subcommands).into(),
+    ])
+    .into()
+}
+
+fn key_spec(spec: &CommandSpec) -> Option<RespFrame> {
+    let (index, find_keys) = match spec.keys {
+        KeySpec::None => return None,
+        KeySpec::Range { first, last, step } => {
+            // the last key is relative to the first one
+            let last = if last < 0 { last } else { last - first };
+            let mut find = RespMap::new();
+            find.insert("lastkey", RespFrame::Integer(last));
+            find.insert("keystep", RespFrame::Integer(step));
+            find.insert("limit", RespFrame::Integer(0));
+            (first, ("range", find))
+        }
+        KeySpec::KeyNum { index } => {
+            let mut find = RespMap::new();
+            find.insert("keynumidx", RespFrame::Integer(0));
+            find.insert("firstkey", RespFrame::Integer(1));
+            find.insert("keystep", RespFrame::Integer(1));
+            (index as i64, ("keynum", find))
+        }
+        KeySpec::Keyword { index, .. } => {
+            let mut find = RespMap::new();
+            find.insert("lastkey", RespFrame::Integer(0));
+            find.insert("keystep", RespFrame::Integer(1));
+            find.insert("limit", RespFrame::Integer(0));
+            (index as i64, ("range", find))
+        }
+    };
+    let flags: &[&str] = if spec.has_flag(CommandFlag::ReadOnly) {
+        &["RO", "access"]
+    } else if spec.has_flag(CommandFlag::Write) || spec.has_flag(CommandFlag::MovableKeys) {
+        &["RW", "access", "update"]
+    } else {
+        &["RO"]
+    };
+
+    let mut begin_spec = RespMap::new();
+    begin_spec.insert("index", RespFrame::Integer(index));
+    let mut begin = RespMap::new();
+    begin.insert("type", BulkString::from("index"));
+    begin.insert("spec", begin_spec);
+    let (find_type, find_spec) = find_keys;
+    let mut find = RespMap::new();
+    find.insert("type", BulkString::from(find_type));
+    find.insert("spec", find_spec);
+
+    let mut res = RespMap::new();
+    res.insert(
+        "flags",
+        RespSet::with_vec(
+            flags
+                .iter()
+                .map(|f| SimpleString::new(*f).into())
+                .collect::<Vec<RespFrame>>(),
+        ),
+    );
+    res.insert("begin_search", begin);
+    res.insert("find_keys", find);
+    Some(res.into())
+}
+
+fn command_doc(spec: &CommandSpec) -> RespFrame {
+    let mut doc = RespMap::new();
+    doc.insert("summary", BulkString::from(spec.doc.summary));
+    doc.insert("since", BulkString::from(spec.doc.since));
+    doc.insert("group", BulkString::from(spec.doc.group));
+    doc.insert("complexity", BulkString::from(spec.doc.complexity));
+    if !spec.subcommands.is_empty() {
+        let mut subcommands = RespMap::new();
+        for sub in spec.subcommands {
+            subcommands.insert(sub.name, command_doc(sub));
+        }
+        doc.insert("subcommands", subcommands);
+    }
+    doc.into()
+}
+
+impl TryFrom<RespArray> for CommandInfo {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        // COMMAND alone is handled as COMMAND INFO
+        let command = match value.as_ref().map(|v| v.len()) {
+            Some(1) => "command",
+            _ => "command|info",
+        };
+        let names = parse_strings(get_args(value, command)?)?;
+        Ok(CommandInfo { names })
+    }
+}
+
+impl TryFrom<RespArray> for CommandList {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = parse_strings(get_args(value, "command|list")?)?;
+        let filter = match args.as_slice() {
+            [] => None,
+            [filterby, kind, value] if filterby.eq_ignore_ascii_case("filterby") => {
+                match kind.to_lowercase().as_str() {
+                    "module" => Some(CommandListFilter::Module(value.clone())),
+                    "aclcat" => Some(CommandListFilter::AclCat(value.clone())),
+                    "pattern" => Some(CommandListFilter::Pattern(value.clone())),
+                    _ => return Err(CommandError::SyntaxError),
+                }
+            }
+            _ => return Err(CommandError::SyntaxError),
+        };
+        Ok(CommandList { filter })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::decode_inline;
+    use bytes::BytesMut;
+
+    fn execute(line: &str) -> RespFrame {
+        let mut data = BytesMut::from(format!("{}\r\n", line).as_bytes());
+        let array = decode_inline(&mut data).unwrap().unwrap();
+        Command::try_from(array).unwrap().execute(&Backend::new())
+    }
+
+    fn bulks(v: &[&str]) -> RespFrame {
+        RespArray::with_vec(
+            v.iter()
+                .map(|s| BulkString::from(*s).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+        .into()
+    }
+
+    #[test]
+    fn test_command_info() {
+        let res = execute("command info get foo");
+        let RespFrame::Array(RespArray(Some(v))) = res else {
+            panic!("expect an array");
+        };
+        assert_eq!(v[1], RespNull::new().into());
+        let RespFrame::Array(RespArray(Some(get))) = &v[0] else {
+            panic!("expect an array");
+        };
+        assert_eq!(get.len(), 10);
+        assert_eq!(get[0], BulkString::from("get").into());
+        assert_eq!(get[1], RespFrame::Integer(2));
+        assert_eq!(
+            get[2],
+            RespSet::with_vec(vec![
+                SimpleString::new("readonly").into(),
+                SimpleString::new("fast").into()
+            ])
+            .into()
+        );
+        assert_eq!(&get[3..6], &[1.into(), 1.into(), 1.into()]);
+
+        // every command is described by COMMAND
+        let RespFrame::Array(RespArray(Some(all))) = execute("COMMAND") else {
+            panic!("expect an array");
+        };
+        assert_eq!(
+            all.len() as i64,
+            execute("command count").try_to_int().unwrap()
+        );
+
+        // subcommands are described by their container
+        let RespFrame::Array(RespArray(Some(v))) = execute("command info script|load") else {
+            panic!("expect an array");
+        };
+        assert!(
+            matches!(&v[0], RespFrame::Array(a) if a.as_ref().unwrap()[0] == BulkString::from("script|load").into())
+        );
+    }
+
+    #[test]
+    fn test_command_getkeys() {
+        assert_eq!(execute("command getkeys get k"), bulks(&["k"]));
+        assert_eq!(execute("command getkeys watch a b"), bulks(&["a", "b"]));
+        assert_eq!(
+            execute("command getkeys EVAL 'return 1' 2 k1 k2 a1"),
+            bulks(&["k1", "k2"])
+        );
+        assert_eq!(execute("command getkeys fcall_ro f 1 k"), bulks(&["k"]));
+    }
+
+    #[test]
+    fn test_command_list_and_docs() {
+        let RespFrame::Array(RespArray(Some(all))) = execute("command list") else {
+            panic!("expect an array");
+        };
+        assert!(all.contains(&BulkString::from("script|load").into()));
+        assert_eq!(
+            execute("command list filterby pattern h*et"),
+            bulks(&["hget", "hset", "hmget"])
+        );
+        assert_eq!(
+            execute("command list filterby aclcat set"),
+            bulks(&["sadd", "sismember"])
+        );
+        assert_eq!(execute("command list filterby module json"), bulks(&[]));
+
+        let RespFrame::Map(docs) = execute("command docs get nosuch") else {
+            panic!("expect a map");
+        };
+        assert_eq!(docs.len(), 1);
+        let RespFrame::Map(get) = &docs["get"] else {
+            panic!("expect a map");
+        };
+        assert_eq!(get["group"], BulkString::from("string").into());
+        assert_eq!(get["since"], BulkString::from("1.0.0").into());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[
+                ("command getkeys foo", "ERR Invalid command specified"),
+                (
+                    "command getkeys get",
+                    "ERR Invalid number of arguments specified for command",
+                ),
+                (
+                    "command getkeys echo a",
+                    "ERR The command has no key arguments",
+                ),
+                (
+                    "command getkeys eval s 2 a",
+                    "ERR Invalid arguments specified for command",
+                ),
+                (
+                    "command count x",
+                    "ERR wrong number of arguments for 'command|count' command",
+                ),
+                (
+                    "command foo",
+                    "ERR unknown subcommand 'foo'. Try COMMAND HELP.",
+                ),
+                ("command list filterby x y", "ERR syntax error"),
+            ],
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use super::*;
+use crate::rdb::{self, RdbError};
+use crate::snapshot::unix_time_ms;
+
+impl CommandExecutor for Dump {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match rdb::dump(backend, &self.key) {
+            Some(payload) => BulkString::new(payload).into(),
+            None => BulkString::new_null().into(),
+        }
+    }
+}
+
+impl CommandExecutor for Restore {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        if self.idletime.is_some_and(|v| v < 0) {
+            return SimpleError::new("ERR Invalid IDLETIME value, must be >= 0").into();
+        }
+        if self.freq.is_some_and(|v| !(0..=255).contains(&v)) {
+            return SimpleError::new("ERR Invalid FREQ value, must be >= 0 and <= 255").into();
+        }
+        // IDLETIME and FREQ are accepted for compatibility, there is no
+        // eviction policy which would use them
+        if self.idletime.is_some() && self.freq.is_some() {
+            return CommandError::SyntaxError.into();
+        }
+        let _lock = backend.lock_key(&self.key);
+        if !self.replace && backend.key_type(&self.key).is_some() {
+            return SimpleError::new("BUSYKEY Target key name already exists.").into();
+        }
+        if self.ttl < 0 {
+            return SimpleError::new("ERR Invalid TTL value, must be >= 0").into();
+        }
+        let value = match rdb::read_dump(&self.payload) {
+            Ok(value) => value,
+            Err(e @ RdbError::BadPayload) => return SimpleError::new(format!("ERR {}", e)).into(),
+            Err(_) => return SimpleError::new("ERR Bad data format").into(),
+        };
+        let now = unix_time_ms();
+        let expire = match (self.ttl as u64, self.absttl) {
+            (0, _) => None,
+            (at, true) => Some(at),
+            (ttl, false) => Some(now + ttl),
+        };
+        // expired already, the key is only deleted
+        if expire.is_some_and(|at| at <= now) {
+            if backend.remove(&self.key) {
+                backend.touch(&self.key);
+            }
+            return RESP_OK.clone();
+        }
+        rdb::restore(backend, self.key.clone(), value);
+        if let Some(at) = expire {
+            backend.expires.insert(self.key.clone(), at);
+        }
+        RESP_OK.clone()
+    }
+}
+
+// MIGRATE waits on the target, so it's run by the session in `network.rs`.
+// The executor only runs when MIGRATE is issued inside MULTI.
+impl CommandExecutor for Migrate {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR Command not allowed inside a transaction").into()
+    }
+}
+
+impl TryFrom<RespArray> for Migrate {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = CommandArgs::new(value, "migrate")?;
+        let host = args.next_string()?;
+        let port = args.next_string()?.parse().map_err(|_| {
+            CommandError::InvalidArgument("value is not an integer or out of range".to_string())
+        })?;
+        let key = args.next_bytes()?;
+        let db = args.next_integer()?;
+        let timeout = args.next_integer()?;
+        let mut migrate = Migrate {
+            host,
+            port,
+            keys: Vec::new(),
+            db,
+            timeout,
+            copy: false,
+            replace: false,
+            auth: None,
+        };
+        while !args.is_empty() {
+            if args.next_keyword("copy") {
+                migrate.copy = true;
+            } else if args.next_keyword("replace") {
+                migrate.replace = true;
+            } else if args.next_keyword("auth") {
+                migrate.auth = Some((None, args.next_string()?));
+            } else if args.next_keyword("auth2") {
+                let username = args.next_string()?;
+                migrate.auth = Some((Some(username), args.next_string()?));
+            } else if args.next_keyword("keys") {
+                if !key.is_empty() {
+                    return Err(CommandError::InvalidArgument(
+                        "When using MIGRATE KEYS option, the key argument must be set to the empty string".to_string(),
+                    ));
+                }
+                while !args.is_empty() {
+                    migrate.keys.push(args.next_bytes()?);
+                }
+            } else {
+                return Err(CommandError::SyntaxError);
+            }
+        }
+        if migrate.keys.is_empty() {
+            migrate.keys.push(key);
+        }
+        Ok(migrate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+
+    fn run(backend: &Backend, args: &[&[u8]]) -> RespFrame {
+        let frames: Vec<RespFrame> = args.iter().map(|a| BulkString::new(*a).into()).collect();
+        match Command::try_from(RespArray::with_vec(frames)) {
+            Ok(cmd) => cmd.execute(backend),
+            Err(e) => e.into(),
+        }
+    }
+
+    fn payload(backend: &Backend, key: &[u8]) -> Vec<u8> {
+        let res = run(backend, &[b"dump", key]);
+        res.as_bytes().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"s"), BulkString::new("v").into());
+        backend.sadd(Bytes::from_static(b"set"), Bytes::from_static(b"m"));
+        assert_eq!(
+            run(&backend, &[b"dump", b"nokey"]),
+            BulkString::new_null().into()
+        );
+        let string = payload(&backend, b"s");
+        let res = run(&backend, &[b"restore", b"copy", b"0", &string]);
+        assert_eq!(res, RESP_OK.clone());
+        assert_eq!(backend.get(b"copy"), Some(BulkString::new("v").into()));
+        assert_eq!(
+            run(&backend, &[b"restore", b"copy", b"0", &string]),
+            SimpleError::new("BUSYKEY Target key name already exists.").into()
+        );
+
+        // REPLACE overwrites a key of another type
+        let set = payload(&backend, b"set");
+        let args: [&[u8]; 7] = [
+            b"restore",
+            b"copy",
+            b"0",
+            &set,
+            b"replace",
+            b"idletime",
+            b"10",
+        ];
+        assert_eq!(run(&backend, &args), RESP_OK.clone());
+        assert!(backend.sismember(b"copy", b"m"));
+        assert_eq!(backend.get(b"copy"), None);
+        assert!(!backend.expires.contains_key(&b"copy"[..]));
+
+        let now = unix_time_ms();
+        let res = run(
+            &backend,
+            &[b"restore", b"ttl", b"10000", &string, b"freq", b"5"],
+        );
+        assert_eq!(res, RESP_OK.clone());
+        let at = *backend.expires.get(&b"ttl"[..]).unwrap();
+        assert!(at >= now + 10000 && at < now + 20000);
+
+        let at = (now + 60000).to_string();
+        let res = run(
+            &backend,
+            &[b"restore", b"abs", at.as_bytes(), &string, b"absttl"],
+        );
+        assert_eq!(res, RESP_OK.clone());
+        assert_eq!(
+            backend.expires.get(&b"abs"[..]).map(|v| *v),
+            Some(now + 60000)
+        );
+
+        // an expiry in the past deletes the key
+        let res = run(
+            &backend,
+            &[b"restore", b"abs", b"1", &string, b"absttl", b"replace"],
+        );
+        assert_eq!(res, RESP_OK.clone());
+        assert_eq!(backend.key_type(b"abs"), None);
+    }
+
+    #[test]
+    fn test_migrate_debug_redacts_password() {
+        let frames: Vec<RespFrame> = [
+            "migrate", "host", "1", "k", "0", "0", "auth2", "user", "secret",
+        ]
+        .iter()
+        .map(|a| BulkString::from(*a).into())
+        .collect();
+        let migrate = Migrate::try_from(RespArray::with_vec(frames)).unwrap();
+        let debug = format!("{:?}", migrate);
+        assert!(
+            debug.contains("user") && !debug.contains("secret"),
+            "{}",
+            debug
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let backend = Backend::new();
+        assert_errors(
+            &backend,
+            &[
+                ("dump", "ERR wrong number of arguments for 'dump' command"),
+                (
+                    "restore k 0",
+                    "ERR wrong number of arguments for 'restore' command",
+                ),
+                (
+                    "restore k x payload",
+                    "ERR value is not an integer or out of range",
+                ),
+                (
+                    "restore k -1 payload",
+                    "ERR Invalid TTL value, must be >= 0",
+                ),
+                (
+                    "restore k 0 payload",
+                    "ERR DUMP payload version or checksum are wrong",
+                ),
+                ("restore k 0 payload nx", "ERR syntax error"),
+                ("restore k 0 payload idletime", "ERR syntax error"),
+                (
+                    "restore k 0 payload idletime -1",
+                    "ERR Invalid IDLETIME value, must be >= 0",
+                ),
+                (
+                    "restore k 0 payload freq 256",
+                    "ERR Invalid FREQ value, must be >= 0 and <= 255",
+                ),
+                ("restore k 0 payload idletime 1 freq 1", "ERR syntax error"),
+                (
+                    "migrate host 65536 k 0 0",
+                    "ERR value is not an integer or out of range",
+                ),
+                ("migrate host 1 k 0 0 auth", "ERR wrong number of arguments for 'migrate' command"),
+                ("migrate host 1 k 0 0 nx", "ERR syntax error"),
+                (
+                    "migrate host 1 k 0 0 keys a",
+                    "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                ),
+                // only queued inside MULTI the executor runs
+                (
+                    "migrate host 1 k 0 0",
+                    "ERR Command not allowed inside a transaction",
+                ),
+            ],
+        );
+        // a valid checksum around something which isn't a value
+        let payload = rdb::seal_payload(vec![0x0f, 0]);
+        let res = run(&backend, &[b"restore", b"k", b"0", &payload]);
+        assert_eq!(res, SimpleError::new("ERR Bad data format").into());
+    }
+}
@@ -6,22 +6,18 @@ impl CommandExecutor for Echo {
     }
 }
 
-impl TryFrom<RespArray> for Echo {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let mut args = get_args(value, "echo", 1)?.into_iter();
-        match args.next() {
-            Some(v) => Ok(Echo { value: v }),
-            _ => Err(CommandError::InvalidArgument(
-                "Invalid argument".to_string(),
-            )),
+impl CommandExecutor for Ping {
+    fn execute(&self, _backend: &crate::Backend) -> RespFrame {
+        match &self.message {
+            Some(message) => BulkString::new(message.clone()).into(),
+            None => SimpleString::new("PONG").into(),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::test::assert_errors;
     use super::*;
     use crate::RespDecode;
     use bytes::BytesMut;
@@ -36,4 +32,30 @@ mod test {
 
         assert_eq!(res.try_to_string().unwrap(), "hello")
     }
+
+    #[test]
+    fn test_ping() {
+        let backend = crate::Backend::default();
+        let ping = Ping { message: None };
+        assert_eq!(ping.execute(&backend), SimpleString::new("PONG").into());
+        let ping = Ping {
+            message: Some(Bytes::from_static(b"hi")),
+        };
+        assert_eq!(ping.execute(&backend), BulkString::new("hi").into());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &crate::Backend::default(),
+            &[
+                ("echo", "ERR wrong number of arguments for 'echo' command"),
+                (
+                    "echo a b",
+                    "ERR wrong number of arguments for 'echo' command",
+                ),
+                ("ping a b", "ERR syntax error"),
+            ],
+        );
+    }
 }
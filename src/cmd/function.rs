@@ -0,0 +1,351 @@
+use super::script::parse_eval_args;
+use super::*;
+use crate::script::{self, RestorePolicy};
+use crate::{Backend, BulkString, RespMap, RespNull, RespSet};
+
+impl CommandExecutor for FunctionLoad {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match backend.functions.load(backend, &self.code, self.replace) {
+            Ok(name) => BulkString::new(name).into(),
+            Err(e) => SimpleError::new(e).into(),
+        }
+    }
+}
+
+impl CommandExecutor for FunctionList {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        let mut res = Vec::new();
+        for library in backend.functions.list() {
+            if let Some(pattern) = &self.pattern {
+                if !glob_match(pattern.as_bytes(), library.name.as_bytes()) {
+                    continue;
+                }
+            }
+            let functions: Vec<RespFrame> = library
+                .functions
+                .into_iter()
+                .map(|(name, info)| {
+                    let mut m = RespMap::new();
+                    m.insert("name", BulkString::new(name));
+                    match info.description {
+                        Some(d) => m.insert("description", BulkString::new(d)),
+                        None => m.insert("description", RespNull::new()),
+                    }
+                    let flags: Vec<RespFrame> = info
+                        .flags
+                        .into_iter()
+                        .map(|f| SimpleString::new(f).into())
+                        .collect();
+                    m.insert("flags", RespSet::with_vec(flags));
+                    m.into()
+                })
+                .collect();
+            let mut m = RespMap::new();
+            m.insert("library_name", BulkString::new(library.name));
+            m.insert("engine", BulkString::new(library.engine));
+            m.insert("functions", RespArray::with_vec(functions));
+            if self.with_code {
+                m.insert("library_code", BulkString::new(library.code));
+            }
+            res.push(m.into());
+        }
+        RespArray::with_vec(res).into()
+    }
+}
+
+impl CommandExecutor for FunctionDelete {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match backend.functions.delete(&self.library) {
+            true => RESP_OK.clone(),
+            false => SimpleError::new("ERR Library not found").into(),
+        }
+    }
+}
+
+impl CommandExecutor for FunctionFlush {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        backend.functions.flush();
+        RESP_OK.clone()
+    }
+}
+
+impl CommandExecutor for FunctionDump {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        BulkString::new(backend.functions.dump()).into()
+    }
+}
+
+impl CommandExecutor for FunctionRestore {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match backend
+            .functions
+            .restore(backend, &self.payload, self.policy)
+        {
+            Ok(_) => RESP_OK.clone(),
+            Err(e) => SimpleError::new(e).into(),
+        }
+    }
+}
+
+impl CommandExecutor for FCall {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        self.run(backend, Caller::Client)
+    }
+}
+
+impl FCall {
+    pub(crate) fn run(&self, backend: &Backend, caller: Caller) -> RespFrame {
+        script::fcall(
+            backend,
+            &self.function,
+            &self.keys,
+            &self.args,
+            self.read_only,
+            caller,
+        )
+    }
+}
+
+impl TryFrom<RespArray> for FCall {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let read_only = value
+            .as_ref()
+            .and_then(|v| v.first())
+            .and_then(|v| v.as_bytes())
+            .is_some_and(|v| v.eq_ignore_ascii_case(b"fcall_ro"));
+        let name = if read_only { "fcall_ro" } else { "fcall" };
+        let args = get_args(value, name)?;
+        let (function, keys, args) = parse_eval_args(name, args)?;
+        Ok(FCall {
+            function,
+            keys,
+            args,
+            read_only,
+        })
+    }
+}
+
+// FUNCTION LOAD [REPLACE] code
+impl TryFrom<RespArray> for FunctionLoad {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = parse_strings(get_args(value, "function|load")?)?;
+        let code = args.pop().unwrap_or_default();
+        let mut replace = false;
+        for opt in args {
+            match opt.to_lowercase().as_str() {
+                "replace" => replace = true,
+                _ => {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "Unknown option given: {}",
+                        opt
+                    )))
+                }
+            }
+        }
+        Ok(FunctionLoad { replace, code })
+    }
+}
+
+// FUNCTION LIST [LIBRARYNAME pattern] [WITHCODE]
+impl TryFrom<RespArray> for FunctionList {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = parse_strings(get_args(value, "function|list")?)?;
+        let mut list = FunctionList {
+            pattern: None,
+            with_code: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.to_lowercase().as_str() {
+                "withcode" => list.with_code = true,
+                "libraryname" => match args.next() {
+                    Some(pattern) => list.pattern = Some(pattern),
+                    None => {
+                        return Err(CommandError::InvalidArgument(
+                            "library name argument was not given".to_string(),
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "Unknown argument {}",
+                        arg
+                    )))
+                }
+            }
+        }
+        Ok(list)
+    }
+}
+
+impl TryFrom<RespArray> for FunctionFlush {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = parse_strings(get_args(value, "function|flush")?)?;
+        match args.as_slice() {
+            [] => Ok(FunctionFlush),
+            [mode] if matches!(mode.to_lowercase().as_str(), "sync" | "async") => Ok(FunctionFlush),
+            _ => Err(CommandError::InvalidArgument(
+                "FUNCTION FLUSH only supports SYNC|ASYNC option".to_string(),
+            )),
+        }
+    }
+}
+
+// FUNCTION RESTORE payload [FLUSH | APPEND | REPLACE]
+impl TryFrom<RespArray> for FunctionRestore {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        // the payload is binary, don't turn it into a string
+        let mut args = get_args(value, "function|restore")?.into_iter();
+        let payload = args
+            .next()
+            .map(parse_bytes)
+            .transpose()?
+            .unwrap_or_default();
+        let policy = match args.next() {
+            None => RestorePolicy::Append,
+            Some(v) => match parse_string(v)?.to_lowercase().as_str() {
+                "append" => RestorePolicy::Append,
+                "replace" => RestorePolicy::Replace,
+                "flush" => RestorePolicy::Flush,
+                _ => return Err(CommandError::InvalidArgument(
+                    "Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                        .to_string(),
+                )),
+            },
+        };
+        if args.next().is_some() {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(FunctionRestore { payload, policy })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+
+    fn command(args: &[&[u8]]) -> Result<Command, CommandError> {
+        let frames: Vec<RespFrame> = args.iter().map(|a| BulkString::new(*a).into()).collect();
+        Command::try_from(RespArray::with_vec(frames))
+    }
+
+    #[test]
+    fn test_function_commands() {
+        let backend = Backend::new();
+        let code = b"#!lua name=lib\nredis.register_function('f', function(keys, args) return #keys + #args end)";
+        let res = command(&[b"function", b"load", code])
+            .unwrap()
+            .execute(&backend);
+        assert_eq!(res, BulkString::new("lib").into());
+
+        let res = command(&[b"fcall", b"f", b"1", b"k", b"a", b"b"])
+            .unwrap()
+            .execute(&backend);
+        assert_eq!(res, RespFrame::Integer(3));
+
+        let dump = command(&[b"function", b"dump"]).unwrap().execute(&backend);
+        let payload = match dump {
+            RespFrame::BulkString(BulkString(Some(v))) => v,
+            _ => panic!("dump should return bulk string"),
+        };
+
+        let res = command(&[b"function", b"delete", b"lib"])
+            .unwrap()
+            .execute(&backend);
+        assert_eq!(res, RESP_OK.clone());
+        let res = command(&[b"fcall", b"f", b"0"]).unwrap().execute(&backend);
+        assert_eq!(res, SimpleError::new("ERR Function not found").into());
+
+        let res = command(&[b"function", b"restore", &payload])
+            .unwrap()
+            .execute(&backend);
+        assert_eq!(res, RESP_OK.clone());
+
+        let res = command(&[b"function", b"list", b"libraryname", b"l*", b"withcode"])
+            .unwrap()
+            .execute(&backend);
+        let mut f = RespMap::new();
+        f.insert("name", BulkString::new("f"));
+        f.insert("description", RespNull::new());
+        f.insert("flags", RespSet::new());
+        let mut lib = RespMap::new();
+        lib.insert("library_name", BulkString::new("lib"));
+        lib.insert("engine", BulkString::new("LUA"));
+        lib.insert("functions", RespArray::with_vec(vec![f.into()]));
+        lib.insert("library_code", BulkString::new(code.to_vec()));
+        assert_eq!(res, RespArray::with_vec(vec![lib.into()]).into());
+
+        let res = command(&[b"function", b"list", b"libraryname", b"x*"])
+            .unwrap()
+            .execute(&backend);
+        assert_eq!(res, RespArray::new().into());
+    }
+
+    #[test]
+    fn test_fcall_ro_try_from() {
+        for name in [b"FCALL_RO", b"fcall_ro", b"Fcall_ro"] {
+            match command(&[name, b"f", b"0"]).unwrap() {
+                Command::FCall(f) => assert!(f.read_only),
+                _ => panic!("Command type not equal"),
+            }
+        }
+        assert!(command(&[b"function", b"restore", b"x", b"bad"]).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[
+                ("fcall f", "ERR wrong number of arguments for 'fcall' command"),
+                ("fcall_ro", "ERR wrong number of arguments for 'fcall_ro' command"),
+                ("fcall f 0", "ERR Function not found"),
+                ("function", "ERR wrong number of arguments for 'function' command"),
+                ("function Foo", "ERR unknown subcommand 'Foo'. Try FUNCTION HELP."),
+                (
+                    "function load",
+                    "ERR wrong number of arguments for 'function|load' command",
+                ),
+                ("function load now code", "ERR Unknown option given: now"),
+                ("function list foo", "ERR Unknown argument foo"),
+                (
+                    "function list libraryname",
+                    "ERR library name argument was not given",
+                ),
+                (
+                    "function delete",
+                    "ERR wrong number of arguments for 'function|delete' command",
+                ),
+                (
+                    "function flush now",
+                    "ERR FUNCTION FLUSH only supports SYNC|ASYNC option",
+                ),
+                (
+                    "function dump x",
+                    "ERR wrong number of arguments for 'function|dump' command",
+                ),
+                (
+                    "function restore",
+                    "ERR wrong number of arguments for 'function|restore' command",
+                ),
+                (
+                    "function restore x bad",
+                    "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.",
+                ),
+                ("function restore x append y", "ERR syntax error"),
+                ("function delete lib", "ERR Library not found"),
+            ],
+        );
+    }
+}
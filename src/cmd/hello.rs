@@ -0,0 +1,185 @@
+use super::*;
+use crate::Backend;
+use std::fmt;
+
+pub(super) const REDACTED: &str = "(redacted)";
+
+// HELLO changes the connection state, so it's handled by the session in
+// `network.rs`. The executor only runs when HELLO is issued inside MULTI.
+impl CommandExecutor for Hello {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR Command not allowed inside a transaction").into()
+    }
+}
+
+// AUTH changes the connection state too, see HELLO.
+impl CommandExecutor for Auth {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR Command not allowed inside a transaction").into()
+    }
+}
+
+impl fmt::Debug for Hello {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let auth = self.auth.as_ref().map(|(user, _)| (user, REDACTED));
+        f.debug_struct("Hello")
+            .field("protover", &self.protover)
+            .field("auth", &auth)
+            .field("setname", &self.setname)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+impl TryFrom<RespArray> for Auth {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = parse_strings(get_args(value, "auth")?)?;
+        match args.len() {
+            1 => Ok(Auth {
+                username: None,
+                password: args.remove(0),
+            }),
+            2 => Ok(Auth {
+                password: args.remove(1),
+                username: Some(args.remove(0)),
+            }),
+            _ => Err(CommandError::SyntaxError),
+        }
+    }
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+impl TryFrom<RespArray> for Hello {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = parse_strings(get_args(value, "hello")?)?;
+        let mut args = args.into_iter();
+        let mut hello = Hello {
+            protover: None,
+            auth: None,
+            setname: None,
+        };
+        let Some(protover) = args.next() else {
+            return Ok(hello);
+        };
+        hello.protover = Some(protover.parse().map_err(|_| {
+            CommandError::InvalidArgument(
+                "Protocol version is not an integer or out of range".to_string(),
+            )
+        })?);
+        while let Some(opt) = args.next() {
+            match opt.to_lowercase().as_str() {
+                "auth" => match (args.next(), args.next()) {
+                    (Some(user), Some(pass)) => hello.auth = Some((user, pass)),
+                    _ => {
+                        return Err(CommandError::InvalidArgument(
+                            "Syntax error in HELLO option 'auth'".to_string(),
+                        ))
+                    }
+                },
+                "setname" => match args.next() {
+                    Some(name) => hello.setname = Some(name),
+                    None => {
+                        return Err(CommandError::InvalidArgument(
+                            "Syntax error in HELLO option 'setname'".to_string(),
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "Syntax error in HELLO option '{}'",
+                        opt
+                    )))
+                }
+            }
+        }
+        Ok(hello)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::BulkString;
+
+    fn hello(args: &[&str]) -> Result<Hello, CommandError> {
+        let frames: Vec<RespFrame> = args.iter().map(|a| BulkString::from(*a).into()).collect();
+        Hello::try_from(RespArray::with_vec(frames))
+    }
+
+    #[test]
+    fn test_hello_try_from() {
+        let h = hello(&["hello"]).unwrap();
+        assert_eq!(h.protover, None);
+
+        let h = hello(&["HELLO", "3", "AUTH", "default", "pass", "SETNAME", "conn"]).unwrap();
+        assert_eq!(h.protover, Some(3));
+        assert_eq!(h.auth, Some(("default".to_string(), "pass".to_string())));
+        assert_eq!(h.setname, Some("conn".to_string()));
+
+        assert_eq!(
+            hello(&["hello", "three"]).unwrap_err(),
+            CommandError::InvalidArgument(
+                "Protocol version is not an integer or out of range".to_string()
+            )
+        );
+        assert!(hello(&["hello", "3", "auth", "default"]).is_err());
+        assert!(hello(&["hello", "3", "foo"]).is_err());
+    }
+
+    #[test]
+    fn test_debug_redacts_password() {
+        let h = hello(&["hello", "3", "auth", "default", "secret"]).unwrap();
+        let debug = format!("{:?}", h);
+        assert!(
+            debug.contains("default") && !debug.contains("secret"),
+            "{}",
+            debug
+        );
+
+        let frames: Vec<RespFrame> = ["auth", "user", "secret"]
+            .iter()
+            .map(|a| BulkString::from(*a).into())
+            .collect();
+        let auth = Auth::try_from(RespArray::with_vec(frames)).unwrap();
+        assert_eq!(auth.username.as_deref(), Some("user"));
+        assert_eq!(auth.password, "secret");
+        assert!(!format!("{:?}", auth).contains("secret"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[
+                (
+                    "hello three",
+                    "ERR Protocol version is not an integer or out of range",
+                ),
+                (
+                    "hello 3 auth default",
+                    "ERR Syntax error in HELLO option 'auth'",
+                ),
+                (
+                    "hello 3 setname",
+                    "ERR Syntax error in HELLO option 'setname'",
+                ),
+                ("hello 3 foo", "ERR Syntax error in HELLO option 'foo'"),
+                ("auth", "ERR wrong number of arguments for 'auth' command"),
+                ("auth a b c", "ERR syntax error"),
+            ],
+        );
+    }
+}
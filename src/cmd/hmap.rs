@@ -3,6 +3,10 @@ use crate::{Backend, CommandExecutor, RespFrame, RespNull};
 
 impl CommandExecutor for HGet {
     fn execute(&self, backend: &Backend) -> RespFrame {
+        let _lock = backend.lock_key(&self.key);
+        if wrong_type(backend, &self.key, KeyType::Hash) {
+            return RESP_WRONGTYPE.clone();
+        }
         backend
             .hget(&self.key, &self.field)
             .unwrap_or_else(|| RespNull::new().into())
@@ -11,66 +15,21 @@ impl CommandExecutor for HGet {
 
 impl CommandExecutor for HSet {
     fn execute(&self, backend: &Backend) -> RespFrame {
+        let _lock = backend.lock_key(&self.key);
+        if wrong_type(backend, &self.key, KeyType::Hash) {
+            return RESP_WRONGTYPE.clone();
+        }
         backend.hset(self.key.clone(), self.field.clone(), self.value.clone());
         RESP_OK.clone()
     }
 }
 
-impl TryFrom<RespArray> for HGet {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let mut args = get_args(value, "hget", 2)?.into_iter();
-        match (args.next(), args.next()) {
-            (Some(k), Some(f)) => Ok(HGet {
-                key: k
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-                field: f
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-            }),
-            _ => Err(CommandError::InvalidArgument(
-                "Invalid argument".to_string(),
-            )),
-        }
-    }
-}
-
-impl TryFrom<RespArray> for HSet {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let mut args = get_args(value, "hset", 3)?.into_iter();
-        match (args.next(), args.next(), args.next()) {
-            (Some(k), Some(f), Some(v)) => Ok(HSet {
-                key: k
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-                field: f
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-                value: v,
-            }),
-            _ => Err(CommandError::InvalidArgument(
-                "Invalid argument".to_string(),
-            )),
-        }
-    }
-}
-
-impl TryFrom<RespArray> for HMGet {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let args = get_args_without_check(value, "hmget")?;
-        let (key, fields) = parse_key_values_as_string(args)?;
-        Ok(HMGet { key, fields })
-    }
-}
-
 impl CommandExecutor for HMGet {
     fn execute(&self, backend: &Backend) -> RespFrame {
+        let _lock = backend.lock_key(&self.key);
+        if wrong_type(backend, &self.key, KeyType::Hash) {
+            return RESP_WRONGTYPE.clone();
+        }
         let mut res = Vec::new();
         for field in &self.fields {
             res.push(
@@ -85,6 +44,7 @@ impl CommandExecutor for HMGet {
 
 #[cfg(test)]
 mod test {
+    use super::super::test::assert_errors;
     use super::*;
     use crate::RespDecode;
     use bytes::BytesMut;
@@ -97,8 +57,16 @@ mod test {
         let frame = RespFrame::decode(&mut bytes).unwrap();
         let hmget = Command::try_from(frame).unwrap();
         let b = Backend::default();
-        b.hset("key".to_string(), "field1".to_string(), "value1".into());
-        b.hset("key".to_string(), "field2".to_string(), "value2".into());
+        b.hset(
+            Bytes::from_static(b"key"),
+            Bytes::from_static(b"field1"),
+            "value1".into(),
+        );
+        b.hset(
+            Bytes::from_static(b"key"),
+            Bytes::from_static(b"field2"),
+            "value2".into(),
+        );
 
         let res = hmget.execute(&b);
         assert_eq!(
@@ -109,4 +77,31 @@ mod test {
             ]))
         );
     }
+
+    #[test]
+    fn test_errors() {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"str"), "value".into());
+        let wrongtype = "WRONGTYPE Operation against a key holding the wrong kind of value";
+        assert_errors(
+            &backend,
+            &[
+                (
+                    "hget key",
+                    "ERR wrong number of arguments for 'hget' command",
+                ),
+                (
+                    "hset key f",
+                    "ERR wrong number of arguments for 'hset' command",
+                ),
+                (
+                    "hmget key",
+                    "ERR wrong number of arguments for 'hmget' command",
+                ),
+                ("hget str f", wrongtype),
+                ("hset str f v", wrongtype),
+                ("hmget str f", wrongtype),
+            ],
+        );
+    }
 }
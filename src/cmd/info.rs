@@ -0,0 +1,98 @@
+use super::*;
+use std::fmt::Write;
+
+const SECTIONS: [&str; 5] = ["server", "persistence", "stats", "replication", "cluster"];
+
+impl CommandExecutor for Info {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        let all = self.sections.is_empty()
+            || self
+                .sections
+                .iter()
+                .any(|s| matches!(s.to_lowercase().as_str(), "all" | "everything" | "default"));
+        let info = SECTIONS
+            .iter()
+            .filter(|name| all || self.sections.iter().any(|s| s.eq_ignore_ascii_case(name)))
+            .map(|name| section(backend, name))
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        BulkString::new(info).into()
+    }
+}
+
+fn section(backend: &Backend, name: &str) -> String {
+    let config = &backend.config;
+    if name == "replication" {
+        return backend.repl.info(config.replica_read_only);
+    }
+    let mut info = String::new();
+    let mut line = |k: &str, v: &dyn std::fmt::Display| {
+        let _ = write!(info, "{}:{}\r\n", k, v);
+    };
+    match name {
+        "server" => {
+            line("redis_version", &env!("CARGO_PKG_VERSION"));
+            let mode = match backend.cluster {
+                Some(_) => "cluster",
+                None => "standalone",
+            };
+            line("redis_mode", &mode);
+            line("process_id", &std::process::id());
+            line("tcp_port", &config.port);
+        }
+        "stats" => {
+            let (full, partial_ok, partial_err) = backend.repl.sync_stats();
+            line("sync_full", &full);
+            line("sync_partial_ok", &partial_ok);
+            line("sync_partial_err", &partial_err);
+            line(
+                "latest_fork_usec",
+                &backend.save_state.latest_capture_usec(),
+            );
+        }
+        "cluster" => line("cluster_enabled", &(backend.cluster.is_some() as u8)),
+        _ => {
+            let save_state = &backend.save_state;
+            line("loading", &0);
+            line("rdb_changes_since_last_save", &save_state.dirty());
+            line("rdb_bgsave_in_progress", &(save_state.in_progress() as u8));
+            line("rdb_last_save_time", &save_state.lastsave());
+            line("aof_enabled", &(config.appendonly as u8));
+            line("aof_rewrite_in_progress", &(backend.aof.rewriting() as u8));
+        }
+    }
+    let title = format!("# {}{}\r\n", name[..1].to_uppercase(), &name[1..]);
+    title + &info
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info(backend: &Backend, sections: &[&str]) -> String {
+        let info = Info {
+            sections: sections.iter().map(|s| s.to_string()).collect(),
+        };
+        let res = info.execute(backend);
+        String::from_utf8(res.as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_info() {
+        let backend = Backend::new();
+        let all = info(&backend, &[]);
+        assert!(all.starts_with("# Server\r\n"));
+        assert!(all.contains("\r\n\r\n# Persistence\r\n"));
+        assert!(all.contains("\r\n\r\n# Stats\r\nsync_full:0\r\n"));
+        assert!(all.contains("\r\nlatest_fork_usec:0\r\n"));
+        assert!(all.contains("\r\n\r\n# Replication\r\nrole:master\r\n"));
+        assert!(all.ends_with("\r\n\r\n# Cluster\r\ncluster_enabled:0\r\n"));
+        assert_eq!(info(&backend, &["everything"]), all);
+
+        let repl = info(&backend, &["REPLICATION"]);
+        assert!(repl.starts_with("# Replication\r\n"));
+        assert!(repl.contains("master_repl_offset:0\r\n"));
+        assert!(!repl.contains("# Server"));
+        assert_eq!(info(&backend, &["nosuchsection"]), "");
+    }
+}
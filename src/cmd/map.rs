@@ -1,52 +1,18 @@
 use super::*;
-use crate::{Backend, RespArray, RespNull};
-use anyhow::Result;
-
-impl TryFrom<RespArray> for Get {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let mut args = get_args(value, "get", 1)?.into_iter();
-        match args.next() {
-            Some(k) => Ok(Get {
-                key: k
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-            }),
-            _ => Err(CommandError::InvalidArgument(
-                "Invalid argument".to_string(),
-            )),
-        }
-    }
-}
+use crate::{Backend, RespNull};
 
 impl CommandExecutor for Get {
     fn execute(&self, backend: &Backend) -> RespFrame {
+        let _lock = backend.lock_key(&self.key);
+        if wrong_type(backend, &self.key, KeyType::String) {
+            return RESP_WRONGTYPE.clone();
+        }
         backend
             .get(&self.key)
             .unwrap_or_else(|| RespNull::new().into())
     }
 }
 
-impl TryFrom<RespArray> for Set {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let mut args = get_args(value, "set", 2)?.into_iter();
-        match (args.next(), args.next()) {
-            (Some(k), Some(v)) => Ok(Set {
-                key: k
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-                value: v,
-            }),
-            _ => Err(CommandError::InvalidArgument(
-                "Invalid argument".to_string(),
-            )),
-        }
-    }
-}
-
 impl CommandExecutor for Set {
     fn execute(&self, backend: &Backend) -> RespFrame {
         backend.set(self.key.clone(), self.value.clone());
@@ -54,9 +20,25 @@ impl CommandExecutor for Set {
     }
 }
 
+impl CommandExecutor for Del {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        let mut removed = 0;
+        for key in &self.keys {
+            if backend.remove(key) {
+                backend.touch(key);
+                removed += 1;
+            }
+        }
+        RespFrame::Integer(removed)
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::super::test::assert_errors;
     use super::*;
+    use crate::RespArray;
+    use anyhow::Result;
 
     #[test]
     fn test_get_try_from() -> Result<()> {
@@ -67,4 +49,74 @@ mod test {
         assert_eq!(get.key, "key");
         Ok(())
     }
+
+    #[test]
+    fn test_binary_key() -> Result<()> {
+        let backend = Backend::new();
+        let key = b"\xff\x00key\xfe".to_vec();
+        let set = Command::try_from(RespArray::with_vec(vec![
+            b"set".into(),
+            BulkString::new(key.clone()).into(),
+            b"value".into(),
+        ]))?;
+        set.execute(&backend);
+
+        // a lossy conversion would have mapped both keys to the same string
+        assert_eq!(backend.get(b"\xfe\x00key\xff"), None);
+        let get = Get::try_from(RespArray::with_vec(vec![
+            b"get".into(),
+            BulkString::new(key).into(),
+        ]))?;
+        assert_eq!(get.execute(&backend), b"value".into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() {
+        let backend = Backend::new();
+        backend.sadd(Bytes::from_static(b"set"), Bytes::from_static(b"a"));
+        assert_errors(
+            &backend,
+            &[
+                ("get", "ERR wrong number of arguments for 'get' command"),
+                ("GET a b", "ERR wrong number of arguments for 'get' command"),
+                ("set a", "ERR wrong number of arguments for 'set' command"),
+                (
+                    "set a b nx",
+                    "ERR wrong number of arguments for 'set' command",
+                ),
+                (
+                    "get set",
+                    "WRONGTYPE Operation against a key holding the wrong kind of value",
+                ),
+            ],
+        );
+        // SET overwrites a key of any type
+        let set = Command::try_from(RespArray::with_vec(vec![
+            b"set".into(),
+            b"set".into(),
+            b"value".into(),
+        ]))
+        .unwrap();
+        assert_eq!(set.execute(&backend), RESP_OK.clone());
+        assert!(!backend.sismember(b"set", b"a"));
+    }
+
+    #[test]
+    fn test_del() -> Result<()> {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"a"), b"1".into());
+        backend.sadd(Bytes::from_static(b"b"), Bytes::from_static(b"m"));
+        let del = Command::try_from(RespArray::with_vec(vec![
+            b"del".into(),
+            b"a".into(),
+            b"b".into(),
+            b"c".into(),
+        ]))?;
+        assert_eq!(del.execute(&backend), RespFrame::Integer(2));
+        assert_eq!(backend.key_type(b"a"), None);
+        assert_eq!(backend.key_type(b"b"), None);
+        assert_eq!(del.execute(&backend), RespFrame::Integer(0));
+        Ok(())
+    }
 }
@@ -1,17 +1,41 @@
+mod args;
+mod cluster;
+mod command;
+mod dump;
 mod echo;
+mod function;
+mod hello;
 mod hmap;
+mod info;
 mod map;
+mod replication;
+mod save;
+mod script;
 mod set;
+mod table;
+mod transaction;
 
-use crate::{RespArray, RespFrame, SimpleError, SimpleString};
+use crate::script::Caller;
+use crate::{Backend, BulkString, KeyType, RespArray, RespFrame, SimpleError, SimpleString};
+use bytes::Bytes;
 use enum_dispatch::enum_dispatch;
 use lazy_static::lazy_static;
+use macro_definitions::RedisCommand;
+use std::fmt;
 use thiserror::Error;
 
+pub use args::CommandArgs;
+pub use table::{
+    command_table, find_command, lookup_command, CommandDoc, CommandFlag, CommandSpec, KeySpec,
+};
+
 // you could also use once_cell instead of lazy_static
 lazy_static! {
-    static ref RESP_OK: RespFrame = SimpleString::new("OK").into();
-    static ref RESP_NOT_SUPPORT: RespFrame = SimpleError::new("Not support yet").into();
+    pub(crate) static ref RESP_OK: RespFrame = SimpleString::new("OK").into();
+    pub(crate) static ref RESP_QUEUED: RespFrame = SimpleString::new("QUEUED").into();
+    pub(crate) static ref RESP_WRONGTYPE: RespFrame =
+        SimpleError::new("WRONGTYPE Operation against a key holding the wrong kind of value")
+            .into();
 }
 
 #[enum_dispatch(CommandExecutor)]
@@ -28,79 +52,614 @@ pub enum Command {
 
     Echo(Echo),
 
-    // unrecognized command
-    Unrecognized(Unrecognized),
+    Multi(Multi),
+    Exec(Exec),
+    Discard(Discard),
+    Watch(Watch),
+    Unwatch(Unwatch),
+
+    Eval(Eval),
+    EvalSha(EvalSha),
+    ScriptLoad(ScriptLoad),
+    ScriptExists(ScriptExists),
+    ScriptFlush(ScriptFlush),
+    ScriptKill(ScriptKill),
+
+    FunctionLoad(FunctionLoad),
+    FunctionList(FunctionList),
+    FunctionDelete(FunctionDelete),
+    FunctionFlush(FunctionFlush),
+    FunctionDump(FunctionDump),
+    FunctionRestore(FunctionRestore),
+    FCall(FCall),
+
+    Hello(Hello),
+    Auth(Auth),
+
+    CommandInfo(CommandInfo),
+    CommandCount(CommandCount),
+    CommandDocs(CommandDocs),
+    CommandGetKeys(CommandGetKeys),
+    CommandList(CommandList),
+
+    Save(Save),
+    BgSave(BgSave),
+    LastSave(LastSave),
+    BgRewriteAof(BgRewriteAof),
+
+    Dump(Dump),
+    Restore(Restore),
+
+    Ping(Ping),
+    Info(Info),
+    ReplicaOf(ReplicaOf),
+    Psync(Psync),
+    ReplConf(ReplConf),
+    Wait(Wait),
+    WaitAof(WaitAof),
+
+    ClusterInfo(ClusterInfo),
+    ClusterMyId(ClusterMyId),
+    ClusterMeet(ClusterMeet),
+    ClusterAddSlots(ClusterAddSlots),
+    ClusterAddSlotsRange(ClusterAddSlotsRange),
+    ClusterDelSlots(ClusterDelSlots),
+    ClusterDelSlotsRange(ClusterDelSlotsRange),
+    ClusterSlots(ClusterSlots),
+    ClusterShards(ClusterShards),
+    ClusterNodes(ClusterNodes),
+    ClusterKeySlot(ClusterKeySlot),
+    ClusterCountKeysInSlot(ClusterCountKeysInSlot),
+    ClusterGetKeysInSlot(ClusterGetKeysInSlot),
+    ClusterSetSlot(ClusterSetSlot),
+    Asking(Asking),
+    Migrate(Migrate),
+    Del(Del),
 }
 
-#[derive(Debug)]
-pub struct Unrecognized;
-
-#[derive(Debug)]
+#[derive(Debug, RedisCommand)]
+#[command(name = "sadd")]
 pub struct SADD {
-    pub key: String,
-    pub members: Vec<String>,
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(bytes, variadic)]
+    pub members: Vec<Bytes>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, RedisCommand)]
+#[command(name = "sismember")]
 pub struct SISMEBER {
-    pub key: String,
-    pub member: String,
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(bytes)]
+    pub member: Bytes,
 }
 
-#[derive(Debug)]
+#[derive(Debug, RedisCommand)]
+#[command(name = "get")]
 pub struct Get {
-    pub key: String,
+    #[arg(key)]
+    pub key: Bytes,
 }
 
-#[derive(Debug)]
+#[derive(Debug, RedisCommand)]
+#[command(name = "echo")]
 pub struct Echo {
+    #[arg(frame)]
     pub value: RespFrame,
 }
 
-#[derive(Debug)]
+#[derive(Debug, RedisCommand)]
+#[command(name = "hmget")]
 pub struct HMGet {
-    pub key: String,
-    pub fields: Vec<String>,
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(bytes, variadic)]
+    pub fields: Vec<Bytes>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, RedisCommand)]
+#[command(name = "set")]
 pub struct Set {
-    pub key: String,
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(frame)]
     pub value: RespFrame,
 }
 
+#[derive(Debug, RedisCommand)]
+#[command(name = "multi")]
+pub struct Multi;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "exec")]
+pub struct Exec;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "discard")]
+pub struct Discard;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "watch")]
+pub struct Watch {
+    #[arg(key, variadic)]
+    pub keys: Vec<Bytes>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "unwatch")]
+pub struct Unwatch;
+
 #[derive(Debug)]
-pub struct HGet {
-    pub key: String,
-    pub field: String,
+pub struct Eval {
+    pub script: String,
+    pub keys: Vec<Bytes>,
+    pub args: Vec<Bytes>,
 }
 
 #[derive(Debug)]
+pub struct EvalSha {
+    pub sha1: String,
+    pub keys: Vec<Bytes>,
+    pub args: Vec<Bytes>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "script|load")]
+pub struct ScriptLoad {
+    #[arg(string)]
+    pub script: String,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "script|exists")]
+pub struct ScriptExists {
+    #[arg(string, variadic)]
+    pub sha1s: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ScriptFlush;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "script|kill")]
+pub struct ScriptKill;
+
+#[derive(Debug)]
+pub struct FunctionLoad {
+    pub replace: bool,
+    pub code: String,
+}
+
+#[derive(Debug)]
+pub struct FunctionList {
+    pub pattern: Option<String>,
+    pub with_code: bool,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "function|delete")]
+pub struct FunctionDelete {
+    #[arg(string)]
+    pub library: String,
+}
+
+#[derive(Debug)]
+pub struct FunctionFlush;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "function|dump")]
+pub struct FunctionDump;
+
+#[derive(Debug)]
+pub struct FunctionRestore {
+    pub payload: Bytes,
+    pub policy: crate::script::RestorePolicy,
+}
+
+// FCALL and FCALL_RO
+#[derive(Debug)]
+pub struct FCall {
+    pub function: String,
+    pub keys: Vec<Bytes>,
+    pub args: Vec<Bytes>,
+    pub read_only: bool,
+}
+
+// HELLO, AUTH and MIGRATE print their password as redacted, commands are
+// logged
+pub struct Hello {
+    pub protover: Option<i64>,
+    pub auth: Option<(String, String)>,
+    pub setname: Option<String>,
+}
+
+// AUTH [username] password
+pub struct Auth {
+    pub username: Option<String>,
+    pub password: String,
+}
+
+// COMMAND and COMMAND INFO, every command when no name is given
+#[derive(Debug)]
+pub struct CommandInfo {
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "command|count")]
+pub struct CommandCount;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "command|docs")]
+pub struct CommandDocs {
+    #[arg(string, variadic)]
+    pub names: Vec<String>,
+}
+
+// COMMAND GETKEYS command [arg ...]
+#[derive(Debug, RedisCommand)]
+#[command(name = "command|getkeys")]
+pub struct CommandGetKeys {
+    #[arg(frame, variadic)]
+    pub args: Vec<RespFrame>,
+}
+
+// COMMAND LIST [FILTERBY MODULE name | ACLCAT category | PATTERN pattern]
+#[derive(Debug)]
+pub struct CommandList {
+    pub filter: Option<CommandListFilter>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CommandListFilter {
+    Module(String),
+    AclCat(String),
+    Pattern(String),
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "hget")]
+pub struct HGet {
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(bytes)]
+    pub field: Bytes,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "hset")]
 pub struct HSet {
-    pub key: String,
-    pub field: String,
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(bytes)]
+    pub field: Bytes,
+    #[arg(frame)]
     pub value: RespFrame,
 }
 
+#[derive(Debug, RedisCommand)]
+#[command(name = "save")]
+pub struct Save;
+
+// BGSAVE [SCHEDULE]
+#[derive(Debug, RedisCommand)]
+#[command(name = "bgsave")]
+pub struct BgSave {
+    #[arg(flag = "schedule")]
+    pub schedule: bool,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "lastsave")]
+pub struct LastSave;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "bgrewriteaof")]
+pub struct BgRewriteAof;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "dump")]
+pub struct Dump {
+    #[arg(key)]
+    pub key: Bytes,
+}
+
+// RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+#[derive(Debug, RedisCommand)]
+#[command(name = "restore")]
+pub struct Restore {
+    #[arg(key)]
+    pub key: Bytes,
+    #[arg(integer)]
+    pub ttl: i64,
+    #[arg(bytes)]
+    pub payload: Bytes,
+    #[arg(flag = "replace")]
+    pub replace: bool,
+    #[arg(flag = "absttl")]
+    pub absttl: bool,
+    #[arg(option = "idletime", integer)]
+    pub idletime: Option<i64>,
+    #[arg(option = "freq", integer)]
+    pub freq: Option<i64>,
+}
+
+// PING [message]
+#[derive(Debug, RedisCommand)]
+#[command(name = "ping")]
+pub struct Ping {
+    #[arg(bytes, optional)]
+    pub message: Option<Bytes>,
+}
+
+// INFO [section [section ...]]
+#[derive(Debug, RedisCommand)]
+#[command(name = "info")]
+pub struct Info {
+    #[arg(string, variadic)]
+    pub sections: Vec<String>,
+}
+
+// REPLICAOF host port, or REPLICAOF NO ONE
+#[derive(Debug, RedisCommand)]
+#[command(name = "replicaof")]
+pub struct ReplicaOf {
+    #[arg(string)]
+    pub host: String,
+    #[arg(string)]
+    pub port: String,
+}
+
+// PSYNC replid offset, sent by a replica
+#[derive(Debug, RedisCommand)]
+#[command(name = "psync")]
+pub struct Psync {
+    #[arg(string)]
+    pub replid: String,
+    #[arg(integer)]
+    pub offset: i64,
+}
+
+// REPLCONF option value [option value ...], sent by a replica
+#[derive(Debug, RedisCommand)]
+#[command(name = "replconf")]
+pub struct ReplConf {
+    #[arg(string, variadic)]
+    pub args: Vec<String>,
+}
+
+// WAIT numreplicas timeout
+#[derive(Debug, RedisCommand)]
+#[command(name = "wait")]
+pub struct Wait {
+    #[arg(integer)]
+    pub numreplicas: i64,
+    #[arg(integer)]
+    pub timeout: i64,
+}
+
+// WAITAOF numlocal numreplicas timeout
+#[derive(Debug, RedisCommand)]
+#[command(name = "waitaof")]
+pub struct WaitAof {
+    #[arg(integer)]
+    pub numlocal: i64,
+    #[arg(integer)]
+    pub numreplicas: i64,
+    #[arg(integer)]
+    pub timeout: i64,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|info")]
+pub struct ClusterInfo;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|myid")]
+pub struct ClusterMyId;
+
+// CLUSTER MEET ip port [cluster-bus-port]
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|meet")]
+pub struct ClusterMeet {
+    #[arg(string)]
+    pub ip: String,
+    #[arg(string)]
+    pub port: String,
+    #[arg(string, optional)]
+    pub bus_port: Option<String>,
+}
+
+// slots are checked by the executors, which reply with the errors of Redis
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|addslots")]
+pub struct ClusterAddSlots {
+    #[arg(string, variadic)]
+    pub slots: Vec<String>,
+}
+
+// CLUSTER ADDSLOTSRANGE start end [start end ...]
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|addslotsrange")]
+pub struct ClusterAddSlotsRange {
+    #[arg(string, variadic)]
+    pub ranges: Vec<String>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|delslots")]
+pub struct ClusterDelSlots {
+    #[arg(string, variadic)]
+    pub slots: Vec<String>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|delslotsrange")]
+pub struct ClusterDelSlotsRange {
+    #[arg(string, variadic)]
+    pub ranges: Vec<String>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|slots")]
+pub struct ClusterSlots;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|shards")]
+pub struct ClusterShards;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|nodes")]
+pub struct ClusterNodes;
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|keyslot")]
+pub struct ClusterKeySlot {
+    #[arg(bytes)]
+    pub key: Bytes,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|countkeysinslot")]
+pub struct ClusterCountKeysInSlot {
+    #[arg(integer)]
+    pub slot: i64,
+}
+
+// CLUSTER GETKEYSINSLOT slot count
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|getkeysinslot")]
+pub struct ClusterGetKeysInSlot {
+    #[arg(integer)]
+    pub slot: i64,
+    #[arg(integer)]
+    pub count: i64,
+}
+
+// CLUSTER SETSLOT slot IMPORTING|MIGRATING|NODE node-id, or STABLE
+#[derive(Debug, RedisCommand)]
+#[command(name = "cluster|setslot")]
+pub struct ClusterSetSlot {
+    #[arg(string)]
+    pub slot: String,
+    #[arg(string)]
+    pub action: String,
+    #[arg(string, optional)]
+    pub node: Option<String>,
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "asking")]
+pub struct Asking;
+
+// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+//     [AUTH password | AUTH2 username password] [KEYS key [key ...]]
+pub struct Migrate {
+    pub host: String,
+    pub port: u16,
+    // the key, or those after KEYS
+    pub keys: Vec<Bytes>,
+    pub db: i64,
+    pub timeout: i64,
+    pub copy: bool,
+    pub replace: bool,
+    // the username of AUTH2 and the password
+    pub auth: Option<(Option<String>, String)>,
+}
+
+impl fmt::Debug for Migrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let auth = self.auth.as_ref().map(|(user, _)| (user, hello::REDACTED));
+        f.debug_struct("Migrate")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("keys", &self.keys)
+            .field("db", &self.db)
+            .field("timeout", &self.timeout)
+            .field("copy", &self.copy)
+            .field("replace", &self.replace)
+            .field("auth", &auth)
+            .finish()
+    }
+}
+
+#[derive(Debug, RedisCommand)]
+#[command(name = "del")]
+pub struct Del {
+    #[arg(key, variadic)]
+    pub keys: Vec<Bytes>,
+}
+
 #[enum_dispatch]
 pub trait CommandExecutor {
     fn execute(&self, backend: &crate::Backend) -> RespFrame;
 }
 
+// The Display of every error is the text Redis replies with.
 #[derive(Debug, Error, PartialEq, PartialOrd)]
 pub enum CommandError {
     // NotEqualCommand,
     // May try to use other command.
-    #[error("Command type not equal")]
+    #[error("ERR Command type not equal")]
     NotEqualCommand,
-    #[error("Unexpected error")]
+    #[error("ERR Unexpected error")]
     UnexpectedError,
     // InvalidCommand
     // It must have some error that must stop.
-    #[error("Invalid command {0}")]
+    #[error("ERR {0}")]
     InvalidCommand(String),
-    #[error("Invalid type {0}")]
+    #[error("ERR {0}")]
     InvalidArgument(String),
+    // the name of the command and its first arguments, quoted
+    #[error("ERR unknown command '{0}', with args beginning with: {1}")]
+    UnknownCommand(String, String),
+    // the name of the command, `container|subcommand` for a subcommand
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    // the name of the container command and the subcommand
+    #[error("ERR unknown subcommand '{1}'. Try {0} HELP.")]
+    UnknownSubcommand(String, String),
+    #[error("ERR syntax error")]
+    SyntaxError,
+}
+
+impl CommandError {
+    // Build the error Redis replies for an unknown command, quoting at most
+    // 128 bytes of its arguments.
+    fn unknown_command(args: &[RespFrame]) -> Self {
+        let name = args.first().and_then(|v| v.as_bytes()).unwrap_or_default();
+        let mut quoted = Vec::new();
+        for arg in args.iter().skip(1) {
+            if quoted.len() >= 128 {
+                break;
+            }
+            let arg = arg.as_bytes().unwrap_or_default();
+            let n = arg.len().min(128 - quoted.len());
+            quoted.push(b'\'');
+            quoted.extend_from_slice(&arg[..n]);
+            quoted.extend_from_slice(b"' ");
+        }
+        let name = &name[..name.len().min(128)];
+        CommandError::UnknownCommand(printable(name), printable(&quoted))
+    }
+
+    pub(crate) fn unknown_subcommand(command: &str, sub: &str) -> Self {
+        let sub = &sub.as_bytes()[..sub.len().min(128)];
+        CommandError::UnknownSubcommand(command.to_uppercase(), printable(sub))
+    }
+}
+
+// Text echoed back in an error must stay on one line, Redis replaces
+// CR and LF with spaces in the same way.
+fn printable(s: &[u8]) -> String {
+    String::from_utf8_lossy(s).replace(['\r', '\n'], " ")
+}
+
+impl From<CommandError> for RespFrame {
+    fn from(e: CommandError) -> Self {
+        SimpleError::new(e.to_string()).into()
+    }
 }
 
 impl TryFrom<RespFrame> for Command {
@@ -119,33 +678,104 @@ impl TryFrom<RespArray> for Command {
 
     fn try_from(array: RespArray) -> Result<Self, Self::Error> {
         match &array.0 {
-            Some(v) => {
-                let v = match v.first() {
-                    None => Err(CommandError::InvalidCommand("empty command".to_string())),
-                    Some(v) => Ok(v.try_to_string().unwrap_or_default()),
-                }?
-                .to_lowercase();
-
-                match v.as_bytes() {
-                    b"get" => Ok(Get::try_from(array)?.into()),
-                    b"set" => Ok(Set::try_from(array)?.into()),
-                    b"hset" => Ok(HSet::try_from(array)?.into()),
-                    b"hget" => Ok(HGet::try_from(array)?.into()),
-                    b"echo" => Ok(Echo::try_from(array)?.into()),
-                    b"hmget" => Ok(HMGet::try_from(array)?.into()),
-                    b"sadd" => Ok(SADD::try_from(array)?.into()),
-                    b"sismember" => Ok(SISMEBER::try_from(array)?.into()),
-                    _ => Ok(Unrecognized.into()),
-                }
-            }
-            None => Err(CommandError::InvalidCommand("Empty command".to_string())),
+            Some(v) if !v.is_empty() => table::resolve(v)?.parse(array),
+            _ => Err(CommandError::InvalidCommand("Empty command".to_string())),
         }
     }
 }
 
-impl CommandExecutor for Unrecognized {
-    fn execute(&self, _: &crate::Backend) -> RespFrame {
-        RESP_NOT_SUPPORT.clone()
+impl Command {
+    // the entry of the command table this command was parsed from
+    pub fn spec(&self) -> &'static CommandSpec {
+        let name = match self {
+            Command::Get(_) => "get",
+            Command::Set(_) => "set",
+            Command::HGet(_) => "hget",
+            Command::HSet(_) => "hset",
+            Command::HMGet(_) => "hmget",
+            Command::SADD(_) => "sadd",
+            Command::SISMEBER(_) => "sismember",
+            Command::Echo(_) => "echo",
+            Command::Multi(_) => "multi",
+            Command::Exec(_) => "exec",
+            Command::Discard(_) => "discard",
+            Command::Watch(_) => "watch",
+            Command::Unwatch(_) => "unwatch",
+            Command::Eval(_) => "eval",
+            Command::EvalSha(_) => "evalsha",
+            Command::ScriptLoad(_) => "script|load",
+            Command::ScriptExists(_) => "script|exists",
+            Command::ScriptFlush(_) => "script|flush",
+            Command::ScriptKill(_) => "script|kill",
+            Command::FunctionLoad(_) => "function|load",
+            Command::FunctionList(_) => "function|list",
+            Command::FunctionDelete(_) => "function|delete",
+            Command::FunctionFlush(_) => "function|flush",
+            Command::FunctionDump(_) => "function|dump",
+            Command::FunctionRestore(_) => "function|restore",
+            Command::FCall(f) if f.read_only => "fcall_ro",
+            Command::FCall(_) => "fcall",
+            Command::Hello(_) => "hello",
+            Command::Auth(_) => "auth",
+            Command::CommandInfo(_) => "command|info",
+            Command::CommandCount(_) => "command|count",
+            Command::CommandDocs(_) => "command|docs",
+            Command::CommandGetKeys(_) => "command|getkeys",
+            Command::CommandList(_) => "command|list",
+            Command::Save(_) => "save",
+            Command::BgSave(_) => "bgsave",
+            Command::LastSave(_) => "lastsave",
+            Command::BgRewriteAof(_) => "bgrewriteaof",
+            Command::Dump(_) => "dump",
+            Command::Restore(_) => "restore",
+            Command::Ping(_) => "ping",
+            Command::Info(_) => "info",
+            Command::ReplicaOf(_) => "replicaof",
+            Command::Psync(_) => "psync",
+            Command::ReplConf(_) => "replconf",
+            Command::Wait(_) => "wait",
+            Command::WaitAof(_) => "waitaof",
+            Command::ClusterInfo(_) => "cluster|info",
+            Command::ClusterMyId(_) => "cluster|myid",
+            Command::ClusterMeet(_) => "cluster|meet",
+            Command::ClusterAddSlots(_) => "cluster|addslots",
+            Command::ClusterAddSlotsRange(_) => "cluster|addslotsrange",
+            Command::ClusterDelSlots(_) => "cluster|delslots",
+            Command::ClusterDelSlotsRange(_) => "cluster|delslotsrange",
+            Command::ClusterSlots(_) => "cluster|slots",
+            Command::ClusterShards(_) => "cluster|shards",
+            Command::ClusterNodes(_) => "cluster|nodes",
+            Command::ClusterKeySlot(_) => "cluster|keyslot",
+            Command::ClusterCountKeysInSlot(_) => "cluster|countkeysinslot",
+            Command::ClusterGetKeysInSlot(_) => "cluster|getkeysinslot",
+            Command::ClusterSetSlot(_) => "cluster|setslot",
+            Command::Asking(_) => "asking",
+            Command::Migrate(_) => "migrate",
+            Command::Del(_) => "del",
+        };
+        // every variant has an entry, which the tests of the table check
+        table::find_command(name)
+            .unwrap_or_else(|| panic!("{} is missing from the command table", name))
+    }
+
+    // whether the command may modify the dataset
+    pub fn is_write(&self) -> bool {
+        self.spec().has_flag(CommandFlag::Write)
+    }
+
+    // whether the command can be called by redis.call inside a script
+    pub fn is_allowed_in_script(&self) -> bool {
+        !self.spec().has_flag(CommandFlag::NoScript)
+    }
+
+    // Execute the command for the caller, only scripts tell callers apart.
+    pub(crate) fn execute_as(&self, backend: &Backend, caller: Caller) -> RespFrame {
+        match self {
+            Command::Eval(eval) => eval.run(backend, caller),
+            Command::EvalSha(evalsha) => evalsha.run(backend, caller),
+            Command::FCall(fcall) => fcall.run(backend, caller),
+            cmd => cmd.execute(backend),
+        }
     }
 }
 
@@ -157,71 +787,186 @@ fn get_args_without_check(value: RespArray, command: &str) -> Result<Vec<RespFra
     let mut iter = frame.into_iter();
     match iter.next() {
         None => Err(CommandError::InvalidCommand("Empty command".to_string())),
-        Some(v) => match v.try_to_string() {
-            Ok(v) => {
-                if v.to_lowercase() != command {
-                    return Err(CommandError::NotEqualCommand);
-                }
-                Ok(iter.collect())
-            }
-            Err(_) => Err(CommandError::InvalidCommand("Invalid command".to_string())),
+        Some(v) => match v.as_bytes() {
+            Some(name) if name.eq_ignore_ascii_case(command.as_bytes()) => Ok(iter.collect()),
+            Some(_) => Err(CommandError::NotEqualCommand),
+            None => Err(CommandError::InvalidCommand("Invalid command".to_string())),
         },
     }
 }
 
-fn get_args(value: RespArray, command: &str, args: usize) -> Result<Vec<RespFrame>, CommandError> {
-    let frame = get_args_without_check(value, command)?;
-    if frame.len() != args {
-        return Err(CommandError::InvalidCommand(format!(
-            "Command args not equal, expect: {}, got: {}",
-            args,
-            frame.len()
-        )));
+// Take the arguments following the command name, and the subcommand name
+// for `container|subcommand`, checking the arity of the command table.
+fn get_args(value: RespArray, command: &str) -> Result<Vec<RespFrame>, CommandError> {
+    let (name, sub) = match command.split_once('|') {
+        Some((name, sub)) => (name, Some(sub)),
+        None => (command, None),
+    };
+    let mut args = get_args_without_check(value, name)?;
+    if let Some(sub) = sub {
+        match args.first().and_then(|v| v.as_bytes()) {
+            Some(v) if v.eq_ignore_ascii_case(sub.as_bytes()) => args.remove(0),
+            _ => return Err(CommandError::NotEqualCommand),
+        };
+    }
+    let spec = table::find_command(command).ok_or(CommandError::UnexpectedError)?;
+    let argc = args.len() + 1 + sub.is_some() as usize;
+    if !spec.check_arity(argc) {
+        return Err(CommandError::WrongArity(spec.name.to_string()));
     }
-    Ok(frame)
+    Ok(args)
 }
 
-fn parse_key_values_as_string(args: Vec<RespFrame>) -> Result<(String, Vec<String>), CommandError> {
-    let mut args = args.into_iter();
-    let key = match args.next() {
-        None => Err(CommandError::InvalidCommand("Empty command".to_string())),
-        Some(v) => match v.try_to_string() {
-            Ok(v) => Ok(v),
-            Err(e) => Err(CommandError::InvalidCommand(e.to_string())),
-        },
-    }?;
-    let fields: Vec<String> = args.filter_map(|v| v.try_to_string().ok()).collect();
-    Ok((key, fields))
+// Whether the key holds another kind of value than the command works on.
+fn wrong_type(backend: &Backend, key: &[u8], expected: KeyType) -> bool {
+    backend.key_type(key).is_some_and(|t| t != expected)
+}
+
+// Take a key, field or member as raw bytes, keys are binary safe.
+fn parse_bytes(frame: RespFrame) -> Result<Bytes, CommandError> {
+    match frame {
+        RespFrame::BulkString(BulkString(Some(v))) => Ok(v),
+        RespFrame::SimpleString(s) => Ok(s.0.into_bytes().into()),
+        _ => Err(CommandError::InvalidArgument(
+            "expect a bulk string".to_string(),
+        )),
+    }
+}
+
+// Take a textual argument such as a subcommand, an option or a script,
+// invalid UTF-8 is rejected rather than replaced.
+fn parse_string(frame: RespFrame) -> Result<String, CommandError> {
+    let bytes = match frame {
+        RespFrame::BulkString(BulkString(Some(v))) => v,
+        RespFrame::SimpleString(s) => return Ok(s.0),
+        _ => {
+            return Err(CommandError::InvalidArgument(
+                "expect a bulk string".to_string(),
+            ))
+        }
+    };
+    String::from_utf8(bytes.into())
+        .map_err(|_| CommandError::InvalidArgument("invalid UTF-8 string".to_string()))
+}
+
+fn parse_strings(args: Vec<RespFrame>) -> Result<Vec<String>, CommandError> {
+    args.into_iter().map(parse_string).collect()
+}
+
+// Match the string against a glob-style pattern, supporting `*`, `?`,
+// `[...]` classes and `\\` escapes.
+pub(crate) fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && glob_match(rest, &s[1..]),
+        Some((b'[', rest)) => {
+            let Some((&c, s_rest)) = s.split_first() else {
+                return false;
+            };
+            let (negate, rest) = match rest.split_first() {
+                Some((b'^', r)) => (true, r),
+                _ => (false, rest),
+            };
+            let mut i = 0;
+            let mut matched = false;
+            while i < rest.len() && rest[i] != b']' {
+                if rest[i] == b'\\' && i + 1 < rest.len() {
+                    i += 1;
+                    matched |= rest[i] == c;
+                } else if i + 2 < rest.len() && rest[i + 1] == b'-' && rest[i + 2] != b']' {
+                    let (lo, hi) = (rest[i].min(rest[i + 2]), rest[i].max(rest[i + 2]));
+                    matched |= lo <= c && c <= hi;
+                    i += 2;
+                } else {
+                    matched |= rest[i] == c;
+                }
+                i += 1;
+            }
+            let rest = rest.get(i + 1..).unwrap_or_default();
+            matched != negate && glob_match(rest, s_rest)
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            s.first() == Some(&rest[0]) && glob_match(&rest[1..], &s[1..])
+        }
+        Some((&p, rest)) => s.first() == Some(&p) && glob_match(rest, &s[1..]),
+    }
 }
 
 #[cfg(test)]
-mod test {
+pub(super) mod test {
     use super::*;
-    use crate::{RespArray, RespDecode};
+    use crate::{decode_inline, RespArray, RespDecode};
     use bytes::BytesMut;
 
+    // Run the inline command of every row and compare the error it replies with.
+    pub(crate) fn assert_errors(backend: &Backend, table: &[(&str, &str)]) {
+        for (line, expected) in table {
+            let mut data = BytesMut::from(format!("{}\r\n", line).as_bytes());
+            let array = decode_inline(&mut data).unwrap().unwrap();
+            let res = match Command::try_from(array) {
+                Ok(cmd) => cmd.execute(backend),
+                Err(e) => e.into(),
+            };
+            assert_eq!(res, SimpleError::new(*expected).into(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let backend = Backend::new();
+        let long = "x".repeat(200);
+        assert_errors(
+            &backend,
+            &[
+                (
+                    "foo",
+                    "ERR unknown command 'foo', with args beginning with: ",
+                ),
+                (
+                    "FOO bar \"a\\r\\nb\"",
+                    "ERR unknown command 'FOO', with args beginning with: 'bar' 'a  b' ",
+                ),
+                (
+                    &format!("foo {} y", long),
+                    &format!(
+                        "ERR unknown command 'foo', with args beginning with: '{}' ",
+                        "x".repeat(128)
+                    ),
+                ),
+            ],
+        );
+    }
+
     #[test]
     fn test_get_args() {
         let mut data = BytesMut::from("*2\r\n$3\r\nget\r\n$3\r\nkey\r\n");
         let res = RespArray::decode(&mut data).unwrap();
-        let args = get_args(res, "get", 1).unwrap();
+        let args = get_args(res, "get").unwrap();
         assert_eq!(args.len(), 1);
 
         let mut data = BytesMut::from("*2\r\n+get\r\n$3\r\nkey\r\n");
         let res = RespArray::decode(&mut data).unwrap();
-        let args = get_args(res, "get", 1).unwrap();
+        let args = get_args(res, "get").unwrap();
         assert_eq!(args.len(), 1);
 
         let mut data = BytesMut::from("*3\r\n$3\r\nget\r\n$3\r\nkey\r\n+abc\r\n");
         let res = RespArray::decode(&mut data).unwrap();
-        let err = get_args(res, "get", 1).unwrap_err();
-        assert_eq!(
-            err,
-            CommandError::InvalidCommand(format!(
-                "Command args not equal, expect: {}, got: {}",
-                1, 2
-            ))
-        );
+        let err = get_args(res, "get").unwrap_err();
+        assert_eq!(err, CommandError::WrongArity("get".to_string()));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"h*o", b"hello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-f]llo", b"hello"));
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+        assert!(!glob_match(b"lib", b"library"));
     }
 
     #[test]
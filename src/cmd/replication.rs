@@ -0,0 +1,262 @@
+use super::*;
+use crate::replication::run_replica;
+use std::time::Duration;
+use tracing::info;
+
+impl CommandExecutor for ReplicaOf {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        if self.host.eq_ignore_ascii_case("no") && self.port.eq_ignore_ascii_case("one") {
+            if backend.repl.become_master() {
+                info!("MASTER MODE enabled");
+            }
+            return RESP_OK.clone();
+        }
+        let Ok(port) = self.port.parse::<u16>() else {
+            return SimpleError::new("ERR Invalid master port").into();
+        };
+        match backend.repl.become_replica(&self.host, port) {
+            Some(generation) => {
+                info!("REPLICAOF {}:{} enabled", self.host, port);
+                let (backend, host) = (backend.clone(), self.host.clone());
+                tokio::spawn(run_replica(backend, host, port, generation));
+                RESP_OK.clone()
+            }
+            None => SimpleString::new("OK Already connected to specified master").into(),
+        }
+    }
+}
+
+// PSYNC hands the connection over to the replication, so it's handled by
+// the session in `network.rs`. The executor only runs inside MULTI.
+impl CommandExecutor for Psync {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR Command not allowed inside a transaction").into()
+    }
+}
+
+impl ReplConf {
+    fn option(&self, name: &str) -> Option<&str> {
+        self.args
+            .chunks(2)
+            .find(|opt| opt[0].eq_ignore_ascii_case(name))
+            .and_then(|opt| opt.get(1))
+            .map(String::as_str)
+    }
+
+    // the port of REPLCONF listening-port, which the session keeps
+    pub(crate) fn listening_port(&self) -> Option<u16> {
+        self.option("listening-port")?.parse().ok()
+    }
+
+    // REPLCONF ACK offset [FACK aof_offset], sent by a replica
+    pub(crate) fn ack(&self) -> Option<(u64, Option<u64>)> {
+        let offset = self.option("ack")?.parse().ok()?;
+        let aof_offset = self.option("fack").and_then(|o| o.parse().ok());
+        Some((offset, aof_offset))
+    }
+
+    // REPLCONF GETACK *, sent by a master
+    pub(crate) fn is_getack(&self) -> bool {
+        self.option("getack").is_some()
+    }
+}
+
+impl CommandExecutor for ReplConf {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        if !self.args.len().is_multiple_of(2) {
+            return CommandError::SyntaxError.into();
+        }
+        for opt in self.args.chunks(2) {
+            match opt[0].to_lowercase().as_str() {
+                "listening-port" if opt[1].parse::<u16>().is_err() => {
+                    return SimpleError::new("ERR value is not an integer or out of range").into()
+                }
+                "listening-port" | "ip-address" | "capa" | "ack" | "fack" | "getack" => {}
+                _ => {
+                    return SimpleError::new(format!(
+                        "ERR Unrecognized REPLCONF option: {}",
+                        opt[0]
+                    ))
+                    .into()
+                }
+            }
+        }
+        RESP_OK.clone()
+    }
+}
+
+// The timeout of WAIT and WAITAOF in milliseconds, 0 blocks forever.
+fn wait_timeout(
+    backend: &Backend,
+    name: &str,
+    timeout: i64,
+) -> Result<Option<Duration>, RespFrame> {
+    if backend.repl.is_replica() {
+        return Err(SimpleError::new(format!(
+            "ERR {} cannot be used with replica instances. Please also note that writes to \
+             replicas are just local and are not propagated.",
+            name
+        ))
+        .into());
+    }
+    match timeout {
+        t if t < 0 => Err(SimpleError::new("ERR timeout is negative").into()),
+        0 => Ok(None),
+        t => Ok(Some(Duration::from_millis(t as u64))),
+    }
+}
+
+// WAIT blocks the connection, so it's handled by the session in `network.rs`
+// with these. Inside MULTI it replies right away.
+impl Wait {
+    pub(crate) fn timeout(&self, backend: &Backend) -> Result<Option<Duration>, RespFrame> {
+        wait_timeout(backend, "WAIT", self.timeout)
+    }
+
+    // whether enough replicas acknowledged `offset`, and the reply
+    pub(crate) fn acked(&self, backend: &Backend, offset: u64) -> (bool, RespFrame) {
+        let replicas = backend.repl.count_acks(offset, false) as i64;
+        (replicas >= self.numreplicas, RespFrame::Integer(replicas))
+    }
+}
+
+impl CommandExecutor for Wait {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match self.timeout(backend) {
+            Ok(_) => self.acked(backend, backend.repl.offset()).1,
+            Err(e) => e,
+        }
+    }
+}
+
+impl WaitAof {
+    pub(crate) fn timeout(&self, backend: &Backend) -> Result<Option<Duration>, RespFrame> {
+        let timeout = wait_timeout(backend, "WAITAOF", self.timeout)?;
+        if self.numlocal > 0 && !backend.aof.enabled() {
+            return Err(SimpleError::new(
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.",
+            )
+            .into());
+        }
+        Ok(timeout)
+    }
+
+    // whether `offset` was fsynced locally and by enough replicas, and the reply
+    pub(crate) fn acked(&self, backend: &Backend, offset: u64) -> (bool, RespFrame) {
+        let aof = &backend.aof;
+        let local = (aof.enabled() && aof.fsynced_offset(&backend.repl) >= offset) as i64;
+        let replicas = backend.repl.count_acks(offset, true) as i64;
+        let done = local >= self.numlocal && replicas >= self.numreplicas;
+        let reply = RespArray::with_vec(vec![
+            RespFrame::Integer(local),
+            RespFrame::Integer(replicas),
+        ]);
+        (done, reply.into())
+    }
+}
+
+impl CommandExecutor for WaitAof {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match self.timeout(backend) {
+            Ok(_) => self.acked(backend, backend.repl.offset()).1,
+            Err(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::decode_inline;
+    use bytes::BytesMut;
+
+    fn run(backend: &Backend, line: &str) -> RespFrame {
+        let mut data = BytesMut::from(format!("{line}\r\n").as_str());
+        let array = decode_inline(&mut data).unwrap().unwrap();
+        Command::try_from(array).unwrap().execute(backend)
+    }
+
+    #[tokio::test]
+    async fn test_replicaof() {
+        let backend = Backend::new();
+        assert_eq!(run(&backend, "replicaof no one"), RESP_OK.clone());
+        assert!(!backend.repl.is_replica());
+        // nothing listens there, the link keeps retrying
+        assert_eq!(run(&backend, "replicaof 127.0.0.1 1"), RESP_OK.clone());
+        assert!(backend.repl.is_replica());
+        assert_eq!(
+            run(&backend, "replicaof 127.0.0.1 1"),
+            SimpleString::new("OK Already connected to specified master").into()
+        );
+        assert_eq!(run(&backend, "REPLICAOF NO ONE"), RESP_OK.clone());
+        assert!(!backend.repl.is_replica());
+    }
+
+    #[test]
+    fn test_replconf() {
+        let conf = ReplConf {
+            args: vec![
+                "capa".into(),
+                "psync2".into(),
+                "LISTENING-PORT".into(),
+                "7000".into(),
+            ],
+        };
+        assert_eq!(conf.listening_port(), Some(7000));
+        assert_eq!(conf.ack(), None);
+        assert_eq!(conf.execute(&Backend::new()), RESP_OK.clone());
+
+        let conf = ReplConf {
+            args: vec!["ack".into(), "10".into(), "fack".into(), "5".into()],
+        };
+        assert_eq!(conf.ack(), Some((10, Some(5))));
+        assert!(!conf.is_getack());
+        let conf = ReplConf {
+            args: vec!["GETACK".into(), "*".into()],
+        };
+        assert!(conf.is_getack());
+    }
+
+    #[test]
+    fn test_wait() {
+        let backend = Backend::new();
+        assert_eq!(run(&backend, "wait 0 0"), RespFrame::Integer(0));
+        assert_eq!(run(&backend, "wait 1 100"), RespFrame::Integer(0));
+        assert_eq!(
+            run(&backend, "waitaof 0 0 0"),
+            RespArray::with_vec(vec![RespFrame::Integer(0), RespFrame::Integer(0)]).into()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[
+                ("replicaof localhost 65536", "ERR Invalid master port"),
+                (
+                    "replicaof localhost",
+                    "ERR wrong number of arguments for 'replicaof' command",
+                ),
+                (
+                    "psync ?",
+                    "ERR wrong number of arguments for 'psync' command",
+                ),
+                ("psync ? x", "ERR value is not an integer or out of range"),
+                ("replconf capa", "ERR syntax error"),
+                (
+                    "replconf listening-port x",
+                    "ERR value is not an integer or out of range",
+                ),
+                ("replconf foo bar", "ERR Unrecognized REPLCONF option: foo"),
+                ("wait 1 -1", "ERR timeout is negative"),
+                ("wait 1", "ERR wrong number of arguments for 'wait' command"),
+                (
+                    "waitaof 1 0 0",
+                    "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.",
+                ),
+            ],
+        );
+    }
+}
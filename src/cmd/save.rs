@@ -0,0 +1,144 @@
+use super::*;
+use crate::{aof, snapshot};
+
+impl CommandExecutor for Save {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        if backend.save_state.in_progress() {
+            return SimpleError::new("ERR Background save already in progress").into();
+        }
+        match snapshot::save(backend) {
+            Ok(_) => RESP_OK.clone(),
+            Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+        }
+    }
+}
+
+impl CommandExecutor for BgSave {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        if snapshot::bgsave(backend) {
+            return SimpleString::new("Background saving started").into();
+        }
+        match self.schedule {
+            true => {
+                backend.save_state.schedule();
+                SimpleString::new("Background saving scheduled").into()
+            }
+            false => SimpleError::new("ERR Background save already in progress").into(),
+        }
+    }
+}
+
+impl CommandExecutor for LastSave {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        RespFrame::Integer(backend.save_state.lastsave() as i64)
+    }
+}
+
+impl CommandExecutor for BgRewriteAof {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        match aof::bgrewriteaof(backend) {
+            Ok(_) => SimpleString::new("Background append only file rewriting started").into(),
+            Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::{decode_inline, Config};
+    use bytes::BytesMut;
+    use std::time::Duration;
+
+    fn run(backend: &Backend, line: &str) -> RespFrame {
+        let mut data = BytesMut::from(format!("{line}\r\n").as_str());
+        let array = decode_inline(&mut data).unwrap().unwrap();
+        Command::try_from(array).unwrap().execute(backend)
+    }
+
+    #[tokio::test]
+    async fn test_save_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Backend::with_config(Config {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        backend.set(Bytes::from_static(b"k"), BulkString::new("v").into());
+        let before = backend.save_state.lastsave();
+        assert_eq!(run(&backend, "save"), RESP_OK.clone());
+        assert!(dir.path().join("dump.srdb").exists());
+        assert!(matches!(run(&backend, "lastsave"), RespFrame::Integer(t) if t as u64 >= before));
+
+        assert_eq!(
+            run(&backend, "bgsave"),
+            SimpleString::new("Background saving started").into()
+        );
+        // a save is running until its thread is done
+        if backend.save_state.in_progress() {
+            assert_eq!(
+                run(&backend, "bgsave schedule"),
+                SimpleString::new("Background saving scheduled").into()
+            );
+        }
+        while backend.save_state.in_progress() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(backend.save_state.dirty(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_bgrewriteaof() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Backend::with_config(Config {
+            dir: dir.path().to_path_buf(),
+            appendonly: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            run(&backend, "bgrewriteaof"),
+            SimpleError::new("ERR Append only file is disabled").into()
+        );
+        crate::load_aof(&backend).unwrap();
+        assert_eq!(
+            run(&backend, "bgrewriteaof"),
+            SimpleString::new("Background append only file rewriting started").into()
+        );
+        if backend.aof.rewriting() {
+            assert_eq!(
+                run(&backend, "bgrewriteaof"),
+                SimpleError::new("ERR Background append only file rewriting already in progress")
+                    .into()
+            );
+        }
+        while backend.aof.rewriting() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(dir
+            .path()
+            .join("appendonlydir/appendonly.aof.1.base.rdb")
+            .exists());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[
+                (
+                    "save now",
+                    "ERR wrong number of arguments for 'save' command",
+                ),
+                ("bgsave now", "ERR syntax error"),
+                (
+                    "lastsave 1",
+                    "ERR wrong number of arguments for 'lastsave' command",
+                ),
+                (
+                    "bgrewriteaof now",
+                    "ERR wrong number of arguments for 'bgrewriteaof' command",
+                ),
+            ],
+        );
+    }
+}
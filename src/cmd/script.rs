@@ -0,0 +1,269 @@
+use super::*;
+use crate::{script, Backend, BulkString};
+
+impl CommandExecutor for Eval {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        self.run(backend, Caller::Client)
+    }
+}
+
+impl Eval {
+    pub(crate) fn run(&self, backend: &Backend, caller: Caller) -> RespFrame {
+        let sha1 = script::sha1_hex(&self.script);
+        backend.scripts.insert(sha1, self.script.clone());
+        script::eval(backend, &self.script, &self.keys, &self.args, caller)
+    }
+}
+
+impl CommandExecutor for EvalSha {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        self.run(backend, Caller::Client)
+    }
+}
+
+impl EvalSha {
+    pub(crate) fn run(&self, backend: &Backend, caller: Caller) -> RespFrame {
+        let script = match backend.scripts.get(&self.sha1.to_lowercase()) {
+            Some(s) => s.value().clone(),
+            None => {
+                return SimpleError::new("NOSCRIPT No matching script. Please use EVAL.").into()
+            }
+        };
+        script::eval(backend, &script, &self.keys, &self.args, caller)
+    }
+}
+
+impl CommandExecutor for ScriptLoad {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        let sha1 = script::sha1_hex(&self.script);
+        backend.scripts.insert(sha1.clone(), self.script.clone());
+        BulkString::new(sha1).into()
+    }
+}
+
+impl CommandExecutor for ScriptExists {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        let res: Vec<RespFrame> = self
+            .sha1s
+            .iter()
+            .map(|sha1| {
+                RespFrame::Integer(backend.scripts.contains_key(&sha1.to_lowercase()) as i64)
+            })
+            .collect();
+        RespArray::with_vec(res).into()
+    }
+}
+
+impl CommandExecutor for ScriptFlush {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        backend.scripts.clear();
+        RESP_OK.clone()
+    }
+}
+
+impl CommandExecutor for ScriptKill {
+    fn execute(&self, backend: &Backend) -> RespFrame {
+        backend.script_state.kill()
+    }
+}
+
+impl TryFrom<RespArray> for Eval {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = get_args(value, "eval")?;
+        let (script, keys, args) = parse_eval_args("eval", args)?;
+        Ok(Eval { script, keys, args })
+    }
+}
+
+impl TryFrom<RespArray> for EvalSha {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = get_args(value, "evalsha")?;
+        let (sha1, keys, args) = parse_eval_args("evalsha", args)?;
+        Ok(EvalSha { sha1, keys, args })
+    }
+}
+
+impl TryFrom<RespArray> for ScriptFlush {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = parse_strings(get_args(value, "script|flush")?)?;
+        match args.as_slice() {
+            [] => Ok(ScriptFlush),
+            [mode] if matches!(mode.to_lowercase().as_str(), "sync" | "async") => Ok(ScriptFlush),
+            _ => Err(CommandError::InvalidArgument(
+                "SCRIPT FLUSH only support SYNC|ASYNC option".to_string(),
+            )),
+        }
+    }
+}
+
+// script|sha1 numkeys [key ...] [arg ...]
+pub(super) fn parse_eval_args(
+    command: &str,
+    args: Vec<RespFrame>,
+) -> Result<(String, Vec<Bytes>, Vec<Bytes>), CommandError> {
+    let mut args = args.into_iter();
+    let (script, numkeys) = match (args.next(), args.next()) {
+        (Some(s), Some(n)) => (parse_string(s)?, parse_string(n)?),
+        _ => return Err(CommandError::WrongArity(command.to_string())),
+    };
+    let numkeys: i64 = numkeys.parse().map_err(|_| {
+        CommandError::InvalidArgument("value is not an integer or out of range".to_string())
+    })?;
+    let rest = args.map(parse_bytes).collect::<Result<Vec<_>, _>>()?;
+    if numkeys < 0 {
+        return Err(CommandError::InvalidArgument(
+            "Number of keys can't be negative".to_string(),
+        ));
+    }
+    if numkeys as usize > rest.len() {
+        return Err(CommandError::InvalidArgument(
+            "Number of keys can't be greater than number of args".to_string(),
+        ));
+    }
+    let mut keys = rest;
+    let args = keys.split_off(numkeys as usize);
+    Ok((script, keys, args))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::RespDecode;
+    use bytes::BytesMut;
+
+    fn command(input: &str) -> Result<Command, CommandError> {
+        let mut bytes = BytesMut::from(input);
+        Command::try_from(RespFrame::decode(&mut bytes).unwrap())
+    }
+
+    #[test]
+    fn test_eval_try_from() {
+        let cmd =
+            command("*5\r\n$4\r\neval\r\n$8\r\nreturn 1\r\n$1\r\n1\r\n$1\r\nk\r\n$1\r\na\r\n");
+        match cmd.unwrap() {
+            Command::Eval(e) => {
+                assert_eq!(e.script, "return 1");
+                assert_eq!(e.keys, vec!["k"]);
+                assert_eq!(e.args, vec!["a"]);
+            }
+            _ => panic!("Command type not equal"),
+        }
+
+        let err = command("*3\r\n$4\r\neval\r\n$8\r\nreturn 1\r\n$1\r\n2\r\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR Number of keys can't be greater than number of args"
+        );
+    }
+
+    #[test]
+    fn test_evalsha() {
+        let backend = Backend::new();
+        let res = ScriptLoad {
+            script: "return ARGV[1]".to_string(),
+        }
+        .execute(&backend);
+        let sha1 = res.try_to_string().unwrap();
+        assert_eq!(sha1, script::sha1_hex("return ARGV[1]"));
+
+        let exists = ScriptExists {
+            sha1s: vec![sha1.clone(), "abc".to_string()],
+        }
+        .execute(&backend);
+        assert_eq!(
+            exists,
+            RespArray::with_vec(vec![RespFrame::Integer(1), RespFrame::Integer(0)]).into()
+        );
+
+        let res = EvalSha {
+            sha1: sha1.to_uppercase(),
+            keys: vec![],
+            args: vec![Bytes::from_static(b"hello")],
+        }
+        .execute(&backend);
+        assert_eq!(res, BulkString::new("hello").into());
+
+        ScriptFlush.execute(&backend);
+        let res = EvalSha {
+            sha1,
+            keys: vec![],
+            args: vec![],
+        }
+        .execute(&backend);
+        assert_eq!(
+            res,
+            SimpleError::new("NOSCRIPT No matching script. Please use EVAL.").into()
+        );
+    }
+
+    #[test]
+    fn test_script_try_from() {
+        let cmd = command("*2\r\n$6\r\nscript\r\n$4\r\nkill\r\n").unwrap();
+        assert!(matches!(cmd, Command::ScriptKill(_)));
+        let cmd = command("*3\r\n$6\r\nscript\r\n$5\r\nFLUSH\r\n$5\r\nASYNC\r\n").unwrap();
+        assert!(matches!(cmd, Command::ScriptFlush(_)));
+        assert!(command("*2\r\n$6\r\nscript\r\n$4\r\nload\r\n").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_errors(
+            &Backend::new(),
+            &[
+                ("eval", "ERR wrong number of arguments for 'eval' command"),
+                (
+                    "eval 'return 1'",
+                    "ERR wrong number of arguments for 'eval' command",
+                ),
+                (
+                    "evalsha abc",
+                    "ERR wrong number of arguments for 'evalsha' command",
+                ),
+                (
+                    "eval 'return 1' x",
+                    "ERR value is not an integer or out of range",
+                ),
+                ("eval 'return 1' -1", "ERR Number of keys can't be negative"),
+                (
+                    "eval 'return 1' 2 k",
+                    "ERR Number of keys can't be greater than number of args",
+                ),
+                (
+                    "evalsha 0123 0",
+                    "NOSCRIPT No matching script. Please use EVAL.",
+                ),
+                (
+                    "script",
+                    "ERR wrong number of arguments for 'script' command",
+                ),
+                (
+                    "script foo",
+                    "ERR unknown subcommand 'foo'. Try SCRIPT HELP.",
+                ),
+                (
+                    "script LOAD",
+                    "ERR wrong number of arguments for 'script|load' command",
+                ),
+                (
+                    "script exists",
+                    "ERR wrong number of arguments for 'script|exists' command",
+                ),
+                (
+                    "script flush now",
+                    "ERR SCRIPT FLUSH only support SYNC|ASYNC option",
+                ),
+                (
+                    "script kill now",
+                    "ERR wrong number of arguments for 'script|kill' command",
+                ),
+            ],
+        );
+    }
+}
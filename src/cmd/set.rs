@@ -1,18 +1,12 @@
 use super::*;
 use crate::Backend;
 
-impl TryFrom<RespArray> for SADD {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let args = get_args_without_check(value, "sadd")?;
-        let (key, members) = parse_key_values_as_string(args)?;
-        Ok(SADD { key, members })
-    }
-}
-
 impl CommandExecutor for SADD {
     fn execute(&self, backend: &Backend) -> RespFrame {
+        let _lock = backend.lock_key(&self.key);
+        if wrong_type(backend, &self.key, KeyType::Set) {
+            return RESP_WRONGTYPE.clone();
+        }
         let mut res = 0;
         for member in &self.members {
             res += backend.sadd(self.key.clone(), member.clone()) as i64;
@@ -21,29 +15,12 @@ impl CommandExecutor for SADD {
     }
 }
 
-impl TryFrom<RespArray> for SISMEBER {
-    type Error = CommandError;
-
-    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
-        let mut args = get_args(value, "sismember", 2)?.into_iter();
-        match (args.next(), args.next()) {
-            (Some(k), Some(m)) => Ok(SISMEBER {
-                key: k
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-                member: m
-                    .try_to_string()
-                    .map_err(|e| CommandError::InvalidCommand(e.to_string()))?,
-            }),
-            _ => Err(CommandError::InvalidArgument(
-                "Invalid argument".to_string(),
-            )),
-        }
-    }
-}
-
 impl CommandExecutor for SISMEBER {
     fn execute(&self, backend: &Backend) -> RespFrame {
+        let _lock = backend.lock_key(&self.key);
+        if wrong_type(backend, &self.key, KeyType::Set) {
+            return RESP_WRONGTYPE.clone();
+        }
         match backend.sismember(&self.key, &self.member) {
             true => 1,
             false => 0,
@@ -54,18 +31,19 @@ impl CommandExecutor for SISMEBER {
 
 #[cfg(test)]
 mod test {
+    use super::super::test::assert_errors;
     use super::*;
 
     #[test]
     fn test_sadd() {
         let backend = Backend::default();
-        backend.sadd("key".to_string(), "member1".to_string());
+        backend.sadd(Bytes::from_static(b"key"), Bytes::from_static(b"member1"));
         let sadd = SADD {
-            key: "key".to_string(),
+            key: Bytes::from_static(b"key"),
             members: vec![
-                "member1".to_string(),
-                "member2".to_string(),
-                "member3".to_string(),
+                Bytes::from_static(b"member1"),
+                Bytes::from_static(b"member2"),
+                Bytes::from_static(b"member3"),
             ],
         };
         let res = sadd.execute(&backend);
@@ -75,12 +53,34 @@ mod test {
     #[test]
     fn test_sismember() {
         let backend = Backend::default();
-        backend.sadd("key".to_string(), "member1".to_string());
+        backend.sadd(Bytes::from_static(b"key"), Bytes::from_static(b"member1"));
         let sismember = SISMEBER {
-            key: "key".to_string(),
-            member: "member1".to_string(),
+            key: Bytes::from_static(b"key"),
+            member: Bytes::from_static(b"member1"),
         };
         let res = sismember.execute(&backend);
         assert_eq!(res.try_to_int().unwrap(), 1);
     }
+
+    #[test]
+    fn test_errors() {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"str"), "value".into());
+        let wrongtype = "WRONGTYPE Operation against a key holding the wrong kind of value";
+        assert_errors(
+            &backend,
+            &[
+                (
+                    "sadd key",
+                    "ERR wrong number of arguments for 'sadd' command",
+                ),
+                (
+                    "sismember key",
+                    "ERR wrong number of arguments for 'sismember' command",
+                ),
+                ("sadd str a", wrongtype),
+                ("sismember str a", wrongtype),
+            ],
+        );
+    }
 }
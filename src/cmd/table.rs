@@ -0,0 +1,576 @@
+use super::*;
+use std::collections::HashMap;
+
+// The command table, every supported command is described here the same way
+// Redis describes its commands: dispatch, arity errors and introspection all
+// look the command up in this table.
+
+type Parse = fn(RespArray) -> Result<Command, CommandError>;
+
+// Same meaning as the command flags of Redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandFlag {
+    Write,
+    ReadOnly,
+    DenyOom,
+    Fast,
+    Blocking,
+    PubSub,
+    Loading,
+    Stale,
+    NoScript,
+    NoAuth,
+    AllowBusy,
+    Admin,
+    // the keys can't be found from first/last/step, e.g. EVAL takes numkeys
+    MovableKeys,
+}
+
+// Where the keys are in the arguments, the command name is at index 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeySpec {
+    None,
+    // from first to last, a negative last counts from the end
+    Range {
+        first: i64,
+        last: i64,
+        step: i64,
+    },
+    // the argument at index is the number of keys, which follow it
+    KeyNum {
+        index: usize,
+    },
+    // the argument at index, or when it's empty the arguments after the
+    // last occurrence of the keyword past the first `skip` arguments
+    Keyword {
+        index: usize,
+        keyword: &'static str,
+        skip: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandDoc {
+    pub summary: &'static str,
+    pub since: &'static str,
+    pub group: &'static str,
+    pub complexity: &'static str,
+}
+
+#[derive(Debug)]
+pub struct CommandSpec {
+    // `container|subcommand` for a subcommand
+    pub name: &'static str,
+    // a negative arity is the minimum number of arguments, both count the name
+    pub arity: i64,
+    pub flags: &'static [CommandFlag],
+    pub acl_categories: &'static [&'static str],
+    pub keys: KeySpec,
+    pub subcommands: &'static [CommandSpec],
+    pub doc: CommandDoc,
+    // None for a container command which needs a subcommand
+    parse: Option<Parse>,
+}
+
+impl CommandFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandFlag::Write => "write",
+            CommandFlag::ReadOnly => "readonly",
+            CommandFlag::DenyOom => "denyoom",
+            CommandFlag::Fast => "fast",
+            CommandFlag::Blocking => "blocking",
+            CommandFlag::PubSub => "pubsub",
+            CommandFlag::Loading => "loading",
+            CommandFlag::Stale => "stale",
+            CommandFlag::NoScript => "noscript",
+            CommandFlag::NoAuth => "no_auth",
+            CommandFlag::AllowBusy => "allow_busy",
+            CommandFlag::Admin => "admin",
+            CommandFlag::MovableKeys => "movablekeys",
+        }
+    }
+}
+
+impl CommandSpec {
+    pub fn has_flag(&self, flag: CommandFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    // whether argc arguments, the name included, satisfy the arity
+    pub fn check_arity(&self, argc: usize) -> bool {
+        match self.arity {
+            n if n < 0 => argc as i64 >= -n,
+            n => argc as i64 == n,
+        }
+    }
+
+    pub fn subcommand(&self, name: &[u8]) -> Option<&'static CommandSpec> {
+        self.subcommands.iter().find(|spec| {
+            spec.name
+                .split_once('|')
+                .is_some_and(|(_, sub)| sub.as_bytes().eq_ignore_ascii_case(name))
+        })
+    }
+
+    // the first key, the last key and the step as COMMAND reports them,
+    // all 0 when the keys can't be found without the arguments
+    pub fn key_range(&self) -> (i64, i64, i64) {
+        match self.keys {
+            KeySpec::Range { first, last, step } => (first, last, step),
+            KeySpec::Keyword { index, .. } => (index as i64, index as i64, 1),
+            KeySpec::None | KeySpec::KeyNum { .. } => (0, 0, 0),
+        }
+    }
+
+    // Positions of the keys in the arguments of a request, None when the
+    // request gives an invalid number of keys.
+    pub fn key_positions(&self, args: &[RespFrame]) -> Option<Vec<usize>> {
+        match self.keys {
+            KeySpec::None => Some(Vec::new()),
+            KeySpec::Range { first, last, step } => {
+                let last = match last {
+                    n if n < 0 => args.len() as i64 + n,
+                    n => n.min(args.len() as i64 - 1),
+                };
+                if first <= 0 || last < first {
+                    return Some(Vec::new());
+                }
+                Some(
+                    (first..=last)
+                        .step_by(step as usize)
+                        .map(|i| i as usize)
+                        .collect(),
+                )
+            }
+            KeySpec::KeyNum { index } => {
+                let numkeys = args.get(index)?.as_bytes()?;
+                let numkeys: usize = std::str::from_utf8(numkeys).ok()?.parse().ok()?;
+                if index + numkeys >= args.len() {
+                    return None;
+                }
+                Some((index + 1..=index + numkeys).collect())
+            }
+            KeySpec::Keyword {
+                index,
+                keyword,
+                skip,
+            } => {
+                if !args.get(index)?.as_bytes()?.is_empty() {
+                    return Some(vec![index]);
+                }
+                let is_keyword = |a: &RespFrame| {
+                    a.as_bytes()
+                        .is_some_and(|a| a.eq_ignore_ascii_case(keyword.as_bytes()))
+                };
+                // searched from the end, an option value may be the keyword
+                let found = args.iter().skip(skip).rposition(is_keyword)? + skip;
+                Some((found + 1..args.len()).collect())
+            }
+        }
+    }
+
+    // parse a command which was resolved to this spec
+    pub(crate) fn parse(&self, value: RespArray) -> Result<Command, CommandError> {
+        match self.parse {
+            Some(parse) => parse(value),
+            None => Err(CommandError::UnexpectedError),
+        }
+    }
+
+    const fn doc(
+        self,
+        group: &'static str,
+        since: &'static str,
+        complexity: &'static str,
+        summary: &'static str,
+    ) -> Self {
+        CommandSpec {
+            doc: CommandDoc {
+                summary,
+                since,
+                group,
+                complexity,
+            },
+            ..self
+        }
+    }
+}
+
+const fn command(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [CommandFlag],
+    acl_categories: &'static [&'static str],
+    keys: KeySpec,
+    parse: Parse,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        acl_categories,
+        keys,
+        subcommands: &[],
+        doc: CommandDoc {
+            summary: "",
+            since: "",
+            group: "",
+            complexity: "",
+        },
+        parse: Some(parse),
+    }
+}
+
+// A command which takes a subcommand, the parser, if any, handles the
+// command called without one.
+const fn container(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [CommandFlag],
+    acl_categories: &'static [&'static str],
+    subcommands: &'static [CommandSpec],
+    parse: Option<Parse>,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        acl_categories,
+        keys: KeySpec::None,
+        subcommands,
+        doc: CommandDoc {
+            summary: "",
+            since: "",
+            group: "",
+            complexity: "",
+        },
+        parse,
+    }
+}
+
+fn parse<T>(value: RespArray) -> Result<Command, CommandError>
+where
+    T: TryFrom<RespArray, Error = CommandError>,
+    Command: From<T>,
+{
+    Ok(T::try_from(value)?.into())
+}
+
+use CommandFlag::*;
+
+const NO_KEYS: KeySpec = KeySpec::None;
+const ONE_KEY: KeySpec = KeySpec::Range {
+    first: 1,
+    last: 1,
+    step: 1,
+};
+const ALL_KEYS: KeySpec = KeySpec::Range {
+    first: 1,
+    last: -1,
+    step: 1,
+};
+const NUM_KEYS: KeySpec = KeySpec::KeyNum { index: 2 };
+// MIGRATE takes a key, or the keys after KEYS
+const MIGRATE_KEYS: KeySpec = KeySpec::Keyword {
+    index: 3,
+    keyword: "keys",
+    skip: 6,
+};
+
+#[rustfmt::skip]
+static COMMANDS: [CommandSpec; 39] = [
+    command("get", 2, &[ReadOnly, Fast], &["@read", "@string", "@fast"], ONE_KEY, parse::<Get>)
+        .doc("string", "1.0.0", "O(1)", "Returns the string value of a key."),
+    command("set", 3, &[Write, DenyOom], &["@write", "@string", "@slow"], ONE_KEY, parse::<Set>)
+        .doc("string", "1.0.0", "O(1)", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."),
+    command("hget", 3, &[ReadOnly, Fast], &["@read", "@hash", "@fast"], ONE_KEY, parse::<HGet>)
+        .doc("hash", "2.0.0", "O(1)", "Returns the value of a field in a hash."),
+    command("hset", 4, &[Write, DenyOom, Fast], &["@write", "@hash", "@fast"], ONE_KEY, parse::<HSet>)
+        .doc("hash", "2.0.0", "O(1)", "Creates or modifies the value of a field in a hash."),
+    command("hmget", -3, &[ReadOnly, Fast], &["@read", "@hash", "@fast"], ONE_KEY, parse::<HMGet>)
+        .doc("hash", "2.0.0", "O(N) where N is the number of fields being requested.", "Returns the values of all fields in a hash."),
+    command("sadd", -3, &[Write, DenyOom, Fast], &["@write", "@set", "@fast"], ONE_KEY, parse::<SADD>)
+        .doc("set", "1.0.0", "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.", "Adds one or more members to a set. Creates the key if it doesn't exist."),
+    command("sismember", 3, &[ReadOnly, Fast], &["@read", "@set", "@fast"], ONE_KEY, parse::<SISMEBER>)
+        .doc("set", "1.0.0", "O(1)", "Determines whether a member belongs to a set."),
+    command("echo", 2, &[Loading, Stale, Fast], &["@fast", "@connection"], NO_KEYS, parse::<Echo>)
+        .doc("connection", "1.0.0", "O(1)", "Returns the given string."),
+    command("multi", 1, &[NoScript, Loading, Stale, Fast, AllowBusy], &["@fast", "@transaction"], NO_KEYS, parse::<Multi>)
+        .doc("transactions", "1.2.0", "O(1)", "Starts a transaction."),
+    command("exec", 1, &[NoScript, Loading, Stale], &["@slow", "@transaction"], NO_KEYS, parse::<Exec>)
+        .doc("transactions", "1.2.0", "Depends on commands in the transaction", "Executes all commands in a transaction."),
+    command("discard", 1, &[NoScript, Loading, Stale, Fast, AllowBusy], &["@fast", "@transaction"], NO_KEYS, parse::<Discard>)
+        .doc("transactions", "2.0.0", "O(N), when N is the number of queued commands", "Discards a transaction."),
+    command("watch", -2, &[NoScript, Loading, Stale, Fast, AllowBusy], &["@fast", "@transaction"], ALL_KEYS, parse::<Watch>)
+        .doc("transactions", "2.2.0", "O(1) for every key.", "Monitors changes to keys to determine the execution of a transaction."),
+    command("unwatch", 1, &[NoScript, Loading, Stale, Fast, AllowBusy], &["@fast", "@transaction"], NO_KEYS, parse::<Unwatch>)
+        .doc("transactions", "2.2.0", "O(1)", "Forgets about watched keys of a transaction."),
+    command("eval", -3, &[NoScript, Stale, MovableKeys], &["@slow", "@scripting"], NUM_KEYS, parse::<Eval>)
+        .doc("scripting", "2.6.0", "Depends on the script that is executed.", "Executes a server-side Lua script."),
+    command("evalsha", -3, &[NoScript, Stale, MovableKeys], &["@slow", "@scripting"], NUM_KEYS, parse::<EvalSha>)
+        .doc("scripting", "2.6.0", "Depends on the script that is executed.", "Executes a server-side Lua script by SHA1 digest."),
+    container("script", -2, &[], &["@slow"], &SCRIPT_SUBCOMMANDS, None)
+        .doc("scripting", "2.6.0", "Depends on subcommand.", "A container for Lua scripts management commands."),
+    container("function", -2, &[], &["@slow"], &FUNCTION_SUBCOMMANDS, None)
+        .doc("scripting", "7.0.0", "Depends on subcommand.", "A container for function commands."),
+    command("fcall", -3, &[NoScript, Stale, MovableKeys], &["@slow", "@scripting"], NUM_KEYS, parse::<FCall>)
+        .doc("scripting", "7.0.0", "Depends on the function that is executed.", "Invokes a function."),
+    // FCALL_RO shares the parser of FCALL, which tells them apart by name
+    command("fcall_ro", -3, &[NoScript, Stale, ReadOnly, MovableKeys], &["@slow", "@scripting"], NUM_KEYS, parse::<FCall>)
+        .doc("scripting", "7.0.0", "Depends on the function that is executed.", "Invokes a read-only function."),
+    command("hello", -1, &[NoScript, Loading, Stale, Fast, NoAuth, AllowBusy], &["@fast", "@connection"], NO_KEYS, parse::<Hello>)
+        .doc("connection", "6.0.0", "O(1)", "Handshakes with the Redis server."),
+    command("auth", -2, &[NoScript, Loading, Stale, Fast, NoAuth, AllowBusy], &["@fast", "@connection"], NO_KEYS, parse::<Auth>)
+        .doc("connection", "1.0.0", "O(N) where N is the number of passwords defined for the user", "Authenticates the connection."),
+    container("command", -1, &[Loading, Stale], &["@slow", "@connection"], &COMMAND_SUBCOMMANDS, Some(parse::<CommandInfo>))
+        .doc("server", "2.8.13", "O(N) where N is the total number of Redis commands", "Returns detailed information about all commands."),
+    command("save", 1, &[Admin, NoScript], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<Save>)
+        .doc("server", "1.0.0", "O(N) where N is the total number of keys in all databases", "Synchronously saves the database(s) to disk."),
+    command("bgsave", -1, &[Admin, NoScript], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<BgSave>)
+        .doc("server", "1.0.0", "O(1)", "Asynchronously saves the database(s) to disk."),
+    command("lastsave", 1, &[Loading, Stale, Fast], &["@admin", "@fast", "@dangerous"], NO_KEYS, parse::<LastSave>)
+        .doc("server", "1.0.0", "O(1)", "Returns the Unix timestamp of the last successful save to disk."),
+    command("bgrewriteaof", 1, &[Admin, NoScript], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<BgRewriteAof>)
+        .doc("server", "1.0.0", "O(1)", "Asynchronously rewrites the append-only file to disk."),
+    command("dump", 2, &[ReadOnly], &["@keyspace", "@read", "@slow"], ONE_KEY, parse::<Dump>)
+        .doc("generic", "2.6.0", "O(1) to access the key and additional O(N*M) to serialize it, where N is the number of Redis objects composing the value and M their average size. For small string values the time complexity is thus O(1)+O(1*M) where M is small, so simply O(1).", "Returns a serialized representation of the value stored at a key."),
+    command("restore", -4, &[Write, DenyOom], &["@keyspace", "@write", "@slow", "@dangerous"], ONE_KEY, parse::<Restore>)
+        .doc("generic", "2.6.0", "O(1) to create the new key and additional O(N*M) to reconstruct the serialized value, where N is the number of Redis objects composing the value and M their average size. For small string values the time complexity is thus O(1)+O(1*M) where M is small, so simply O(1). However for sorted set values the complexity is O(N*M*log(N)) because inserting values into sorted sets is O(log(N)).", "Creates a key from the serialized representation of a value."),
+    command("ping", -1, &[Fast, Stale], &["@fast", "@connection"], NO_KEYS, parse::<Ping>)
+        .doc("connection", "1.0.0", "O(1)", "Returns the server's liveliness response."),
+    command("info", -1, &[Loading, Stale], &["@slow", "@dangerous"], NO_KEYS, parse::<Info>)
+        .doc("server", "1.0.0", "O(1)", "Returns information and statistics about the server."),
+    command("replicaof", 3, &[Admin, NoScript, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ReplicaOf>)
+        .doc("server", "5.0.0", "O(1)", "Configures a server as replica of another, or promotes it to a master."),
+    command("psync", -3, &[Admin, NoScript], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<Psync>)
+        .doc("server", "2.8.0", "", "An internal command used in replication."),
+    command("replconf", -1, &[Admin, NoScript, Loading, Stale, AllowBusy], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ReplConf>)
+        .doc("server", "3.0.0", "O(1)", "An internal command for configuring the replication stream."),
+    command("wait", 3, &[NoScript], &["@slow", "@connection"], NO_KEYS, parse::<Wait>)
+        .doc("generic", "3.0.0", "O(1)", "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed."),
+    command("waitaof", 4, &[NoScript], &["@slow", "@connection"], NO_KEYS, parse::<WaitAof>)
+        .doc("generic", "7.2.0", "O(1)", "Blocks until all of the preceding write commands sent by the connection are written to the append-only file of the master and/or replicas."),
+    container("cluster", -2, &[], &["@slow"], &CLUSTER_SUBCOMMANDS, None)
+        .doc("cluster", "3.0.0", "Depends on subcommand.", "A container for Redis Cluster commands."),
+    command("asking", 1, &[Fast], &["@fast", "@connection"], NO_KEYS, parse::<Asking>)
+        .doc("cluster", "3.0.0", "O(1)", "Signals that a cluster client is following an -ASK redirect."),
+    command("migrate", -6, &[Write, NoScript, MovableKeys], &["@keyspace", "@write", "@slow", "@dangerous"], MIGRATE_KEYS, parse::<Migrate>)
+        .doc("generic", "2.6.0", "This command actually executes a DUMP+DEL in the source instance, and a RESTORE in the target instance. See the pages of these commands for time complexity. Also an O(N) data transfer between the two instances is performed.", "Atomically transfers a key from one Redis instance to another."),
+    command("del", -2, &[Write], &["@keyspace", "@write", "@slow"], ALL_KEYS, parse::<Del>)
+        .doc("generic", "1.0.0", "O(N) where N is the number of keys that will be removed. When a key to remove holds a value other than a string, the individual complexity for this key is O(M) where M is the number of elements in the list, set, sorted set or hash. Removing a single key that holds a string value is O(1).", "Deletes one or more keys."),
+];
+
+#[rustfmt::skip]
+static SCRIPT_SUBCOMMANDS: [CommandSpec; 4] = [
+    command("script|load", 3, &[NoScript, Stale], &["@slow", "@scripting"], NO_KEYS, parse::<ScriptLoad>)
+        .doc("scripting", "2.6.0", "O(N) with N being the length in bytes of the script body.", "Loads a server-side Lua script to the script cache."),
+    command("script|exists", -3, &[NoScript], &["@slow", "@scripting"], NO_KEYS, parse::<ScriptExists>)
+        .doc("scripting", "2.6.0", "O(N) with N being the number of scripts to check (so checking a single script is an O(1) operation).", "Determines whether server-side Lua scripts exist in the script cache."),
+    command("script|flush", -2, &[NoScript], &["@slow", "@scripting"], NO_KEYS, parse::<ScriptFlush>)
+        .doc("scripting", "2.6.0", "O(N) with N being the number of scripts in cache", "Removes all server-side Lua scripts from the script cache."),
+    command("script|kill", 2, &[NoScript, AllowBusy], &["@slow", "@scripting"], NO_KEYS, parse::<ScriptKill>)
+        .doc("scripting", "2.6.0", "O(1)", "Terminates a server-side Lua script during execution."),
+];
+
+#[rustfmt::skip]
+static FUNCTION_SUBCOMMANDS: [CommandSpec; 6] = [
+    command("function|load", -3, &[Write, DenyOom, NoScript], &["@write", "@slow", "@scripting"], NO_KEYS, parse::<FunctionLoad>)
+        .doc("scripting", "7.0.0", "O(1) (considering compilation time is redundant)", "Creates a library."),
+    command("function|list", -2, &[NoScript], &["@slow", "@scripting"], NO_KEYS, parse::<FunctionList>)
+        .doc("scripting", "7.0.0", "O(N) where N is the number of functions", "Returns information about all libraries."),
+    command("function|delete", 3, &[Write, NoScript], &["@write", "@slow", "@scripting"], NO_KEYS, parse::<FunctionDelete>)
+        .doc("scripting", "7.0.0", "O(1)", "Deletes a library and its functions."),
+    command("function|flush", -2, &[Write, NoScript], &["@write", "@slow", "@scripting"], NO_KEYS, parse::<FunctionFlush>)
+        .doc("scripting", "7.0.0", "O(N) where N is the number of functions deleted", "Deletes all libraries and functions."),
+    command("function|dump", 2, &[NoScript], &["@slow", "@scripting"], NO_KEYS, parse::<FunctionDump>)
+        .doc("scripting", "7.0.0", "O(N) where N is the number of functions", "Dumps all libraries into a serialized binary payload."),
+    command("function|restore", -3, &[Write, DenyOom, NoScript], &["@write", "@slow", "@scripting"], NO_KEYS, parse::<FunctionRestore>)
+        .doc("scripting", "7.0.0", "O(N) where N is the number of functions on the payload", "Restores all libraries from a payload."),
+];
+
+#[rustfmt::skip]
+static COMMAND_SUBCOMMANDS: [CommandSpec; 5] = [
+    command("command|count", 2, &[Loading, Stale], &["@slow", "@connection"], NO_KEYS, parse::<CommandCount>)
+        .doc("server", "2.8.13", "O(1)", "Returns a count of commands."),
+    command("command|docs", -2, &[Loading, Stale], &["@slow", "@connection"], NO_KEYS, parse::<CommandDocs>)
+        .doc("server", "7.0.0", "O(N) where N is the number of commands to look up", "Returns documentary information about one, multiple or all commands."),
+    command("command|getkeys", -3, &[Loading, Stale], &["@slow", "@connection"], NO_KEYS, parse::<CommandGetKeys>)
+        .doc("server", "2.8.13", "O(N) where N is the number of arguments to the command", "Extracts the key names from an arbitrary command."),
+    command("command|info", -2, &[Loading, Stale], &["@slow", "@connection"], NO_KEYS, parse::<CommandInfo>)
+        .doc("server", "2.8.13", "O(N) where N is the number of commands to look up", "Returns information about one, multiple or all commands."),
+    command("command|list", -2, &[Loading, Stale], &["@slow", "@connection"], NO_KEYS, parse::<CommandList>)
+        .doc("server", "7.0.0", "O(N) where N is the total number of Redis commands", "Returns a list of command names."),
+];
+
+#[rustfmt::skip]
+static CLUSTER_SUBCOMMANDS: [CommandSpec; 14] = [
+    command("cluster|addslots", -3, &[Admin, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ClusterAddSlots>)
+        .doc("cluster", "3.0.0", "O(N) where N is the total number of hash slot arguments", "Assigns new hash slots to a node."),
+    command("cluster|addslotsrange", -4, &[Admin, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ClusterAddSlotsRange>)
+        .doc("cluster", "7.0.0", "O(N) where N is the total number of the slots between the start slot and end slot arguments.", "Assigns new hash slot ranges to a node."),
+    command("cluster|countkeysinslot", 3, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterCountKeysInSlot>)
+        .doc("cluster", "3.0.0", "O(1)", "Returns the number of keys in a hash slot."),
+    command("cluster|delslots", -3, &[Admin, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ClusterDelSlots>)
+        .doc("cluster", "3.0.0", "O(N) where N is the total number of hash slot arguments", "Sets hash slots as unbound for a node."),
+    command("cluster|delslotsrange", -4, &[Admin, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ClusterDelSlotsRange>)
+        .doc("cluster", "7.0.0", "O(N) where N is the total number of the slots between the start slot and end slot arguments.", "Sets hash slot ranges as unbound for a node."),
+    command("cluster|getkeysinslot", 4, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterGetKeysInSlot>)
+        .doc("cluster", "3.0.0", "O(N) where N is the number of requested keys", "Returns the key names in a hash slot."),
+    command("cluster|info", 2, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterInfo>)
+        .doc("cluster", "3.0.0", "O(1)", "Returns information about the state of a node."),
+    command("cluster|keyslot", 3, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterKeySlot>)
+        .doc("cluster", "3.0.0", "O(N) where N is the number of bytes in the key", "Returns the hash slot for a key."),
+    command("cluster|meet", -4, &[Admin, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ClusterMeet>)
+        .doc("cluster", "3.0.0", "O(1)", "Forces a node to handshake with another node."),
+    command("cluster|myid", 2, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterMyId>)
+        .doc("cluster", "3.0.0", "O(1)", "Returns the ID of a node."),
+    command("cluster|nodes", 2, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterNodes>)
+        .doc("cluster", "3.0.0", "O(N) where N is the total number of Cluster nodes", "Returns the cluster configuration for a node."),
+    command("cluster|setslot", -4, &[Admin, Stale], &["@admin", "@slow", "@dangerous"], NO_KEYS, parse::<ClusterSetSlot>)
+        .doc("cluster", "3.0.0", "O(1)", "Binds a hash slot to a node."),
+    command("cluster|shards", 2, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterShards>)
+        .doc("cluster", "7.0.0", "O(N) where N is the total number of cluster nodes", "Returns the mapping of cluster slots to shards."),
+    command("cluster|slots", 2, &[Stale], &["@slow"], NO_KEYS, parse::<ClusterSlots>)
+        .doc("cluster", "3.0.0", "O(N) where N is the total number of Cluster nodes", "Returns the mapping of cluster slots to nodes."),
+];
+
+lazy_static! {
+    static ref INDEX: HashMap<&'static str, &'static CommandSpec> =
+        COMMANDS.iter().map(|spec| (spec.name, spec)).collect();
+}
+
+// every command of the table, subcommands are found through their container
+pub fn command_table() -> impl Iterator<Item = &'static CommandSpec> {
+    COMMANDS.iter()
+}
+
+// Look a command up by its name, case insensitive.
+pub fn lookup_command(name: &[u8]) -> Option<&'static CommandSpec> {
+    let name = std::str::from_utf8(name).ok()?.to_ascii_lowercase();
+    INDEX.get(name.as_str()).copied()
+}
+
+// Look a command up by its full name, `container|subcommand` for a subcommand.
+pub fn find_command(full_name: &str) -> Option<&'static CommandSpec> {
+    match full_name.split_once('|') {
+        Some((name, sub)) => lookup_command(name.as_bytes())?.subcommand(sub.as_bytes()),
+        None => lookup_command(full_name.as_bytes()),
+    }
+}
+
+// Find the spec a request runs, the subcommand for a container command,
+// and check its arity.
+pub(crate) fn resolve(args: &[RespFrame]) -> Result<&'static CommandSpec, CommandError> {
+    let name = args.first().and_then(|v| v.as_bytes()).unwrap_or_default();
+    let mut spec = lookup_command(name).ok_or_else(|| CommandError::unknown_command(args))?;
+    if !spec.subcommands.is_empty() && (args.len() > 1 || spec.parse.is_none()) {
+        if !spec.check_arity(args.len()) {
+            return Err(CommandError::WrongArity(spec.name.to_string()));
+        }
+        let sub = args[1].as_bytes().unwrap_or_default();
+        spec = spec.subcommand(sub).ok_or_else(|| {
+            CommandError::unknown_subcommand(spec.name, &String::from_utf8_lossy(sub))
+        })?;
+    }
+    if !spec.check_arity(args.len()) {
+        return Err(CommandError::WrongArity(spec.name.to_string()));
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<RespFrame> {
+        args.iter().map(|a| BulkString::from(*a).into()).collect()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let get = lookup_command(b"GET").unwrap();
+        assert_eq!(get.name, "get");
+        assert!(get.has_flag(ReadOnly));
+        assert!(get.check_arity(2));
+        assert!(!get.check_arity(3));
+
+        let set = lookup_command(b"set").unwrap();
+        assert!(set.check_arity(3));
+        assert!(!set.check_arity(5));
+        assert!(!set.check_arity(2));
+
+        assert_eq!(find_command("script|load").unwrap().arity, 3);
+        assert_eq!(find_command("FUNCTION|Dump").unwrap().name, "function|dump");
+        assert!(find_command("script|foo").is_none());
+        assert!(lookup_command(b"foo").is_none());
+    }
+
+    #[test]
+    fn test_key_positions() {
+        let get = lookup_command(b"get").unwrap();
+        assert_eq!(get.key_positions(&args(&["get", "k"])), Some(vec![1]));
+        let watch = lookup_command(b"watch").unwrap();
+        assert_eq!(
+            watch.key_positions(&args(&["watch", "a", "b", "c"])),
+            Some(vec![1, 2, 3])
+        );
+        let eval = lookup_command(b"eval").unwrap();
+        assert_eq!(eval.key_range(), (0, 0, 0));
+        assert_eq!(
+            eval.key_positions(&args(&["eval", "s", "2", "a", "b", "c"])),
+            Some(vec![3, 4])
+        );
+        assert_eq!(
+            eval.key_positions(&args(&["eval", "s", "0", "a"])),
+            Some(vec![])
+        );
+        assert_eq!(eval.key_positions(&args(&["eval", "s", "2", "a"])), None);
+        assert_eq!(eval.key_positions(&args(&["eval", "s", "-1"])), None);
+        let migrate = lookup_command(b"migrate").unwrap();
+        assert_eq!(
+            migrate.key_positions(&args(&["migrate", "h", "1", "k", "0", "0", "copy"])),
+            Some(vec![3])
+        );
+        let keys = args(&[
+            "migrate", "h", "1", "", "0", "0", "auth", "keys", "KEYS", "a", "b",
+        ]);
+        assert_eq!(migrate.key_positions(&keys), Some(vec![9, 10]));
+    }
+
+    #[test]
+    fn test_every_command_is_dispatched() {
+        // a table entry without a parser or with a wrong name would not dispatch
+        for spec in command_table().flat_map(|c| c.subcommands.iter().chain(std::iter::once(c))) {
+            if spec.parse.is_none() {
+                continue;
+            }
+            let mut args: Vec<RespFrame> = spec
+                .name
+                .split('|')
+                .map(|n| BulkString::from(n).into())
+                .collect();
+            while !spec.check_arity(args.len()) {
+                args.push(BulkString::from("0").into());
+            }
+            let res = Command::try_from(RespArray::with_vec(args));
+            assert!(
+                !matches!(
+                    res,
+                    Err(CommandError::UnknownCommand(..)
+                        | CommandError::UnknownSubcommand(..)
+                        | CommandError::WrongArity(_)
+                        | CommandError::NotEqualCommand
+                        | CommandError::UnexpectedError)
+                ),
+                "{}: {:?}",
+                spec.name,
+                res
+            );
+            // COMMAND without a subcommand is COMMAND INFO of every command
+            if let (Ok(cmd), false) = (res, spec.name == "command") {
+                assert_eq!(cmd.spec().name, spec.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::*;
+use crate::Backend;
+
+// MULTI, EXEC, DISCARD, WATCH and UNWATCH depend on the connection state,
+// so they are intercepted by the session in `network.rs`. The executors below
+// only run when the command is issued in a state where it is not allowed.
+
+impl CommandExecutor for Multi {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR MULTI calls can not be nested").into()
+    }
+}
+
+impl CommandExecutor for Exec {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR EXEC without MULTI").into()
+    }
+}
+
+impl CommandExecutor for Discard {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR DISCARD without MULTI").into()
+    }
+}
+
+impl CommandExecutor for Watch {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        SimpleError::new("ERR WATCH inside MULTI is not allowed").into()
+    }
+}
+
+impl CommandExecutor for Unwatch {
+    fn execute(&self, _backend: &Backend) -> RespFrame {
+        RESP_OK.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::assert_errors;
+    use super::*;
+    use crate::RespDecode;
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_watch_try_from() {
+        let mut bytes = BytesMut::from("*3\r\n$5\r\nwatch\r\n$2\r\nk1\r\n$2\r\nk2\r\n");
+        let frame = RespFrame::decode(&mut bytes).unwrap();
+        match Command::try_from(frame).unwrap() {
+            Command::Watch(w) => assert_eq!(w.keys, vec!["k1", "k2"]),
+            _ => panic!("Command type not equal"),
+        }
+
+        let mut bytes = BytesMut::from("*1\r\n$5\r\nwatch\r\n");
+        let frame = RespFrame::decode(&mut bytes).unwrap();
+        assert!(Command::try_from(frame).is_err());
+    }
+
+    #[test]
+    fn test_transaction_commands_outside_session() {
+        let backend = Backend::new();
+        assert_eq!(
+            Exec.execute(&backend),
+            SimpleError::new("ERR EXEC without MULTI").into()
+        );
+        assert_eq!(
+            Discard.execute(&backend),
+            SimpleError::new("ERR DISCARD without MULTI").into()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let backend = Backend::new();
+        assert_errors(
+            &backend,
+            &[
+                (
+                    "multi a",
+                    "ERR wrong number of arguments for 'multi' command",
+                ),
+                ("exec a", "ERR wrong number of arguments for 'exec' command"),
+                (
+                    "discard a",
+                    "ERR wrong number of arguments for 'discard' command",
+                ),
+                ("watch", "ERR wrong number of arguments for 'watch' command"),
+                (
+                    "unwatch a",
+                    "ERR wrong number of arguments for 'unwatch' command",
+                ),
+                ("exec", "ERR EXEC without MULTI"),
+                ("discard", "ERR DISCARD without MULTI"),
+            ],
+        );
+    }
+}
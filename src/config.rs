@@ -0,0 +1,515 @@
+use crate::RespLimits;
+use std::path::PathBuf;
+use std::{fs, io};
+use thiserror::Error;
+
+// Config holds the server settings.
+#[derive(Debug, Clone)]
+pub struct Config {
+    // the port clients connect to, replicas announce it to their master
+    pub port: u16,
+    // password of the default user, None means no authentication is needed
+    pub requirepass: Option<String>,
+    // max length of a single bulk string sent by a client
+    pub proto_max_bulk_len: usize,
+    // max number of elements of an aggregate sent by a client
+    pub max_multibulk_len: usize,
+    // max nesting depth of aggregates sent by a client
+    pub max_nesting_depth: usize,
+    // max bytes buffered for a client while its request is incomplete
+    pub client_query_buffer_limit: usize,
+    // the working directory, where the snapshot is written
+    pub dir: PathBuf,
+    // file name of the snapshot
+    pub dbfilename: String,
+    // the format SAVE and BGSAVE write, both are recognized when loading
+    pub snapshot_format: SnapshotFormat,
+    // `save <seconds> <changes>` rules, empty to never save automatically
+    pub save: Vec<SaveRule>,
+    // log every write to the append only file, which is loaded instead of the snapshot
+    pub appendonly: bool,
+    // base name of the append only files, the name of the single file of older versions
+    pub appendfilename: String,
+    // directory in `dir` holding the append only files and their manifest
+    pub appenddirname: String,
+    pub appendfsync: AppendFsync,
+    // rewrite the AOF once it grew by this percentage since the last rewrite, 0 to never
+    pub auto_aof_rewrite_percentage: u64,
+    // ... and is at least this many bytes
+    pub auto_aof_rewrite_min_size: u64,
+    // bytes of the replication stream kept for replicas which reconnect
+    pub repl_backlog_size: usize,
+    // how much of the stream may wait to be sent to a replica before it's disconnected
+    pub client_output_buffer_limit_replica: OutputBufferLimit,
+    // password a replica authenticates to its master with
+    pub masterauth: Option<String>,
+    // replicas refuse writes from their clients
+    pub replica_read_only: bool,
+    // serve a part of the hash slots, and talk to the other nodes on the cluster bus
+    pub cluster_enabled: bool,
+    // the port of the cluster bus, 0 for `port` + 10000
+    pub cluster_port: u16,
+    // milliseconds a node can take to reply on the cluster bus before its link is dropped
+    pub cluster_node_timeout: u64,
+    // the IP other nodes are told about, by default the one they connect to
+    pub cluster_announce_ip: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    // the line of the configuration and what's wrong with it, the same
+    // as the messages of Redis
+    #[error("line {line} `{text}`: {reason}")]
+    Directive {
+        line: usize,
+        text: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SnapshotFormat {
+    #[default]
+    Native,
+    // the RDB format of Redis, so that the file can be loaded by Redis
+    Rdb,
+}
+
+// When the append only file is flushed to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AppendFsync {
+    // after every write, before replying
+    Always,
+    // once a second, a crash loses at most a second of writes
+    #[default]
+    EverySec,
+    // when the OS decides
+    No,
+}
+
+// A client is disconnected once more than `hard` bytes wait to be sent to it,
+// or more than `soft` bytes for `soft_seconds` in a row. 0 disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl Default for OutputBufferLimit {
+    // the default of Redis for replicas
+    fn default() -> Self {
+        OutputBufferLimit {
+            hard: 256 * 1024 * 1024,
+            soft: 64 * 1024 * 1024,
+            soft_seconds: 60,
+        }
+    }
+}
+
+// Save when at least `changes` modifications happened in the last `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let limits = RespLimits::default();
+        Self {
+            port: 6379,
+            requirepass: None,
+            proto_max_bulk_len: limits.max_bulk_len,
+            max_multibulk_len: limits.max_multibulk_len,
+            max_nesting_depth: limits.max_depth,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.srdb".to_string(),
+            snapshot_format: SnapshotFormat::default(),
+            // the defaults of Redis: after an hour for 1 change, after 5 minutes
+            // for 100 changes and after a minute for 10000 changes
+            save: vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1,
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100,
+                },
+                SaveRule {
+                    seconds: 60,
+                    changes: 10000,
+                },
+            ],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            appendfsync: AppendFsync::default(),
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            repl_backlog_size: 1024 * 1024,
+            client_output_buffer_limit_replica: OutputBufferLimit::default(),
+            masterauth: None,
+            replica_read_only: true,
+            cluster_enabled: false,
+            cluster_port: 0,
+            cluster_node_timeout: 15000,
+            cluster_announce_ip: None,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn resp_limits(&self) -> RespLimits {
+        RespLimits {
+            max_bulk_len: self.proto_max_bulk_len,
+            max_multibulk_len: self.max_multibulk_len,
+            max_depth: self.max_nesting_depth,
+        }
+    }
+
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    // the single append only file of older versions, upgraded when loading
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+
+    pub fn cluster_bus_port(&self) -> u16 {
+        match self.cluster_port {
+            0 => self.port.saturating_add(10000),
+            port => port,
+        }
+    }
+
+    pub fn aof_dir(&self) -> PathBuf {
+        self.dir.join(&self.appenddirname)
+    }
+}
+
+// The command line of the server is `[config-file] [--name value ...]`, the
+// arguments are read as lines added to the file, like redis-server does.
+impl Config {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Config, ConfigError> {
+        let mut args = args.into_iter().peekable();
+        let mut text = match args.next_if(|a| !a.starts_with("--")) {
+            Some(path) => fs::read_to_string(path)?,
+            None => String::new(),
+        };
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    if !text.is_empty() {
+                        text.push('\n');
+                    }
+                    text.push_str(name)
+                }
+                None => text.push_str(&format!(" {}", quote(&arg))),
+            }
+        }
+        Config::parse(&text)
+    }
+
+    // Read `name value...` directives, one per line, over the defaults.
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        // the first save line replaces the default rules, the next ones add to it
+        let mut saved = false;
+        for (i, line) in text.lines().enumerate() {
+            let args = split_line(line);
+            let Some((name, args)) = args.split_first() else {
+                continue;
+            };
+            let name = name.to_lowercase();
+            if name == "save" && !std::mem::replace(&mut saved, true) {
+                config.save.clear();
+            }
+            config
+                .set(&name, args)
+                .map_err(|reason| ConfigError::Directive {
+                    line: i + 1,
+                    text: line.trim().to_string(),
+                    reason,
+                })?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        if name == "save" {
+            return self.add_save_rules(args);
+        }
+        if name == "client-output-buffer-limit" {
+            return self.set_output_buffer_limit(args);
+        }
+        let [value] = args else {
+            return Err("Bad directive or wrong number of arguments".to_string());
+        };
+        match name {
+            "port" => self.port = number(value)?,
+            "requirepass" => self.requirepass = (!value.is_empty()).then(|| value.clone()),
+            "proto-max-bulk-len" => self.proto_max_bulk_len = memory(value)?,
+            "max-multibulk-len" => self.max_multibulk_len = number(value)?,
+            "max-nesting-depth" => self.max_nesting_depth = number(value)?,
+            "client-query-buffer-limit" => self.client_query_buffer_limit = memory(value)?,
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => self.dbfilename = value.clone(),
+            "snapshot-format" => {
+                self.snapshot_format = match value.to_lowercase().as_str() {
+                    "native" => SnapshotFormat::Native,
+                    "rdb" => SnapshotFormat::Rdb,
+                    _ => return Err("argument must be 'native' or 'rdb'".to_string()),
+                }
+            }
+            "appendonly" => self.appendonly = yes_no(value)?,
+            "appendfilename" => self.appendfilename = value.clone(),
+            "appenddirname" => self.appenddirname = value.clone(),
+            "appendfsync" => {
+                self.appendfsync = match value.to_lowercase().as_str() {
+                    "always" => AppendFsync::Always,
+                    "everysec" => AppendFsync::EverySec,
+                    "no" => AppendFsync::No,
+                    _ => return Err("argument must be 'no', 'always' or 'everysec'".to_string()),
+                }
+            }
+            "auto-aof-rewrite-percentage" => self.auto_aof_rewrite_percentage = number(value)?,
+            "auto-aof-rewrite-min-size" => self.auto_aof_rewrite_min_size = memory(value)?,
+            "repl-backlog-size" => self.repl_backlog_size = memory(value)?,
+            "masterauth" => self.masterauth = (!value.is_empty()).then(|| value.clone()),
+            "replica-read-only" | "slave-read-only" => self.replica_read_only = yes_no(value)?,
+            "cluster-enabled" => self.cluster_enabled = yes_no(value)?,
+            "cluster-port" => self.cluster_port = number(value)?,
+            "cluster-node-timeout" => self.cluster_node_timeout = number(value)?,
+            "cluster-announce-ip" => {
+                self.cluster_announce_ip = (!value.is_empty()).then(|| value.clone())
+            }
+            _ => return Err("Bad directive or wrong number of arguments".to_string()),
+        }
+        Ok(())
+    }
+
+    // `client-output-buffer-limit <class> <hard> <soft> <soft seconds> [...]`,
+    // only replicas are limited, the limits of normal and pubsub clients are ignored
+    fn set_output_buffer_limit(&mut self, args: &[String]) -> Result<(), String> {
+        if args.is_empty() || !args.len().is_multiple_of(4) {
+            return Err("Wrong number of arguments in buffer limit configuration.".to_string());
+        }
+        for group in args.chunks(4) {
+            let limit = OutputBufferLimit {
+                hard: memory(&group[1])?,
+                soft: memory(&group[2])?,
+                soft_seconds: number(&group[3])?,
+            };
+            match group[0].to_lowercase().as_str() {
+                "replica" | "slave" => self.client_output_buffer_limit_replica = limit,
+                "normal" | "pubsub" => {}
+                _ => {
+                    return Err(
+                        "Invalid client class specified in buffer limit configuration.".to_string(),
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `save <seconds> <changes> [<seconds> <changes> ...]`, or `save ""`
+    fn add_save_rules(&mut self, args: &[String]) -> Result<(), String> {
+        if let [arg] = args {
+            if arg.is_empty() {
+                self.save.clear();
+                return Ok(());
+            }
+        }
+        if args.is_empty() || !args.len().is_multiple_of(2) {
+            return Err("Invalid save parameters".to_string());
+        }
+        for pair in args.chunks(2) {
+            let seconds = number(&pair[0]).map_err(|_| "Invalid save parameters")?;
+            let changes = number(&pair[1]).map_err(|_| "Invalid save parameters")?;
+            self.save.push(SaveRule { seconds, changes });
+        }
+        Ok(())
+    }
+}
+
+// Split a line into its arguments, which may be double quoted. Comments
+// start with #.
+fn split_line(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '#' && args.is_empty() {
+            break;
+        }
+        let mut arg = String::new();
+        if c == '"' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => arg.extend(chars.next()),
+                    c => arg.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+// quote an argument of the command line for `split_line`
+fn quote(arg: &str) -> String {
+    match arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"') {
+        true => format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => arg.to_string(),
+    }
+}
+
+fn yes_no(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+// a number of bytes, with an optional unit: 1k is 1000 bytes, 1kb 1024
+fn memory<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    let lower = value.to_lowercase();
+    let units: [(&str, u64); 7] = [
+        ("kb", 1 << 10),
+        ("mb", 1 << 20),
+        ("gb", 1 << 30),
+        ("k", 1000),
+        ("m", 1000 * 1000),
+        ("g", 1000 * 1000 * 1000),
+        ("b", 1),
+    ];
+    let (digits, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| Some((lower.strip_suffix(suffix)?, *unit)))
+        .unwrap_or((&lower, 1));
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "# a comment\n\
+             port 6380\n\
+             appendonly yes\n\
+             appendfsync always\n\
+             auto-aof-rewrite-min-size 1mb\n\
+             save 900 1\n\
+             save 60 10000 10 100000\n\
+             dir \"/tmp/with space\"\n\
+             requirepass \"\"\n\
+             client-output-buffer-limit normal 0 0 0 replica 1gb 0 0\n\
+             CLUSTER-ENABLED yes\n",
+        )
+        .unwrap();
+        assert_eq!(config.port, 6380);
+        assert!(config.appendonly);
+        assert_eq!(config.appendfsync, AppendFsync::Always);
+        assert_eq!(config.auto_aof_rewrite_min_size, 1024 * 1024);
+        let rules: Vec<_> = config.save.iter().map(|r| (r.seconds, r.changes)).collect();
+        assert_eq!(rules, [(900, 1), (60, 10000), (10, 100000)]);
+        assert_eq!(config.dir, PathBuf::from("/tmp/with space"));
+        assert_eq!(config.requirepass, None);
+        assert!(config.cluster_enabled);
+        let limit = config.client_output_buffer_limit_replica;
+        assert_eq!((limit.hard, limit.soft), (1 << 30, 0));
+        // the rest keeps the defaults
+        assert_eq!(config.dbfilename, "dump.srdb");
+
+        assert!(Config::parse("save \"\"").unwrap().save.is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| Config::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error("port 1\nappendonly maybe"),
+            "line 2 `appendonly maybe`: argument must be 'yes' or 'no'"
+        );
+        assert_eq!(
+            error("nosuchoption 1"),
+            "line 1 `nosuchoption 1`: Bad directive or wrong number of arguments"
+        );
+        assert_eq!(
+            error("port"),
+            "line 1 `port`: Bad directive or wrong number of arguments"
+        );
+        assert_eq!(
+            error("save 900"),
+            "line 1 `save 900`: Invalid save parameters"
+        );
+        assert_eq!(
+            error("repl-backlog-size 1xb"),
+            "line 1 `repl-backlog-size 1xb`: argument must be a memory value"
+        );
+        assert_eq!(
+            error("client-output-buffer-limit master 0 0 0"),
+            "line 1 `client-output-buffer-limit master 0 0 0`: \
+             Invalid client class specified in buffer limit configuration."
+        );
+    }
+
+    #[test]
+    fn test_from_args() -> Result<(), ConfigError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("redis.conf");
+        fs::write(&path, "port 7000\nappendonly no\n")?;
+        let path = path.to_string_lossy().to_string();
+        let config = Config::from_args(args(&[&path, "--appendonly", "yes", "--save", ""]))?;
+        assert_eq!(config.port, 7000);
+        assert!(config.appendonly);
+        assert!(config.save.is_empty());
+
+        let config = Config::from_args(args(&["--dir", "a b", "--save", "60", "1"]))?;
+        assert_eq!(config.dir, PathBuf::from("a b"));
+        assert_eq!(
+            config.save,
+            [SaveRule {
+                seconds: 60,
+                changes: 1
+            }]
+        );
+        assert!(Config::from_args(args(&["missing.conf"])).is_err());
+        Ok(())
+    }
+}
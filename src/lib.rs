@@ -1,11 +1,31 @@
 extern crate core;
+// the code `#[derive(RedisCommand)]` generates refers to the crate by name
+extern crate self as simple_redis;
 
+mod aof;
 mod backend;
+mod cluster;
 mod cmd;
+mod config;
 mod network;
+mod rdb;
+mod replication;
 mod resp;
+mod script;
+mod snapshot;
 
+pub use aof::{
+    aof_parts, check_rdb, load_aof, run_aof_cron, scan_aof, AofError, AofPart, AofScan, AofTail,
+};
 pub use backend::*;
+pub use cluster::{key_slot, run_cluster_bus, CLUSTER_SLOTS};
 pub use cmd::*;
+pub use config::*;
 pub use network::*;
 pub use resp::*;
+pub use snapshot::{load_snapshot, run_save_rules, SnapshotError};
+// the derives share the names of the traits they implement
+pub use macro_definitions::{RespDecode, RespEncode};
+
+#[doc(hidden)]
+pub use bytes;
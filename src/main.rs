@@ -1,5 +1,8 @@
 use anyhow::Result;
-use simple_redis::{stream_handler, Backend};
+use simple_redis::{
+    load_aof, load_snapshot, run_aof_cron, run_cluster_bus, run_expire_cycle, run_save_rules,
+    stream_handler, Backend, Config,
+};
 use tokio::net::TcpListener;
 use tracing::{info, warn, Level};
 
@@ -9,10 +12,32 @@ async fn main() -> Result<()> {
         .with_max_level(Level::TRACE) // 设置日志级别
         .init();
 
-    let addr = "0.0.0.0:6379";
+    // simple-redis [config-file] [--name value ...]
+    let config = Config::from_args(std::env::args().skip(1))?;
+    let addr = format!("0.0.0.0:{}", config.port);
     info!("Simple-Redis-Server is listening on {}", addr);
     let listener = TcpListener::bind(addr).await?;
-    let backend = Backend::new();
+    let appendonly = config.appendonly;
+    // nodes of a cluster talk to each other on a port of their own
+    let bus_listener = match config.cluster_enabled {
+        true => Some(TcpListener::bind(format!("0.0.0.0:{}", config.cluster_bus_port())).await?),
+        false => None,
+    };
+    let backend = Backend::with_config(config);
+    // the AOF is more complete than the snapshot when both exist
+    if appendonly {
+        if load_aof(&backend)? {
+            info!("DB loaded from append only file");
+        }
+    } else if load_snapshot(&backend)? {
+        info!("DB loaded from disk");
+    }
+    tokio::spawn(run_save_rules(backend.clone()));
+    tokio::spawn(run_aof_cron(backend.clone()));
+    tokio::spawn(run_expire_cycle(backend.clone()));
+    if let Some(bus_listener) = bus_listener {
+        tokio::spawn(run_cluster_bus(bus_listener, backend.clone()));
+    }
     loop {
         let (stream, raddr) = listener.accept().await?;
         info!("Accepted connection from: {}", raddr);
@@ -1,14 +1,31 @@
+use crate::aof::{logged_frame, must_append, run_blocking};
+use crate::backend::WatchedKey;
+use crate::replication::{block_until, serve_replica, LinkStatus, Role};
+use crate::script::Caller;
 use crate::{
-    Backend, Command, CommandExecutor, RespDecode, RespEncode, RespError, RespFrame, SimpleError,
+    Auth, Backend, BulkString, Command, CommandExecutor, CommandFlag, Config, Hello, InlineDecoder,
+    RespArray, RespDecoder, RespEncode, RespError, RespFrame, RespMap, RespNull, SimpleError,
+    RESP_OK, RESP_QUEUED,
 };
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use bytes::Bytes;
 use futures::{SinkExt, StreamExt};
+use std::time::Duration;
 use tokio::net::TcpStream;
+use tokio::sync::RwLockReadGuard;
 use tokio_util::codec::{Decoder, Encoder, Framed};
 use tracing::info;
 
+// how often a blocked command checks whether a script became busy
+const BUSY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug)]
-struct RespCodec {}
+struct RespCodec {
+    decoder: RespDecoder,
+    inline: InlineDecoder,
+    // max bytes buffered while a request is incomplete
+    query_buffer_limit: usize,
+}
 
 #[derive(Debug)]
 struct RedisRequest {
@@ -21,8 +38,34 @@ struct RedisResponse {
     frame: RespFrame,
 }
 
+// Session keeps the per-connection state.
+#[derive(Debug)]
+struct Session {
+    id: u64,
+    // protocol version negotiated by HELLO, replies are downgraded for RESP2
+    protocol: i64,
+    name: Option<String>,
+    authenticated: bool,
+    // commands queued after MULTI with the frames they were parsed from,
+    // None when not in a transaction
+    queued: Option<Vec<(Command, RespFrame)>>,
+    // set when a command failed to queue, EXEC will be aborted
+    aborted: bool,
+    // the keys of WATCH, unwatched once dropped
+    watched: Vec<WatchedKey>,
+    // the port a replica listens on, sent with REPLCONF listening-port
+    replica_port: u16,
+    // set by PSYNC, the connection is then handed over to serve the replica
+    psync: Option<(String, i64)>,
+    // the replication offset after the last command, WAIT waits for it
+    woff: u64,
+    // set by ASKING, the next command may access a slot being imported
+    asking: bool,
+}
+
 pub async fn stream_handler(stream: TcpStream, backend: Backend) -> Result<()> {
-    let mut framed = Framed::new(stream, RespCodec::new());
+    let mut framed = Framed::new(stream, RespCodec::new(&backend.config));
+    let mut session = Session::new(&backend);
     loop {
         match framed.next().await {
             Some(Ok(frame)) => {
@@ -30,44 +73,431 @@ pub async fn stream_handler(stream: TcpStream, backend: Backend) -> Result<()> {
                     frame,
                     backend: backend.clone(),
                 };
-                let response = request_handler(request).await?;
+                let response = request_handler(request, &mut session).await?;
+                if let Some((replid, offset)) = session.psync.take() {
+                    let parts = framed.into_parts();
+                    let port = session.replica_port;
+                    return serve_replica(
+                        parts.io,
+                        parts.read_buf,
+                        &backend,
+                        &replid,
+                        offset,
+                        port,
+                    )
+                    .await;
+                }
                 info!("Sending response: {:?}", response.frame);
                 framed.send(response.frame).await?;
             }
-            Some(Err(e)) => return Err(e),
+            Some(Err(e)) => {
+                // reply before closing the connection when the client broke the protocol
+                if let Some(e) = e.downcast_ref::<RespError>() {
+                    let msg = match e {
+                        RespError::RespProtocolError(_) => format!("ERR {}", e),
+                        _ => format!("ERR Protocol error: {}", e),
+                    };
+                    framed.send(SimpleError::new(msg).into()).await?;
+                }
+                return Err(e);
+            }
             None => return Ok(()),
         }
     }
 }
 
-async fn request_handler(request: RedisRequest) -> Result<RedisResponse> {
+// the keys of a request, which a cluster node has to serve
+fn request_keys(cmd: &Command, frame: &RespFrame) -> Vec<Bytes> {
+    let RespFrame::Array(RespArray(Some(args))) = frame else {
+        return Vec::new();
+    };
+    let positions = cmd.spec().key_positions(args).unwrap_or_default();
+    let keys = positions.into_iter().filter_map(|i| args[i].as_bytes());
+    keys.map(Bytes::copy_from_slice).collect()
+}
+
+// whether this node serves the keys of the request, or the redirection
+fn check_cluster(
+    backend: &Backend,
+    cmd: &Command,
+    frame: &RespFrame,
+    asking: bool,
+) -> Result<(), RespFrame> {
+    match &backend.cluster {
+        Some(cluster) => {
+            let migrate = matches!(cmd, Command::Migrate(_));
+            cluster.check_keys(backend, &request_keys(cmd, frame), asking, migrate)
+        }
+        None => Ok(()),
+    }
+}
+
+async fn request_handler(request: RedisRequest, session: &mut Session) -> Result<RedisResponse> {
     let (frame, backend) = (request.frame, request.backend);
-    let cmd = match Command::try_from(frame) {
+    // the frame is kept to be appended to the AOF
+    let cmd = match Command::try_from(frame.clone()) {
         Ok(c) => c,
         Err(e) => {
-            return Ok(RedisResponse {
-                frame: SimpleError::new(format!("Command Err: {}", e)).into(),
-            })
+            session.mark_aborted();
+            return Ok(RedisResponse { frame: e.into() });
         }
     };
     info!("Executing command: {:?}", cmd);
-    let frame = cmd.execute(&backend);
+    let frame = if session.authenticated || cmd.spec().has_flag(CommandFlag::NoAuth) {
+        session.execute(cmd, frame, &backend).await
+    } else {
+        SimpleError::new("NOAUTH Authentication required.").into()
+    };
+    let frame = match session.protocol {
+        2 => frame.into_resp2(),
+        _ => frame,
+    };
     Ok(RedisResponse { frame })
 }
 
+impl Session {
+    fn new(backend: &Backend) -> Self {
+        Session {
+            id: backend.next_client_id(),
+            protocol: 2,
+            name: None,
+            authenticated: backend.config.requirepass.is_none(),
+            queued: None,
+            aborted: false,
+            watched: Vec::new(),
+            replica_port: 0,
+            psync: None,
+            woff: 0,
+            asking: false,
+        }
+    }
+
+    async fn execute(&mut self, cmd: Command, frame: RespFrame, backend: &Backend) -> RespFrame {
+        if cmd.is_write() && backend.repl.deny_writes(backend.config.replica_read_only) {
+            self.mark_aborted();
+            return SimpleError::new("READONLY You can't write against a read only replica.")
+                .into();
+        }
+        // writes wait for the AOF to be writable again
+        if let Some(misconf) = cmd.is_write().then(|| backend.aof.misconf()).flatten() {
+            self.mark_aborted();
+            return misconf;
+        }
+        let asking = std::mem::take(&mut self.asking);
+        if let Err(redirect) = check_cluster(backend, &cmd, &frame, asking) {
+            self.mark_aborted();
+            return redirect;
+        }
+        match (cmd, self.queued.as_mut()) {
+            (Command::Hello(hello), None) => self.hello(hello, backend),
+            (Command::Auth(auth), None) => self.auth(auth, backend),
+            (Command::Psync(psync), None) => {
+                if let Role::Replica(link) = backend.repl.role() {
+                    if link.status != LinkStatus::Connected {
+                        return SimpleError::new(
+                            "NOMASTERLINK Can't SYNC while not connected with my master",
+                        )
+                        .into();
+                    }
+                }
+                // the reply is the start of the sync, sent by serve_replica
+                self.psync = Some((psync.replid, psync.offset));
+                RespNull::new().into()
+            }
+            (Command::Wait(wait), None) => match wait.timeout(backend) {
+                Ok(timeout) => {
+                    let woff = self.woff;
+                    block_until(backend, timeout, || wait.acked(backend, woff)).await
+                }
+                Err(e) => e,
+            },
+            (Command::WaitAof(wait), None) => match wait.timeout(backend) {
+                Ok(timeout) => {
+                    let woff = self.woff;
+                    block_until(backend, timeout, || wait.acked(backend, woff)).await
+                }
+                Err(e) => e,
+            },
+            (Command::ReplConf(conf), None) => {
+                if let Some(port) = conf.listening_port() {
+                    self.replica_port = port;
+                }
+                conf.execute(backend)
+            }
+            (Command::Asking(_), _) if backend.cluster.is_some() => {
+                self.asking = true;
+                RESP_OK.clone()
+            }
+            (Command::Migrate(migrate), None) => {
+                crate::cluster::migrate(backend, &migrate, &mut self.woff).await
+            }
+            (Command::Multi(_), None) => {
+                self.queued = Some(Vec::new());
+                RESP_OK.clone()
+            }
+            (Command::Exec(_), Some(_)) => self.exec(backend, asking).await,
+            (Command::Discard(_), Some(_)) => {
+                self.reset();
+                RESP_OK.clone()
+            }
+            (Command::Watch(watch), None) => {
+                for key in watch.keys {
+                    self.watched.push(backend.watch(key));
+                }
+                RESP_OK.clone()
+            }
+            (Command::Unwatch(_), None) => {
+                self.watched.clear();
+                RESP_OK.clone()
+            }
+            // nested MULTI and WATCH inside MULTI are rejected without aborting
+            (cmd @ (Command::Multi(_) | Command::Watch(_)), Some(_)) => cmd.execute(backend),
+            (cmd, Some(queued)) => {
+                queued.push((cmd, frame));
+                RESP_QUEUED.clone()
+            }
+            // SCRIPT KILL must not wait for the running script
+            (cmd @ Command::ScriptKill(_), None) => cmd.execute(backend),
+            // scripts run atomically with respect to other connections
+            (cmd @ (Command::Eval(_) | Command::EvalSha(_) | Command::FCall(_)), None) => {
+                let _guard = backend.gate.write().await;
+                let (res, logged) = run_logged(cmd, frame, backend, Caller::Client);
+                let logged = logged.into_iter().collect();
+                let (error, offset) =
+                    run_blocking(backend, |b| b.aof.append(Some(&b.repl), logged)).await;
+                self.fed(offset);
+                error.unwrap_or(res)
+            }
+            // snapshots see the dataset at a single point in time
+            (cmd @ (Command::Save(_) | Command::BgSave(_) | Command::BgRewriteAof(_)), None) => {
+                let _guard = backend.gate.write().await;
+                cmd.execute(backend)
+            }
+            (cmd, None) => {
+                let _guard = match read_gate(backend).await {
+                    Ok(guard) => guard,
+                    Err(busy) => return busy,
+                };
+                // a MIGRATE may have moved the keys while waiting for the gate
+                if let Err(redirect) = check_cluster(backend, &cmd, &frame, asking) {
+                    return redirect;
+                }
+                if cmd.spec().has_flag(CommandFlag::Write) {
+                    let (res, offset) = run_blocking(backend, move |b| {
+                        b.aof.execute(Some(&b.repl), || {
+                            let res = cmd.execute(b);
+                            (res, logged_frame(&cmd, frame, b))
+                        })
+                    })
+                    .await;
+                    self.fed(offset);
+                    res
+                } else {
+                    cmd.execute(backend)
+                }
+            }
+        }
+    }
+
+    // A command of this client was fed to the replicas, WAIT waits for them
+    // to get up to there.
+    fn fed(&mut self, offset: Option<u64>) {
+        if let Some(offset) = offset {
+            self.woff = offset;
+        }
+    }
+
+    async fn exec(&mut self, backend: &Backend, asking: bool) -> RespFrame {
+        let aborted = self.aborted;
+        let watched = std::mem::take(&mut self.watched);
+        let queued = self.queued.take().unwrap_or_default();
+        self.reset();
+        if aborted {
+            return SimpleError::new("EXECABORT Transaction discarded because of previous errors.")
+                .into();
+        }
+
+        let _guard = backend.gate.write().await;
+        if watched.iter().any(WatchedKey::changed) {
+            return RespArray::new_null().into();
+        }
+        // each command was checked when queued, but together they must
+        // hash to a single slot, which may have moved since
+        if let Some(cluster) = &backend.cluster {
+            let keys: Vec<_> = queued
+                .iter()
+                .flat_map(|(cmd, frame)| request_keys(cmd, frame))
+                .collect();
+            if let Err(e) = cluster.check_keys(backend, &keys, asking, false) {
+                return e;
+            }
+        }
+        let mut logged = Vec::new();
+        let mut res = Vec::with_capacity(queued.len());
+        for (cmd, frame) in queued {
+            let (frame, log) = run_logged(cmd, frame, backend, Caller::Client);
+            res.push(frame);
+            logged.extend(log);
+        }
+        let (error, offset) = run_blocking(backend, |b| b.aof.append(Some(&b.repl), logged)).await;
+        self.fed(offset);
+        error.unwrap_or_else(|| RespArray::with_vec(res).into())
+    }
+
+    // Authenticate as the user, only the default user exists.
+    fn check_password(
+        &mut self,
+        backend: &Backend,
+        user: &str,
+        pass: &str,
+    ) -> Result<(), RespFrame> {
+        let valid = user == "default"
+            && match &backend.config.requirepass {
+                Some(p) => *p == pass,
+                None => true,
+            };
+        if !valid {
+            return Err(SimpleError::new(
+                "WRONGPASS invalid username-password pair or user is disabled.",
+            )
+            .into());
+        }
+        self.authenticated = true;
+        Ok(())
+    }
+
+    fn auth(&mut self, auth: Auth, backend: &Backend) -> RespFrame {
+        let user =
+            match auth.username {
+                Some(user) => user,
+                None if backend.config.requirepass.is_none() => return SimpleError::new(
+                    "ERR AUTH <password> called without any password configured for the default \
+                     user. Are you sure your configuration is correct?",
+                )
+                .into(),
+                None => "default".to_string(),
+            };
+        match self.check_password(backend, &user, &auth.password) {
+            Ok(()) => RESP_OK.clone(),
+            Err(e) => e,
+        }
+    }
+
+    fn hello(&mut self, hello: Hello, backend: &Backend) -> RespFrame {
+        if let Some(protover) = hello.protover {
+            if protover != 2 && protover != 3 {
+                return SimpleError::new("NOPROTO unsupported protocol version").into();
+            }
+        }
+        match hello.auth {
+            Some((user, pass)) => {
+                if let Err(e) = self.check_password(backend, &user, &pass) {
+                    return e;
+                }
+            }
+            None if !self.authenticated => {
+                return SimpleError::new(
+                    "NOAUTH HELLO must be called with the client already authenticated, \
+                     otherwise the HELLO <proto> AUTH <user> <pass> option can be used to \
+                     authenticate the client and select the RESP protocol version at the same time",
+                )
+                .into();
+            }
+            None => {}
+        }
+        if let Some(name) = hello.setname {
+            if name.bytes().any(|c| !(b'!'..=b'~').contains(&c)) {
+                return SimpleError::new(
+                    "ERR Client names cannot contain spaces, newlines or special characters.",
+                )
+                .into();
+            }
+            self.name = Some(name);
+        }
+        if let Some(protover) = hello.protover {
+            self.protocol = protover;
+        }
+
+        let mut m = RespMap::new();
+        m.insert("server", BulkString::new("redis"));
+        m.insert("version", BulkString::new(env!("CARGO_PKG_VERSION")));
+        m.insert("proto", RespFrame::Integer(self.protocol));
+        m.insert("id", RespFrame::Integer(self.id as i64));
+        let mode = match backend.cluster {
+            Some(_) => "cluster",
+            None => "standalone",
+        };
+        m.insert("mode", BulkString::new(mode));
+        let role = if backend.repl.is_replica() {
+            "replica"
+        } else {
+            "master"
+        };
+        m.insert("role", BulkString::new(role));
+        m.insert("modules", RespArray::new());
+        m.into()
+    }
+
+    fn mark_aborted(&mut self) {
+        if self.queued.is_some() {
+            self.aborted = true;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.queued = None;
+        self.aborted = false;
+        self.watched.clear();
+    }
+}
+
+// Run a command under the write side of the gate, returns its reply and
+// the frame to append to the AOF if it modified the dataset.
+pub(crate) fn run_logged(
+    cmd: Command,
+    frame: RespFrame,
+    backend: &Backend,
+    caller: Caller,
+) -> (RespFrame, Option<RespFrame>) {
+    let dirty = backend.save_state.dirty();
+    let res = cmd.execute_as(backend, caller);
+    let logged = must_append(&cmd, &res, backend.save_state.dirty() != dirty)
+        .then(|| logged_frame(&cmd, frame, backend));
+    (res, logged)
+}
+
+// Wait for the read side of the gate, or give up with a BUSY error
+// when a script has been running for too long.
+async fn read_gate(backend: &Backend) -> Result<RwLockReadGuard<'_, ()>, RespFrame> {
+    loop {
+        if backend.script_state.is_busy() {
+            return Err(SimpleError::new(
+                "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.",
+            )
+            .into());
+        }
+        if let Ok(guard) = tokio::time::timeout(BUSY_CHECK_INTERVAL, backend.gate.read()).await {
+            return Ok(guard);
+        }
+    }
+}
+
 impl Encoder<RespFrame> for RespCodec {
     type Error = std::io::Error;
 
     fn encode(&mut self, item: RespFrame, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
-        let vec = item.encode();
-        dst.extend_from_slice(&vec);
+        item.encode_to(dst);
         Ok(())
     }
 }
 
 impl RespCodec {
-    pub fn new() -> Self {
-        RespCodec {}
+    pub fn new(config: &Config) -> Self {
+        RespCodec {
+            decoder: RespDecoder::with_limits(config.resp_limits()),
+            inline: InlineDecoder::default(),
+            query_buffer_limit: config.client_query_buffer_limit,
+        }
     }
 }
 
@@ -75,20 +505,453 @@ impl Decoder for RespCodec {
     type Item = RespFrame;
     type Error = anyhow::Error;
 
-    // make sure the bytes' cursor is at the ending after successfully decoding
-    // TODO
-    //  May be we don't need to read again when we get RespNotComplete.
+    // the decoder keeps its progress, so a partial frame is not parsed again on the next read.
+    // Like Redis, anything which doesn't start with a multibulk is an inline command.
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        //let mut src_clone = src.clone();
-        match RespFrame::decode(src) {
-            Ok(resp) => {
-                info!("Received: {}", resp);
-                Ok(Some(resp))
-            }
-            Err(e) => match e {
-                RespError::RespNotComplete => Ok(None),
-                _ => Err(anyhow!(format!("{:?}", e))),
-            },
+        loop {
+            let frame = match src.first() {
+                None => return Ok(None),
+                Some(b'*') => self.decoder.decode(src),
+                Some(_) => self.inline.decode(src).map(|v| v.map(RespFrame::from)),
+            };
+            match frame {
+                // skip the blank lines of inline clients
+                Ok(Some(RespFrame::Array(RespArray(Some(v))))) if v.is_empty() => continue,
+                Ok(Some(resp)) => return Ok(Some(resp)),
+                Ok(None) if src.len() > self.query_buffer_limit => {
+                    return Err(RespError::RespProtocolError(
+                        "query buffer limit exceeded".to_string(),
+                    )
+                    .into())
+                }
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{decode_inline, RespDecode, RespNull};
+    use bytes::BytesMut;
+
+    async fn handle(session: &mut Session, backend: &Backend, input: &str) -> RespFrame {
+        let mut bytes = BytesMut::from(input);
+        let request = RedisRequest {
+            frame: RespFrame::decode(&mut bytes).unwrap(),
+            backend: backend.clone(),
+        };
+        request_handler(request, session).await.unwrap().frame
+    }
+
+    #[tokio::test]
+    async fn test_multi_exec() {
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        let res = handle(&mut session, &backend, "*1\r\n$5\r\nmulti\r\n").await;
+        assert_eq!(res, RESP_OK.clone());
+        let res = handle(
+            &mut session,
+            &backend,
+            "*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue\r\n",
+        )
+        .await;
+        assert_eq!(res, RESP_QUEUED.clone());
+        let res = handle(&mut session, &backend, "*2\r\n$3\r\nget\r\n$3\r\nkey\r\n").await;
+        assert_eq!(res, RESP_QUEUED.clone());
+        assert_eq!(backend.get(b"key"), None);
+
+        let res = handle(&mut session, &backend, "*1\r\n$4\r\nexec\r\n").await;
+        assert_eq!(
+            res,
+            RespArray::with_vec(vec![RESP_OK.clone(), BulkString::new("value").into()]).into()
+        );
+        assert!(session.queued.is_none());
+    }
+
+    async fn inline(session: &mut Session, backend: &Backend, line: &str) -> RespFrame {
+        let mut data = BytesMut::from(format!("{}\r\n", line).as_str());
+        let request = RedisRequest {
+            frame: decode_inline(&mut data).unwrap().unwrap().into(),
+            backend: backend.clone(),
+        };
+        request_handler(request, session).await.unwrap().frame
+    }
+
+    #[tokio::test]
+    async fn test_watch_abort() {
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        handle(&mut session, &backend, "*2\r\n$5\r\nwatch\r\n$3\r\nkey\r\n").await;
+        handle(&mut session, &backend, "*1\r\n$5\r\nmulti\r\n").await;
+        handle(
+            &mut session,
+            &backend,
+            "*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$1\r\na\r\n",
+        )
+        .await;
+
+        // another connection modifies the watched key
+        backend.set(Bytes::from_static(b"key"), BulkString::new("b").into());
+
+        let res = handle(&mut session, &backend, "*1\r\n$4\r\nexec\r\n").await;
+        assert_eq!(res, RespArray::new_null().into());
+        assert_eq!(backend.get(b"key"), Some(BulkString::new("b").into()));
+        assert!(session.watched.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_exec_abort() {
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        handle(&mut session, &backend, "*1\r\n$5\r\nmulti\r\n").await;
+        let res = handle(&mut session, &backend, "*1\r\n$3\r\nget\r\n").await;
+        assert!(matches!(res, RespFrame::Error(_)));
+        handle(
+            &mut session,
+            &backend,
+            "*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$1\r\na\r\n",
+        )
+        .await;
+        let res = handle(&mut session, &backend, "*1\r\n$4\r\nexec\r\n").await;
+        assert_eq!(
+            res,
+            SimpleError::new("EXECABORT Transaction discarded because of previous errors.").into()
+        );
+        assert_eq!(backend.get(b"key"), None);
+    }
+
+    #[tokio::test]
+    async fn test_exec_cross_slot() {
+        let backend = Backend::with_config(crate::Config {
+            cluster_enabled: true,
+            port: 7000,
+            ..Default::default()
+        });
+        let cluster = backend.cluster.as_ref().unwrap();
+        cluster
+            .add_slots(&[crate::key_slot(b"a"), crate::key_slot(b"b")])
+            .unwrap();
+        let mut session = Session::new(&backend);
+        handle(&mut session, &backend, "*1\r\n$5\r\nmulti\r\n").await;
+        for key in ["a", "b"] {
+            let set = format!("*3\r\n$3\r\nset\r\n$1\r\n{}\r\n$1\r\nv\r\n", key);
+            let res = handle(&mut session, &backend, &set).await;
+            assert_eq!(res, RESP_QUEUED.clone());
+        }
+        let res = handle(&mut session, &backend, "*1\r\n$4\r\nexec\r\n").await;
+        assert_eq!(
+            res,
+            SimpleError::new("CROSSSLOT Keys in request don't hash to the same slot").into()
+        );
+        assert_eq!(backend.get(b"a"), None);
+        assert!(session.queued.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_discard() {
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        let res = handle(&mut session, &backend, "*1\r\n$7\r\ndiscard\r\n").await;
+        assert_eq!(res, SimpleError::new("ERR DISCARD without MULTI").into());
+        handle(&mut session, &backend, "*1\r\n$5\r\nmulti\r\n").await;
+        let res = handle(&mut session, &backend, "*1\r\n$5\r\nmulti\r\n").await;
+        assert_eq!(
+            res,
+            SimpleError::new("ERR MULTI calls can not be nested").into()
+        );
+        let res = handle(&mut session, &backend, "*1\r\n$7\r\ndiscard\r\n").await;
+        assert_eq!(res, RESP_OK.clone());
+        assert!(session.queued.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hello() {
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        // RESP2 clients get the map as a flat array
+        let res = handle(&mut session, &backend, "*1\r\n$5\r\nhello\r\n").await;
+        match res {
+            RespFrame::Array(RespArray(Some(v))) => assert_eq!(v.len(), 14),
+            _ => panic!("HELLO should reply an array in RESP2"),
+        }
+
+        let res = handle(&mut session, &backend, "*2\r\n$5\r\nhello\r\n$1\r\n4\r\n").await;
+        assert_eq!(
+            res,
+            SimpleError::new("NOPROTO unsupported protocol version").into()
+        );
+
+        let res = handle(
+            &mut session,
+            &backend,
+            "*4\r\n$5\r\nhello\r\n$1\r\n3\r\n$7\r\nsetname\r\n$4\r\nconn\r\n",
+        )
+        .await;
+        match res {
+            RespFrame::Map(m) => assert_eq!(m.get("proto"), Some(&RespFrame::Integer(3))),
+            _ => panic!("HELLO 3 should reply a map"),
+        }
+        assert_eq!(session.protocol, 3);
+        assert_eq!(session.name.as_deref(), Some("conn"));
+
+        // RESP3 clients receive nulls as is
+        let res = handle(&mut session, &backend, "*2\r\n$3\r\nget\r\n$3\r\nkey\r\n").await;
+        assert_eq!(res, RespNull::new().into());
+    }
+
+    #[tokio::test]
+    async fn test_hello_auth() {
+        let backend = Backend::with_config(Config {
+            requirepass: Some("secret".to_string()),
+            ..Default::default()
+        });
+        let mut session = Session::new(&backend);
+        let res = handle(&mut session, &backend, "*2\r\n$3\r\nget\r\n$3\r\nkey\r\n").await;
+        assert_eq!(
+            res,
+            SimpleError::new("NOAUTH Authentication required.").into()
+        );
+
+        let res = handle(
+            &mut session,
+            &backend,
+            "*5\r\n$5\r\nhello\r\n$1\r\n2\r\n$4\r\nauth\r\n$7\r\ndefault\r\n$5\r\nwrong\r\n",
+        )
+        .await;
+        assert_eq!(
+            res,
+            SimpleError::new("WRONGPASS invalid username-password pair or user is disabled.")
+                .into()
+        );
+
+        handle(
+            &mut session,
+            &backend,
+            "*5\r\n$5\r\nhello\r\n$1\r\n2\r\n$4\r\nauth\r\n$7\r\ndefault\r\n$6\r\nsecret\r\n",
+        )
+        .await;
+        let res = handle(&mut session, &backend, "*2\r\n$3\r\nget\r\n$3\r\nkey\r\n").await;
+        assert_eq!(res, BulkString::new_null().into());
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let backend = Backend::with_config(Config {
+            requirepass: Some("secret".to_string()),
+            ..Default::default()
+        });
+        let mut session = Session::new(&backend);
+        let wrongpass: RespFrame =
+            SimpleError::new("WRONGPASS invalid username-password pair or user is disabled.")
+                .into();
+        assert_eq!(
+            inline(&mut session, &backend, "AUTH wrong").await,
+            wrongpass
+        );
+        assert_eq!(
+            inline(&mut session, &backend, "AUTH other secret").await,
+            wrongpass
+        );
+        assert_eq!(
+            inline(&mut session, &backend, "GET key").await,
+            SimpleError::new("NOAUTH Authentication required.").into()
+        );
+        assert_eq!(
+            inline(&mut session, &backend, "AUTH secret").await,
+            RESP_OK.clone()
+        );
+        assert_eq!(
+            inline(&mut session, &backend, "GET key").await,
+            BulkString::new_null().into()
+        );
+        let mut session = Session::new(&backend);
+        assert_eq!(
+            inline(&mut session, &backend, "AUTH default secret").await,
+            RESP_OK.clone()
+        );
+
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        assert!(matches!(
+            inline(&mut session, &backend, "AUTH secret").await,
+            RespFrame::Error(e) if e.0.starts_with("ERR AUTH <password> called without")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_aof_logging() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = Config {
+            dir: dir.path().to_path_buf(),
+            appendonly: true,
+            ..Default::default()
+        };
+        let backend = Backend::with_config(config.clone());
+        crate::load_aof(&backend)?;
+        let mut session = Session::new(&backend);
+        inline(&mut session, &backend, "SET k v").await;
+        inline(&mut session, &backend, "GET k").await;
+        // a failed write is not logged
+        inline(&mut session, &backend, "HSET k f v").await;
+        inline(&mut session, &backend, "MULTI").await;
+        inline(&mut session, &backend, "SET a 1").await;
+        inline(&mut session, &backend, "GET a").await;
+        inline(&mut session, &backend, "SADD s m").await;
+        inline(&mut session, &backend, "EXEC").await;
+        let sha = inline(
+            &mut session,
+            &backend,
+            "SCRIPT LOAD \"return redis.call('SET', 'script', 'x')\"",
+        )
+        .await;
+        let sha = String::from_utf8(sha.as_bytes().unwrap().to_vec())?;
+        inline(&mut session, &backend, &format!("EVALSHA {} 0", sha)).await;
+        inline(&mut session, &backend, "EVAL \"return 1\" 0").await;
+
+        let data = std::fs::read(config.aof_dir().join("appendonly.aof.1.incr.aof"))?;
+        let names: Vec<String> = crate::scan_aof(&data)
+            .commands
+            .iter()
+            .map(|frame| match frame {
+                RespFrame::Array(RespArray(Some(args))) => {
+                    String::from_utf8_lossy(args[0].as_bytes().unwrap()).to_uppercase()
+                }
+                _ => panic!("not a command"),
+            })
+            .collect();
+        assert_eq!(names, ["SET", "MULTI", "SET", "SADD", "EXEC", "EVAL"]);
+
+        let loaded = Backend::with_config(config);
+        crate::load_aof(&loaded)?;
+        assert_eq!(loaded.get(b"k"), Some(BulkString::new("v").into()));
+        assert_eq!(loaded.get(b"a"), Some(BulkString::new("1").into()));
+        assert!(loaded.sismember(b"s", b"m"));
+        assert_eq!(loaded.get(b"script"), Some(BulkString::new("x").into()));
+        Ok(())
+    }
+
+    // the logs written while a test runs
+    #[derive(Clone, Default)]
+    struct Logs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
         }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logs_redact_passwords() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let backend = Backend::new();
+        let mut session = Session::new(&backend);
+        let mut codec = RespCodec::new(&backend.config);
+        let mut input = BytesMut::from(
+            "auth secret\r\n\
+             *3\r\n$4\r\nauth\r\n$7\r\ndefault\r\n$6\r\nsecret\r\n\
+             hello 3 auth default secret\r\n\
+             migrate host 1 k 0 0 auth2 user secret\r\n",
+        );
+        while let Some(frame) = codec.decode(&mut input).unwrap() {
+            let request = RedisRequest {
+                frame,
+                backend: backend.clone(),
+            };
+            request_handler(request, &mut session).await.unwrap();
+        }
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("Migrate"), "{}", logs);
+        assert!(!logs.contains("secret"), "{}", logs);
+    }
+
+    #[test]
+    fn test_codec_inline() {
+        let mut codec = RespCodec::new(&Config::default());
+        let mut buf = BytesMut::from("\r\nSET foo \"bar baz\"\r\n*2\r\n$3\r\nget\r\n$3\r\nfoo\r\n");
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            frame,
+            RespArray::with_vec(vec![b"SET".into(), b"foo".into(), b"bar baz".into()]).into()
+        );
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            frame,
+            RespArray::with_vec(vec![b"get".into(), b"foo".into()]).into()
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        let mut buf = BytesMut::from("SET foo \"bar\r\n");
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_codec_limits() {
+        let config = Config {
+            proto_max_bulk_len: 8,
+            client_query_buffer_limit: 16,
+            ..Default::default()
+        };
+        let mut codec = RespCodec::new(&config);
+        let mut buf = BytesMut::from("*2\r\n$3\r\nget\r\n$9\r\n");
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RespError>(),
+            Some(&RespError::RespProtocolError(
+                "invalid bulk length".to_string()
+            ))
+        );
+
+        let mut codec = RespCodec::new(&config);
+        let mut buf = BytesMut::from("*2\r\n$3\r\nget\r\n$8\r\nabcd");
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RespError>(),
+            Some(&RespError::RespProtocolError(
+                "query buffer limit exceeded".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_protocol_error_closes_connection() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = stream_handler(stream, Backend::new()).await;
+        });
+
+        let client = TcpStream::connect(addr).await?;
+        client.writable().await?;
+        client.try_write(b"*9999999999\r\n")?;
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 128];
+        loop {
+            client.readable().await?;
+            match client.try_read(&mut buf) {
+                // the server closed the connection
+                Ok(0) => break,
+                Ok(n) => reply.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        assert_eq!(reply, b"-ERR Protocol error: invalid multibulk length\r\n");
+        Ok(())
     }
 }
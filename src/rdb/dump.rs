@@ -0,0 +1,193 @@
+use super::file::{frame_string, read_value, write_value, Value};
+use super::*;
+use crate::{Backend, BulkString};
+use bytes::Bytes;
+
+// DUMP and RESTORE payloads: the type and the value as in an RDB file, then
+// the RDB version and a CRC64, the way Redis serializes them. Redis writes
+// small values in compact encodings, which are read as well.
+
+pub(crate) fn dump(backend: &Backend, key: &[u8]) -> Option<Vec<u8>> {
+    backend.expire_if_needed(key);
+    let bytes = |v: &Bytes| v.to_vec();
+    let value = if let Some(v) = backend.map.get(key) {
+        Value::String(frame_string(&v))
+    } else if let Some(fields) = backend.hmap.get(key) {
+        let fields = fields
+            .iter()
+            .map(|f| (bytes(f.key()), frame_string(f.value())));
+        Value::Hash(fields.collect())
+    } else if let Some(members) = backend.set.get(key) {
+        Value::Set(members.iter().map(|m| bytes(&m)).collect())
+    } else if let Some(items) = backend.list.get(key) {
+        Value::List(items.iter().map(bytes).collect())
+    } else if let Some(members) = backend.zset.get(key) {
+        Value::ZSet(
+            members
+                .iter()
+                .map(|m| (bytes(m.key()), *m.value()))
+                .collect(),
+        )
+    } else {
+        return None;
+    };
+    let mut buf = Vec::new();
+    write_value(&mut buf, &value);
+    Some(seal_payload(buf))
+}
+
+// Check a payload and read its value, keys holding nothing don't exist so
+// empty values are refused.
+pub(crate) fn read_dump(payload: &[u8]) -> Result<Value, RdbError> {
+    let mut data = open_payload(payload)?;
+    let kind = read_u8(&mut data)?;
+    let value = read_value(&mut data, kind)?;
+    if !data.is_empty() {
+        return Err(RdbError::Invalid(
+            "trailing bytes after the value".to_string(),
+        ));
+    }
+    let empty = match &value {
+        Value::String(_) => false,
+        Value::List(v) | Value::Set(v) => v.is_empty(),
+        Value::ZSet(v) => v.is_empty(),
+        Value::Hash(v) => v.is_empty(),
+    };
+    match empty {
+        true => Err(RdbError::Invalid("empty value".to_string())),
+        false => Ok(value),
+    }
+}
+
+// Store the value, replacing whatever the key held.
+pub(crate) fn restore(backend: &Backend, key: Bytes, value: Value) {
+    backend.remove(&key);
+    backend.touch(&key);
+    let bytes = |v: Vec<Vec<u8>>| v.into_iter().map(Bytes::from);
+    match value {
+        Value::String(v) => {
+            backend.map.insert(key, BulkString::new(v).into());
+        }
+        Value::List(items) => {
+            backend.list.insert(key, bytes(items).collect());
+        }
+        Value::Set(members) => {
+            backend.set.insert(key, bytes(members).collect());
+        }
+        Value::ZSet(members) => {
+            let members = members.into_iter().map(|(m, s)| (Bytes::from(m), s));
+            backend.zset.insert(key, members.collect());
+        }
+        Value::Hash(fields) => {
+            let fields = fields
+                .into_iter()
+                .map(|(f, v)| (Bytes::from(f), BulkString::new(v).into()));
+            backend.hmap.insert(key, fields.collect());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn payload(body: &[u8], version: u16) -> Vec<u8> {
+        let mut buf = body.to_vec();
+        buf.extend_from_slice(&version.to_le_bytes());
+        let crc = crc64(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_dump_round_trip() -> Result<(), RdbError> {
+        let backend = Backend::new();
+        backend.set(Bytes::from_static(b"s"), BulkString::new("hello").into());
+        backend.hset(
+            Bytes::from_static(b"h"),
+            Bytes::from_static(b"f"),
+            BulkString::new("v").into(),
+        );
+        backend.sadd(Bytes::from_static(b"set"), Bytes::from_static(b"m"));
+        backend.list.insert(
+            Bytes::from_static(b"l"),
+            [Bytes::from_static(b"a"), Bytes::from_static(b"b")].into(),
+        );
+        backend.zset.insert(
+            Bytes::from_static(b"z"),
+            [(Bytes::from_static(b"m"), 1.5)].into_iter().collect(),
+        );
+        assert_eq!(dump(&backend, b"missing"), None);
+
+        let restored = Backend::new();
+        for key in ["s", "h", "set", "l", "z"] {
+            let payload = dump(&backend, key.as_bytes()).unwrap();
+            restore(&restored, Bytes::from(key), read_dump(&payload)?);
+            assert_eq!(dump(&restored, key.as_bytes()), Some(payload));
+        }
+        assert_eq!(restored.get(b"s"), Some(BulkString::new("hello").into()));
+        assert_eq!(restored.hget(b"h", b"f"), Some(BulkString::new("v").into()));
+        assert!(restored.sismember(b"set", b"m"));
+        assert_eq!(restored.key_type(b"z"), Some(crate::KeyType::ZSet));
+
+        // restoring replaces a value of another type
+        let payload = dump(&backend, b"s").unwrap();
+        restore(&restored, Bytes::from_static(b"h"), read_dump(&payload)?);
+        assert_eq!(restored.key_type(b"h"), Some(crate::KeyType::String));
+        Ok(())
+    }
+
+    #[test]
+    fn test_redis_payloads() -> Result<(), RdbError> {
+        // the example of the DUMP documentation, SET mykey 10 on Redis 6
+        let int = b"\x00\xc0\n\n\x00n\x9fWE\x0e\xaec\xbb";
+        assert_eq!(read_dump(int)?, Value::String(b"10".to_vec()));
+        let string = payload(b"\x00\x05hello", 11);
+        assert_eq!(read_dump(&string)?, Value::String(b"hello".to_vec()));
+        // HSET h f v, as a listpack
+        let hash = payload(
+            b"\x10\x0d\x0d\x00\x00\x00\x02\x00\x81f\x02\x81v\x02\xff",
+            11,
+        );
+        assert_eq!(
+            read_dump(&hash)?,
+            Value::Hash(vec![(b"f".to_vec(), b"v".to_vec())])
+        );
+        // SADD s 1 2, as an intset
+        let set = payload(
+            b"\x0b\x0c\x02\x00\x00\x00\x02\x00\x00\x00\x01\x00\x02\x00",
+            11,
+        );
+        assert_eq!(
+            read_dump(&set)?,
+            Value::Set(vec![b"1".to_vec(), b"2".to_vec()])
+        );
+        // SADD s a, as a listpack (Redis 7.2)
+        let set = payload(b"\x14\x0a\x0a\x00\x00\x00\x01\x00\x81a\x02\xff", 11);
+        assert_eq!(read_dump(&set)?, Value::Set(vec![b"a".to_vec()]));
+
+        // our payloads use the plain encodings Redis reads as well
+        let backend = Backend::new();
+        backend.sadd(Bytes::from_static(b"s"), Bytes::from_static(b"a"));
+        let payload = dump(&backend, b"s").unwrap();
+        assert_eq!(&payload[..3], b"\x02\x01\x01");
+        assert_eq!(&payload[4..6], &RDB_VERSION.to_le_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_payloads() {
+        let newer = payload(b"\x00\x05hello", RDB_VERSION + 1);
+        assert_eq!(read_dump(&newer), Err(RdbError::BadPayload));
+        let mut broken = payload(b"\x00\x05hello", 11);
+        broken[2] = b'j';
+        assert_eq!(read_dump(&broken), Err(RdbError::BadPayload));
+
+        for body in [&b"\x00\x05hel"[..], b"\x00\x01ab", b"\x02\x00", b"\x0f\x00"] {
+            assert!(matches!(
+                read_dump(&payload(body, 11)),
+                Err(RdbError::Invalid(_) | RdbError::UnexpectedEof)
+            ));
+        }
+    }
+}
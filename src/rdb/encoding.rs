@@ -0,0 +1,237 @@
+use super::{read_bytes, read_u8, RdbError};
+
+// Decoders of the compact encodings Redis stores small collections with:
+// ziplist, listpack, intset and zipmap. Each one is saved as a single RDB
+// string, the entries come back in order with integers in their decimal form.
+
+const END: u8 = 0xff;
+
+fn corrupt(what: &str) -> RdbError {
+    RdbError::Invalid(format!("corrupt {}", what))
+}
+
+// read a little endian integer of n bytes, sign extended
+fn read_int(data: &mut &[u8], n: usize) -> Result<i64, RdbError> {
+    let mut buf = [0u8; 8];
+    buf[..n].copy_from_slice(read_bytes(data, n)?);
+    let shift = 64 - 8 * n as u32;
+    Ok((i64::from_le_bytes(buf) << shift) >> shift)
+}
+
+fn read_u32(data: &mut &[u8]) -> Result<u32, RdbError> {
+    Ok(u32::from_le_bytes(read_bytes(data, 4)?.try_into().unwrap()))
+}
+
+fn int_entry(v: i64) -> Vec<u8> {
+    v.to_string().into_bytes()
+}
+
+// <zlbytes u32><zltail u32><zllen u16><entry>...<0xff>
+// entry: <prevlen><encoding><data>
+pub(crate) fn read_ziplist(blob: &[u8]) -> Result<Vec<Vec<u8>>, RdbError> {
+    let mut data = blob.get(10..).ok_or_else(|| corrupt("ziplist"))?;
+    let mut entries = Vec::new();
+    loop {
+        // the length of the previous entry, only needed to walk backwards
+        match read_u8(&mut data)? {
+            END => return Ok(entries),
+            0xfe => {
+                read_bytes(&mut data, 4)?;
+            }
+            _ => {}
+        }
+        let enc = read_u8(&mut data)?;
+        let entry = match enc >> 6 {
+            0 => read_bytes(&mut data, (enc & 0x3f) as usize)?.to_vec(),
+            1 => {
+                let len = (((enc & 0x3f) as usize) << 8) | read_u8(&mut data)? as usize;
+                read_bytes(&mut data, len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(read_bytes(&mut data, 4)?.try_into().unwrap());
+                read_bytes(&mut data, len as usize)?.to_vec()
+            }
+            _ => int_entry(match enc {
+                0xc0 => read_int(&mut data, 2)?,
+                0xd0 => read_int(&mut data, 4)?,
+                0xe0 => read_int(&mut data, 8)?,
+                0xf0 => read_int(&mut data, 3)?,
+                0xfe => read_int(&mut data, 1)?,
+                // 4 bit immediate, 0001 to 1101 stand for 0 to 12
+                0xf1..=0xfd => (enc & 0x0f) as i64 - 1,
+                _ => return Err(corrupt("ziplist")),
+            }),
+        };
+        entries.push(entry);
+    }
+}
+
+// <total bytes u32><elements u16><entry>...<0xff>
+// entry: <encoding><data><backlen>, backlen is the size of the encoding and
+// the data, so that the listpack can be walked backwards
+pub(crate) fn read_listpack(blob: &[u8]) -> Result<Vec<Vec<u8>>, RdbError> {
+    let mut data = blob.get(6..).ok_or_else(|| corrupt("listpack"))?;
+    let mut entries = Vec::new();
+    loop {
+        let start = data;
+        let enc = read_u8(&mut data)?;
+        let entry = if enc == END {
+            return Ok(entries);
+        } else if enc & 0x80 == 0 {
+            // 7 bit unsigned integer
+            int_entry(enc as i64)
+        } else if enc & 0xc0 == 0x80 {
+            read_bytes(&mut data, (enc & 0x3f) as usize)?.to_vec()
+        } else if enc & 0xe0 == 0xc0 {
+            // 13 bit signed integer
+            let v = (((enc & 0x1f) as i64) << 8) | read_u8(&mut data)? as i64;
+            int_entry(if v >= 1 << 12 { v - (1 << 13) } else { v })
+        } else if enc & 0xf0 == 0xe0 {
+            let len = (((enc & 0x0f) as usize) << 8) | read_u8(&mut data)? as usize;
+            read_bytes(&mut data, len)?.to_vec()
+        } else {
+            match enc {
+                0xf0 => {
+                    let len = read_u32(&mut data)?;
+                    read_bytes(&mut data, len as usize)?.to_vec()
+                }
+                0xf1 => int_entry(read_int(&mut data, 2)?),
+                0xf2 => int_entry(read_int(&mut data, 3)?),
+                0xf3 => int_entry(read_int(&mut data, 4)?),
+                0xf4 => int_entry(read_int(&mut data, 8)?),
+                _ => return Err(corrupt("listpack")),
+            }
+        };
+        let len = start.len() - data.len();
+        let backlen = match len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        read_bytes(&mut data, backlen)?;
+        entries.push(entry);
+    }
+}
+
+// <encoding u32><length u32><integers>, encoding is the size of every integer
+pub(crate) fn read_intset(blob: &[u8]) -> Result<Vec<Vec<u8>>, RdbError> {
+    let mut data = blob;
+    let width = read_u32(&mut data)? as usize;
+    if !matches!(width, 2 | 4 | 8) {
+        return Err(corrupt("intset"));
+    }
+    let len = read_u32(&mut data)?;
+    (0..len)
+        .map(|_| read_int(&mut data, width).map(int_entry))
+        .collect()
+}
+
+// <zmlen u8><len>field<len><free u8>value<free bytes>...<0xff>
+// lengths are a byte, or 254 followed by a u32. Comes back as field, value pairs.
+pub(crate) fn read_zipmap(blob: &[u8]) -> Result<Vec<Vec<u8>>, RdbError> {
+    fn read_len(data: &mut &[u8]) -> Result<Option<usize>, RdbError> {
+        match read_u8(data)? {
+            END => Ok(None),
+            254 => Ok(Some(read_u32(data)? as usize)),
+            len => Ok(Some(len as usize)),
+        }
+    }
+
+    let mut data = blob;
+    read_u8(&mut data)?;
+    let mut entries = Vec::new();
+    while let Some(len) = read_len(&mut data)? {
+        entries.push(read_bytes(&mut data, len)?.to_vec());
+        let len = read_len(&mut data)?.ok_or_else(|| corrupt("zipmap"))?;
+        let free = read_u8(&mut data)?;
+        entries.push(read_bytes(&mut data, len)?.to_vec());
+        read_bytes(&mut data, free as usize)?;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn strings(entries: &[&str]) -> Vec<Vec<u8>> {
+        entries.iter().map(|e| e.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_ziplist() -> Result<(), RdbError> {
+        let mut blob = vec![0; 10];
+        blob.extend_from_slice(&[0x00, 0x02, b'h', b'i']);
+        blob.extend_from_slice(&[0x04, 0xf1]);
+        blob.extend_from_slice(&[0x02, 0xfd]);
+        blob.extend_from_slice(&[0x02, 0xfe, 0x80]);
+        blob.extend_from_slice(&[0x03, 0xc0, 0x30, 0xf8]);
+        blob.extend_from_slice(&[0x04, 0xf0, 0xa0, 0x86, 0x01]);
+        blob.extend_from_slice(&[0x05, 0xd0, 0x00, 0x00, 0x00, 0x80]);
+        blob.extend_from_slice(&[0x06, 0xe0, 0, 0xf2, 0x05, 0x2a, 0x01, 0, 0, 0]);
+        blob.push(0x0a);
+        blob.extend_from_slice(&[0x40, 0x41]);
+        blob.extend_from_slice(&[b'x'; 65]);
+        blob.push(END);
+        let mut expected = strings(&[
+            "hi",
+            "0",
+            "12",
+            "-128",
+            "-2000",
+            "100000",
+            "-2147483648",
+            "5000000000",
+        ]);
+        expected.push(vec![b'x'; 65]);
+        assert_eq!(read_ziplist(&blob)?, expected);
+
+        assert!(read_ziplist(&[0; 4]).is_err());
+        assert!(read_ziplist(&blob[..blob.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_listpack() -> Result<(), RdbError> {
+        let mut blob = vec![0; 6];
+        blob.extend_from_slice(&[0x83, b'a', b'b', b'c', 0x04]);
+        blob.extend_from_slice(&[0x07, 0x01]);
+        blob.extend_from_slice(&[0xd0, 0x60, 0x02]);
+        blob.extend_from_slice(&[0xc1, 0x00, 0x02]);
+        blob.extend_from_slice(&[0xf2, 0xa0, 0x86, 0x01, 0x04]);
+        blob.extend_from_slice(&[0xf4, 0, 0xf2, 0x05, 0x2a, 0x01, 0, 0, 0, 0x09]);
+        // a 200 bytes string has a two bytes backlen
+        blob.extend_from_slice(&[0xe0, 200]);
+        blob.extend_from_slice(&[b'y'; 200]);
+        blob.extend_from_slice(&[0x01, 0xca]);
+        blob.push(END);
+        let mut expected = strings(&["abc", "7", "-4000", "256", "100000", "5000000000"]);
+        expected.push(vec![b'y'; 200]);
+        assert_eq!(read_listpack(&blob)?, expected);
+
+        assert!(read_listpack(&[0; 6]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_intset() -> Result<(), RdbError> {
+        let blob = [2, 0, 0, 0, 3, 0, 0, 0, 0xff, 0xff, 0x01, 0x00, 0x2c, 0x01];
+        assert_eq!(read_intset(&blob)?, strings(&["-1", "1", "300"]));
+        assert!(read_intset(&[3, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(read_intset(&blob[..blob.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_zipmap() -> Result<(), RdbError> {
+        let blob = [
+            0x02, 0x03, b'f', b'o', b'o', 0x03, 0x01, b'b', b'a', b'r', 0x00, 0x01, b'k', 0x01,
+            0x00, b'v', END,
+        ];
+        assert_eq!(read_zipmap(&blob)?, strings(&["foo", "bar", "k", "v"]));
+        assert!(read_zipmap(&blob[..6]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use super::RdbError;
+
+// Decompress LZF data, Redis compresses strings longer than 20 bytes with it.
+//
+// Each chunk starts with a control byte: below 32 it is followed by that many
+// plus one literal bytes, otherwise its upper 3 bits are the length of a back
+// reference (7 means one more length byte follows) and the lower 5 bits with
+// the next byte are the distance to copy from, minus one.
+pub(crate) fn decompress(input: &[u8], len: usize) -> Result<Vec<u8>, RdbError> {
+    let corrupt = || RdbError::Invalid("corrupt LZF compressed string".to_string());
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            let literal = input.get(i..i + ctrl + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(literal);
+            i += ctrl + 1;
+            continue;
+        }
+
+        let mut n = ctrl >> 5;
+        if n == 7 {
+            n += *input.get(i).ok_or_else(corrupt)? as usize;
+            i += 1;
+        }
+        let distance = ((ctrl & 0x1f) << 8) + *input.get(i).ok_or_else(corrupt)? as usize + 1;
+        i += 1;
+        let start = out.len().checked_sub(distance).ok_or_else(corrupt)?;
+        // the reference may overlap the bytes it produces
+        for j in start..start + n + 2 {
+            out.push(out[j]);
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // a literal "a" then a back reference of 19 bytes at distance 1
+        let data = decompress(&[0x00, b'a', 0xe0, 0x0a, 0x00], 20).unwrap();
+        assert_eq!(data, [b'a'; 20]);
+
+        // "abcabcabc!"
+        let data = decompress(&[0x02, b'a', b'b', b'c', 0x80, 0x02, 0x00, b'!'], 10).unwrap();
+        assert_eq!(data, b"abcabcabc!");
+
+        assert!(decompress(&[0x00, b'a', 0xe0, 0x0a, 0x05], 20).is_err());
+        assert!(decompress(&[0x05, b'a'], 6).is_err());
+        assert!(decompress(&[0x00, b'a'], 2).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// Building blocks of the Redis RDB serialization format.
+mod dump;
+mod encoding;
+mod file;
+mod lzf;
+
+use crc::{Crc, CRC_64_REDIS};
+use thiserror::Error;
+
+pub(crate) use dump::{dump, read_dump, restore};
+pub(crate) use file::{read_rdb, write_rdb};
+
+pub const RDB_VERSION: u16 = 11;
+pub(crate) const RDB_MAGIC: &[u8] = b"REDIS";
+
+pub(crate) const RDB_OPCODE_FUNCTION2: u8 = 245;
+
+const RDB_6BITLEN: u8 = 0;
+const RDB_14BITLEN: u8 = 1;
+const RDB_32BITLEN: u8 = 0x80;
+const RDB_64BITLEN: u8 = 0x81;
+const RDB_ENCVAL: u8 = 3;
+const RDB_ENC_INT8: u64 = 0;
+const RDB_ENC_INT16: u64 = 1;
+const RDB_ENC_INT32: u64 = 2;
+const RDB_ENC_LZF: u64 = 3;
+
+const CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_REDIS);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RdbError {
+    #[error("unexpected end of RDB data")]
+    UnexpectedEof,
+    #[error("DUMP payload version or checksum are wrong")]
+    BadPayload,
+    #[error("invalid RDB data ({0})")]
+    Invalid(String),
+}
+
+pub fn crc64(data: &[u8]) -> u64 {
+    CRC64.checksum(data)
+}
+
+// Append the RDB version and the CRC64 checksum to the payload,
+// as DUMP and FUNCTION DUMP do.
+pub(crate) fn seal_payload(mut buf: Vec<u8>) -> Vec<u8> {
+    buf.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(&buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+// Verify the trailer of a payload made by `seal_payload`, returning the body.
+pub(crate) fn open_payload(payload: &[u8]) -> Result<&[u8], RdbError> {
+    if payload.len() < 10 {
+        return Err(RdbError::BadPayload);
+    }
+    let (data, crc) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]]);
+    if version > RDB_VERSION || crc64(data).to_le_bytes() != crc {
+        return Err(RdbError::BadPayload);
+    }
+    Ok(&data[..data.len() - 2])
+}
+
+pub(crate) fn write_length(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push((RDB_6BITLEN << 6) | len as u8);
+    } else if len < 1 << 14 {
+        buf.push((RDB_14BITLEN << 6) | (len >> 8) as u8);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        buf.push(RDB_32BITLEN);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(RDB_64BITLEN);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+pub(crate) fn write_string(buf: &mut Vec<u8>, s: &[u8]) {
+    write_length(buf, s.len() as u64);
+    buf.extend_from_slice(s);
+}
+
+pub(crate) fn read_u8(data: &mut &[u8]) -> Result<u8, RdbError> {
+    let (v, rest) = data.split_first().ok_or(RdbError::UnexpectedEof)?;
+    *data = rest;
+    Ok(*v)
+}
+
+pub(crate) fn read_bytes<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8], RdbError> {
+    if data.len() < n {
+        return Err(RdbError::UnexpectedEof);
+    }
+    let (v, rest) = data.split_at(n);
+    *data = rest;
+    Ok(v)
+}
+
+// Read a length, the second value is true if it's a special encoding
+// instead of a length.
+pub(crate) fn read_length_with_encoding(data: &mut &[u8]) -> Result<(u64, bool), RdbError> {
+    let first = read_u8(data)?;
+    match first >> 6 {
+        RDB_6BITLEN => Ok(((first & 0x3f) as u64, false)),
+        RDB_14BITLEN => Ok((
+            (((first & 0x3f) as u64) << 8) | read_u8(data)? as u64,
+            false,
+        )),
+        RDB_ENCVAL => Ok(((first & 0x3f) as u64, true)),
+        _ => match first {
+            RDB_32BITLEN => {
+                let v = read_bytes(data, 4)?;
+                Ok((u32::from_be_bytes(v.try_into().unwrap()) as u64, false))
+            }
+            RDB_64BITLEN => {
+                let v = read_bytes(data, 8)?;
+                Ok((u64::from_be_bytes(v.try_into().unwrap()), false))
+            }
+            _ => Err(RdbError::Invalid(format!(
+                "unknown length encoding {}",
+                first
+            ))),
+        },
+    }
+}
+
+pub(crate) fn read_string(data: &mut &[u8]) -> Result<Vec<u8>, RdbError> {
+    match read_length_with_encoding(data)? {
+        (len, false) => Ok(read_bytes(data, len as usize)?.to_vec()),
+        (RDB_ENC_INT8, true) => Ok((read_u8(data)? as i8).to_string().into_bytes()),
+        (RDB_ENC_INT16, true) => {
+            let v = read_bytes(data, 2)?;
+            Ok(i16::from_le_bytes([v[0], v[1]]).to_string().into_bytes())
+        }
+        (RDB_ENC_INT32, true) => {
+            let v = read_bytes(data, 4)?;
+            Ok(i32::from_le_bytes(v.try_into().unwrap())
+                .to_string()
+                .into_bytes())
+        }
+        (RDB_ENC_LZF, true) => {
+            let (compressed, _) = read_length_with_encoding(data)?;
+            let (len, _) = read_length_with_encoding(data)?;
+            lzf::decompress(read_bytes(data, compressed as usize)?, len as usize)
+        }
+        (enc, true) => Err(RdbError::Invalid(format!(
+            "unknown string encoding {}",
+            enc
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_length_round_trip() -> Result<(), RdbError> {
+        for len in [
+            0,
+            63,
+            64,
+            16383,
+            16384,
+            u32::MAX as u64,
+            u32::MAX as u64 + 1,
+        ] {
+            let mut buf = Vec::new();
+            write_length(&mut buf, len);
+            let mut data = buf.as_slice();
+            assert_eq!(read_length_with_encoding(&mut data)?, (len, false));
+            assert!(data.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_int_encoded_string() -> Result<(), RdbError> {
+        let mut data: &[u8] = &[0xc0, 0xfe, 0xc1, 0x39, 0x30, 0xc2, 0x87, 0xd6, 0x12, 0x00];
+        assert_eq!(read_string(&mut data)?, b"-2");
+        assert_eq!(read_string(&mut data)?, b"12345");
+        assert_eq!(read_string(&mut data)?, b"1234567");
+        Ok(())
+    }
+
+    #[test]
+    fn test_payload() {
+        let payload = seal_payload(b"hello".to_vec());
+        assert_eq!(open_payload(&payload).unwrap(), b"hello");
+
+        let mut broken = payload.clone();
+        broken[0] = b'j';
+        assert_eq!(open_payload(&broken), Err(RdbError::BadPayload));
+        assert_eq!(open_payload(b"short"), Err(RdbError::BadPayload));
+    }
+}
@@ -0,0 +1,100 @@
+use super::Sync;
+use crate::rdb::write_rdb;
+use crate::snapshot::Snapshot;
+use crate::{Backend, Command, RespDecode, RespError, RespFrame};
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::info;
+
+// Serve a replica which sent PSYNC: a snapshot or the part of the backlog it
+// misses, then the stream as it's fed. `pending` is what the replica sent
+// after PSYNC, which isn't a request anymore.
+pub(crate) async fn serve_replica(
+    mut stream: TcpStream,
+    mut pending: BytesMut,
+    backend: &Backend,
+    replid: &str,
+    offset: i64,
+    port: u16,
+) -> Result<()> {
+    let ip = stream.peer_addr()?.ip().to_string();
+    let (id, sync, mut receiver, output, snapshot) = {
+        let _guard = backend.gate.write().await;
+        let (id, sync, receiver, output) = backend.repl.attach(ip.clone(), port, replid, offset);
+        let snapshot = matches!(sync, Sync::Full { .. }).then(|| Snapshot::capture(backend));
+        (id, sync, receiver, output, snapshot)
+    };
+    let serve = async {
+        match sync {
+            Sync::Partial { replid, backlog } => {
+                info!(
+                    "Partial resynchronization of replica {}:{} accepted",
+                    ip, port
+                );
+                stream
+                    .write_all(format!("+CONTINUE {}\r\n", replid).as_bytes())
+                    .await?;
+                stream.write_all(&backlog).await?;
+            }
+            Sync::Full { replid, offset } => {
+                info!("Full resync requested by replica {}:{}", ip, port);
+                stream
+                    .write_all(format!("+FULLRESYNC {} {}\r\n", replid, offset).as_bytes())
+                    .await?;
+                let snapshot = snapshot.unwrap_or_default();
+                let rdb = tokio::task::spawn_blocking(move || write_rdb(&snapshot)).await?;
+                // a bulk string without the final CRLF, as Redis sends it
+                stream
+                    .write_all(format!("${}\r\n", rdb.len()).as_bytes())
+                    .await?;
+                stream.write_all(&rdb).await?;
+                backend.repl.online(id);
+                info!("Synchronization with replica {}:{} succeeded", ip, port);
+            }
+        }
+        loop {
+            tokio::select! {
+                data = receiver.recv() => match data {
+                    Some(data) => {
+                        output.sent(data.len());
+                        stream.write_all(&data).await?
+                    }
+                    // detached, after this server synced with a new master
+                    None => return Ok(()),
+                },
+                n = stream.read_buf(&mut pending) => {
+                    if n? == 0 {
+                        return Ok(());
+                    }
+                    // the replica only sends REPLCONF ACK, which isn't answered
+                    loop {
+                        let frame = match RespFrame::decode(&mut pending) {
+                            Ok(frame) => frame,
+                            Err(RespError::RespNotComplete) => break,
+                            Err(e) => return Err(e.into()),
+                        };
+                        if let Ok(Command::ReplConf(conf)) = Command::try_from(frame) {
+                            if let Some((offset, aof_offset)) = conf.ack() {
+                                backend.repl.ack(id, offset, aof_offset);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    // a replica which doesn't keep up is dropped rather than buffering the
+    // stream for it without bound, it resyncs when it reconnects
+    let result = tokio::select! {
+        result = serve => result,
+        _ = output.exceeded() => {
+            info!("Replica {}:{} closed for overcoming of output buffer limits", ip, port);
+            Err(anyhow!("output buffer limit reached"))
+        }
+    };
+    backend.repl.detach(id);
+    info!("Connection with replica {}:{} lost", ip, port);
+    result
+}
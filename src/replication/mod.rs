@@ -0,0 +1,639 @@
+mod master;
+mod replica;
+mod wait;
+
+pub(crate) use master::serve_replica;
+pub(crate) use replica::run_replica;
+pub(crate) use wait::block_until;
+
+use crate::OutputBufferLimit;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch, Notify};
+
+// Replication.
+//
+// A master feeds every write it applies to the replication stream, the same
+// bytes it appends to the AOF. Positions in the stream are offsets in the
+// history named by the replication id. The end of the stream is kept in a
+// backlog, so that a replica which lost its link for a moment asks for the
+// stream after the offset it processed (PSYNC) and gets it from the backlog.
+// Otherwise it gets a snapshot, and the stream from the offset of the snapshot.
+//
+// A replica feeds the stream it gets from its master to its own backlog and
+// replicas unchanged, so offsets are the same all along a chain of replicas.
+
+const NO_REPLID: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug)]
+pub(crate) struct Replication {
+    state: Mutex<ReplState>,
+    // bumped whenever the master changes, the link to the previous one stops
+    generation: watch::Sender<u64>,
+    // bumped when replicas acknowledge offsets or the AOF is fsynced, WAIT watches it
+    acks: watch::Sender<u64>,
+    next_replica_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct ReplState {
+    role: Role,
+    replid: String,
+    // the history this one continues, after a replica was promoted
+    replid2: String,
+    // the first offset which isn't part of the history of replid2
+    second_replid_offset: Option<u64>,
+    // the offset of the last byte of the stream
+    offset: u64,
+    backlog: VecDeque<u8>,
+    backlog_size: usize,
+    output_limit: OutputBufferLimit,
+    replicas: Vec<Replica>,
+    // full syncs, and PSYNC requests which could or couldn't continue
+    sync_full: u64,
+    sync_partial_ok: u64,
+    sync_partial_err: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Role {
+    Master,
+    Replica(MasterLink),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MasterLink {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) status: LinkStatus,
+    last_io: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LinkStatus {
+    Connecting,
+    // transferring the snapshot
+    Sync,
+    Connected,
+}
+
+// A replica connected to this server, it's sent the stream through `sender`.
+#[derive(Debug)]
+struct Replica {
+    id: u64,
+    ip: String,
+    port: u16,
+    online: bool,
+    sender: mpsc::UnboundedSender<Bytes>,
+    output: Arc<ReplicaOutput>,
+    // since when more than the soft limit waits to be sent
+    soft_since: Option<Instant>,
+    // the offsets it processed and fsynced to its AOF, with REPLCONF ACK
+    ack: u64,
+    aof_ack: u64,
+    last_ack: Instant,
+}
+
+// The part of the stream sent to a replica which its connection didn't take
+// yet. The connection is closed once `exceeded` is notified.
+#[derive(Debug, Default)]
+pub(crate) struct ReplicaOutput {
+    pending: AtomicUsize,
+    exceeded: Notify,
+}
+
+impl ReplicaOutput {
+    // `len` bytes were taken from the channel
+    pub(crate) fn sent(&self, len: usize) {
+        self.pending.fetch_sub(len, Ordering::Relaxed);
+    }
+
+    pub(crate) async fn exceeded(&self) {
+        self.exceeded.notified().await
+    }
+}
+
+// How PSYNC goes on.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Sync {
+    // the stream after the offset the replica asked for, which is in the backlog
+    Partial { replid: String, backlog: Vec<u8> },
+    // a snapshot of the dataset at the offset
+    Full { replid: String, offset: u64 },
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        let config = crate::Config::default();
+        Self::new(
+            config.repl_backlog_size,
+            config.client_output_buffer_limit_replica,
+        )
+    }
+}
+
+impl Replication {
+    pub(crate) fn new(backlog_size: usize, output_limit: OutputBufferLimit) -> Self {
+        Replication {
+            state: Mutex::new(ReplState {
+                role: Role::Master,
+                replid: random_id(),
+                replid2: NO_REPLID.to_string(),
+                second_replid_offset: None,
+                offset: 0,
+                backlog: VecDeque::new(),
+                backlog_size,
+                output_limit,
+                replicas: Vec::new(),
+                sync_full: 0,
+                sync_partial_ok: 0,
+                sync_partial_err: 0,
+            }),
+            generation: watch::Sender::new(0),
+            acks: watch::Sender::new(0),
+            next_replica_id: AtomicU64::new(1),
+        }
+    }
+
+    pub(crate) fn role(&self) -> Role {
+        self.state.lock().unwrap().role.clone()
+    }
+
+    pub(crate) fn is_replica(&self) -> bool {
+        matches!(self.state.lock().unwrap().role, Role::Replica(_))
+    }
+
+    // the replication id and the offset of the last byte of the stream
+    pub(crate) fn position(&self) -> (String, u64) {
+        let state = self.state.lock().unwrap();
+        (state.replid.clone(), state.offset)
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.state.lock().unwrap().offset
+    }
+
+    // Append to the stream, and send it to the replicas. Returns the offset
+    // of the last byte of the stream. A replica which doesn't keep up with
+    // the stream is detached once it exceeds the output buffer limit.
+    pub(crate) fn feed(&self, data: &[u8]) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.offset += data.len() as u64;
+        state.backlog.extend(data);
+        let excess = state.backlog.len().saturating_sub(state.backlog_size);
+        state.backlog.drain(..excess);
+        if !state.replicas.is_empty() {
+            let data = Bytes::copy_from_slice(data);
+            let limit = state.output_limit;
+            state.replicas.retain_mut(|r| {
+                // counted before it's sent, it may be taken from the channel right away
+                let pending =
+                    r.output.pending.fetch_add(data.len(), Ordering::Relaxed) + data.len();
+                if r.sender.send(data.clone()).is_err() {
+                    return false;
+                }
+                if r.exceeds(pending, &limit) {
+                    r.output.exceeded.notify_one();
+                    return false;
+                }
+                true
+            });
+        }
+        state.offset
+    }
+
+    // Add a replica which asks for the stream after `offset`, minus one, of
+    // the history `replid`. The caller holds the write side of the gate, so
+    // that nothing is fed before it takes the snapshot of a full sync.
+    pub(crate) fn attach(
+        &self,
+        ip: String,
+        port: u16,
+        replid: &str,
+        offset: i64,
+    ) -> (
+        u64,
+        Sync,
+        mpsc::UnboundedReceiver<Bytes>,
+        Arc<ReplicaOutput>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let id = self.next_replica_id.fetch_add(1, Ordering::Relaxed);
+        let sync = match state.backlog_since(replid, offset) {
+            Some(backlog) => Sync::Partial {
+                replid: state.replid.clone(),
+                backlog,
+            },
+            None => Sync::Full {
+                replid: state.replid.clone(),
+                offset: state.offset,
+            },
+        };
+        match &sync {
+            Sync::Partial { .. } => state.sync_partial_ok += 1,
+            Sync::Full { .. } => {
+                state.sync_full += 1;
+                // "?" asks for a full sync
+                if replid != "?" {
+                    state.sync_partial_err += 1;
+                }
+            }
+        }
+        let output = Arc::new(ReplicaOutput::default());
+        state.replicas.push(Replica {
+            id,
+            ip,
+            port,
+            online: matches!(sync, Sync::Partial { .. }),
+            sender,
+            output: output.clone(),
+            soft_since: None,
+            ack: 0,
+            aof_ack: 0,
+            last_ack: Instant::now(),
+        });
+        (id, sync, receiver, output)
+    }
+
+    // the snapshot of a full sync was sent
+    pub(crate) fn online(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(replica) = state.replicas.iter_mut().find(|r| r.id == id) {
+            replica.online = true;
+        }
+    }
+
+    pub(crate) fn detach(&self, id: u64) {
+        self.state.lock().unwrap().replicas.retain(|r| r.id != id);
+    }
+
+    // REPLCONF ACK offset [FACK aof_offset] of a replica
+    pub(crate) fn ack(&self, id: u64, offset: u64, aof_offset: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(replica) = state.replicas.iter_mut().find(|r| r.id == id) {
+            replica.ack = offset;
+            replica.aof_ack = aof_offset.unwrap_or(replica.aof_ack);
+            replica.last_ack = Instant::now();
+        }
+        drop(state);
+        self.notify_acks();
+    }
+
+    pub(crate) fn acks(&self) -> watch::Receiver<u64> {
+        self.acks.subscribe()
+    }
+
+    pub(crate) fn notify_acks(&self) {
+        self.acks.send_modify(|a| *a += 1);
+    }
+
+    // replicas which acknowledged `offset`, and fsynced it to their AOF when `aof`
+    pub(crate) fn count_acks(&self, offset: u64, aof: bool) -> usize {
+        let state = self.state.lock().unwrap();
+        (state.replicas.iter())
+            .filter(|r| r.online && if aof { r.aof_ack } else { r.ack } >= offset)
+            .count()
+    }
+
+    // ask the replicas to acknowledge their offset now, the request is part of the stream
+    pub(crate) fn get_ack(&self) {
+        if !self.state.lock().unwrap().replicas.is_empty() {
+            self.feed(b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n");
+        }
+    }
+
+    // REPLICAOF NO ONE: the history goes on under a new id, replicas of the
+    // former master can still resume with the old one.
+    pub(crate) fn become_master(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.role == Role::Master {
+            return false;
+        }
+        self.generation.send_modify(|g| *g += 1);
+        state.role = Role::Master;
+        state.replid2 = std::mem::replace(&mut state.replid, random_id());
+        state.second_replid_offset = Some(state.offset + 1);
+        true
+    }
+
+    // REPLICAOF host port, returns the generation of the new link or None
+    // when already replicating from this master.
+    pub(crate) fn become_replica(&self, host: &str, port: u16) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if let Role::Replica(link) = &state.role {
+            if link.host == host && link.port == port {
+                return None;
+            }
+        }
+        self.generation.send_modify(|g| *g += 1);
+        state.role = Role::Replica(MasterLink {
+            host: host.to_string(),
+            port,
+            status: LinkStatus::Connecting,
+            last_io: None,
+        });
+        Some(*self.generation.borrow())
+    }
+
+    pub(crate) fn generation(&self) -> watch::Receiver<u64> {
+        self.generation.subscribe()
+    }
+
+    // Update the link of the current master, unless it changed since `generation`.
+    fn set_link(&self, generation: u64, status: LinkStatus) {
+        let mut state = self.state.lock().unwrap();
+        if *self.generation.borrow() != generation {
+            return;
+        }
+        if let Role::Replica(link) = &mut state.role {
+            link.status = status;
+            link.last_io = Some(Instant::now());
+        }
+    }
+
+    // After a full sync the stream is the one of the master from its offset.
+    // Replicas of this server have to sync again.
+    fn reset_history(&self, replid: String, offset: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.replid = replid;
+        state.replid2 = NO_REPLID.to_string();
+        state.second_replid_offset = None;
+        state.offset = offset;
+        state.backlog.clear();
+        state.replicas.clear();
+    }
+
+    // After a partial sync, the master may have been promoted since and go
+    // on under a new id.
+    fn continue_history(&self, replid: String) {
+        let mut state = self.state.lock().unwrap();
+        if replid != state.replid {
+            state.replid2 = std::mem::replace(&mut state.replid, replid);
+            state.second_replid_offset = Some(state.offset + 1);
+        }
+    }
+
+    // Replicas refuse writes of their clients, but not of their master.
+    pub(crate) fn deny_writes(&self, replica_read_only: bool) -> bool {
+        replica_read_only && self.is_replica()
+    }
+
+    // full syncs, accepted and refused partial syncs
+    pub(crate) fn sync_stats(&self) -> (u64, u64, u64) {
+        let state = self.state.lock().unwrap();
+        (
+            state.sync_full,
+            state.sync_partial_ok,
+            state.sync_partial_err,
+        )
+    }
+
+    // INFO replication
+    pub(crate) fn info(&self, replica_read_only: bool) -> String {
+        let state = self.state.lock().unwrap();
+        let mut info = String::from("# Replication\r\n");
+        let mut line = |k: &str, v: &dyn std::fmt::Display| {
+            let _ = write!(info, "{}:{}\r\n", k, v);
+        };
+        match &state.role {
+            Role::Master => line("role", &"master"),
+            Role::Replica(link) => {
+                line("role", &"slave");
+                line("master_host", &link.host);
+                line("master_port", &link.port);
+                let up = link.status == LinkStatus::Connected;
+                line("master_link_status", &if up { "up" } else { "down" });
+                let last_io = link.last_io.map_or(-1, |t| t.elapsed().as_secs() as i64);
+                line("master_last_io_seconds_ago", &last_io);
+                let syncing = link.status == LinkStatus::Sync;
+                line("master_sync_in_progress", &(syncing as u8));
+                line("slave_read_repl_offset", &state.offset);
+                line("slave_repl_offset", &state.offset);
+                line("slave_read_only", &(replica_read_only as u8));
+            }
+        }
+        line("connected_slaves", &state.replicas.len());
+        for (i, replica) in state.replicas.iter().enumerate() {
+            let status = if replica.online {
+                "online"
+            } else {
+                "wait_bgsave"
+            };
+            let value = format!(
+                "ip={},port={},state={},offset={},lag={}",
+                replica.ip,
+                replica.port,
+                status,
+                replica.ack,
+                replica.last_ack.elapsed().as_secs()
+            );
+            line(&format!("slave{}", i), &value);
+        }
+        line("master_replid", &state.replid);
+        line("master_replid2", &state.replid2);
+        line("master_repl_offset", &state.offset);
+        let second = state.second_replid_offset.map_or(-1, |o| o as i64);
+        line("second_repl_offset", &second);
+        line("repl_backlog_active", &1);
+        line("repl_backlog_size", &state.backlog_size);
+        line(
+            "repl_backlog_first_byte_offset",
+            &state.backlog_first_byte(),
+        );
+        line("repl_backlog_histlen", &state.backlog.len());
+        info
+    }
+}
+
+impl Replica {
+    // whether `pending` bytes waiting to be sent exceed the limit
+    fn exceeds(&mut self, pending: usize, limit: &OutputBufferLimit) -> bool {
+        if limit.hard > 0 && pending > limit.hard {
+            return true;
+        }
+        if limit.soft == 0 || pending <= limit.soft {
+            self.soft_since = None;
+            return false;
+        }
+        let since = *self.soft_since.get_or_insert_with(Instant::now);
+        since.elapsed() >= Duration::from_secs(limit.soft_seconds)
+    }
+}
+
+impl ReplState {
+    fn backlog_first_byte(&self) -> u64 {
+        self.offset + 1 - self.backlog.len() as u64
+    }
+
+    // The stream from `offset`, which is one past the last byte the replica
+    // has, if it's in the backlog and the replica has the same history.
+    fn backlog_since(&self, replid: &str, offset: i64) -> Option<Vec<u8>> {
+        let offset = u64::try_from(offset).ok()?;
+        let same_history = replid == self.replid
+            || (replid == self.replid2 && self.second_replid_offset.is_some_and(|o| offset <= o));
+        if !same_history || offset < self.backlog_first_byte() || offset > self.offset + 1 {
+            return None;
+        }
+        let skip = (offset - self.backlog_first_byte()) as usize;
+        Some(self.backlog.range(skip..).copied().collect())
+    }
+}
+
+// 40 random hex characters, for replication and cluster node ids
+pub(crate) fn random_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!(
+        "{}-{}-{}",
+        now,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    sha1_smol::Sha1::from(seed).digest().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backlog() {
+        let repl = Replication::new(8, OutputBufferLimit::default());
+        let (replid, _) = repl.position();
+        repl.feed(b"hello");
+        repl.feed(b"world");
+        assert_eq!(repl.position().1, 10);
+
+        // the backlog holds offsets 3 to 10
+        let partial = |replid: &str, offset| match repl.attach("ip".into(), 1, replid, offset).1 {
+            Sync::Partial { backlog, .. } => Some(backlog),
+            Sync::Full { offset, .. } => {
+                assert_eq!(offset, 10);
+                None
+            }
+        };
+        assert_eq!(partial(&replid, 3), Some(b"lloworld".to_vec()));
+        assert_eq!(partial(&replid, 11), Some(Vec::new()));
+        assert_eq!(partial(&replid, 2), None);
+        assert_eq!(partial(&replid, 12), None);
+        assert_eq!(partial("?", -1), None);
+        assert_eq!(partial(NO_REPLID, 5), None);
+        assert_eq!(repl.sync_stats(), (4, 2, 3));
+
+        // replicas get what is fed after they attached
+        let (id, _, mut receiver, _) = repl.attach("ip".into(), 1, &replid, 11);
+        repl.feed(b"!");
+        assert_eq!(receiver.try_recv().unwrap(), Bytes::from_static(b"!"));
+        repl.detach(id);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limit() {
+        let limit = OutputBufferLimit {
+            hard: 10,
+            soft: 0,
+            soft_seconds: 0,
+        };
+        let repl = Replication::new(100, limit);
+        let (replid, _) = repl.position();
+        let (_, _, mut receiver, output) = repl.attach("ip".into(), 1, &replid, 1);
+        let exceeded = || async {
+            let exceeded = output.exceeded();
+            tokio::time::timeout(Duration::ZERO, exceeded).await.is_ok()
+        };
+
+        // what was sent doesn't count
+        repl.feed(b"12345678");
+        output.sent(receiver.try_recv().unwrap().len());
+        repl.feed(b"12345678");
+        assert!(!exceeded().await);
+        assert_eq!(repl.state.lock().unwrap().replicas.len(), 1);
+
+        repl.feed(b"123");
+        assert!(exceeded().await);
+        assert!(repl.state.lock().unwrap().replicas.is_empty());
+
+        // more than the soft limit for the soft seconds
+        let limit = OutputBufferLimit {
+            hard: 0,
+            soft: 4,
+            soft_seconds: 0,
+        };
+        let repl = Replication::new(100, limit);
+        let (_, _, _receiver, output) = repl.attach("ip".into(), 1, &replid, 1);
+        repl.feed(b"1234");
+        assert_eq!(repl.state.lock().unwrap().replicas.len(), 1);
+        repl.feed(b"5");
+        assert!(tokio::time::timeout(Duration::ZERO, output.exceeded())
+            .await
+            .is_ok());
+        assert!(repl.state.lock().unwrap().replicas.is_empty());
+    }
+
+    #[test]
+    fn test_acks() {
+        let repl = Replication::new(100, OutputBufferLimit::default());
+        let (replid, _) = repl.position();
+        let (id, _, mut receiver, _) = repl.attach("ip".into(), 1, &replid, 1);
+        let (other, ..) = repl.attach("ip".into(), 2, "?", -1);
+        assert_eq!(repl.feed(b"set"), 3);
+        assert_eq!(repl.count_acks(3, false), 0);
+
+        let acks = repl.acks();
+        repl.ack(id, 3, Some(0));
+        assert!(acks.has_changed().unwrap());
+        assert_eq!(repl.count_acks(3, false), 1);
+        assert_eq!(repl.count_acks(3, true), 0);
+        repl.ack(id, 3, Some(3));
+        assert_eq!(repl.count_acks(3, true), 1);
+        // replicas waiting for the snapshot don't count
+        repl.ack(other, 3, Some(3));
+        assert_eq!(repl.count_acks(3, false), 1);
+
+        repl.get_ack();
+        receiver.try_recv().unwrap();
+        let getack = receiver.try_recv().unwrap();
+        assert!(getack.ends_with(b"GETACK\r\n$1\r\n*\r\n"));
+        assert_eq!(repl.offset(), 3 + getack.len() as u64);
+    }
+
+    #[test]
+    fn test_promotion() {
+        let repl = Replication::new(100, OutputBufferLimit::default());
+        assert_eq!(repl.become_replica("localhost", 6379), Some(1));
+        assert_eq!(repl.become_replica("localhost", 6379), None);
+        repl.reset_history("a".repeat(40), 100);
+        repl.feed(b"set");
+        assert!(repl.deny_writes(true));
+        assert!(!repl.deny_writes(false));
+
+        assert!(repl.become_master());
+        assert!(!repl.become_master());
+        assert!(!repl.deny_writes(true));
+        let (replid, offset) = repl.position();
+        assert_ne!(replid, "a".repeat(40));
+        assert_eq!(offset, 103);
+        // a replica of the former master resumes with the old id
+        let sync = repl.attach("ip".into(), 1, &"a".repeat(40), 104).1;
+        assert_eq!(
+            sync,
+            Sync::Partial {
+                replid,
+                backlog: Vec::new()
+            }
+        );
+        let info = repl.info(true);
+        assert!(info.contains("role:master\r\n"));
+        assert!(info.contains("second_repl_offset:104\r\n"));
+        assert!(info.contains("slave0:ip=ip,port=1,state=online,offset=0,lag=0\r\n"));
+    }
+}
@@ -0,0 +1,257 @@
+use super::LinkStatus;
+use crate::aof::{bgrewriteaof, run_blocking};
+use crate::network::run_logged;
+use crate::rdb::read_rdb;
+use crate::script::Caller;
+use crate::{
+    AofError, Backend, BulkString, Command, RespArray, RespDecode, RespDecoder, RespEncode,
+    RespFrame,
+};
+use anyhow::{anyhow, bail, Result};
+use bytes::{Bytes, BytesMut};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+// how often the offset is acknowledged to the master
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+// The link with the master set by REPLICAOF, reconnects until the master
+// changes. `generation` is the one REPLICAOF started the link in.
+pub(crate) async fn run_replica(backend: Backend, host: String, port: u16, generation: u64) {
+    let mut changed = backend.repl.generation();
+    loop {
+        tokio::select! {
+            result = sync_with_master(&backend, &host, port, generation) => {
+                if let Err(e) = result {
+                    warn!("Link with master {}:{} lost: {}", host, port, e);
+                }
+            }
+            _ = master_changed(&mut changed, generation) => return,
+        }
+        backend.repl.set_link(generation, LinkStatus::Connecting);
+        tokio::select! {
+            _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+            _ = master_changed(&mut changed, generation) => return,
+        }
+    }
+}
+
+async fn master_changed(changed: &mut watch::Receiver<u64>, generation: u64) {
+    while *changed.borrow_and_update() == generation {
+        if changed.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+// The connection to the master, replies and the stream are read from `buf`.
+struct Link {
+    stream: TcpStream,
+    buf: BytesMut,
+    decoder: RespDecoder,
+}
+
+impl Link {
+    async fn read_more(&mut self) -> Result<()> {
+        if self.stream.read_buf(&mut self.buf).await? == 0 {
+            bail!("connection closed by the master");
+        }
+        Ok(())
+    }
+
+    // the next frame with the bytes it was sent as
+    async fn read_frame(&mut self) -> Result<(RespFrame, Bytes)> {
+        loop {
+            if let Some(len) = self.decoder.scan(&self.buf)? {
+                let raw = self.buf.split_to(len).freeze();
+                let frame = RespFrame::parse(&mut BytesMut::from(&raw[..]))?;
+                return Ok((frame, raw));
+            }
+            self.read_more().await?;
+        }
+    }
+
+    async fn send(&mut self, args: &[&str]) -> Result<()> {
+        let args: Vec<RespFrame> = args.iter().map(|a| BulkString::new(*a).into()).collect();
+        let request = RespFrame::from(RespArray::with_vec(args)).encode();
+        self.stream.write_all(&request).await?;
+        Ok(())
+    }
+
+    async fn call(&mut self, args: &[&str]) -> Result<RespFrame> {
+        self.send(args).await?;
+        Ok(self.read_frame().await?.0)
+    }
+
+    // REPLCONF ACK with the offset processed and the one fsynced to the AOF
+    async fn ack(&mut self, backend: &Backend) -> Result<()> {
+        let offset = backend.repl.offset().to_string();
+        let aof_offset = backend.aof.fsynced_offset(&backend.repl).to_string();
+        self.send(&["REPLCONF", "ACK", &offset, "FACK", &aof_offset])
+            .await
+    }
+
+    // The snapshot of a full sync: a bulk string without the final CRLF.
+    // Redis sends newlines to keep the link alive while it prepares it.
+    async fn read_snapshot(&mut self) -> Result<Bytes> {
+        let len = loop {
+            while self.buf.first() == Some(&b'\n') {
+                let _ = self.buf.split_to(1);
+            }
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = self.buf.split_to(end + 2);
+                let len = std::str::from_utf8(&line[..end])
+                    .ok()
+                    .and_then(|l| l.strip_prefix('$')?.parse::<usize>().ok())
+                    .ok_or_else(|| anyhow!("bad snapshot header from the master"))?;
+                break len;
+            }
+            self.read_more().await?;
+        };
+        while self.buf.len() < len {
+            self.read_more().await?;
+        }
+        Ok(self.buf.split_to(len).freeze())
+    }
+}
+
+fn is_error(frame: &RespFrame) -> bool {
+    matches!(frame, RespFrame::Error(_) | RespFrame::BulkError(_))
+}
+
+async fn sync_with_master(backend: &Backend, host: &str, port: u16, generation: u64) -> Result<()> {
+    let config = &backend.config;
+    let mut link = Link {
+        stream: TcpStream::connect((host, port)).await?,
+        buf: BytesMut::new(),
+        decoder: RespDecoder::with_limits(config.resp_limits()),
+    };
+    info!("Connected to master {}:{}", host, port);
+    if let Some(pass) = &config.masterauth {
+        let reply = link.call(&["HELLO", "2", "AUTH", "default", pass]).await?;
+        if is_error(&reply) {
+            bail!("can't authenticate to the master: {:?}", reply);
+        }
+    }
+    let reply = link.call(&["PING"]).await?;
+    if is_error(&reply) {
+        bail!("unexpected reply to PING: {:?}", reply);
+    }
+    // older masters don't know these, it doesn't prevent the sync
+    link.call(&["REPLCONF", "listening-port", &config.port.to_string()])
+        .await?;
+    link.call(&["REPLCONF", "capa", "psync2"]).await?;
+
+    let (replid, offset) = backend.repl.position();
+    let reply = link
+        .call(&["PSYNC", &replid, &(offset + 1).to_string()])
+        .await?;
+    let reply = reply
+        .as_bytes()
+        .filter(|_| !is_error(&reply))
+        .and_then(|r| std::str::from_utf8(r).ok())
+        .ok_or_else(|| anyhow!("PSYNC failed: {:?}", reply))?
+        .to_string();
+    let mut words = reply.split(' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some("FULLRESYNC"), Some(replid), Some(offset)) => {
+            let offset = offset.parse()?;
+            backend.repl.set_link(generation, LinkStatus::Sync);
+            let data = link.read_snapshot().await?;
+            let snapshot = tokio::task::spawn_blocking(move || read_rdb(&data)).await??;
+            let _guard = backend.gate.write().await;
+            snapshot.restore(backend)?;
+            backend.repl.reset_history(replid.to_string(), offset);
+            backend.aof.reset_offsets(offset);
+            // the AOF has to hold the new dataset
+            match bgrewriteaof(backend) {
+                Ok(_) | Err(AofError::Disabled) => {}
+                Err(e) => warn!(
+                    "Can't rewrite the AOF after the sync with the master: {}",
+                    e
+                ),
+            }
+            info!("MASTER <-> REPLICA sync: Finished with success");
+        }
+        (Some("CONTINUE"), replid, None) => {
+            if let Some(replid) = replid {
+                backend.repl.continue_history(replid.to_string());
+            }
+            info!("Successful partial resynchronization with master");
+        }
+        _ => bail!("unexpected reply to PSYNC: {}", reply),
+    }
+    backend.repl.set_link(generation, LinkStatus::Connected);
+
+    // a transaction of the master, with the bytes it came in
+    let mut transaction: Option<Vec<(Command, RespFrame)>> = None;
+    let mut transaction_data = Vec::new();
+    let mut ack_interval = tokio::time::interval(ACK_INTERVAL);
+    loop {
+        // reading a frame resumes where it stopped when it's cancelled
+        let (frame, raw) = tokio::select! {
+            read = link.read_frame() => read?,
+            _ = ack_interval.tick() => {
+                link.ack(backend).await?;
+                continue;
+            }
+        };
+        backend.repl.set_link(generation, LinkStatus::Connected);
+        let cmd = match Command::try_from(frame.clone()) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                warn!("Can't apply a command of the master: {}", e);
+                backend.repl.feed(&raw);
+                continue;
+            }
+        };
+        let getack = matches!(&cmd, Command::ReplConf(conf) if conf.is_getack());
+        match (cmd, transaction.as_mut()) {
+            (Command::Multi(_), None) => {
+                transaction = Some(Vec::new());
+                transaction_data = raw.to_vec();
+            }
+            (Command::Exec(_), Some(_)) => {
+                let queued = transaction.take().unwrap_or_default();
+                transaction_data.extend_from_slice(&raw);
+                let data = std::mem::take(&mut transaction_data);
+                let _guard = backend.gate.write().await;
+                run_blocking(backend, move |b| apply(b, queued, &data)).await;
+            }
+            (cmd, Some(queued)) => {
+                queued.push((cmd, frame));
+                transaction_data.extend_from_slice(&raw);
+            }
+            (cmd @ (Command::Eval(_) | Command::EvalSha(_) | Command::FCall(_)), None) => {
+                let _guard = backend.gate.write().await;
+                run_blocking(backend, move |b| apply(b, vec![(cmd, frame)], &raw)).await;
+            }
+            (cmd, None) => {
+                let _guard = backend.gate.read().await;
+                run_blocking(backend, move |b| apply(b, vec![(cmd, frame)], &raw)).await;
+            }
+        }
+        if getack {
+            link.ack(backend).await?;
+        }
+    }
+}
+
+// Apply commands of the master, the caller holds the gate: the write side for
+// a transaction or a script. They are appended to the AOF, and the bytes
+// they came in are fed to the replicas of this server.
+fn apply(backend: &Backend, commands: Vec<(Command, RespFrame)>, raw: &[u8]) {
+    let mut logged = Vec::new();
+    for (cmd, frame) in commands {
+        // PING and REPLCONF only keep the link alive, they are still part of the stream
+        if matches!(cmd, Command::Ping(_) | Command::ReplConf(_)) {
+            continue;
+        }
+        logged.extend(run_logged(cmd, frame, backend, Caller::Master).1);
+    }
+    backend.aof.append_replicated(&backend.repl, logged, raw);
+}
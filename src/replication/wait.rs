@@ -0,0 +1,32 @@
+use crate::{Backend, RespFrame};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Block WAIT or WAITAOF until `acked` is done, or the timeout. It checks the
+// acknowledgements and returns whether there are enough, with the reply.
+// Replicas are asked to acknowledge right away instead of within a second.
+pub(crate) async fn block_until(
+    backend: &Backend,
+    timeout: Option<Duration>,
+    mut acked: impl FnMut() -> (bool, RespFrame),
+) -> RespFrame {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    // subscribed before checking, so that no acknowledgement is missed
+    let mut acks = backend.repl.acks();
+    let (done, reply) = acked();
+    if done {
+        return reply;
+    }
+    backend.repl.get_ack();
+    loop {
+        let changed = acks.changed();
+        let result = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, changed).await,
+            None => Ok(changed.await),
+        };
+        let (done, reply) = acked();
+        if done || result.is_err() {
+            return reply;
+        }
+    }
+}
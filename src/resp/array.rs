@@ -9,18 +9,14 @@ use std::fmt::Display;
 pub struct RespArray(pub(crate) Option<Vec<RespFrame>>);
 
 impl RespEncode for RespArray {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_to(&self, buf: &mut BytesMut) {
         match self.0.as_ref() {
-            None => b"*-1\r\n".to_vec(),
+            None => buf.extend_from_slice(b"*-1\r\n"),
             Some(v) => {
-                let mut buf = Vec::with_capacity(DEFAULT_CAPACITY);
-                buf.extend_from_slice(b"*");
-                buf.extend_from_slice(v.len().to_string().as_bytes());
-                buf.extend_from_slice(b"\r\n");
+                write_header(buf, b'*', v.len());
                 for frame in v.iter() {
-                    buf.extend_from_slice(frame.encode().as_slice());
+                    frame.encode_to(buf);
                 }
-                buf
             }
         }
     }
@@ -28,7 +24,7 @@ impl RespEncode for RespArray {
 
 impl RespDecode for RespArray {
     const PREFIX: &'static str = "*";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (len, pos) = parse_length(data)?;
         if len == -1 {
@@ -37,9 +33,9 @@ impl RespDecode for RespArray {
         }
 
         data.advance(pos);
-        let mut ra = Vec::with_capacity(len as usize);
+        let mut ra = Vec::with_capacity((len as usize).min(data.len()));
         for _ in 0..len {
-            let frame = RespFrame::decode(data).map_err(|e| e.map_not_complete())?;
+            let frame = RespFrame::parse(data)?;
             ra.push(frame);
         }
         Ok(RespArray::with_vec(ra))
@@ -179,5 +175,7 @@ mod test {
         let mut data = BytesMut::from("*2\r\n+hello\r\n+abc");
         let res = RespArray::decode(&mut data);
         assert_eq!(res.unwrap_err(), RespError::RespNotComplete);
+        // the incomplete frame is left untouched
+        assert_eq!(data.as_ref(), b"*2\r\n+hello\r\n+abc");
     }
 }
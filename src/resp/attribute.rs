@@ -0,0 +1,78 @@
+use super::*;
+use bytes::Buf;
+use std::fmt::Display;
+
+// Attributes are auxiliary key-value pairs sent right before a reply,
+// so they are decoded together with the frame they describe.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct RespAttribute {
+    pub(crate) attributes: RespMap,
+    pub(crate) frame: Box<RespFrame>,
+}
+
+// |<number-of-entries>\r\n<key-1><value-1>...<key-n><value-n><frame>
+impl RespEncode for RespAttribute {
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_header(buf, b'|', self.attributes.len());
+        for (k, v) in self.attributes.iter() {
+            k.0.encode_to(buf);
+            v.encode_to(buf);
+        }
+        self.frame.encode_to(buf);
+    }
+}
+
+impl RespDecode for RespAttribute {
+    const PREFIX: &'static str = "|";
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
+        data.advance(Self::PREFIX.len());
+        let (len, pos) = parse_length(data)?;
+        data.advance(pos);
+        let attributes = RespMap(map::parse_entries(data, len)?);
+        let frame = RespFrame::parse(data)?;
+        Ok(RespAttribute::new(attributes, frame))
+    }
+}
+
+impl RespAttribute {
+    pub fn new(attributes: RespMap, frame: impl Into<RespFrame>) -> Self {
+        RespAttribute {
+            attributes,
+            frame: Box::new(frame.into()),
+        }
+    }
+
+    pub fn attributes(&self) -> &RespMap {
+        &self.attributes
+    }
+
+    pub fn frame(&self) -> &RespFrame {
+        &self.frame
+    }
+}
+
+impl Display for RespAttribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.attributes, self.frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resp_attribute_decode() {
+        let mut data = BytesMut::from("|1\r\n+ttl\r\n:3600\r\n$5\r\nhello\r\n");
+        let res = RespAttribute::decode(&mut data).unwrap();
+        assert_eq!(data.len(), 0);
+        assert_eq!(res.attributes().get("ttl"), Some(&RespFrame::Integer(3600)));
+        assert_eq!(res.frame(), &BulkString::new("hello").into());
+        assert_eq!(res.encode(), b"|1\r\n+ttl\r\n:3600\r\n$5\r\nhello\r\n");
+
+        // the attributed frame has not arrived yet
+        let mut data = BytesMut::from("|1\r\n+ttl\r\n:3600\r\n");
+        let err = RespAttribute::decode(&mut data).unwrap_err();
+        assert_eq!(err, RespError::RespNotComplete);
+    }
+}
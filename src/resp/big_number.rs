@@ -0,0 +1,68 @@
+use super::*;
+use bytes::Buf;
+use std::fmt::Display;
+
+// Big numbers are kept as their decimal representation.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct BigNumber(pub(crate) String);
+
+// (<big number>\r\n
+impl RespEncode for BigNumber {
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_line(buf, b'(', self.0.as_bytes());
+    }
+}
+
+impl RespDecode for BigNumber {
+    const PREFIX: &'static str = "(";
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
+        data.advance(Self::PREFIX.len());
+        let (s, pos) = split_cr_lf(data)?;
+        let digits = s.strip_prefix(b"-").or(s.strip_prefix(b"+")).unwrap_or(s);
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return Err(RespError::RespInvalid("Invalid big number".to_string()));
+        }
+        let res = BigNumber(String::from_utf8_lossy(s).to_string());
+        data.advance(pos);
+        Ok(res)
+    }
+}
+
+impl BigNumber {
+    pub fn new(s: impl Into<String>) -> Self {
+        BigNumber(s.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for BigNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_big_number_decode() {
+        let mut data = BytesMut::from("(3492890328409238509324850943850943825024385\r\n");
+        let res = BigNumber::decode(&mut data).unwrap();
+        assert_eq!(data.len(), 0);
+        assert_eq!(res.as_str(), "3492890328409238509324850943850943825024385");
+        assert_eq!(
+            res.encode(),
+            b"(3492890328409238509324850943850943825024385\r\n"
+        );
+
+        let mut data = BytesMut::from("(-12\r\n");
+        assert_eq!(BigNumber::decode(&mut data).unwrap(), BigNumber::new("-12"));
+
+        let mut data = BytesMut::from("(12a\r\n");
+        assert!(BigNumber::decode(&mut data).is_err());
+    }
+}
@@ -2,14 +2,14 @@ use super::*;
 use bytes::Buf;
 
 impl RespEncode for bool {
-    fn encode(&self) -> Vec<u8> {
-        format!("#{}\r\n", if *self { "t" } else { "f" }).into_bytes()
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_line(buf, b'#', if *self { b"t" } else { b"f" });
     }
 }
 
 impl RespDecode for bool {
     const PREFIX: &'static str = "#";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (s, pos) = split_cr_lf(data)?;
         let res = match s {
@@ -0,0 +1,62 @@
+use super::*;
+use bytes::Buf;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct BulkError(pub(crate) String);
+
+// !<length>\r\n<error>\r\n
+impl RespEncode for BulkError {
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_header(buf, b'!', self.0.len());
+        buf.extend_from_slice(self.0.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+impl RespDecode for BulkError {
+    const PREFIX: &'static str = "!";
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
+        data.advance(Self::PREFIX.len());
+        let (len, pos) = parse_length(data)?;
+        data.advance(pos);
+        let (s, pos) = split_blob(data, len)?;
+        let res = BulkError::new(String::from_utf8_lossy(s).to_string());
+        data.advance(pos);
+        Ok(res)
+    }
+}
+
+impl BulkError {
+    pub fn new(s: impl Into<String>) -> Self {
+        BulkError(s.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for BulkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bulk_error_decode() {
+        let mut data = BytesMut::from("!22\r\nSYNTAX invalid\r\nsyntax\r\n");
+        let res = BulkError::decode(&mut data).unwrap();
+        assert_eq!(data.len(), 0);
+        assert_eq!(res.as_str(), "SYNTAX invalid\r\nsyntax");
+        assert_eq!(res.encode(), b"!22\r\nSYNTAX invalid\r\nsyntax\r\n");
+
+        let mut data = BytesMut::from("!22\r\nSYNTAX invalid");
+        let err = BulkError::decode(&mut data).unwrap_err();
+        assert_eq!(err, RespError::RespNotComplete);
+    }
+}
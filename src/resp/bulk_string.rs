@@ -1,29 +1,27 @@
 use super::*;
 use anyhow::Result;
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 use std::fmt::Display;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
-pub struct BulkString(pub(crate) Option<Vec<u8>>);
+pub struct BulkString(pub(crate) Option<Bytes>);
 // $<length>\r\n<data>\r\n
 impl RespEncode for BulkString {
-    fn encode(&self) -> Vec<u8> {
+    fn encode_to(&self, buf: &mut BytesMut) {
         match &self.0 {
             Some(v) => {
-                let mut buf = Vec::with_capacity(DEFAULT_CAPACITY);
-                buf.extend_from_slice(format!("${}\r\n", v.len()).as_bytes());
-                buf.extend_from_slice(v.as_slice());
+                write_header(buf, b'$', v.len());
+                buf.extend_from_slice(v);
                 buf.extend_from_slice(b"\r\n");
-                buf
             }
-            None => b"$-1\r\n".to_vec(),
+            None => buf.extend_from_slice(b"$-1\r\n"),
         }
     }
 }
 
 impl RespDecode for BulkString {
     const PREFIX: &'static str = "$";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
 
         // get length and move cursor to the end of \r\n
@@ -32,18 +30,33 @@ impl RespDecode for BulkString {
         if len == -1 {
             return Ok(BulkString::new_null());
         }
+        if len < 0 {
+            return Err(RespError::RespInvalid(format!("Invalid length {}", len)));
+        }
 
-        let (s, pos) = split_cr_lf(data)?;
-        if s.len() != len as usize {
+        // rely on the length so that the payload is not scanned for CR LF
+        let len = len as usize;
+        if data.len() < len + 2 {
+            return Err(RespError::RespNotComplete);
+        }
+        if &data[len..len + 2] != b"\r\n" {
+            let (s, _) = split_cr_lf(data)?;
             return Err(RespError::RespNotEqualLength {
-                expected: len as usize,
+                expected: len,
                 decoded: s.len(),
             });
         }
 
-        let res = BulkString::new(s);
-        data.advance(pos);
-        Ok(res)
+        // split the payload off the read buffer instead of copying it
+        let res = data.split_to(len).freeze();
+        data.advance(2);
+        Ok(BulkString(Some(res)))
+    }
+}
+
+impl From<Bytes> for BulkString {
+    fn from(b: Bytes) -> Self {
+        BulkString(Some(b))
     }
 }
 
@@ -55,7 +68,11 @@ impl From<&str> for BulkString {
 
 impl BulkString {
     pub fn new(s: impl Into<Vec<u8>>) -> Self {
-        BulkString(Some(s.into()))
+        BulkString(Some(s.into().into()))
+    }
+
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        self.0.as_ref()
     }
 
     pub fn new_null() -> Self {
@@ -111,4 +128,14 @@ mod test {
         assert_eq!(data.len(), 0);
         assert_eq!(res.encode(), b"$-1\r\n");
     }
+
+    #[test]
+    fn test_bulk_string_decode_zero_copy() {
+        let mut data = BytesMut::from("$5\r\nhello\r\n+OK\r\n");
+        let start = data.as_ptr() as usize;
+        let res = BulkString::decode(&mut data).unwrap();
+        // the payload still points into the read buffer
+        assert_eq!(res.as_bytes().unwrap().as_ptr() as usize, start + 4);
+        assert_eq!(data.as_ref(), b"+OK\r\n");
+    }
 }
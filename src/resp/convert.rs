@@ -0,0 +1,518 @@
+use super::*;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+// ToFrame and FromFrame
+//
+// Conversions between Rust values and frames, `#[derive(RespEncode, RespDecode)]`
+// builds the conversions of a user type out of the conversions of its fields.
+// Decoding is lenient about the frame it gets: a RESP2 reply carries numbers as
+// bulk strings and maps as flat key-value arrays, so both are accepted.
+pub trait ToFrame {
+    fn to_frame(&self) -> RespFrame;
+}
+
+pub trait FromFrame: Sized {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError>;
+}
+
+fn parse_error(typ: &str, frame: &RespFrame) -> RespError {
+    RespError::RespParseError {
+        typ: typ.to_string(),
+        data: frame.to_string(),
+    }
+}
+
+fn is_null(frame: &RespFrame) -> bool {
+    matches!(
+        frame,
+        RespFrame::Null(_)
+            | RespFrame::BulkString(BulkString(None))
+            | RespFrame::Array(RespArray(None))
+    )
+}
+
+// the text of a simple string, a bulk string or a verbatim string
+pub(super) fn frame_bytes(frame: &RespFrame) -> Option<&[u8]> {
+    match frame {
+        RespFrame::VerbatimString(v) => Some(v.data()),
+        frame => frame.as_bytes(),
+    }
+}
+
+fn frame_str<'a>(frame: &'a RespFrame, typ: &str) -> Result<&'a str, RespError> {
+    frame_bytes(frame)
+        .and_then(|v| std::str::from_utf8(v).ok())
+        .ok_or_else(|| parse_error(typ, frame))
+}
+
+impl ToFrame for RespFrame {
+    fn to_frame(&self) -> RespFrame {
+        self.clone()
+    }
+}
+
+impl FromFrame for RespFrame {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        Ok(frame)
+    }
+}
+
+impl ToFrame for String {
+    fn to_frame(&self) -> RespFrame {
+        BulkString::new(self.as_bytes()).into()
+    }
+}
+
+impl ToFrame for str {
+    fn to_frame(&self) -> RespFrame {
+        BulkString::new(self.as_bytes()).into()
+    }
+}
+
+impl FromFrame for String {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        frame_str(&frame, "string").map(|s| s.to_string())
+    }
+}
+
+impl ToFrame for Bytes {
+    fn to_frame(&self) -> RespFrame {
+        RespFrame::BulkString(BulkString(Some(self.clone())))
+    }
+}
+
+impl FromFrame for Bytes {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        match frame {
+            RespFrame::BulkString(BulkString(Some(v))) => Ok(v),
+            frame => frame_bytes(&frame)
+                .map(Bytes::copy_from_slice)
+                .ok_or_else(|| parse_error("bytes", &frame)),
+        }
+    }
+}
+
+impl ToFrame for bool {
+    fn to_frame(&self) -> RespFrame {
+        RespFrame::Boolean(*self)
+    }
+}
+
+// RESP2 has no booleans, Redis replies with 0 or 1 instead
+impl FromFrame for bool {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        match &frame {
+            RespFrame::Boolean(b) => Ok(*b),
+            RespFrame::Integer(0) => Ok(false),
+            RespFrame::Integer(1) => Ok(true),
+            _ => match frame_str(&frame, "bool")? {
+                "0" | "false" => Ok(false),
+                "1" | "true" => Ok(true),
+                _ => Err(parse_error("bool", &frame)),
+            },
+        }
+    }
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl ToFrame for $t {
+                fn to_frame(&self) -> RespFrame {
+                    match i64::try_from(*self) {
+                        Ok(v) => RespFrame::Integer(v),
+                        Err(_) => BigNumber::new(self.to_string()).into(),
+                    }
+                }
+            }
+
+            impl FromFrame for $t {
+                fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+                    let typ = stringify!($t);
+                    let v = match &frame {
+                        RespFrame::Integer(i) => <$t>::try_from(*i).ok(),
+                        RespFrame::BigNumber(n) => n.0.parse().ok(),
+                        _ => frame_str(&frame, typ)?.parse().ok(),
+                    };
+                    v.ok_or_else(|| parse_error(typ, &frame))
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_float {
+    ($($t:ty),*) => {
+        $(
+            impl ToFrame for $t {
+                fn to_frame(&self) -> RespFrame {
+                    RespFrame::Double(*self as f64)
+                }
+            }
+
+            impl FromFrame for $t {
+                fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+                    let typ = stringify!($t);
+                    match &frame {
+                        RespFrame::Double(d) => Ok(*d as $t),
+                        RespFrame::Integer(i) => Ok(*i as $t),
+                        _ => frame_str(&frame, typ)?
+                            .parse()
+                            .map_err(|_| parse_error(typ, &frame)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+impl<T: ToFrame> ToFrame for Option<T> {
+    fn to_frame(&self) -> RespFrame {
+        match self {
+            Some(v) => v.to_frame(),
+            None => RespNull::new().into(),
+        }
+    }
+}
+
+impl<T: FromFrame> FromFrame for Option<T> {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        match is_null(&frame) {
+            true => Ok(None),
+            false => T::from_frame(frame).map(Some),
+        }
+    }
+}
+
+impl<T: ToFrame> ToFrame for Box<T> {
+    fn to_frame(&self) -> RespFrame {
+        self.as_ref().to_frame()
+    }
+}
+
+impl<T: FromFrame> FromFrame for Box<T> {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        T::from_frame(frame).map(Box::new)
+    }
+}
+
+impl<T: ToFrame> ToFrame for Vec<T> {
+    fn to_frame(&self) -> RespFrame {
+        RespArray::with_vec(self.iter().map(|v| v.to_frame()).collect::<Vec<_>>()).into()
+    }
+}
+
+impl<T: FromFrame> FromFrame for Vec<T> {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        frame_items(frame, "array")?
+            .into_iter()
+            .map(T::from_frame)
+            .collect()
+    }
+}
+
+impl<T: ToFrame> ToFrame for BTreeMap<String, T> {
+    fn to_frame(&self) -> RespFrame {
+        RespMap::with_map(
+            self.iter()
+                .map(|(k, v)| (k.clone(), v.to_frame()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+        .into()
+    }
+}
+
+impl<T: FromFrame> FromFrame for BTreeMap<String, T> {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        FrameFields::new(frame, "map")?
+            .entries
+            .into_iter()
+            .map(|(k, v)| Ok((k, T::from_frame(v)?)))
+            .collect()
+    }
+}
+
+impl<T: ToFrame> ToFrame for HashMap<String, T> {
+    fn to_frame(&self) -> RespFrame {
+        RespMap::with_map(
+            self.iter()
+                .map(|(k, v)| (k.clone(), v.to_frame()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+        .into()
+    }
+}
+
+impl<T: FromFrame> FromFrame for HashMap<String, T> {
+    fn from_frame(frame: RespFrame) -> Result<Self, RespError> {
+        FrameFields::new(frame, "map")?
+            .entries
+            .into_iter()
+            .map(|(k, v)| Ok((k, T::from_frame(v)?)))
+            .collect()
+    }
+}
+
+// The items of an array, a set or a push.
+#[doc(hidden)]
+pub fn frame_items(frame: RespFrame, typ: &str) -> Result<Vec<RespFrame>, RespError> {
+    match frame {
+        RespFrame::Array(RespArray(Some(v))) | RespFrame::Push(RespPush(v)) => Ok(v),
+        RespFrame::Set(s) => Ok(s.0),
+        frame => Err(parse_error(typ, &frame)),
+    }
+}
+
+// Split a tagged frame into the name of the variant and its value.
+// A unit variant is a string, any other one is a map of a single entry,
+// which a RESP2 reply turns into an array of two items.
+#[doc(hidden)]
+pub fn frame_variant(
+    frame: RespFrame,
+    typ: &'static str,
+) -> Result<(String, Option<RespFrame>), RespError> {
+    if let Some(v) = frame_bytes(&frame) {
+        return Ok((String::from_utf8_lossy(v).to_string(), None));
+    }
+    let mut fields = FrameFields::new(frame, typ)?;
+    match (fields.entries.len(), fields.entries.pop_first()) {
+        (1, Some((name, value))) => Ok((name, Some(value))),
+        _ => Err(RespError::RespInvalid(format!(
+            "expect a single variant of {}",
+            typ
+        ))),
+    }
+}
+
+// Merge the entries of a flattened field into the map of its struct,
+// anything but a map, such as the null of a `None`, adds nothing.
+#[doc(hidden)]
+pub fn flatten_frame(map: &mut RespMap, frame: RespFrame) {
+    if let Ok(fields) = FrameFields::new(frame, "map") {
+        for (k, v) in fields.entries {
+            map.insert(k, v);
+        }
+    }
+}
+
+// FrameFields
+//
+// The fields of a struct being decoded from a map, or from the flat key-value
+// array of a RESP2 reply. Entries without a matching field are ignored.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct FrameFields {
+    typ: &'static str,
+    entries: BTreeMap<String, RespFrame>,
+}
+
+impl FrameFields {
+    pub fn new(frame: RespFrame, typ: &'static str) -> Result<Self, RespError> {
+        let entries = match frame {
+            RespFrame::Map(m) => {
+                let mut entries = BTreeMap::new();
+                for (k, v) in m.0 {
+                    entries.insert(frame_str(k.frame(), typ)?.to_string(), v);
+                }
+                entries
+            }
+            RespFrame::Array(RespArray(Some(v))) if v.len() % 2 == 0 => {
+                let mut entries = BTreeMap::new();
+                let mut items = v.into_iter();
+                while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                    entries.insert(frame_str(&k, typ)?.to_string(), v);
+                }
+                entries
+            }
+            frame => return Err(parse_error(typ, &frame)),
+        };
+        Ok(FrameFields { typ, entries })
+    }
+
+    // A missing field is decoded from a null, so only an `Option` may be missing.
+    pub fn take<T: FromFrame>(&mut self, key: &str) -> Result<T, RespError> {
+        match self.entries.remove(key) {
+            Some(v) => T::from_frame(v),
+            None => T::from_frame(RespNull::new().into()).map_err(|_| {
+                RespError::RespInvalid(format!("missing field {} of {}", key, self.typ))
+            }),
+        }
+    }
+
+    // the entries no field has taken, for a flattened field
+    pub fn rest(&self) -> RespFrame {
+        RespMap::with_map(self.entries.clone()).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RespDecode, RespEncode};
+
+    #[derive(Debug, PartialEq, Default, RespEncode, RespDecode)]
+    struct Meta {
+        created: i64,
+        tags: Option<Vec<String>>,
+    }
+
+    #[derive(Debug, PartialEq, RespEncode, RespDecode)]
+    struct User {
+        name: String,
+        #[resp(rename = "years")]
+        age: u32,
+        email: Option<String>,
+        #[resp(skip)]
+        session: u64,
+        #[resp(flatten)]
+        meta: Meta,
+    }
+
+    #[derive(Debug, PartialEq, RespEncode, RespDecode)]
+    #[resp(array)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    #[derive(Debug, PartialEq, RespEncode, RespDecode)]
+    enum Shape {
+        Empty,
+        #[resp(rename = "circle")]
+        Circle(f64),
+        Line(Point, Point),
+        Rect {
+            width: i64,
+            height: i64,
+        },
+    }
+
+    #[test]
+    fn test_from_resp2_frames() {
+        let frame: RespFrame = BulkString::new("42").into();
+        assert_eq!(<i64>::from_frame(frame.clone()), Ok(42));
+        assert_eq!(f64::from_frame(frame.clone()), Ok(42.0));
+        assert_eq!(String::from_frame(frame), Ok("42".to_string()));
+        assert_eq!(<bool>::from_frame(RespFrame::Integer(1)), Ok(true));
+        assert_eq!(
+            Option::<i64>::from_frame(BulkString::new_null().into()),
+            Ok(None)
+        );
+        assert!(u8::from_frame(RespFrame::Integer(256)).is_err());
+
+        let array = RespArray::with_vec(vec![
+            BulkString::new("a").into(),
+            BulkString::new("1").into(),
+        ]);
+        let map = BTreeMap::<String, i64>::from_frame(array.into()).unwrap();
+        assert_eq!(map, BTreeMap::from([("a".to_string(), 1)]));
+    }
+
+    #[test]
+    fn test_derive_struct() {
+        let user = User {
+            name: "alice".to_string(),
+            age: 30,
+            email: None,
+            session: 7,
+            meta: Meta {
+                created: 1,
+                tags: Some(vec!["a".to_string()]),
+            },
+        };
+        let mut expected = RespMap::new();
+        expected.insert("name", BulkString::new("alice"));
+        expected.insert("years", RespFrame::Integer(30));
+        expected.insert("email", RespNull::new());
+        expected.insert("created", RespFrame::Integer(1));
+        expected.insert(
+            "tags",
+            RespArray::with_vec(vec![BulkString::new("a").into()]),
+        );
+        assert_eq!(user.to_frame(), expected.clone().into());
+        assert_eq!(user.encode(), expected.encode());
+
+        let mut buf = BytesMut::from(&user.encode()[..]);
+        let decoded = User::decode(&mut buf).unwrap();
+        assert_eq!(decoded, User { session: 0, ..user });
+
+        let point = Point { x: 1.0, y: 2.5 };
+        assert_eq!(
+            point.to_frame(),
+            RespArray::with_vec(vec![RespFrame::Double(1.0), RespFrame::Double(2.5)]).into()
+        );
+        assert_eq!(Point::from_frame(point.to_frame()), Ok(point));
+    }
+
+    #[test]
+    fn test_derive_from_hgetall_reply() {
+        // a RESP2 HGETALL reply, every value is a bulk string
+        let mut buf = BytesMut::from(
+            "*6\r\n$4\r\nname\r\n$3\r\nbob\r\n$5\r\nyears\r\n$2\r\n41\r\n$7\r\ncreated\r\n$1\r\n9\r\n",
+        );
+        let user = User::decode(&mut buf).unwrap();
+        assert_eq!(user.name, "bob");
+        assert_eq!(user.age, 41);
+        assert_eq!(user.email, None);
+        assert_eq!(user.meta.created, 9);
+
+        let frame = RespArray::with_vec(vec![BulkString::new("name").into()]);
+        assert!(User::from_frame(frame.into()).is_err());
+        let mut missing = RespMap::new();
+        missing.insert("name", BulkString::new("bob"));
+        assert_eq!(
+            User::from_frame(missing.into()),
+            Err(RespError::RespInvalid(
+                "missing field years of User".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_derive_enum() {
+        assert_eq!(Shape::Empty.to_frame(), SimpleString::new("Empty").into());
+        let mut circle = RespMap::new();
+        circle.insert("circle", RespFrame::Double(1.5));
+        assert_eq!(Shape::Circle(1.5).to_frame(), circle.into());
+
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(1.5),
+            Shape::Line(Point { x: 0.0, y: 0.0 }, Point { x: 1.0, y: 1.0 }),
+            Shape::Rect {
+                width: 2,
+                height: 3,
+            },
+        ];
+        for shape in shapes {
+            assert_eq!(Shape::from_frame(shape.to_frame()).as_ref(), Ok(&shape));
+            // a RESP2 client sees the map of a variant as an array of two items
+            assert_eq!(Shape::from_frame(shape.to_frame().into_resp2()), Ok(shape));
+        }
+        assert_eq!(
+            Shape::from_frame(SimpleString::new("Square").into()),
+            Err(RespError::RespInvalid(
+                "unknown variant Square of Shape".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_to_frame() {
+        assert_eq!("a".to_frame(), BulkString::new("a").into());
+        assert_eq!(
+            u64::MAX.to_frame(),
+            BigNumber::new(u64::MAX.to_string()).into()
+        );
+        assert_eq!(None::<i64>.to_frame(), RespNull::new().into());
+        assert_eq!(
+            vec![1, 2].to_frame(),
+            RespArray::with_vec(vec![RespFrame::Integer(1), RespFrame::Integer(2)]).into()
+        );
+    }
+}
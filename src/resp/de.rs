@@ -0,0 +1,341 @@
+use super::convert::frame_bytes;
+use super::*;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+// Deserialize a value out of a frame. The frame is trusted to carry the types
+// the value asks for only loosely: a RESP2 reply carries numbers and booleans as
+// bulk strings and maps as flat key-value arrays, both are accepted.
+pub fn from_frame<T: DeserializeOwned>(frame: RespFrame) -> Result<T, RespError> {
+    T::deserialize(frame)
+}
+
+impl de::Error for RespError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        RespError::RespInvalid(msg.to_string())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, RespError> for RespFrame {
+    type Deserializer = RespFrame;
+
+    fn into_deserializer(self) -> RespFrame {
+        self
+    }
+}
+
+// the value is decoded the way `FromFrame` does, then handed to the visitor
+macro_rules! deserialize_from_frame {
+    ($($method:ident => $t:ty, $visit:ident;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+                visitor.$visit(<$t>::from_frame(self)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for RespFrame {
+    type Error = RespError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        match self {
+            RespFrame::SimpleString(s) => visitor.visit_string(s.0),
+            RespFrame::BulkString(BulkString(Some(v))) => match String::from_utf8(v.to_vec()) {
+                Ok(s) => visitor.visit_string(s),
+                Err(_) => visitor.visit_byte_buf(v.to_vec()),
+            },
+            RespFrame::VerbatimString(v) => match String::from_utf8(v.data) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            RespFrame::BulkString(BulkString(None))
+            | RespFrame::Array(RespArray(None))
+            | RespFrame::Null(_) => visitor.visit_unit(),
+            RespFrame::Integer(i) => visitor.visit_i64(i),
+            RespFrame::Boolean(b) => visitor.visit_bool(b),
+            RespFrame::Double(d) => visitor.visit_f64(d),
+            RespFrame::BigNumber(n) => match n.0.parse::<i128>() {
+                Ok(i) => visitor.visit_i128(i),
+                Err(_) => visitor.visit_string(n.0),
+            },
+            RespFrame::Array(RespArray(Some(v)))
+            | RespFrame::Set(RespSet(v))
+            | RespFrame::Push(RespPush(v)) => {
+                let mut seq = SeqDeserializer::new(v.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            RespFrame::Map(m) => {
+                let mut map = MapDeserializer::new(m.0.into_iter().map(|(k, v)| (k.0, v)));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            RespFrame::Attribute(a) => a.frame.deserialize_any(visitor),
+            // an error reply can't be a value
+            RespFrame::Error(SimpleError(e)) | RespFrame::BulkError(BulkError(e)) => {
+                Err(RespError::RespInvalid(e))
+            }
+        }
+    }
+
+    deserialize_from_frame! {
+        deserialize_bool => bool, visit_bool;
+        deserialize_i8 => i8, visit_i8;
+        deserialize_i16 => i16, visit_i16;
+        deserialize_i32 => i32, visit_i32;
+        deserialize_i64 => i64, visit_i64;
+        deserialize_u8 => u8, visit_u8;
+        deserialize_u16 => u16, visit_u16;
+        deserialize_u32 => u32, visit_u32;
+        deserialize_u64 => u64, visit_u64;
+        deserialize_f32 => f32, visit_f32;
+        deserialize_f64 => f64, visit_f64;
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        match self {
+            RespFrame::Integer(i) => visitor.visit_string(i.to_string()),
+            RespFrame::Double(d) => visitor.visit_string(d.to_string()),
+            frame => frame.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        match frame_bytes(&self) {
+            Some(v) => visitor.visit_byte_buf(v.to_vec()),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        match self {
+            RespFrame::BulkString(BulkString(None))
+            | RespFrame::Array(RespArray(None))
+            | RespFrame::Null(_) => visitor.visit_none(),
+            frame => visitor.visit_some(frame),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, RespError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // a flat key-value array of a RESP2 reply is taken as a map
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        let entries = BTreeMap::<String, RespFrame>::from_frame(self)?;
+        let mut map = MapDeserializer::new(entries.into_iter());
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RespError> {
+        match self {
+            // a struct may be serialized as a sequence too
+            RespFrame::Array(RespArray(Some(ref v))) if v.len() % 2 != 0 => {
+                self.deserialize_any(visitor)
+            }
+            frame => frame.deserialize_map(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RespError> {
+        let (variant, value) = frame_variant(self, name)?;
+        visitor.visit_enum(VariantDeserializer { variant, value })
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, RespError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char unit unit_struct seq tuple tuple_struct identifier
+    }
+}
+
+// VariantDeserializer
+//
+// A variant split out of its tagged frame, unit variants carry no value.
+struct VariantDeserializer {
+    variant: String,
+    value: Option<RespFrame>,
+}
+
+impl<'de> de::EnumAccess<'de> for VariantDeserializer {
+    type Error = RespError;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        mut self,
+        seed: V,
+    ) -> Result<(V::Value, Self), RespError> {
+        let variant = std::mem::take(&mut self.variant);
+        let variant = seed.deserialize(variant.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = RespError;
+
+    fn unit_variant(self) -> Result<(), RespError> {
+        match self.value {
+            None => Ok(()),
+            Some(frame) => de::Deserialize::deserialize(frame),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, RespError> {
+        seed.deserialize(self.value.unwrap_or_else(|| RespNull::new().into()))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, RespError> {
+        match self.value {
+            Some(frame) => de::Deserializer::deserialize_seq(frame, visitor),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, RespError> {
+        match self.value {
+            Some(frame) => de::Deserializer::deserialize_struct(frame, "", fields, visitor),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        email: Option<String>,
+        admin: bool,
+        tags: Vec<String>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Ping,
+        Echo(String),
+        Move(i64, i64),
+        Rename { from: String, to: String },
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let user = User {
+            name: "alice".to_string(),
+            age: 30,
+            email: Some("a@b.c".to_string()),
+            admin: false,
+            tags: vec!["x".to_string()],
+        };
+        let frame = to_frame(&user).unwrap();
+        assert_eq!(from_frame::<User>(frame.clone()), Ok(user));
+        // what a RESP2 client gets back
+        assert!(from_frame::<User>(frame.into_resp2()).is_ok());
+
+        let events = vec![
+            Event::Ping,
+            Event::Echo("hi".to_string()),
+            Event::Move(1, -1),
+            Event::Rename {
+                from: "a".to_string(),
+                to: "b".to_string(),
+            },
+        ];
+        let frame = to_frame(&events).unwrap();
+        assert_eq!(from_frame::<Vec<Event>>(frame.clone()), Ok(events));
+        assert!(from_frame::<Vec<Event>>(frame.into_resp2()).is_ok());
+    }
+
+    #[test]
+    fn test_from_hgetall_reply() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Profile {
+            name: String,
+            age: u32,
+            admin: bool,
+            email: Option<String>,
+        }
+
+        let reply = RespArray::with_vec(
+            ["name", "bob", "age", "41", "admin", "1"]
+                .iter()
+                .map(|v| BulkString::new(*v).into())
+                .collect::<Vec<RespFrame>>(),
+        );
+        let profile: Profile = from_frame(reply.clone().into()).unwrap();
+        assert_eq!(
+            profile,
+            Profile {
+                name: "bob".to_string(),
+                age: 41,
+                admin: true,
+                email: None,
+            }
+        );
+        let map: HashMap<String, String> = from_frame(reply.into()).unwrap();
+        assert_eq!(map["age"], "41");
+    }
+
+    #[test]
+    fn test_from_frame_errors() {
+        assert!(from_frame::<u8>(RespFrame::Integer(300)).is_err());
+        assert_eq!(
+            from_frame::<String>(SimpleError::new("ERR oops").into()),
+            Err(RespError::RespInvalid("ERR oops".to_string()))
+        );
+        assert!(from_frame::<Event>(SimpleString::new("Jump").into()).is_err());
+        assert!(from_frame::<User>(RespFrame::Integer(1)).is_err());
+    }
+}
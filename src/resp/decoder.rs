@@ -0,0 +1,304 @@
+use super::*;
+
+// RespDecoder
+//
+// A resumable decoder. It walks the buffer element by element and remembers
+// how far it got, so a frame received in many pieces is only scanned once.
+// No byte is consumed until the whole frame has arrived, then the frame is
+// split off the buffer and parsed.
+//
+// The same buffer must be passed to every call until a frame is returned.
+#[derive(Debug, Default)]
+pub struct RespDecoder {
+    limits: RespLimits,
+    // end of the elements scanned so far
+    pos: usize,
+    // where to resume searching CR LF for the line of the current element
+    line_scanned: usize,
+    // remaining elements of every open aggregate, innermost last
+    remaining: Vec<usize>,
+}
+
+// RespLimits bounds what a peer may send, they are checked while scanning
+// so that nothing is allocated for a frame which breaks them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RespLimits {
+    pub max_bulk_len: usize,
+    pub max_multibulk_len: usize,
+    pub max_depth: usize,
+}
+
+impl Default for RespLimits {
+    fn default() -> Self {
+        Self {
+            // same as the defaults of Redis
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: i32::MAX as usize,
+            max_depth: 128,
+        }
+    }
+}
+
+impl RespDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: RespLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    // Decode the next frame, Ok(None) if it has not fully arrived yet.
+    pub fn decode(&mut self, data: &mut BytesMut) -> Result<Option<RespFrame>, RespError> {
+        let len = match self.scan(data) {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.reset();
+                return Err(e);
+            }
+        };
+        RespFrame::parse(&mut data.split_to(len)).map(Some)
+    }
+
+    // Scan the frame at the beginning of data, resuming from the previous call.
+    // Returns the length of the frame once it is complete.
+    pub(crate) fn scan(&mut self, data: &[u8]) -> Result<Option<usize>, RespError> {
+        loop {
+            let Some((end, elements)) = self.scan_element(data)? else {
+                return Ok(None);
+            };
+            self.pos = end;
+            self.line_scanned = end;
+            if elements > 0 {
+                self.remaining.push(elements);
+                continue;
+            }
+            // the element is complete, so is every aggregate it was the last element of
+            loop {
+                match self.remaining.last_mut() {
+                    None => {
+                        self.reset();
+                        return Ok(Some(end));
+                    }
+                    Some(n) => {
+                        *n -= 1;
+                        if *n > 0 {
+                            break;
+                        }
+                        self.remaining.pop();
+                    }
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.line_scanned = 0;
+        self.remaining.clear();
+    }
+
+    // Scan the element at `pos`, returning its end and the number of nested
+    // elements it opens, 0 for a complete element.
+    fn scan_element(&mut self, data: &[u8]) -> Result<Option<(usize, usize)>, RespError> {
+        let start = self.pos;
+        let Some(cr) = self.find_line(data) else {
+            return Ok(None);
+        };
+        let prefix = data[start];
+        let line = &data[start + 1..cr];
+        let next = cr + 2;
+        match prefix {
+            b'+' | b'-' | b':' | b',' | b'#' | b'_' | b'(' => Ok(Some((next, 0))),
+            b'$' | b'!' | b'=' => {
+                let len = parse_line_length(line)?;
+                if len == -1 && prefix == b'$' {
+                    return Ok(Some((next, 0)));
+                }
+                if len < 0 || len as u64 > self.limits.max_bulk_len as u64 {
+                    return Err(RespError::RespProtocolError(
+                        "invalid bulk length".to_string(),
+                    ));
+                }
+                let len = len as usize;
+                if data.len() < next + len + 2 {
+                    return Ok(None);
+                }
+                if &data[next + len..next + len + 2] != b"\r\n" {
+                    return Err(match find_cr_lf(data, next) {
+                        Some(cr) => RespError::RespNotEqualLength {
+                            expected: len,
+                            decoded: cr - next,
+                        },
+                        None => RespError::RespInvalid("Missing CR LF".to_string()),
+                    });
+                }
+                Ok(Some((next + len + 2, 0)))
+            }
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                let len = parse_line_length(line)?;
+                if len == -1 && prefix == b'*' {
+                    return Ok(Some((next, 0)));
+                }
+                if len < 0 || len as u64 > self.limits.max_multibulk_len as u64 {
+                    return Err(RespError::RespProtocolError(
+                        "invalid multibulk length".to_string(),
+                    ));
+                }
+                if len > 0 && self.remaining.len() >= self.limits.max_depth {
+                    return Err(RespError::RespProtocolError(
+                        "too many nested aggregates".to_string(),
+                    ));
+                }
+                let len = len as usize;
+                // maps hold key-value pairs, an attribute is followed by the frame it describes
+                let elements = match prefix {
+                    b'%' => len.checked_mul(2),
+                    b'|' => len.checked_mul(2).and_then(|n| n.checked_add(1)),
+                    _ => Some(len),
+                }
+                .ok_or_else(|| RespError::RespInvalid(format!("Invalid length {}", len)))?;
+                Ok(Some((next, elements)))
+            }
+            _ => Err(RespError::RespInvalid(format!(
+                "does not support prefix {}",
+                prefix as char
+            ))),
+        }
+    }
+
+    // Find the CR of the line of the element at `pos`, remembering how far
+    // the search went so that a long line is not searched twice.
+    fn find_line(&mut self, data: &[u8]) -> Option<usize> {
+        let from = self.line_scanned.max(self.pos + 1);
+        if from > data.len() {
+            return None;
+        }
+        match find_cr_lf(data, from) {
+            Some(cr) => Some(cr),
+            None => {
+                // a trailing CR may be followed by LF in the next read
+                self.line_scanned = data.len().saturating_sub(1).max(from);
+                None
+            }
+        }
+    }
+}
+
+fn find_cr_lf(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| from + i)
+}
+
+fn parse_line_length(line: &[u8]) -> Result<i64, RespError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| RespError::RespInvalid("Invalid length".to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_byte_by_byte() {
+        let input = b"*3\r\n$3\r\nset\r\n$3\r\nkey\r\n%1\r\n+a\r\n~1\r\n:1\r\n+OK\r\n";
+        let mut decoder = RespDecoder::new();
+        let mut data = BytesMut::new();
+        let mut frames = Vec::new();
+        for b in input {
+            data.extend_from_slice(&[*b]);
+            let len = data.len();
+            match decoder.decode(&mut data).unwrap() {
+                Some(frame) => frames.push(frame),
+                // nothing is consumed until the frame is complete
+                None => assert_eq!(data.len(), len),
+            }
+        }
+        assert!(data.is_empty());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[0].encode(),
+            b"*3\r\n$3\r\nset\r\n$3\r\nkey\r\n%1\r\n+a\r\n~1\r\n:1\r\n"
+        );
+        assert_eq!(frames[1], SimpleString::new("OK").into());
+    }
+
+    #[test]
+    fn test_decode_large_array_in_chunks() {
+        // re-parsing from the start on every read would make this quadratic
+        let n = 100_000;
+        let mut input = BytesMut::new();
+        input.extend_from_slice(format!("*{}\r\n", n).as_bytes());
+        for _ in 0..n {
+            input.extend_from_slice(b"$5\r\nvalue\r\n");
+        }
+
+        let mut decoder = RespDecoder::new();
+        let mut data = BytesMut::new();
+        let mut frame = None;
+        for chunk in input.chunks(7) {
+            data.extend_from_slice(chunk);
+            if let Some(f) = decoder.decode(&mut data).unwrap() {
+                frame = Some(f);
+            }
+        }
+        match frame {
+            Some(RespFrame::Array(RespArray(Some(v)))) => assert_eq!(v.len(), n),
+            _ => panic!("expect an array of {} elements", n),
+        }
+    }
+
+    #[test]
+    fn test_decode_bulk_string_with_cr_lf() {
+        let mut decoder = RespDecoder::new();
+        let mut data = BytesMut::from("$4\r\na\r\n");
+        assert_eq!(decoder.decode(&mut data).unwrap(), None);
+        data.extend_from_slice(b"b\r\n");
+        assert_eq!(
+            decoder.decode(&mut data).unwrap(),
+            Some(BulkString::new("a\r\nb").into())
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut decoder = RespDecoder::new();
+        let mut data = BytesMut::from("*2\r\n+a\r\n?b\r\n");
+        assert!(decoder.decode(&mut data).is_err());
+
+        let mut data = BytesMut::from("*-2\r\n");
+        assert!(decoder.decode(&mut data).is_err());
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let limits = RespLimits {
+            max_bulk_len: 4,
+            max_multibulk_len: 2,
+            max_depth: 2,
+        };
+        let invalid = |input: &str| {
+            let mut decoder = RespDecoder::with_limits(limits);
+            let err = decoder.decode(&mut BytesMut::from(input)).unwrap_err();
+            assert!(matches!(err, RespError::RespProtocolError(_)), "{}", input);
+        };
+        // limits are checked before the payload arrives
+        invalid("$5\r\n");
+        invalid("*3\r\n");
+        invalid("*9999999999\r\n");
+        invalid("*1\r\n*1\r\n*1\r\n");
+        invalid("%3\r\n");
+
+        let mut decoder = RespDecoder::with_limits(limits);
+        let mut data = BytesMut::from("*2\r\n*1\r\n$4\r\nabcd\r\n*0\r\n");
+        assert!(decoder.decode(&mut data).unwrap().is_some());
+    }
+}
@@ -1,23 +1,21 @@
 use super::*;
 use bytes::Buf;
+use std::fmt::Write;
 
 impl RespEncode for f64 {
-    fn encode(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(DEFAULT_CAPACITY);
-        buf.extend_from_slice(b",");
-        let res = if self.abs() > 1e-6 {
-            format!("{:?}\r\n", self)
+    fn encode_to(&self, buf: &mut BytesMut) {
+        // writing into BytesMut never fails
+        let _ = if self.abs() > 1e-6 {
+            write!(buf, ",{:?}\r\n", self)
         } else {
-            format!("{:e}\r\n", self)
+            write!(buf, ",{:e}\r\n", self)
         };
-        buf.extend_from_slice(res.as_bytes());
-        buf
     }
 }
 
 impl RespDecode for f64 {
     const PREFIX: &'static str = ",";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (s, len) = split_cr_lf(data)?;
         let res =
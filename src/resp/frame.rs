@@ -19,6 +19,11 @@ pub enum RespFrame {
     Double(f64),
     Map(RespMap),
     Set(RespSet),
+    BigNumber(BigNumber),
+    VerbatimString(VerbatimString),
+    BulkError(BulkError),
+    Push(RespPush),
+    Attribute(RespAttribute),
 }
 
 impl Display for RespFrame {
@@ -34,6 +39,11 @@ impl Display for RespFrame {
             RespFrame::Double(d) => write!(f, "{}", d),
             RespFrame::Map(m) => write!(f, "{}", m),
             RespFrame::Set(s) => write!(f, "{}", s),
+            RespFrame::BigNumber(n) => write!(f, "{}", n),
+            RespFrame::VerbatimString(v) => write!(f, "{}", v),
+            RespFrame::BulkError(e) => write!(f, "{}", e),
+            RespFrame::Push(p) => write!(f, "{}", p),
+            RespFrame::Attribute(a) => write!(f, "{}", a),
         }
     }
 }
@@ -43,13 +53,22 @@ impl RespFrame {
         match self {
             RespFrame::SimpleString(s1) => s1.0.as_bytes() == s,
             RespFrame::BulkString(b) => match &b.0 {
-                Some(v) => v.as_slice() == s,
+                Some(v) => v.as_ref() == s,
                 None => false,
             },
             _ => false,
         }
     }
 
+    // the raw bytes of a simple string or a bulk string
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            RespFrame::SimpleString(s) => Some(s.0.as_bytes()),
+            RespFrame::BulkString(BulkString(Some(v))) => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+
     // TODO
     //  reactor to trait TryFrom
     //  impl TryFrom<RespFrame> for String
@@ -64,6 +83,44 @@ impl RespFrame {
         }
     }
 
+    // Convert the frame to what a RESP2 client can parse:
+    // maps become flat arrays, sets and pushes become arrays, nulls become null bulk strings,
+    // booleans become integers, doubles, big numbers and verbatim strings become bulk strings,
+    // bulk errors become simple errors and attributes are dropped.
+    pub fn into_resp2(self) -> RespFrame {
+        match self {
+            RespFrame::Null(_) => BulkString::new_null().into(),
+            RespFrame::Boolean(b) => RespFrame::Integer(b as i64),
+            RespFrame::Double(d) => BulkString::new(d.to_string()).into(),
+            RespFrame::Map(m) => {
+                let mut v = Vec::with_capacity(m.len() * 2);
+                for (key, value) in m.0 {
+                    // string keys are bulk strings as Redis replies them
+                    let key = match key.0 {
+                        RespFrame::SimpleString(s) => BulkString::new(s.0).into(),
+                        key => key.into_resp2(),
+                    };
+                    v.push(key);
+                    v.push(value.into_resp2());
+                }
+                RespArray::with_vec(v).into()
+            }
+            RespFrame::Set(s) => {
+                RespArray::with_vec(s.0.into_iter().map(|f| f.into_resp2()).collect::<Vec<_>>())
+                    .into()
+            }
+            RespFrame::Array(RespArray(Some(v))) | RespFrame::Push(RespPush(v)) => {
+                RespArray::with_vec(v.into_iter().map(|f| f.into_resp2()).collect::<Vec<_>>())
+                    .into()
+            }
+            RespFrame::BigNumber(n) => BulkString::new(n.0).into(),
+            RespFrame::VerbatimString(v) => BulkString::new(v.data).into(),
+            RespFrame::BulkError(e) => SimpleError::new(e.0.replace(['\r', '\n'], " ")).into(),
+            RespFrame::Attribute(a) => a.frame.into_resp2(),
+            frame => frame,
+        }
+    }
+
     // TODO
     //  reactor to trait TryFrom
     //  impl TryFrom<RespFrame> for i64
@@ -77,19 +134,27 @@ impl RespFrame {
 
 impl RespDecode for RespFrame {
     const PREFIX: &'static str = "";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         if data.is_empty() {
             return Err(RespError::RespNotComplete);
         }
         let prefix = data[0] as char;
         match prefix {
-            '+' => Ok(SimpleString::decode(data)?.into()),
-            '*' => Ok(RespArray::decode(data)?.into()),
-            '$' => Ok(BulkString::decode(data)?.into()),
-            '#' => Ok(bool::decode(data)?.into()),
-            '-' => Ok(SimpleError::decode(data)?.into()),
-            ':' => Ok(i64::decode(data)?.into()),
-            ',' => Ok(f64::decode(data)?.into()),
+            '+' => Ok(SimpleString::parse(data)?.into()),
+            '*' => Ok(RespArray::parse(data)?.into()),
+            '$' => Ok(BulkString::parse(data)?.into()),
+            '#' => Ok(bool::parse(data)?.into()),
+            '-' => Ok(SimpleError::parse(data)?.into()),
+            ':' => Ok(i64::parse(data)?.into()),
+            ',' => Ok(f64::parse(data)?.into()),
+            '%' => Ok(RespMap::parse(data)?.into()),
+            '~' => Ok(RespSet::parse(data)?.into()),
+            '_' => Ok(RespNull::parse(data)?.into()),
+            '(' => Ok(BigNumber::parse(data)?.into()),
+            '=' => Ok(VerbatimString::parse(data)?.into()),
+            '!' => Ok(BulkError::parse(data)?.into()),
+            '>' => Ok(RespPush::parse(data)?.into()),
+            '|' => Ok(RespAttribute::parse(data)?.into()),
             _ => Err(RespError::RespInvalid(format!(
                 "does not support prefix {}",
                 prefix
@@ -112,6 +177,9 @@ pub enum RespError {
     RespParseError { typ: String, data: String },
     #[error("RESP (type: {typ:?}) Wrapped (err: {err:?})")]
     RespWrappedError { typ: String, err: Box<RespError> },
+    // the client broke the protocol or a limit, the connection should be closed
+    #[error("Protocol error: {0}")]
+    RespProtocolError(String),
 }
 
 impl RespError {
@@ -183,4 +251,56 @@ mod test {
         let res = s.try_to_string();
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_into_resp2() {
+        let mut m = RespMap::new();
+        m.insert("proto", RespFrame::Integer(2));
+        m.insert("flag", true);
+        m.insert("empty", RespNull::new());
+        let frame: RespFrame = RespArray::with_vec(vec![
+            m.into(),
+            RespSet::with_vec(vec![1.5f64.into()]).into(),
+        ])
+        .into();
+        assert_eq!(
+            frame.into_resp2().encode(),
+            b"*2\r\n*6\r\n$5\r\nempty\r\n$-1\r\n$4\r\nflag\r\n:1\r\n$5\r\nproto\r\n:2\r\n*1\r\n$3\r\n1.5\r\n"
+        );
+    }
+
+    #[test]
+    fn test_resp3_decode() {
+        let cases: [&[u8]; 8] = [
+            b"%1\r\n+key\r\n:1\r\n",
+            b"~2\r\n:1\r\n:2\r\n",
+            b"_\r\n",
+            b"(12345678901234567890\r\n",
+            b"=7\r\ntxt:abc\r\n",
+            b"!7\r\nERR bad\r\n",
+            b">2\r\n+message\r\n+hello\r\n",
+            b"|1\r\n+key\r\n#t\r\n:1\r\n",
+        ];
+        for case in cases {
+            let mut data = BytesMut::from(case);
+            let frame = RespFrame::decode(&mut data).unwrap();
+            assert_eq!(data.len(), 0);
+            assert_eq!(frame.encode(), case);
+        }
+    }
+
+    #[test]
+    fn test_resp3_into_resp2() {
+        let frame: RespFrame = RespPush::with_vec(vec![
+            BigNumber::new("12").into(),
+            VerbatimString::new(*b"txt", "abc").into(),
+            BulkError::new("ERR a\r\nb").into(),
+            RespAttribute::new(RespMap::new(), RespFrame::Integer(1)).into(),
+        ])
+        .into();
+        assert_eq!(
+            frame.into_resp2().encode(),
+            b"*4\r\n$2\r\n12\r\n$3\r\nabc\r\n-ERR a  b\r\n:1\r\n"
+        );
+    }
 }
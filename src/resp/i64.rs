@@ -1,17 +1,18 @@
 use super::*;
 use bytes::Buf;
+use std::fmt::Write;
 
 // :[<+|->]<value>\r\n
 impl RespEncode for i64 {
-    fn encode(&self) -> Vec<u8> {
-        //let sign = if *self < 0 { "" } else { "+" };
-        format!(":{}\r\n", self).into_bytes()
+    fn encode_to(&self, buf: &mut BytesMut) {
+        // writing into BytesMut never fails
+        let _ = write!(buf, ":{}\r\n", self);
     }
 }
 
 impl RespDecode for i64 {
     const PREFIX: &'static str = ":";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (s, len) = split_cr_lf(data)?;
         let res =
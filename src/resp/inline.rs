@@ -0,0 +1,223 @@
+use super::*;
+
+// same as PROTO_INLINE_MAX_SIZE of Redis
+const INLINE_MAX_SIZE: usize = 64 * 1024;
+
+// InlineDecoder
+//
+// Decodes inline commands such as `SET foo "bar baz"`, as typed in telnet or netcat.
+// It remembers how much of the buffer was searched for the end of the line,
+// so a line received in many pieces is only scanned once.
+//
+// The same buffer must be passed to every call until a command is returned.
+#[derive(Debug, Default)]
+pub struct InlineDecoder {
+    scanned: usize,
+}
+
+impl InlineDecoder {
+    // Returns None until the whole line has arrived. A blank line decodes to an empty array.
+    pub fn decode(&mut self, data: &mut BytesMut) -> Result<Option<RespArray>, RespError> {
+        let Some(end) = data[self.scanned..].iter().position(|&b| b == b'\n') else {
+            if data.len() > INLINE_MAX_SIZE {
+                return Err(RespError::RespProtocolError(
+                    "too big inline request".to_string(),
+                ));
+            }
+            self.scanned = data.len();
+            return Ok(None);
+        };
+        let end = self.scanned + end;
+        self.scanned = 0;
+        let line = data.split_to(end + 1);
+        let line = &line[..end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let args = split_args(line)?
+            .into_iter()
+            .map(|arg| BulkString::new(arg).into())
+            .collect::<Vec<RespFrame>>();
+        Ok(Some(RespArray::with_vec(args)))
+    }
+}
+
+// Decode one inline command from the start of the buffer.
+pub fn decode_inline(data: &mut BytesMut) -> Result<Option<RespArray>, RespError> {
+    InlineDecoder::default().decode(data)
+}
+
+#[derive(PartialEq)]
+enum Quote {
+    None,
+    Double,
+    Single,
+}
+
+// Split the line into arguments the way redis-cli and Redis do:
+// arguments are separated by whitespace, double quoted strings support
+// `\n`, `\r`, `\t`, `\b`, `\a` and `\xHH` escapes, single quoted strings only `\'`.
+fn split_args(line: &[u8]) -> Result<Vec<Vec<u8>>, RespError> {
+    let unbalanced = || RespError::RespProtocolError("unbalanced quotes in request".to_string());
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < line.len() && is_separator(line[i]) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(args);
+        }
+
+        let mut arg = Vec::new();
+        let mut quote = Quote::None;
+        loop {
+            let Some(&c) = line.get(i) else {
+                if quote != Quote::None {
+                    return Err(unbalanced());
+                }
+                break;
+            };
+            match quote {
+                Quote::None => match c {
+                    c if is_separator(c) => break,
+                    b'"' => quote = Quote::Double,
+                    b'\'' => quote = Quote::Single,
+                    _ => arg.push(c),
+                },
+                Quote::Double => match c {
+                    b'\\'
+                        if i + 3 < line.len()
+                            && line[i + 1] == b'x'
+                            && line[i + 2].is_ascii_hexdigit()
+                            && line[i + 3].is_ascii_hexdigit() =>
+                    {
+                        arg.push(hex_value(line[i + 2]) * 16 + hex_value(line[i + 3]));
+                        i += 3;
+                    }
+                    b'\\' if i + 1 < line.len() => {
+                        i += 1;
+                        arg.push(match line[i] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            c => c,
+                        });
+                    }
+                    b'"' => {
+                        // the closing quote must be followed by a space or nothing
+                        if line.get(i + 1).is_some_and(|&c| !is_separator(c)) {
+                            return Err(unbalanced());
+                        }
+                        i += 1;
+                        break;
+                    }
+                    _ => arg.push(c),
+                },
+                Quote::Single => match c {
+                    b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                        arg.push(b'\'');
+                        i += 1;
+                    }
+                    b'\'' => {
+                        if line.get(i + 1).is_some_and(|&c| !is_separator(c)) {
+                            return Err(unbalanced());
+                        }
+                        i += 1;
+                        break;
+                    }
+                    _ => arg.push(c),
+                },
+            }
+            i += 1;
+        }
+        args.push(arg);
+    }
+}
+
+// the bytes which end an unquoted argument, same as sdssplitargs of Redis
+fn is_separator(c: u8) -> bool {
+    matches!(c, b' ' | b'\n' | b'\r' | b'\t' | b'\0')
+}
+
+fn hex_value(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        _ => c - b'A' + 10,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Result<Vec<Vec<u8>>, RespError> {
+        split_args(line.as_bytes())
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            args("SET foo bar").unwrap(),
+            vec![b"SET".to_vec(), b"foo".to_vec(), b"bar".to_vec()]
+        );
+        assert_eq!(
+            args("  get\tfoo  ").unwrap(),
+            vec![b"get".to_vec(), b"foo".to_vec()]
+        );
+        assert_eq!(args("").unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            args(r#"set "hello world" 'it\'s'"#).unwrap(),
+            vec![b"set".to_vec(), b"hello world".to_vec(), b"it's".to_vec()]
+        );
+        assert_eq!(
+            args(r#"set "a\x41\n\"" """#).unwrap(),
+            vec![b"set".to_vec(), b"aA\n\"".to_vec(), b"".to_vec()]
+        );
+        assert_eq!(
+            args(r#"set 'a\nb'"#).unwrap(),
+            vec![b"set".to_vec(), b"a\\nb".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_split_args_unbalanced() {
+        assert!(args(r#"set "foo"#).is_err());
+        assert!(args(r#"set "foo"bar"#).is_err());
+        assert!(args("set 'foo").is_err());
+    }
+
+    #[test]
+    fn test_decode_inline() {
+        let mut data = BytesMut::from("SET foo \"bar baz\"\r\nGET");
+        let res = decode_inline(&mut data).unwrap().unwrap();
+        assert_eq!(
+            res,
+            RespArray::with_vec(vec![b"SET".into(), b"foo".into(), b"bar baz".into()])
+        );
+        assert_eq!(data.as_ref(), b"GET");
+
+        // wait for the end of the line
+        assert_eq!(decode_inline(&mut data).unwrap(), None);
+        data.extend_from_slice(b" foo\n");
+        let res = decode_inline(&mut data).unwrap().unwrap();
+        assert_eq!(res, RespArray::with_vec(vec![b"GET".into(), b"foo".into()]));
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_inline_decoder_resumes() {
+        let mut decoder = InlineDecoder::default();
+        let mut data = BytesMut::from("GET");
+        assert_eq!(decoder.decode(&mut data).unwrap(), None);
+        assert_eq!(decoder.scanned, 3);
+        data.extend_from_slice(b" foo\r\nPING\r\n");
+        let res = decoder.decode(&mut data).unwrap().unwrap();
+        assert_eq!(res, RespArray::with_vec(vec![b"GET".into(), b"foo".into()]));
+        assert_eq!(decoder.scanned, 0);
+        let res = decoder.decode(&mut data).unwrap().unwrap();
+        assert_eq!(res, RespArray::with_vec(vec![b"PING".into()]));
+        assert!(data.is_empty());
+    }
+}
@@ -1,49 +1,125 @@
 use super::*;
 use bytes::Buf;
 use macro_definitions::AutoDeref;
+use std::cmp::Ordering;
 use std::fmt::Display;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, AutoDeref)]
-pub struct RespMap(pub(crate) BTreeMap<String, RespFrame>);
+pub struct RespMap(pub(crate) BTreeMap<MapKey, RespFrame>);
+
+// MapKey
+//
+// A key of a map or an attribute, kept as the frame it was received as so it
+// is encoded back the same way. Keys of any string type compare by their
+// bytes, the other keys come after them and compare by their encoding.
+#[derive(Debug, Clone)]
+pub struct MapKey(pub(crate) RespFrame);
+
+impl MapKey {
+    pub fn frame(&self) -> &RespFrame {
+        &self.0
+    }
+
+    pub fn into_frame(self) -> RespFrame {
+        self.0
+    }
+
+    fn string(&self) -> Option<&[u8]> {
+        match &self.0 {
+            RespFrame::VerbatimString(v) => Some(v.data()),
+            frame => frame.as_bytes(),
+        }
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.string(), other.string()) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => self.0.encode().cmp(&other.0.encode()),
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+// a key which can't be a simple string is a bulk string
+impl From<String> for MapKey {
+    fn from(s: String) -> Self {
+        match s.contains(['\r', '\n']) {
+            true => MapKey(BulkString::new(s).into()),
+            false => MapKey(SimpleString::new(s).into()),
+        }
+    }
+}
+
+impl From<&str> for MapKey {
+    fn from(s: &str) -> Self {
+        s.to_string().into()
+    }
+}
+
+impl From<RespFrame> for MapKey {
+    fn from(frame: RespFrame) -> Self {
+        MapKey(frame)
+    }
+}
+
+impl Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 // %<number-of-entries>\r\n<key-1><value-1>...<key-n><value-n>
 impl RespEncode for RespMap {
-    fn encode(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.extend_from_slice(format!("%{}\r\n", self.len()).as_bytes());
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_header(buf, b'%', self.len());
         for (k, v) in self.iter() {
-            buf.extend_from_slice(SimpleString::new(k).encode().as_slice());
-            buf.extend_from_slice(v.encode().as_slice());
+            k.0.encode_to(buf);
+            v.encode_to(buf);
         }
-        buf
     }
 }
 
 impl RespDecode for RespMap {
     const PREFIX: &'static str = "%";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (len, pos) = parse_length(data)?;
         data.advance(pos);
-        let mut rm = RespMap::new();
-        for _ in 0..len {
-            let key = RespFrame::decode(data).map_err(|e| e.map_not_complete())?;
-            let value = RespFrame::decode(data).map_err(|e| e.map_not_complete())?;
-            match key {
-                RespFrame::SimpleString(k) => {
-                    rm.insert(k.0, value);
-                }
-                _ => {
-                    return Err(RespError::RespInvalid(
-                        "map key must be simple string".to_string(),
-                    ))
-                }
-            }
-        }
-        Ok(rm)
+        Ok(RespMap(parse_entries(data, len)?))
     }
 }
 
+// Decode the key-value pairs of a map or an attribute, the keys may be of any type.
+pub(super) fn parse_entries(
+    data: &mut BytesMut,
+    len: i64,
+) -> Result<BTreeMap<MapKey, RespFrame>, RespError> {
+    let mut entries = BTreeMap::new();
+    for _ in 0..len {
+        let key = RespFrame::parse(data)?;
+        let value = RespFrame::parse(data)?;
+        entries.insert(MapKey(key), value);
+    }
+    Ok(entries)
+}
+
 impl Display for RespMap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{{")?;
@@ -62,16 +138,29 @@ impl RespMap {
         RespMap(BTreeMap::new())
     }
 
-    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<RespFrame>) {
+    pub fn insert(&mut self, key: impl Into<MapKey>, value: impl Into<RespFrame>) {
         self.0.insert(key.into(), value.into());
     }
 
-    pub fn with_map(m: impl Into<BTreeMap<String, RespFrame>>) -> Self {
-        RespMap(m.into())
+    // the value of a string key, whichever string type the key is
+    pub fn get(&self, key: &str) -> Option<&RespFrame> {
+        self.0.get(&MapKey::from(key))
+    }
+
+    pub fn with_map<K: Into<MapKey>>(m: impl IntoIterator<Item = (K, RespFrame)>) -> Self {
+        RespMap(m.into_iter().map(|(k, v)| (k.into(), v)).collect())
     }
 
     pub fn with_vec(v: impl Into<Vec<(String, RespFrame)>>) -> Self {
-        RespMap(v.into().into_iter().collect())
+        Self::with_map(v.into())
+    }
+}
+
+impl std::ops::Index<&str> for RespMap {
+    type Output = RespFrame;
+
+    fn index(&self, key: &str) -> &RespFrame {
+        self.get(key).expect("no entry found for key")
     }
 }
 
@@ -119,4 +208,27 @@ mod test {
         let err = RespMap::decode(&mut data).unwrap_err();
         assert_ne!(err, RespError::RespNotComplete);
     }
+
+    #[test]
+    fn test_resp_map_decode_bulk_string_key() {
+        let mut data = BytesMut::from("%1\r\n$5\r\nproto\r\n:3\r\n");
+        let res = RespMap::decode(&mut data).unwrap();
+        assert_eq!(res.get("proto"), Some(&RespFrame::Integer(3)));
+    }
+
+    #[test]
+    fn test_resp_map_keys_keep_their_type() {
+        // binary, multi-line and integer keys are encoded back as they came
+        let encoded: &[u8] = b"%3\r\n$4\r\na\r\nb\r\n:2\r\n$2\r\n\xff\x00\r\n:1\r\n:7\r\n:3\r\n";
+        let mut data = BytesMut::from(encoded);
+        let res = RespMap::decode(&mut data).unwrap();
+        assert_eq!(res.encode(), encoded);
+        assert_eq!(res.get("a\r\nb"), Some(&RespFrame::Integer(2)));
+        let key = MapKey::from(RespFrame::Integer(7));
+        assert_eq!(res.0.get(&key), Some(&RespFrame::Integer(3)));
+
+        let mut rm = RespMap::new();
+        rm.insert("a\r\nb", RespFrame::Integer(1));
+        assert_eq!(rm.encode(), b"%1\r\n$4\r\na\r\nb\r\n:1\r\n");
+    }
 }
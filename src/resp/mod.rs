@@ -1,47 +1,110 @@
 mod array;
+mod attribute;
+mod big_number;
 mod bool;
+mod bulk_error;
 mod bulk_string;
+mod convert;
+mod de;
+mod decoder;
 mod double;
 mod frame;
 mod i64;
+mod inline;
 mod map;
 mod null;
+mod push;
+mod ser;
 mod set;
 mod simple_error;
 mod simple_string;
+mod verbatim_string;
 
-use bytes::BytesMut;
+use bytes::{BufMut, BytesMut};
 use core::f64;
 use enum_dispatch::enum_dispatch;
 use std::collections::BTreeMap;
+use std::fmt::Write;
 
 pub const DEFAULT_CAPACITY: usize = 32;
 
+// used by the code `#[derive(RespEncode, RespDecode)]` generates
+#[doc(hidden)]
+pub use self::convert::{flatten_frame, frame_items, frame_variant, FrameFields};
+
 pub use self::{
     array::RespArray,
+    attribute::RespAttribute,
+    big_number::BigNumber,
+    bulk_error::BulkError,
     bulk_string::BulkString,
+    convert::{FromFrame, ToFrame},
+    de::from_frame,
+    decoder::{RespDecoder, RespLimits},
     frame::{RespError, RespFrame},
-    map::RespMap,
+    inline::{decode_inline, InlineDecoder},
+    map::{MapKey, RespMap},
     null::RespNull,
+    push::RespPush,
+    ser::to_frame,
     set::RespSet,
     simple_error::SimpleError,
     simple_string::SimpleString,
+    verbatim_string::VerbatimString,
 };
 
 #[enum_dispatch]
 pub trait RespEncode {
-    fn encode(&self) -> Vec<u8>;
+    // Write the encoded frame straight into the buffer, nested frames
+    // share the same buffer so there is no intermediate allocation.
+    fn encode_to(&self, buf: &mut BytesMut);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(DEFAULT_CAPACITY);
+        self.encode_to(&mut buf);
+        buf.into()
+    }
 }
 
 // RespDecode trait
 //
-// The trait is used to decode RESP frame from BytesMut,
-// whose length will be zero if successfully decoded.
+// The trait is used to decode RESP frame from BytesMut.
+// `decode` only consumes the bytes of the frame once the whole frame has arrived,
+// `parse` does the actual work on a frame which is known to be complete.
 pub trait RespDecode: Sized {
     const PREFIX: &'static str;
-    // TODO
-    // reactor the signature of decode method from data:&mut BytesMut to mut data: BytesMut
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError>;
+
+    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+        if data.is_empty() {
+            return Err(RespError::RespNotComplete);
+        }
+        if !data.starts_with(Self::PREFIX.as_bytes()) {
+            return Err(RespError::RespInvalid(format!(
+                "expect prefix {}",
+                Self::PREFIX
+            )));
+        }
+        let len = RespDecoder::new()
+            .scan(data)?
+            .ok_or(RespError::RespNotComplete)?;
+        Self::parse(&mut data.split_to(len))
+    }
+
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError>;
+}
+
+// <prefix><length>\r\n
+fn write_header(buf: &mut BytesMut, prefix: u8, len: usize) {
+    buf.put_u8(prefix);
+    // writing into BytesMut never fails
+    let _ = write!(buf, "{}\r\n", len);
+}
+
+// <prefix><data>\r\n
+fn write_line(buf: &mut BytesMut, prefix: u8, data: &[u8]) {
+    buf.put_u8(prefix);
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(b"\r\n");
 }
 
 fn split_cr_lf(data: &BytesMut) -> Result<(&[u8], usize), RespError> {
@@ -60,9 +123,25 @@ fn split_cr_lf(data: &BytesMut) -> Result<(&[u8], usize), RespError> {
 fn parse_length(data: &BytesMut) -> Result<(i64, usize), RespError> {
     let (s, pos) = split_cr_lf(data)?;
     // If CR LF is found, it means the length is valid.
-    let s = String::from_utf8_lossy(s);
-    let len = s
-        .parse()
-        .map_err(|_| RespError::RespInvalid("Invalid length".to_string()))?;
+    let len = std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| RespError::RespInvalid("Invalid length".to_string()))?;
     Ok((len, pos))
 }
+
+// Split a length-prefixed payload such as a bulk error or a verbatim string,
+// the payload may contain CR LF so we rely on the length instead of searching.
+fn split_blob(data: &BytesMut, len: i64) -> Result<(&[u8], usize), RespError> {
+    if len < 0 {
+        return Err(RespError::RespInvalid(format!("Invalid length {}", len)));
+    }
+    let len = len as usize;
+    if data.len() < len + 2 {
+        return Err(RespError::RespNotComplete);
+    }
+    if &data[len..len + 2] != b"\r\n" {
+        return Err(RespError::RespInvalid("Missing CR LF".to_string()));
+    }
+    Ok((&data[..len], len + 2))
+}
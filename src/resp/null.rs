@@ -6,17 +6,17 @@ use std::fmt::Display;
 pub struct RespNull();
 
 impl RespEncode for RespNull {
-    fn encode(&self) -> Vec<u8> {
-        b"_\r\n".to_vec()
+    fn encode_to(&self, buf: &mut BytesMut) {
+        buf.extend_from_slice(b"_\r\n");
     }
 }
 
 impl RespDecode for RespNull {
     const PREFIX: &'static str = "_";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (s, pos) = split_cr_lf(data)?;
-        if s.is_empty() {
+        if !s.is_empty() {
             return Err(RespError::RespInvalid("Invalid null".to_string()));
         }
         data.advance(pos);
@@ -35,3 +35,19 @@ impl Display for RespNull {
         write!(f, "null")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resp_null_decode() {
+        let mut data = BytesMut::from("_\r\n");
+        let res = RespNull::decode(&mut data).unwrap();
+        assert_eq!(data.len(), 0);
+        assert_eq!(res.encode(), b"_\r\n");
+
+        let mut data = BytesMut::from("_x\r\n");
+        assert!(RespNull::decode(&mut data).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use super::*;
+use bytes::Buf;
+use macro_definitions::AutoDeref;
+use std::fmt::Display;
+
+// Out-of-band data sent by the server, such as pub/sub messages.
+#[derive(Debug, Clone, PartialEq, PartialOrd, AutoDeref)]
+#[deref(mutable)]
+pub struct RespPush(pub(crate) Vec<RespFrame>);
+
+// ><number-of-elements>\r\n<element-1>...<element-n>
+impl RespEncode for RespPush {
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_header(buf, b'>', self.len());
+        for frame in self.iter() {
+            frame.encode_to(buf);
+        }
+    }
+}
+
+impl RespDecode for RespPush {
+    const PREFIX: &'static str = ">";
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
+        data.advance(Self::PREFIX.len());
+        let (len, pos) = parse_length(data)?;
+        data.advance(pos);
+        let mut rp = RespPush::new();
+        for _ in 0..len {
+            let frame = RespFrame::parse(data)?;
+            rp.push(frame);
+        }
+        Ok(rp)
+    }
+}
+
+impl RespPush {
+    pub fn new() -> Self {
+        RespPush(Vec::new())
+    }
+
+    pub fn with_vec(v: impl Into<Vec<RespFrame>>) -> Self {
+        RespPush(v.into())
+    }
+}
+
+impl Default for RespPush {
+    fn default() -> Self {
+        RespPush::new()
+    }
+}
+
+impl Display for RespPush {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resp_push_decode() {
+        let mut data = BytesMut::from(">3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$5\r\nhello\r\n");
+        let res = RespPush::decode(&mut data).unwrap();
+        assert_eq!(data.len(), 0);
+        assert_eq!(
+            res,
+            RespPush::with_vec(vec![b"message".into(), b"ch".into(), b"hello".into()])
+        );
+        assert_eq!(
+            res.encode(),
+            b">3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$5\r\nhello\r\n"
+        );
+
+        let mut data = BytesMut::from(">2\r\n$7\r\nmessage\r\n");
+        let err = RespPush::decode(&mut data).unwrap_err();
+        assert_eq!(err, RespError::RespNotComplete);
+    }
+}
@@ -0,0 +1,525 @@
+use super::*;
+use serde::ser::{self, Serialize};
+
+// Serialize a value into a frame: maps and structs become `RespMap`, sequences
+// and tuples `RespArray`, floats `Double`, `None` and `()` `RespNull`, strings
+// and bytes `BulkString`. Enums are tagged like `#[derive(RespEncode)]` does.
+pub fn to_frame<T: Serialize + ?Sized>(value: &T) -> Result<RespFrame, RespError> {
+    value.serialize(FrameSerializer)
+}
+
+impl ser::Error for RespError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        RespError::RespInvalid(msg.to_string())
+    }
+}
+
+// a data variant is a map of its name to its value
+fn tagged(variant: &str, value: RespFrame) -> RespFrame {
+    let mut map = RespMap::new();
+    map.insert(variant, value);
+    map.into()
+}
+
+fn integer(v: i128) -> RespFrame {
+    match i64::try_from(v) {
+        Ok(v) => RespFrame::Integer(v),
+        Err(_) => BigNumber::new(v.to_string()).into(),
+    }
+}
+
+pub struct FrameSerializer;
+
+impl ser::Serializer for FrameSerializer {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeArray;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<RespFrame, RespError> {
+        Ok(integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Integer(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<RespFrame, RespError> {
+        Ok(integer(v.into()))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<RespFrame, RespError> {
+        match i128::try_from(v) {
+            Ok(v) => Ok(integer(v)),
+            Err(_) => Ok(BigNumber::new(v.to_string()).into()),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Double(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<RespFrame, RespError> {
+        Ok(RespFrame::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<RespFrame, RespError> {
+        Ok(BulkString::new(v.to_string()).into())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<RespFrame, RespError> {
+        Ok(BulkString::new(v).into())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<RespFrame, RespError> {
+        Ok(BulkString::new(v).into())
+    }
+
+    fn serialize_none(self) -> Result<RespFrame, RespError> {
+        Ok(RespNull::new().into())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<RespFrame, RespError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<RespFrame, RespError> {
+        Ok(RespNull::new().into())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<RespFrame, RespError> {
+        Ok(RespNull::new().into())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<RespFrame, RespError> {
+        Ok(SimpleString::new(variant).into())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<RespFrame, RespError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<RespFrame, RespError> {
+        Ok(tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, RespError> {
+        Ok(SerializeArray::new(None, len.unwrap_or_default()))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, RespError> {
+        Ok(SerializeArray::new(None, len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, RespError> {
+        Ok(SerializeArray::new(None, len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, RespError> {
+        Ok(SerializeArray::new(Some(variant), len))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, RespError> {
+        Ok(SerializeMap::new(None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<SerializeMap, RespError> {
+        Ok(SerializeMap::new(None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, RespError> {
+        Ok(SerializeMap::new(Some(variant)))
+    }
+}
+
+// SerializeArray
+//
+// The items of a sequence, a tuple or a tuple variant, `variant` tags the array.
+pub struct SerializeArray {
+    variant: Option<&'static str>,
+    items: Vec<RespFrame>,
+}
+
+impl SerializeArray {
+    fn new(variant: Option<&'static str>, len: usize) -> Self {
+        SerializeArray {
+            variant,
+            // the length comes from the value, don't trust it too much
+            items: Vec::with_capacity(len.min(DEFAULT_CAPACITY)),
+        }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespError> {
+        self.items.push(value.serialize(FrameSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<RespFrame, RespError> {
+        let array = RespArray::with_vec(self.items).into();
+        Ok(match self.variant {
+            Some(variant) => tagged(variant, array),
+            None => array,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeArray {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+// SerializeMap
+//
+// The entries of a map, a struct or a struct variant, `variant` tags the map.
+// Map keys must be strings, numbers or booleans, they are stored as strings.
+pub struct SerializeMap {
+    variant: Option<&'static str>,
+    map: RespMap,
+    key: Option<String>,
+}
+
+impl SerializeMap {
+    fn new(variant: Option<&'static str>) -> Self {
+        SerializeMap {
+            variant,
+            map: RespMap::new(),
+            key: None,
+        }
+    }
+
+    fn finish(self) -> Result<RespFrame, RespError> {
+        Ok(match self.variant {
+            Some(variant) => tagged(variant, self.map.into()),
+            None => self.map.into(),
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), RespError> {
+        let key = match key.serialize(FrameSerializer)? {
+            RespFrame::Integer(i) => i.to_string(),
+            RespFrame::Boolean(b) => b.to_string(),
+            RespFrame::BigNumber(n) => n.0,
+            RespFrame::SimpleString(s) => s.0,
+            RespFrame::BulkString(BulkString(Some(v))) => String::from_utf8(v.to_vec())
+                .map_err(|_| RespError::RespInvalid("map key must be a string".to_string()))?,
+            _ => {
+                return Err(RespError::RespInvalid(
+                    "map key must be a string".to_string(),
+                ))
+            }
+        };
+        self.key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), RespError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| RespError::RespInvalid("map value without a key".to_string()))?;
+        self.map.insert(key, value.serialize(FrameSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), RespError> {
+        self.map.insert(key, value.serialize(FrameSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = RespFrame;
+    type Error = RespError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), RespError> {
+        self.map.insert(key, value.serialize(FrameSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespFrame, RespError> {
+        self.finish()
+    }
+}
+
+// A frame as the value it carries, so it can be dumped to JSON for instance.
+// Errors become a map with an `error` entry to tell them from strings.
+impl Serialize for RespFrame {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{SerializeMap, SerializeSeq};
+
+        match self {
+            RespFrame::SimpleString(s) => serializer.serialize_str(&s.0),
+            RespFrame::Error(SimpleError(e)) | RespFrame::BulkError(BulkError(e)) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("error", e)?;
+                map.end()
+            }
+            RespFrame::Integer(i) => serializer.serialize_i64(*i),
+            RespFrame::BulkString(BulkString(Some(v))) => match std::str::from_utf8(v) {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(v),
+            },
+            RespFrame::BulkString(BulkString(None))
+            | RespFrame::Array(RespArray(None))
+            | RespFrame::Null(_) => serializer.serialize_none(),
+            RespFrame::Array(RespArray(Some(v)))
+            | RespFrame::Set(RespSet(v))
+            | RespFrame::Push(RespPush(v)) => {
+                let mut seq = serializer.serialize_seq(Some(v.len()))?;
+                for frame in v {
+                    seq.serialize_element(frame)?;
+                }
+                seq.end()
+            }
+            RespFrame::Boolean(b) => serializer.serialize_bool(*b),
+            RespFrame::Double(d) => serializer.serialize_f64(*d),
+            RespFrame::Map(m) => {
+                let mut map = serializer.serialize_map(Some(m.len()))?;
+                for (k, v) in m.iter() {
+                    map.serialize_entry(k.frame(), v)?;
+                }
+                map.end()
+            }
+            RespFrame::BigNumber(n) => serializer.serialize_str(&n.0),
+            RespFrame::VerbatimString(v) => match std::str::from_utf8(v.data()) {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(v.data()),
+            },
+            RespFrame::Attribute(a) => a.frame.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Serialize)]
+    struct User {
+        name: String,
+        age: u32,
+        email: Option<String>,
+        scores: Vec<f64>,
+        #[serde(with = "serde_bytes_like")]
+        avatar: Vec<u8>,
+    }
+
+    // serialize the bytes as bytes rather than a sequence of integers
+    mod serde_bytes_like {
+        pub fn serialize<S: serde::Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_bytes(v)
+        }
+    }
+
+    #[derive(Serialize)]
+    enum Event {
+        Ping,
+        Move(i64, i64),
+        Rename { from: String },
+    }
+
+    #[test]
+    fn test_to_frame() {
+        let user = User {
+            name: "alice".to_string(),
+            age: 30,
+            email: None,
+            scores: vec![1.5],
+            avatar: vec![0xff, 0x00],
+        };
+        let mut expected = RespMap::new();
+        expected.insert("name", BulkString::new("alice"));
+        expected.insert("age", RespFrame::Integer(30));
+        expected.insert("email", RespNull::new());
+        expected.insert("scores", RespArray::with_vec(vec![RespFrame::Double(1.5)]));
+        expected.insert("avatar", BulkString::new(vec![0xff, 0x00]));
+        assert_eq!(to_frame(&user), Ok(expected.into()));
+
+        assert_eq!(to_frame(&Event::Ping), Ok(SimpleString::new("Ping").into()));
+        let mut expected = RespMap::new();
+        expected.insert(
+            "Move",
+            RespArray::with_vec(vec![RespFrame::Integer(1), RespFrame::Integer(2)]),
+        );
+        assert_eq!(to_frame(&Event::Move(1, 2)), Ok(expected.into()));
+        let mut inner = RespMap::new();
+        inner.insert("from", BulkString::new("a"));
+        let mut expected = RespMap::new();
+        expected.insert("Rename", inner);
+        let event = Event::Rename {
+            from: "a".to_string(),
+        };
+        assert_eq!(to_frame(&event), Ok(expected.into()));
+
+        assert_eq!(
+            to_frame(&u64::MAX),
+            Ok(BigNumber::new(u64::MAX.to_string()).into())
+        );
+        let keys = HashMap::from([(1, true)]);
+        let mut expected = RespMap::new();
+        expected.insert("1", RespFrame::Boolean(true));
+        assert_eq!(to_frame(&keys), Ok(expected.into()));
+        assert!(to_frame(&HashMap::from([((1, 2), 3)])).is_err());
+    }
+
+    #[test]
+    fn test_frame_to_json() {
+        let mut map = RespMap::new();
+        map.insert("ok", RespFrame::Boolean(true));
+        map.insert("err", SimpleError::new("ERR oops"));
+        map.insert(
+            "items",
+            RespArray::with_vec(vec![
+                RespFrame::Integer(1),
+                BulkString::new("two").into(),
+                RespNull::new().into(),
+                RespFrame::Double(3.5),
+            ]),
+        );
+        let json = serde_json::to_string(&RespFrame::from(map)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"err":{"error":"ERR oops"},"items":[1,"two",null,3.5],"ok":true}"#
+        );
+    }
+}
@@ -9,15 +9,11 @@ use std::fmt::Display;
 pub struct RespSet(pub(crate) Vec<RespFrame>);
 
 impl RespEncode for RespSet {
-    fn encode(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(DEFAULT_CAPACITY);
-        buf.extend_from_slice(b"~");
-        buf.extend_from_slice(self.len().to_string().as_bytes());
-        buf.extend_from_slice(b"\r\n");
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_header(buf, b'~', self.len());
         for frame in self.iter() {
-            buf.extend_from_slice(frame.encode().as_slice());
+            frame.encode_to(buf);
         }
-        buf
     }
 }
 
@@ -38,13 +34,13 @@ impl Default for RespSet {
 
 impl RespDecode for RespSet {
     const PREFIX: &'static str = "~";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (len, pos) = parse_length(data)?;
         data.advance(pos);
         let mut rs = RespSet::new();
         for _ in 0..len {
-            let frame = RespFrame::decode(data).map_err(|e| e.map_not_complete())?;
+            let frame = RespFrame::parse(data)?;
             rs.push(frame)
         }
         Ok(rs)
@@ -4,21 +4,17 @@ use macro_definitions::AutoDeref;
 use std::fmt::Display;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, AutoDeref)]
-pub struct SimpleError(String);
+pub struct SimpleError(pub(crate) String);
 
 impl RespEncode for SimpleError {
-    fn encode(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(DEFAULT_CAPACITY);
-        buf.extend_from_slice(b"-");
-        buf.extend_from_slice(self.as_bytes());
-        buf.extend_from_slice(b"\r\n");
-        buf
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_line(buf, b'-', self.as_bytes());
     }
 }
 
 impl RespDecode for SimpleError {
     const PREFIX: &'static str = "-";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (s, len) = split_cr_lf(data)?;
         let res = SimpleError::new(String::from_utf8_lossy(s).to_string());
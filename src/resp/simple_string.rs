@@ -7,12 +7,8 @@ use std::fmt::{Display, Formatter};
 pub struct SimpleString(pub(crate) String);
 
 impl RespEncode for SimpleString {
-    fn encode(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(DEFAULT_CAPACITY);
-        buf.extend_from_slice(b"+");
-        buf.extend_from_slice(self.as_bytes());
-        buf.extend_from_slice(b"\r\n");
-        buf
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_line(buf, b'+', self.as_bytes());
     }
 }
 
@@ -20,7 +16,7 @@ impl RespEncode for SimpleString {
 //
 impl RespDecode for SimpleString {
     const PREFIX: &'static str = "+";
-    fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
         data.advance(Self::PREFIX.len());
         let (s, len) = split_cr_lf(data)?;
         let res = SimpleString::new(String::from_utf8_lossy(s).to_string());
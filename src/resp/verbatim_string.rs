@@ -0,0 +1,82 @@
+use super::*;
+use bytes::Buf;
+use std::fmt::Display;
+
+// A verbatim string carries a three bytes format such as `txt` or `mkd`.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct VerbatimString {
+    pub(crate) format: [u8; 3],
+    pub(crate) data: Vec<u8>,
+}
+
+// =<length>\r\n<format>:<data>\r\n
+impl RespEncode for VerbatimString {
+    fn encode_to(&self, buf: &mut BytesMut) {
+        write_header(buf, b'=', self.data.len() + 4);
+        buf.extend_from_slice(&self.format);
+        buf.extend_from_slice(b":");
+        buf.extend_from_slice(&self.data);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+impl RespDecode for VerbatimString {
+    const PREFIX: &'static str = "=";
+    fn parse(data: &mut BytesMut) -> Result<Self, RespError> {
+        data.advance(Self::PREFIX.len());
+        let (len, pos) = parse_length(data)?;
+        data.advance(pos);
+        let (s, pos) = split_blob(data, len)?;
+        if s.len() < 4 || s[3] != b':' {
+            return Err(RespError::RespInvalid(
+                "Invalid verbatim string".to_string(),
+            ));
+        }
+        let res = VerbatimString {
+            format: [s[0], s[1], s[2]],
+            data: s[4..].to_vec(),
+        };
+        data.advance(pos);
+        Ok(res)
+    }
+}
+
+impl VerbatimString {
+    pub fn new(format: [u8; 3], data: impl Into<Vec<u8>>) -> Self {
+        VerbatimString {
+            format,
+            data: data.into(),
+        }
+    }
+
+    pub fn format(&self) -> &[u8] {
+        &self.format
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Display for VerbatimString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verbatim_string_decode() {
+        let mut data = BytesMut::from("=15\r\ntxt:Some string\r\n");
+        let res = VerbatimString::decode(&mut data).unwrap();
+        assert_eq!(data.len(), 0);
+        assert_eq!(res, VerbatimString::new(*b"txt", "Some string"));
+        assert_eq!(res.encode(), b"=15\r\ntxt:Some string\r\n");
+
+        let mut data = BytesMut::from("=3\r\ntxt\r\n");
+        assert!(VerbatimString::decode(&mut data).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{BulkString, RespArray, RespFrame, RespPush, SimpleError, SimpleString};
+use mlua::{Lua, Table, Value};
+
+// Convert a command reply to a Lua value, following the rules of Redis:
+//
+// - integer -> number
+// - bulk string -> string, null bulk string / null array / null -> false
+// - array -> table
+// - simple string -> table with a single `ok` field
+// - error -> table with a single `err` field
+// - double -> table with a single `double` field
+// - map -> table with a single `map` field holding the key-value pairs
+// - set -> table with a single `set` field holding members mapped to true
+// - big number -> table with a single `big_number` field
+// - verbatim string -> table with a single `verbatim_string` field holding `format` and `string`
+// - bulk error -> table with a single `err` field
+// - push -> table
+// - attribute -> the attributed value, attributes are ignored
+pub(crate) fn frame_to_lua<'lua>(lua: &'lua Lua, frame: RespFrame) -> mlua::Result<Value<'lua>> {
+    let value = match frame {
+        RespFrame::Integer(i) => Value::Integer(i),
+        RespFrame::Boolean(b) => Value::Boolean(b),
+        RespFrame::BulkString(BulkString(Some(v))) => Value::String(lua.create_string(v)?),
+        RespFrame::BulkString(BulkString(None))
+        | RespFrame::Array(RespArray(None))
+        | RespFrame::Null(_) => Value::Boolean(false),
+        RespFrame::Array(RespArray(Some(v))) | RespFrame::Push(RespPush(v)) => {
+            let t = lua.create_table_with_capacity(v.len(), 0)?;
+            for frame in v {
+                t.raw_push(frame_to_lua(lua, frame)?)?;
+            }
+            Value::Table(t)
+        }
+        RespFrame::SimpleString(s) => {
+            single_field(lua, "ok", Value::String(lua.create_string(s.as_str())?))?
+        }
+        RespFrame::Error(e) => {
+            single_field(lua, "err", Value::String(lua.create_string(e.as_str())?))?
+        }
+        RespFrame::Double(d) => single_field(lua, "double", Value::Number(d))?,
+        RespFrame::Map(m) => {
+            let t = lua.create_table()?;
+            for (k, v) in m.0 {
+                // string keys are plain strings rather than status tables
+                let k = match k.frame().as_bytes() {
+                    Some(k) => Value::String(lua.create_string(k)?),
+                    None => frame_to_lua(lua, k.0)?,
+                };
+                t.raw_set(k, frame_to_lua(lua, v)?)?;
+            }
+            single_field(lua, "map", Value::Table(t))?
+        }
+        RespFrame::Set(s) => {
+            let t = lua.create_table()?;
+            for member in s.0 {
+                t.raw_set(frame_to_lua(lua, member)?, true)?;
+            }
+            single_field(lua, "set", Value::Table(t))?
+        }
+        RespFrame::BigNumber(n) => single_field(
+            lua,
+            "big_number",
+            Value::String(lua.create_string(n.as_str())?),
+        )?,
+        RespFrame::VerbatimString(v) => {
+            let t = lua.create_table()?;
+            t.raw_set("format", lua.create_string(v.format())?)?;
+            t.raw_set("string", lua.create_string(v.data())?)?;
+            single_field(lua, "verbatim_string", Value::Table(t))?
+        }
+        RespFrame::BulkError(e) => {
+            single_field(lua, "err", Value::String(lua.create_string(e.as_str())?))?
+        }
+        RespFrame::Attribute(a) => frame_to_lua(lua, *a.frame)?,
+    };
+    Ok(value)
+}
+
+// Convert the value returned by a script to a reply, following the rules of Redis:
+//
+// - number -> integer, the decimal part is truncated
+// - string -> bulk string
+// - table with an `ok` field -> simple string
+// - table with an `err` field -> error
+// - table -> array, stopping at the first nil
+// - true -> integer 1, false and nil -> null bulk string
+pub(crate) fn lua_to_frame(value: Value) -> RespFrame {
+    match value {
+        Value::Boolean(true) => RespFrame::Integer(1),
+        Value::Integer(i) => RespFrame::Integer(i),
+        Value::Number(n) => RespFrame::Integer(n as i64),
+        Value::String(s) => BulkString::new(s.as_bytes()).into(),
+        Value::Table(t) => table_to_frame(t),
+        Value::Error(e) => SimpleError::new(e.to_string()).into(),
+        _ => BulkString::new_null().into(),
+    }
+}
+
+fn table_to_frame(t: Table) -> RespFrame {
+    if let Ok(Value::String(s)) = t.raw_get("err") {
+        return SimpleError::new(s.to_string_lossy()).into();
+    }
+    if let Ok(Value::String(s)) = t.raw_get("ok") {
+        return SimpleString::new(s.to_string_lossy()).into();
+    }
+    let mut res = Vec::new();
+    for i in 1.. {
+        match t.raw_get::<_, Value>(i) {
+            Ok(Value::Nil) | Err(_) => break,
+            Ok(v) => res.push(lua_to_frame(v)),
+        }
+    }
+    RespArray::with_vec(res).into()
+}
+
+fn single_field<'lua>(lua: &'lua Lua, name: &str, value: Value<'lua>) -> mlua::Result<Value<'lua>> {
+    let t = lua.create_table()?;
+    t.raw_set(name, value)?;
+    Ok(Value::Table(t))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RespMap;
+
+    #[test]
+    fn test_frame_to_lua() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let frame: RespFrame = RespArray::with_vec(vec![
+            RespFrame::Integer(1),
+            BulkString::new("a").into(),
+            BulkString::new_null().into(),
+            SimpleString::new("OK").into(),
+        ])
+        .into();
+        lua.globals().set("reply", frame_to_lua(&lua, frame)?)?;
+        let res: (i64, String, bool, String) = lua
+            .load("return reply[1], reply[2], reply[3], reply[4]['ok']")
+            .eval()?;
+        assert_eq!(res, (1, "a".to_string(), false, "OK".to_string()));
+
+        let mut map = RespMap::new();
+        map.insert("field", BulkString::new("value"));
+        lua.globals()
+            .set("reply", frame_to_lua(&lua, map.into())?)?;
+        let res: String = lua.load("return reply['map']['field']").eval()?;
+        assert_eq!(res, "value");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lua_to_frame() -> mlua::Result<()> {
+        let lua = Lua::new();
+        let value = lua
+            .load("return {1, 'a', {ok='fine'}, nil, 'unreachable'}")
+            .eval()?;
+        assert_eq!(
+            lua_to_frame(value),
+            RespArray::with_vec(vec![
+                RespFrame::Integer(1),
+                BulkString::new("a").into(),
+                SimpleString::new("fine").into(),
+            ])
+            .into()
+        );
+        assert_eq!(
+            lua_to_frame(lua.load("return false").eval()?),
+            BulkString::new_null().into()
+        );
+        assert_eq!(
+            lua_to_frame(lua.load("return true").eval()?),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(
+            lua_to_frame(lua.load("return {err='ERR bad'}").eval()?),
+            SimpleError::new("ERR bad").into()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,546 @@
+use super::{
+    enable_calls, invoke, new_lua, run_guarded, set_kill_hook, string_sequence, with_context,
+    Caller, Context, Mode, HOOK_INSTRUCTIONS,
+};
+use crate::rdb::{self, RDB_OPCODE_FUNCTION2};
+use crate::{Backend, RespFrame, SimpleError};
+use bytes::Bytes;
+use mlua::{ChunkMode, HookTriggers, Lua, Table, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// functions registered by the library code are kept in this registry table
+const FUNCTIONS_KEY: &str = "__functions";
+const ENGINE: &str = "LUA";
+// Library code runs under the gate and can't be killed, it's stopped once
+// it runs longer than this. The same as Redis.
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+#[derive(Debug, Clone)]
+pub struct Library {
+    pub name: String,
+    pub engine: String,
+    pub code: String,
+    pub functions: BTreeMap<String, FunctionInfo>,
+    // the interpreter the library code ran in once, which keeps its functions
+    lua: Arc<Mutex<Lua>>,
+}
+
+// libraries are the same when loaded from the same code
+impl PartialEq for Library {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.engine == other.engine
+            && self.code == other.code
+            && self.functions == other.functions
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+// RestorePolicy decides what FUNCTION RESTORE does with the existing libraries.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RestorePolicy {
+    // fail if a library already exists
+    #[default]
+    Append,
+    // replace the libraries with the same name
+    Replace,
+    // delete all the existing libraries first
+    Flush,
+}
+
+// FunctionRegistry holds the libraries loaded by FUNCTION LOAD.
+#[derive(Debug, Default)]
+pub struct FunctionRegistry {
+    libraries: Mutex<BTreeMap<String, Library>>,
+}
+
+impl FunctionRegistry {
+    // Load the library, returning its name.
+    pub fn load(&self, backend: &Backend, code: &str, replace: bool) -> Result<String, String> {
+        let library = compile(backend, code)?;
+        let mut libraries = self.libraries.lock().unwrap();
+        if !replace && libraries.contains_key(&library.name) {
+            return Err(format!("ERR Library '{}' already exists", library.name));
+        }
+        check_conflicts(&libraries, &library)?;
+        let name = library.name.clone();
+        libraries.insert(name.clone(), library);
+        Ok(name)
+    }
+
+    pub fn delete(&self, name: &str) -> bool {
+        self.libraries.lock().unwrap().remove(name).is_some()
+    }
+
+    pub fn flush(&self) {
+        self.libraries.lock().unwrap().clear();
+    }
+
+    pub fn list(&self) -> Vec<Library> {
+        self.libraries.lock().unwrap().values().cloned().collect()
+    }
+
+    // find the library the function belongs to
+    pub fn find(&self, function: &str) -> Option<(Library, FunctionInfo)> {
+        self.libraries
+            .lock()
+            .unwrap()
+            .values()
+            .find_map(|lib| Some((lib.clone(), lib.functions.get(function)?.clone())))
+    }
+
+    // Serialize all the libraries in the format of Redis FUNCTION DUMP.
+    pub fn dump(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for library in self.libraries.lock().unwrap().values() {
+            buf.push(RDB_OPCODE_FUNCTION2);
+            rdb::write_string(&mut buf, library.code.as_bytes());
+        }
+        rdb::seal_payload(buf)
+    }
+
+    pub fn restore(
+        &self,
+        backend: &Backend,
+        payload: &[u8],
+        policy: RestorePolicy,
+    ) -> Result<(), String> {
+        let mut data = rdb::open_payload(payload).map_err(|e| format!("ERR {}", e))?;
+        let mut restored = Vec::new();
+        while !data.is_empty() {
+            let opcode = rdb::read_u8(&mut data).map_err(|e| format!("ERR {}", e))?;
+            if opcode != RDB_OPCODE_FUNCTION2 {
+                return Err("ERR given type is not a function".to_string());
+            }
+            let code = rdb::read_string(&mut data).map_err(|e| format!("ERR {}", e))?;
+            let code = String::from_utf8(code)
+                .map_err(|_| "ERR library code is not valid UTF-8".to_string())?;
+            restored.push(compile(backend, &code)?);
+        }
+
+        let mut libraries = self.libraries.lock().unwrap();
+        let mut merged = match policy {
+            RestorePolicy::Flush => BTreeMap::new(),
+            _ => libraries.clone(),
+        };
+        for library in restored {
+            if policy == RestorePolicy::Append && merged.contains_key(&library.name) {
+                return Err(format!("ERR Library {} already exists", library.name));
+            }
+            merged.remove(&library.name);
+            check_conflicts(&merged, &library)?;
+            merged.insert(library.name.clone(), library);
+        }
+        *libraries = merged;
+        Ok(())
+    }
+}
+
+// Run FCALL or FCALL_RO.
+pub fn fcall(
+    backend: &Backend,
+    function: &str,
+    keys: &[Bytes],
+    args: &[Bytes],
+    read_only: bool,
+    caller: Caller,
+) -> RespFrame {
+    let (library, info) = match backend.functions.find(function) {
+        Some(v) => v,
+        None => return SimpleError::new("ERR Function not found").into(),
+    };
+    let no_writes = info.flags.iter().any(|f| f == "no-writes");
+    if read_only && !no_writes {
+        return SimpleError::new(
+            "ERR Can not execute a script with write flag using *_ro command.",
+        )
+        .into();
+    }
+    let mode = if no_writes {
+        Mode::ReadOnly
+    } else {
+        Mode::ReadWrite
+    };
+
+    let lua = library.lua.lock().unwrap();
+    run_guarded(backend, || {
+        let functions: Table = lua.named_registry_value(FUNCTIONS_KEY)?;
+        let entry: Table = functions.get(function)?;
+        let callback: mlua::Function = entry.get("callback")?;
+        let keys = string_sequence(&lua, keys)?;
+        let args = string_sequence(&lua, args)?;
+        with_context(&lua, backend, mode, caller, || {
+            invoke(&lua, callback, (keys, args))
+        })
+    })
+}
+
+// Build the redis.register_function function, which accepts both
+// `register_function(name, callback)` and
+// `register_function{function_name=..., callback=..., flags={...}, description=...}`.
+pub(crate) fn register_function(lua: &Lua) -> mlua::Result<mlua::Function<'_>> {
+    lua.create_function(|lua, args: mlua::Variadic<Value>| {
+        // the library code may have kept it for its functions
+        let loading = lua
+            .app_data_ref::<Context>()
+            .is_some_and(|ctx| ctx.mode == Mode::Load);
+        if !loading {
+            return Err(runtime_error(
+                "redis.register_function can only be called on FUNCTION LOAD command",
+            ));
+        }
+        let mut args = args.into_iter();
+        let (name, callback, flags, description) = match (args.next(), args.next(), args.next()) {
+            (Some(Value::String(name)), Some(Value::Function(callback)), None) => {
+                (name, callback, None, None)
+            }
+            (Some(Value::Table(t)), None, None) => {
+                let name = t.get::<_, Option<mlua::String>>("function_name")?;
+                let callback = t.get::<_, Option<mlua::Function>>("callback")?;
+                match (name, callback) {
+                    (Some(name), Some(callback)) => (
+                        name,
+                        callback,
+                        t.get::<_, Option<Table>>("flags")?,
+                        t.get::<_, Option<String>>("description")?,
+                    ),
+                    (None, _) => return Err(runtime_error("function_name argument given to redis.register_function must be a string")),
+                    (_, None) => return Err(runtime_error("callback argument given to redis.register_function must be a function")),
+                }
+            }
+            _ => return Err(runtime_error("wrong number of arguments to redis.register_function")),
+        };
+
+        let name = name.to_str()?.to_string();
+        if !is_valid_name(&name) {
+            return Err(runtime_error(
+                "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+            ));
+        }
+        let functions: Table = lua.named_registry_value(FUNCTIONS_KEY)?;
+        if functions.contains_key(name.as_str())? {
+            return Err(runtime_error("Function already exists in the library"));
+        }
+
+        let flag_list = lua.create_table()?;
+        if let Some(flags) = flags {
+            for flag in flags.sequence_values::<String>() {
+                let flag = flag?;
+                if !FLAGS.contains(&flag.as_str()) {
+                    return Err(runtime_error("unknown flag given"));
+                }
+                flag_list.raw_push(flag)?;
+            }
+        }
+        let entry = lua.create_table()?;
+        entry.set("callback", callback)?;
+        entry.set("flags", flag_list)?;
+        entry.set("description", description)?;
+        functions.set(name, entry)
+    })
+}
+
+// Parse the shebang and run the library code once, collecting its functions.
+// Only redis.register_function is available to the library code.
+fn compile(backend: &Backend, code: &str) -> Result<Library, String> {
+    let (name, body) = parse_metadata(code)?;
+    let lua = new_lua(Mode::Load).map_err(|e| format!("ERR {}", e))?;
+    let started = Instant::now();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        move |_, _| match started.elapsed() > LOAD_TIMEOUT {
+            true => Err(mlua::Error::RuntimeError(
+                "FUNCTION LOAD timeout".to_string(),
+            )),
+            false => Ok(()),
+        },
+    );
+    let load = || load_library(&lua, body);
+    let functions = with_context(&lua, backend, Mode::Load, Caller::Client, load).map_err(|e| {
+        format!(
+            "ERR Error registering functions: {}",
+            super::error_message(&e)
+        )
+    })?;
+    set_kill_hook(&lua);
+
+    let mut infos = BTreeMap::new();
+    for pair in functions.pairs::<String, Table>() {
+        let (fname, entry) = pair.map_err(|e| format!("ERR {}", e))?;
+        let flags: Vec<String> = entry
+            .get::<_, Table>("flags")
+            .and_then(|t| t.sequence_values().collect())
+            .map_err(|e| format!("ERR {}", e))?;
+        let description = entry
+            .get::<_, Option<String>>("description")
+            .map_err(|e| format!("ERR {}", e))?;
+        infos.insert(fname, FunctionInfo { description, flags });
+    }
+    if infos.is_empty() {
+        return Err("ERR No functions registered".to_string());
+    }
+    enable_calls(&lua).map_err(|e| format!("ERR {}", e))?;
+    Ok(Library {
+        name,
+        engine: ENGINE.to_string(),
+        code: code.to_string(),
+        functions: infos,
+        lua: Arc::new(Mutex::new(lua)),
+    })
+}
+
+fn load_library<'lua>(lua: &'lua Lua, body: &str) -> mlua::Result<Table<'lua>> {
+    lua.set_named_registry_value(FUNCTIONS_KEY, lua.create_table()?)?;
+    lua.load(body)
+        .set_name("@user_function")
+        .set_mode(ChunkMode::Text)
+        .exec()?;
+    lua.named_registry_value(FUNCTIONS_KEY)
+}
+
+// Parse `#!lua name=<library>`, returning the library name and the code.
+// The shebang line is blanked so line numbers in errors stay the same.
+fn parse_metadata(code: &str) -> Result<(String, &str), String> {
+    let line = match code.strip_prefix("#!") {
+        Some(rest) => rest.lines().next().unwrap_or_default(),
+        None => return Err("ERR Missing library metadata".to_string()),
+    };
+    let mut parts = line.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case(ENGINE) {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(n) => name = Some(n.to_string()),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+    let name = name.ok_or_else(|| "ERR Library name was not given".to_string())?;
+    if !is_valid_name(&name) {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+    let body = &code[line.len() + 2..];
+    Ok((name, body))
+}
+
+fn check_conflicts(libraries: &BTreeMap<String, Library>, library: &Library) -> Result<(), String> {
+    for other in libraries.values().filter(|l| l.name != library.name) {
+        if let Some(f) = library
+            .functions
+            .keys()
+            .find(|f| other.functions.contains_key(*f))
+        {
+            return Err(format!("ERR Function {} already exists", f));
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+fn runtime_error(msg: &str) -> mlua::Error {
+    mlua::Error::RuntimeError(msg.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BulkString;
+
+    const LIB: &str = "#!lua name=mylib\n\
+        redis.register_function('echo', function(keys, args) return args[1] end)\n\
+        redis.register_function{function_name='get', callback=function(keys) return redis.call('get', keys[1]) end, flags={'no-writes'}}\n\
+        redis.register_function{function_name='set', callback=function(keys, args) return redis.call('set', keys[1], args[1]) end, flags={'no-writes'}}";
+
+    #[test]
+    fn test_load_and_fcall() {
+        let backend = Backend::new();
+        let name = backend.functions.load(&backend, LIB, false).unwrap();
+        assert_eq!(name, "mylib");
+        assert_eq!(
+            backend.functions.load(&backend, LIB, false).unwrap_err(),
+            "ERR Library 'mylib' already exists"
+        );
+        assert!(backend.functions.load(&backend, LIB, true).is_ok());
+
+        let res = fcall(
+            &backend,
+            "echo",
+            &[],
+            &[Bytes::from_static(b"hello")],
+            false,
+            Caller::Client,
+        );
+        assert_eq!(res, BulkString::new("hello").into());
+
+        let res = fcall(&backend, "echo", &[], &[], true, Caller::Client);
+        assert_eq!(
+            res,
+            SimpleError::new("ERR Can not execute a script with write flag using *_ro command.")
+                .into()
+        );
+
+        backend.set(Bytes::from_static(b"k"), BulkString::new("v").into());
+        let res = fcall(
+            &backend,
+            "get",
+            &[Bytes::from_static(b"k")],
+            &[],
+            true,
+            Caller::Client,
+        );
+        assert_eq!(res, BulkString::new("v").into());
+
+        let res = fcall(
+            &backend,
+            "set",
+            &[Bytes::from_static(b"k")],
+            &[Bytes::from_static(b"x")],
+            false,
+            Caller::Client,
+        );
+        assert_eq!(
+            res,
+            SimpleError::new("ERR Write commands are not allowed from read-only scripts.").into()
+        );
+
+        let res = fcall(&backend, "missing", &[], &[], false, Caller::Client);
+        assert_eq!(res, SimpleError::new("ERR Function not found").into());
+    }
+
+    #[test]
+    fn test_library_runs_once() {
+        let backend = Backend::new();
+        let code = "#!lua name=counter
+            local count = 0
+            redis.register_function('incr', function() count = count + 1; return count end)";
+        backend.functions.load(&backend, code, false).unwrap();
+        // the functions keep the state of the library code, which isn't run again
+        assert_eq!(
+            fcall(&backend, "incr", &[], &[], false, Caller::Client),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(
+            fcall(&backend, "incr", &[], &[], false, Caller::Client),
+            RespFrame::Integer(2)
+        );
+
+        let code = "#!lua name=late
+            local register = redis.register_function
+            redis.register_function('f', function() return register('g', function() end) end)";
+        backend.functions.load(&backend, code, false).unwrap();
+        let res = fcall(&backend, "f", &[], &[], false, Caller::Client);
+        let msg = "redis.register_function can only be called on FUNCTION LOAD command";
+        assert!(
+            matches!(&res, RespFrame::Error(e) if e.0.ends_with(msg)),
+            "{:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let backend = Backend::new();
+        let registry = &backend.functions;
+        assert_eq!(
+            registry.load(&backend, "return 1", false).unwrap_err(),
+            "ERR Missing library metadata"
+        );
+        assert_eq!(
+            registry
+                .load(&backend, "#!js name=lib\n", false)
+                .unwrap_err(),
+            "ERR Engine 'js' not found"
+        );
+        assert_eq!(
+            registry.load(&backend, "#!lua\n", false).unwrap_err(),
+            "ERR Library name was not given"
+        );
+        assert_eq!(
+            registry
+                .load(&backend, "#!lua name=lib\nlocal a = 1", false)
+                .unwrap_err(),
+            "ERR No functions registered"
+        );
+        assert_eq!(
+            registry
+                .load(&backend, "#!lua name=lib\nredis.call('get', 'a')", false)
+                .unwrap_err(),
+            "ERR Error registering functions: user_function:2: attempt to call field 'call' (a nil value)"
+        );
+
+        registry.load(&backend, LIB, false).unwrap();
+        let other = LIB.replace("mylib", "other");
+        assert_eq!(
+            registry.load(&backend, &other, false).unwrap_err(),
+            "ERR Function echo already exists"
+        );
+    }
+
+    #[test]
+    fn test_load_timeout() {
+        let backend = Backend::new();
+        let started = Instant::now();
+        let err = backend
+            .functions
+            .load(&backend, "#!lua name=lib\nwhile true do end", false)
+            .unwrap_err();
+        assert!(err.contains("FUNCTION LOAD timeout"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(backend.functions.load(&backend, LIB, false).is_ok());
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let backend = Backend::new();
+        backend.functions.load(&backend, LIB, false).unwrap();
+        let payload = backend.functions.dump();
+
+        let restored = Backend::new();
+        restored
+            .functions
+            .restore(&restored, &payload, RestorePolicy::Append)
+            .unwrap();
+        assert_eq!(restored.functions.list(), backend.functions.list());
+
+        assert_eq!(
+            restored
+                .functions
+                .restore(&restored, &payload, RestorePolicy::Append)
+                .unwrap_err(),
+            "ERR Library mylib already exists"
+        );
+        assert!(restored
+            .functions
+            .restore(&restored, &payload, RestorePolicy::Replace)
+            .is_ok());
+
+        let mut broken = payload.clone();
+        broken[3] ^= 0xff;
+        assert_eq!(
+            restored
+                .functions
+                .restore(&restored, &broken, RestorePolicy::Flush)
+                .unwrap_err(),
+            "ERR DUMP payload version or checksum are wrong"
+        );
+    }
+}
@@ -0,0 +1,629 @@
+mod convert;
+mod function;
+
+use crate::{Backend, Command, CommandError, CommandExecutor, RespArray, RespFrame, SimpleError};
+use bytes::Bytes;
+use convert::{frame_to_lua, lua_to_frame};
+pub use function::{fcall, FunctionRegistry, RestorePolicy};
+use mlua::{ChunkMode, HookTriggers, Lua, LuaOptions, StdLib, Value};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// after running longer than this, the script is reported as busy
+// and can be stopped by SCRIPT KILL
+pub const SCRIPT_TIME_LIMIT: Duration = Duration::from_secs(5);
+// how many VM instructions are executed between two kill checks
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+const SCRIPT_KILLED: &str = "Script killed by user with SCRIPT KILL...";
+
+// redis.call raises the error table returned by redis.pcall
+const PRELUDE: &str = r#"
+redis.call = function(...)
+    local reply = redis.pcall(...)
+    if type(reply) == 'table' and reply.err then
+        error(reply)
+    end
+    return reply
+end
+"#;
+
+// ScriptState tracks the script which is running now.
+#[derive(Debug, Default)]
+pub struct ScriptState {
+    running: Mutex<Running>,
+}
+
+// Killing and writing are decided under the same lock, a script is either
+// killed before its first write or can't be killed anymore.
+#[derive(Debug, Default)]
+struct Running {
+    started: Option<Instant>,
+    killed: bool,
+    wrote: bool,
+}
+
+impl ScriptState {
+    // a script has been running longer than SCRIPT_TIME_LIMIT
+    pub fn is_busy(&self) -> bool {
+        match self.running.lock().unwrap().started {
+            Some(started) => started.elapsed() > SCRIPT_TIME_LIMIT,
+            None => false,
+        }
+    }
+
+    pub fn kill(&self) -> RespFrame {
+        let mut running = self.running.lock().unwrap();
+        if running.started.is_none() {
+            return SimpleError::new("NOTBUSY No scripts in execution right now.").into();
+        }
+        if running.wrote {
+            return SimpleError::new(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. \
+                 You can either wait the script termination or kill the server in a hard way using \
+                 the SHUTDOWN NOSAVE command.",
+            )
+            .into();
+        }
+        running.killed = true;
+        crate::RESP_OK.clone()
+    }
+
+    fn is_killed(&self) -> bool {
+        self.running.lock().unwrap().killed
+    }
+
+    // Record a write of the running script, false when it was killed
+    // already and must not write.
+    fn start_write(&self) -> bool {
+        let mut running = self.running.lock().unwrap();
+        running.wrote = !running.killed;
+        running.wrote
+    }
+
+    fn start(&self) {
+        *self.running.lock().unwrap() = Running {
+            started: Some(Instant::now()),
+            ..Default::default()
+        };
+    }
+
+    // whether the script was killed
+    fn finish(&self) -> bool {
+        let mut running = self.running.lock().unwrap();
+        running.started = None;
+        std::mem::take(&mut running.killed)
+    }
+}
+
+pub fn sha1_hex(script: &str) -> String {
+    sha1_smol::Sha1::from(script).digest().to_string()
+}
+
+// Run the script with the given KEYS and ARGV.
+pub fn eval(
+    backend: &Backend,
+    script: &str,
+    keys: &[Bytes],
+    args: &[Bytes],
+    caller: Caller,
+) -> RespFrame {
+    run_guarded(backend, || {
+        let lua = new_lua(Mode::ReadWrite)?;
+        let globals = lua.globals();
+        globals.set("KEYS", string_sequence(&lua, keys)?)?;
+        globals.set("ARGV", string_sequence(&lua, args)?)?;
+        let func = lua
+            .load(script)
+            .set_name("@user_script")
+            .set_mode(ChunkMode::Text)
+            .into_function()?;
+        with_context(&lua, backend, Mode::ReadWrite, caller, || {
+            invoke(&lua, func, ())
+        })
+    })
+}
+
+// Mode decides what the scripts are allowed to do through the redis table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    // loading a function library, only redis.register_function can be called
+    Load,
+    ReadWrite,
+    // running a function with the no-writes flag
+    ReadOnly,
+}
+
+// Who runs a script. The scripts a replica gets from its master write
+// even though the replica is read only.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Caller {
+    Client,
+    Master,
+}
+
+// The backend, the mode and the caller of the script an interpreter runs.
+// It's only set while the script runs, the interpreters of the function
+// libraries are kept and mustn't hold on to the backend.
+struct Context {
+    backend: Backend,
+    mode: Mode,
+    caller: Caller,
+}
+
+// Run `f` with the context the redis calls of the script see.
+pub(crate) fn with_context<R>(
+    lua: &Lua,
+    backend: &Backend,
+    mode: Mode,
+    caller: Caller,
+    f: impl FnOnce() -> R,
+) -> R {
+    lua.set_app_data(Context {
+        backend: backend.clone(),
+        mode,
+        caller,
+    });
+    let res = f();
+    lua.remove_app_data::<Context>();
+    res
+}
+
+// Track the running script in ScriptState while `f` runs, and turn
+// the result into a reply.
+pub(crate) fn run_guarded(
+    backend: &Backend,
+    f: impl FnOnce() -> mlua::Result<RespFrame>,
+) -> RespFrame {
+    let state = &backend.script_state;
+    state.start();
+    let res = f();
+    let killed = state.finish();
+    match res {
+        Ok(frame) => frame,
+        Err(_) if killed => SimpleError::new(format!("ERR {}", SCRIPT_KILLED)).into(),
+        Err(e) => SimpleError::new(format!("ERR {}", error_message(&e))).into(),
+    }
+}
+
+// Create a sandboxed interpreter.
+//
+// EVAL creates a fresh interpreter for every call so scripts can't leak
+// globals into each other. A function library gets its own, created in
+// `Mode::Load` and switched to running its functions by `enable_calls`.
+pub(crate) fn new_lua(mode: Mode) -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::new(),
+    )?;
+    let globals = lua.globals();
+    // crafted bytecode can escape the interpreter, scripts only load source
+    for name in ["dofile", "loadfile", "load"] {
+        globals.set(name, Value::Nil)?;
+    }
+    let string: mlua::Table = globals.get("string")?;
+    string.set("dump", Value::Nil)?;
+    drop(string);
+    globals.set("loadstring", lua.create_function(loadstring)?)?;
+    let redis = redis_table(&lua)?;
+    if mode == Mode::Load {
+        redis.set("register_function", function::register_function(&lua)?)?;
+    }
+    globals.set("redis", redis)?;
+    if mode != Mode::Load {
+        enable_calls(&lua)?;
+    }
+    set_kill_hook(&lua);
+    drop(globals);
+    Ok(lua)
+}
+
+// Stop the running script once SCRIPT KILL asks for it.
+pub(crate) fn set_kill_hook(lua: &Lua) {
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        |lua, _| {
+            let killed = lua
+                .app_data_ref::<Context>()
+                .is_some_and(|ctx| ctx.backend.script_state.is_killed());
+            match killed {
+                true => Err(mlua::Error::RuntimeError(SCRIPT_KILLED.to_string())),
+                false => Ok(()),
+            }
+        },
+    );
+}
+
+// loadstring(chunk [, chunkname]) which refuses precompiled chunks
+fn loadstring<'lua>(
+    lua: &'lua Lua,
+    (chunk, name): (mlua::String<'lua>, Option<String>),
+) -> mlua::Result<(Option<mlua::Function<'lua>>, Option<String>)> {
+    // like Lua, the chunk names itself, up to a NUL
+    let name = name.unwrap_or_else(|| {
+        let source = chunk
+            .as_bytes()
+            .split(|&b| b == 0)
+            .next()
+            .unwrap_or_default();
+        String::from_utf8_lossy(source).into_owned()
+    });
+    let loaded = lua
+        .load(chunk.as_bytes())
+        .set_name(name)
+        .set_mode(ChunkMode::Text)
+        .into_function();
+    match loaded {
+        Ok(func) => Ok((Some(func), None)),
+        Err(e) => Ok((None, Some(error_message(&e)))),
+    }
+}
+
+// Give the scripts redis.call and redis.pcall, and take away
+// redis.register_function once a library is loaded.
+pub(crate) fn enable_calls(lua: &Lua) -> mlua::Result<()> {
+    let redis: mlua::Table = lua.globals().get("redis")?;
+    redis.set("register_function", Value::Nil)?;
+    let pcall = lua.create_function(|lua, args: mlua::Variadic<Value>| {
+        let (backend, mode, caller) = match lua.app_data_ref::<Context>() {
+            Some(ctx) => (ctx.backend.clone(), ctx.mode, ctx.caller),
+            None => {
+                return Err(mlua::Error::RuntimeError(
+                    "No script is running".to_string(),
+                ))
+            }
+        };
+        let frame = call(&backend, mode, caller, args.into_iter().collect());
+        frame_to_lua(lua, frame)
+    })?;
+    redis.set("pcall", pcall)?;
+    lua.load(PRELUDE).set_name("@prelude").exec()
+}
+
+// Call the function in protected mode and convert its result to a reply.
+// Errors raised as tables with an `err` field become error replies.
+pub(crate) fn invoke<'lua>(
+    lua: &'lua Lua,
+    func: mlua::Function<'lua>,
+    args: impl mlua::IntoLuaMulti<'lua>,
+) -> mlua::Result<RespFrame> {
+    let pcall: mlua::Function = lua.globals().get("pcall")?;
+    let mut call_args = args.into_lua_multi(lua)?;
+    call_args.push_front(Value::Function(func));
+    let (ok, value): (bool, Value) = pcall.call(call_args)?;
+    if ok {
+        return Ok(lua_to_frame(value));
+    }
+    match value {
+        Value::Table(t) if t.contains_key("err")? => Ok(lua_to_frame(Value::Table(t))),
+        Value::Error(e) => Err(e),
+        v => Err(mlua::Error::RuntimeError(
+            v.to_string()
+                .unwrap_or_else(|_| "unknown error".to_string()),
+        )),
+    }
+}
+
+pub(crate) fn string_sequence<'lua>(
+    lua: &'lua Lua,
+    values: &[Bytes],
+) -> mlua::Result<mlua::Table<'lua>> {
+    let values = values
+        .iter()
+        .map(|v| lua.create_string(v))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    lua.create_sequence_from(values)
+}
+
+fn redis_table(lua: &Lua) -> mlua::Result<mlua::Table<'_>> {
+    let redis = lua.create_table()?;
+
+    let status_reply = lua.create_function(|lua, s: mlua::String| {
+        let t = lua.create_table()?;
+        t.set("ok", s)?;
+        Ok(t)
+    })?;
+    redis.set("status_reply", status_reply)?;
+
+    let error_reply = lua.create_function(|lua, s: mlua::String| {
+        let t = lua.create_table()?;
+        t.set("err", s)?;
+        Ok(t)
+    })?;
+    redis.set("error_reply", error_reply)?;
+
+    let sha1hex = lua.create_function(|_, s: mlua::String| Ok(sha1_hex(s.to_str()?)))?;
+    redis.set("sha1hex", sha1hex)?;
+
+    Ok(redis)
+}
+
+// Execute a command issued by redis.call or redis.pcall.
+fn call(backend: &Backend, mode: Mode, caller: Caller, args: Vec<Value>) -> RespFrame {
+    if args.is_empty() {
+        return SimpleError::new(
+            "ERR Please specify at least one argument for this redis lib call",
+        )
+        .into();
+    }
+    let mut frames = Vec::with_capacity(args.len());
+    for arg in args {
+        let frame = match arg {
+            Value::String(s) => crate::BulkString::new(s.as_bytes()).into(),
+            Value::Integer(i) => crate::BulkString::new(i.to_string()).into(),
+            Value::Number(n) => crate::BulkString::new(n.to_string()).into(),
+            _ => {
+                return SimpleError::new(
+                    "ERR Lua redis lib command arguments must be strings or integers",
+                )
+                .into()
+            }
+        };
+        frames.push(frame);
+    }
+
+    let cmd = match Command::try_from(RespArray::with_vec(frames)) {
+        Ok(cmd) => cmd,
+        Err(CommandError::UnknownCommand(..)) => {
+            return SimpleError::new("ERR Unknown Redis command called from script").into()
+        }
+        Err(CommandError::WrongArity(_)) => {
+            return SimpleError::new("ERR Wrong number of args calling Redis command from script")
+                .into()
+        }
+        Err(e) => return e.into(),
+    };
+    if !cmd.is_allowed_in_script() {
+        return SimpleError::new("ERR This Redis command is not allowed from script").into();
+    }
+    if cmd.is_write() {
+        if mode == Mode::ReadOnly {
+            return SimpleError::new("ERR Write commands are not allowed from read-only scripts.")
+                .into();
+        }
+        if caller == Caller::Client && backend.repl.deny_writes(backend.config.replica_read_only) {
+            return SimpleError::new("READONLY You can't write against a read only replica.")
+                .into();
+        }
+        if let Some(misconf) = backend.aof.misconf().filter(|_| caller == Caller::Client) {
+            return misconf;
+        }
+        if !backend.script_state.start_write() {
+            return SimpleError::new(format!("ERR {}", SCRIPT_KILLED)).into();
+        }
+    }
+    cmd.execute(backend)
+}
+
+// Strip the traceback mlua attaches to runtime errors.
+fn error_message(err: &mlua::Error) -> String {
+    let msg = match err {
+        mlua::Error::CallbackError { cause, .. } => return error_message(cause),
+        mlua::Error::RuntimeError(msg) => msg.clone(),
+        mlua::Error::SyntaxError { message, .. } => format!("Error compiling script {}", message),
+        e => e.to_string(),
+    };
+    match msg.split_once("\nstack traceback") {
+        Some((msg, _)) => msg.to_string(),
+        None => msg,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BulkString, SimpleString};
+
+    #[test]
+    fn test_sha1_hex() {
+        assert_eq!(
+            sha1_hex("return 1"),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+    }
+
+    #[test]
+    fn test_eval_keys_argv() {
+        let backend = Backend::new();
+        let res = eval(
+            &backend,
+            "return {KEYS[1], ARGV[1], 10, 3.9}",
+            &[Bytes::from_static(b"k")],
+            &[Bytes::from_static(b"a")],
+            Caller::Client,
+        );
+        assert_eq!(
+            res,
+            RespArray::with_vec(vec![
+                BulkString::new("k").into(),
+                BulkString::new("a").into(),
+                RespFrame::Integer(10),
+                RespFrame::Integer(3),
+            ])
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_eval_refuses_bytecode() {
+        let backend = Backend::new();
+        let run = |script: &str| eval(&backend, script, &[], &[], Caller::Client);
+        assert_eq!(
+            run("return loadstring('return 7')()"),
+            RespFrame::Integer(7)
+        );
+        assert_eq!(
+            run("return tostring(string.dump)"),
+            BulkString::new("nil").into()
+        );
+        // compiled by an interpreter which still has string.dump
+        let lua = Lua::new();
+        let dump = lua.load("return string.dump(function() return 7 end)");
+        let bytecode: mlua::String = dump.eval().unwrap();
+        let res = eval(
+            &backend,
+            "local f, err = loadstring(ARGV[1]); return {tostring(f), err}",
+            &[],
+            &[Bytes::copy_from_slice(bytecode.as_bytes())],
+            Caller::Client,
+        );
+        let RespFrame::Array(RespArray(Some(res))) = res else {
+            panic!("loadstring should return nil and an error");
+        };
+        assert_eq!(res[0], BulkString::new("nil").into());
+        let err = String::from_utf8_lossy(res[1].as_bytes().unwrap_or_default());
+        assert!(err.contains("binary chunk"), "{}", err);
+        assert_eq!(run("return tostring(load)"), BulkString::new("nil").into());
+    }
+
+    #[test]
+    fn test_eval_redis_call() {
+        let backend = Backend::new();
+        let res = eval(
+            &backend,
+            "redis.call('set', KEYS[1], ARGV[1]); return redis.call('get', KEYS[1])",
+            &[Bytes::from_static(b"key")],
+            &[Bytes::from_static(b"value")],
+            Caller::Client,
+        );
+        assert_eq!(res, BulkString::new("value").into());
+        assert!(backend.script_state.running.lock().unwrap().wrote);
+
+        let res = eval(
+            &backend,
+            "return redis.call('set', 'a', 'b')",
+            &[],
+            &[],
+            Caller::Client,
+        );
+        assert_eq!(res, SimpleString::new("OK").into());
+
+        let res = eval(
+            &backend,
+            "return redis.call('get', 'missing')",
+            &[],
+            &[],
+            Caller::Client,
+        );
+        assert_eq!(res, BulkString::new_null().into());
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let backend = Backend::new();
+        let res = eval(
+            &backend,
+            "return redis.error_reply('MY error')",
+            &[],
+            &[],
+            Caller::Client,
+        );
+        assert_eq!(res, SimpleError::new("MY error").into());
+
+        let res = eval(
+            &backend,
+            "return redis.call('multi')",
+            &[],
+            &[],
+            Caller::Client,
+        );
+        assert_eq!(
+            res,
+            SimpleError::new("ERR This Redis command is not allowed from script").into()
+        );
+
+        let res = eval(
+            &backend,
+            "local r = redis.pcall('multi'); return r['err']",
+            &[],
+            &[],
+            Caller::Client,
+        );
+        assert_eq!(
+            res,
+            BulkString::new("ERR This Redis command is not allowed from script").into()
+        );
+
+        let res = eval(&backend, "error('oops')", &[], &[], Caller::Client);
+        assert_eq!(res, SimpleError::new("ERR user_script:1: oops").into());
+
+        let res = eval(&backend, "return +", &[], &[], Caller::Client);
+        assert!(matches!(res, RespFrame::Error(_)));
+    }
+
+    #[test]
+    fn test_eval_on_read_only_replica() {
+        let backend = Backend::new();
+        backend.repl.become_replica("localhost", 6379);
+        let script = "return redis.call('set', 'k', 'v')";
+        // the master's scripts write, its clients' don't, whichever runs first
+        let res = eval(&backend, script, &[], &[], Caller::Client);
+        assert_eq!(
+            res,
+            SimpleError::new("READONLY You can't write against a read only replica.").into()
+        );
+        let res = eval(&backend, script, &[], &[], Caller::Master);
+        assert_eq!(res, SimpleString::new("OK").into());
+        let res = eval(&backend, script, &[], &[], Caller::Client);
+        assert!(matches!(res, RespFrame::Error(_)));
+        assert_eq!(backend.get(b"k"), Some(BulkString::new("v").into()));
+    }
+
+    #[test]
+    fn test_eval_no_register_function() {
+        let backend = Backend::new();
+        let res = eval(
+            &backend,
+            "return type(redis.register_function)",
+            &[],
+            &[],
+            Caller::Client,
+        );
+        assert_eq!(res, BulkString::new("nil").into());
+    }
+
+    #[test]
+    fn test_script_kill() {
+        let backend = Backend::new();
+        assert_eq!(
+            backend.script_state.kill(),
+            SimpleError::new("NOTBUSY No scripts in execution right now.").into()
+        );
+
+        let cloned = backend.clone();
+        let handle = std::thread::spawn(move || {
+            eval(&cloned, "while true do end", &[], &[], Caller::Client)
+        });
+        while backend
+            .script_state
+            .running
+            .lock()
+            .unwrap()
+            .started
+            .is_none()
+        {
+            std::thread::yield_now();
+        }
+        assert_eq!(backend.script_state.kill(), crate::RESP_OK.clone());
+        let res = handle.join().unwrap();
+        assert_eq!(
+            res,
+            SimpleError::new(format!("ERR {}", SCRIPT_KILLED)).into()
+        );
+        assert!(!backend.script_state.is_busy());
+    }
+
+    #[test]
+    fn test_script_kill_after_write() {
+        let backend = Backend::new();
+        let state = &backend.script_state;
+        state.start();
+        assert!(state.start_write());
+        assert!(matches!(state.kill(), RespFrame::Error(e) if e.0.starts_with("UNKILLABLE")));
+
+        // a killed script doesn't get to write
+        state.start();
+        assert_eq!(state.kill(), crate::RESP_OK.clone());
+        assert!(!state.start_write());
+        state.finish();
+    }
+}
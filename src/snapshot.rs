@@ -0,0 +1,718 @@
+use crate::rdb::{self, crc64, RdbError};
+use crate::{Backend, RespDecode, RespEncode, RespError, RespFrame, SaveRule, SnapshotFormat};
+use bytes::{Bytes, BytesMut};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::{info, warn};
+
+// Point-in-time snapshot of the whole dataset.
+//
+// The file starts with a magic and a version, followed by one record per key
+// and per function library, and an end of file record holding the number of
+// records before it. Every record carries the CRC64 of its type and body, a
+// damaged file is refused instead of being loaded partially.
+//
+// <magic><version u16 LE>
+// <type u8><body length><body><crc64 u64 LE>...
+// <EOF type><length><number of records as a length><crc64 u64 LE>
+
+const MAGIC: &[u8] = b"SREDIS";
+const VERSION: u16 = 2;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_HASH: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_FUNCTION: u8 = 3;
+// since version 2
+const TYPE_LIST: u8 = 4;
+const TYPE_ZSET: u8 = 5;
+const TYPE_EXPIRE: u8 = 6;
+const TYPE_EOF: u8 = 0xff;
+
+// how often the save rules are checked
+const SAVE_RULES_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("not a snapshot file")]
+    BadMagic,
+    #[error("unsupported snapshot version {0}")]
+    BadVersion(u16),
+    #[error("checksum mismatch in record {0}")]
+    BadChecksum(usize),
+    #[error("unknown record type {0}")]
+    UnknownType(u8),
+    #[error("the file ends before its last record")]
+    Truncated,
+    #[error("{0}")]
+    Rdb(#[from] RdbError),
+    #[error("invalid value ({0})")]
+    Resp(#[from] RespError),
+    #[error("{0}")]
+    Function(String),
+}
+
+// The state of saving, shared by SAVE, BGSAVE, LASTSAVE and the save rules.
+#[derive(Debug)]
+pub(crate) struct SaveState {
+    // modifications since the last successful save
+    dirty: AtomicU64,
+    // unix time of the last successful save, the start time before any
+    lastsave: AtomicU64,
+    in_progress: AtomicBool,
+    // BGSAVE SCHEDULE asked for a save once the running one is done
+    scheduled: AtomicBool,
+    // microseconds the last capture of the dataset blocked every client
+    latest_capture_usec: AtomicU64,
+}
+
+impl Default for SaveState {
+    fn default() -> Self {
+        Self {
+            dirty: AtomicU64::new(0),
+            lastsave: AtomicU64::new(unix_time()),
+            in_progress: AtomicBool::new(false),
+            scheduled: AtomicBool::new(false),
+            latest_capture_usec: AtomicU64::new(0),
+        }
+    }
+}
+
+impl SaveState {
+    pub(crate) fn incr_dirty(&self) {
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    pub fn lastsave(&self) -> u64 {
+        self.lastsave.load(Ordering::Relaxed)
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::Acquire)
+    }
+
+    pub fn latest_capture_usec(&self) -> u64 {
+        self.latest_capture_usec.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn schedule(&self) {
+        self.scheduled.store(true, Ordering::Relaxed);
+    }
+
+    // the changes a save covered are no longer dirty
+    pub(crate) fn saved(&self, dirty: u64) {
+        self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+        self.lastsave.store(unix_time(), Ordering::Relaxed);
+    }
+
+    // whether one of the rules, or BGSAVE SCHEDULE, asks for a save now
+    fn should_save(&self, rules: &[SaveRule]) -> bool {
+        if self.in_progress() {
+            return false;
+        }
+        if self.scheduled.swap(false, Ordering::Relaxed) {
+            return true;
+        }
+        let dirty = self.dirty();
+        let elapsed = unix_time().saturating_sub(self.lastsave());
+        rules
+            .iter()
+            .any(|rule| dirty >= rule.changes && elapsed >= rule.seconds)
+    }
+}
+
+fn unix_time() -> u64 {
+    unix_time_ms() / 1000
+}
+
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// A copy of the dataset, taken while no command runs.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    pub(crate) strings: Vec<(Bytes, RespFrame)>,
+    pub(crate) hashes: Vec<(Bytes, Vec<(Bytes, RespFrame)>)>,
+    pub(crate) sets: Vec<(Bytes, Vec<Bytes>)>,
+    pub(crate) lists: Vec<(Bytes, Vec<Bytes>)>,
+    pub(crate) zsets: Vec<(Bytes, Vec<(Bytes, f64)>)>,
+    // unix time in milliseconds at which the key expires
+    pub(crate) expires: Vec<(Bytes, u64)>,
+    pub(crate) libraries: Vec<String>,
+}
+
+impl Snapshot {
+    // The caller holds the write side of the gate, so every client waits
+    // for the copy. This stands in for the fork of Redis: the keys and values
+    // are Bytes which are shared rather than copied, but the pause still
+    // grows with the number of keys, fields and members. It is logged and
+    // reported as `latest_fork_usec` by INFO.
+    pub(crate) fn capture(backend: &Backend) -> Self {
+        let start = Instant::now();
+        let strings = backend
+            .map
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        let hashes = backend
+            .hmap
+            .iter()
+            .map(|e| {
+                let fields = e
+                    .value()
+                    .iter()
+                    .map(|f| (f.key().clone(), f.value().clone()))
+                    .collect();
+                (e.key().clone(), fields)
+            })
+            .collect();
+        let sets = backend
+            .set
+            .iter()
+            .map(|e| {
+                (
+                    e.key().clone(),
+                    e.value().iter().map(|m| m.clone()).collect(),
+                )
+            })
+            .collect();
+        let lists = backend
+            .list
+            .iter()
+            .map(|e| (e.key().clone(), e.value().iter().cloned().collect()))
+            .collect();
+        let zsets = backend
+            .zset
+            .iter()
+            .map(|e| {
+                let members = e
+                    .value()
+                    .iter()
+                    .map(|m| (m.key().clone(), *m.value()))
+                    .collect();
+                (e.key().clone(), members)
+            })
+            .collect();
+        let expires = backend
+            .expires
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        let libraries = backend
+            .functions
+            .list()
+            .into_iter()
+            .map(|l| l.code)
+            .collect();
+        let usec = start.elapsed().as_micros() as u64;
+        backend
+            .save_state
+            .latest_capture_usec
+            .store(usec, Ordering::Relaxed);
+        info!("Dataset captured in {} microseconds", usec);
+        Snapshot {
+            strings,
+            hashes,
+            sets,
+            lists,
+            zsets,
+            expires,
+            libraries,
+        }
+    }
+
+    pub(crate) fn encode_as(&self, format: SnapshotFormat) -> Vec<u8> {
+        match format {
+            SnapshotFormat::Native => self.encode(),
+            SnapshotFormat::Rdb => rdb::write_rdb(self),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::from(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        let mut body = Vec::new();
+        let mut records = 0;
+        let mut record = |buf: &mut Vec<u8>, body: &mut Vec<u8>, kind: u8| {
+            write_record(buf, kind, body);
+            body.clear();
+            records += 1;
+        };
+
+        for (key, value) in &self.strings {
+            rdb::write_string(&mut body, key);
+            rdb::write_string(&mut body, &value.encode());
+            record(&mut buf, &mut body, TYPE_STRING);
+        }
+        for (key, fields) in &self.hashes {
+            rdb::write_string(&mut body, key);
+            rdb::write_length(&mut body, fields.len() as u64);
+            for (field, value) in fields {
+                rdb::write_string(&mut body, field);
+                rdb::write_string(&mut body, &value.encode());
+            }
+            record(&mut buf, &mut body, TYPE_HASH);
+        }
+        for (key, members) in &self.sets {
+            rdb::write_string(&mut body, key);
+            rdb::write_length(&mut body, members.len() as u64);
+            for member in members {
+                rdb::write_string(&mut body, member);
+            }
+            record(&mut buf, &mut body, TYPE_SET);
+        }
+        for (key, items) in &self.lists {
+            rdb::write_string(&mut body, key);
+            rdb::write_length(&mut body, items.len() as u64);
+            for item in items {
+                rdb::write_string(&mut body, item);
+            }
+            record(&mut buf, &mut body, TYPE_LIST);
+        }
+        for (key, members) in &self.zsets {
+            rdb::write_string(&mut body, key);
+            rdb::write_length(&mut body, members.len() as u64);
+            for (member, score) in members {
+                rdb::write_string(&mut body, member);
+                body.extend_from_slice(&score.to_le_bytes());
+            }
+            record(&mut buf, &mut body, TYPE_ZSET);
+        }
+        for (key, at) in &self.expires {
+            rdb::write_string(&mut body, key);
+            body.extend_from_slice(&at.to_le_bytes());
+            record(&mut buf, &mut body, TYPE_EXPIRE);
+        }
+        for code in &self.libraries {
+            rdb::write_string(&mut body, code.as_bytes());
+            record(&mut buf, &mut body, TYPE_FUNCTION);
+        }
+
+        rdb::write_length(&mut body, records);
+        write_record(&mut buf, TYPE_EOF, &body);
+        buf
+    }
+
+    pub(crate) fn decode(mut data: &[u8]) -> Result<Self, SnapshotError> {
+        let magic = rdb::read_bytes(&mut data, MAGIC.len()).map_err(|_| SnapshotError::BadMagic)?;
+        if magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = rdb::read_bytes(&mut data, 2).map_err(|_| SnapshotError::Truncated)?;
+        let version = u16::from_le_bytes([version[0], version[1]]);
+        if version > VERSION {
+            return Err(SnapshotError::BadVersion(version));
+        }
+
+        let mut snapshot = Snapshot::default();
+        let mut records = 0;
+        loop {
+            if data.is_empty() {
+                return Err(SnapshotError::Truncated);
+            }
+            let (kind, mut body) = read_record(&mut data, records)?;
+            match kind {
+                TYPE_STRING => {
+                    let key = rdb::read_string(&mut body)?;
+                    let value = read_frame(&mut body)?;
+                    snapshot.strings.push((key.into(), value));
+                }
+                TYPE_HASH => {
+                    let key = rdb::read_string(&mut body)?;
+                    let (len, _) = rdb::read_length_with_encoding(&mut body)?;
+                    let mut fields = Vec::new();
+                    for _ in 0..len {
+                        let field = rdb::read_string(&mut body)?;
+                        fields.push((field.into(), read_frame(&mut body)?));
+                    }
+                    snapshot.hashes.push((key.into(), fields));
+                }
+                TYPE_SET => {
+                    let key = rdb::read_string(&mut body)?;
+                    let (len, _) = rdb::read_length_with_encoding(&mut body)?;
+                    let mut members = Vec::new();
+                    for _ in 0..len {
+                        members.push(rdb::read_string(&mut body)?.into());
+                    }
+                    snapshot.sets.push((key.into(), members));
+                }
+                TYPE_LIST => {
+                    let key = rdb::read_string(&mut body)?;
+                    let (len, _) = rdb::read_length_with_encoding(&mut body)?;
+                    let mut items = Vec::new();
+                    for _ in 0..len {
+                        items.push(rdb::read_string(&mut body)?.into());
+                    }
+                    snapshot.lists.push((key.into(), items));
+                }
+                TYPE_ZSET => {
+                    let key = rdb::read_string(&mut body)?;
+                    let (len, _) = rdb::read_length_with_encoding(&mut body)?;
+                    let mut members = Vec::new();
+                    for _ in 0..len {
+                        let member = rdb::read_string(&mut body)?;
+                        members.push((member.into(), f64::from_le_bytes(read_u64(&mut body)?)));
+                    }
+                    snapshot.zsets.push((key.into(), members));
+                }
+                TYPE_EXPIRE => {
+                    let key = rdb::read_string(&mut body)?;
+                    let at = u64::from_le_bytes(read_u64(&mut body)?);
+                    snapshot.expires.push((key.into(), at));
+                }
+                TYPE_FUNCTION => {
+                    let code = rdb::read_string(&mut body)?;
+                    let code = String::from_utf8(code)
+                        .map_err(|_| SnapshotError::Function("invalid library code".to_string()))?;
+                    snapshot.libraries.push(code);
+                }
+                TYPE_EOF => {
+                    let (count, _) = rdb::read_length_with_encoding(&mut body)?;
+                    if count != records as u64 {
+                        return Err(SnapshotError::Truncated);
+                    }
+                    return Ok(snapshot);
+                }
+                kind => return Err(SnapshotError::UnknownType(kind)),
+            }
+            records += 1;
+        }
+    }
+
+    // Replace the dataset with the snapshot, done before serving any client.
+    // Keys that expired while the server was down are not loaded.
+    pub(crate) fn restore(self, backend: &Backend) -> Result<(), SnapshotError> {
+        backend.map.clear();
+        backend.hmap.clear();
+        backend.set.clear();
+        backend.list.clear();
+        backend.zset.clear();
+        backend.expires.clear();
+        for (key, value) in self.strings {
+            backend.map.insert(key, value);
+        }
+        for (key, fields) in self.hashes {
+            backend.hmap.insert(key, fields.into_iter().collect());
+        }
+        for (key, members) in self.sets {
+            backend.set.insert(key, members.into_iter().collect());
+        }
+        for (key, items) in self.lists {
+            backend.list.insert(key, items.into());
+        }
+        for (key, members) in self.zsets {
+            backend.zset.insert(key, members.into_iter().collect());
+        }
+        let now = unix_time_ms();
+        for (key, at) in self.expires {
+            if at > now {
+                backend.expires.insert(key, at);
+            } else {
+                backend.map.remove(&key);
+                backend.hmap.remove(&key);
+                backend.set.remove(&key);
+                backend.list.remove(&key);
+                backend.zset.remove(&key);
+            }
+        }
+        backend.functions.flush();
+        for code in self.libraries {
+            backend
+                .functions
+                .load(backend, &code, false)
+                .map_err(SnapshotError::Function)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_record(buf: &mut Vec<u8>, kind: u8, body: &[u8]) {
+    let start = buf.len();
+    buf.push(kind);
+    rdb::write_string(buf, body);
+    let crc = crc64(&buf[start..]);
+    buf.extend_from_slice(&crc.to_le_bytes());
+}
+
+// Read the type and the body of the record at index, checking its CRC64.
+fn read_record<'a>(data: &mut &'a [u8], index: usize) -> Result<(u8, &'a [u8]), SnapshotError> {
+    let start = *data;
+    let truncated = |_| SnapshotError::Truncated;
+    let kind = rdb::read_u8(data).map_err(truncated)?;
+    let (len, _) = rdb::read_length_with_encoding(data).map_err(truncated)?;
+    let body = rdb::read_bytes(data, len as usize).map_err(truncated)?;
+    let checked = &start[..start.len() - data.len()];
+    let crc = rdb::read_bytes(data, 8).map_err(truncated)?;
+    if crc64(checked).to_le_bytes() != crc {
+        return Err(SnapshotError::BadChecksum(index));
+    }
+    Ok((kind, body))
+}
+
+fn read_u64(data: &mut &[u8]) -> Result<[u8; 8], SnapshotError> {
+    Ok(rdb::read_bytes(data, 8)?.try_into().unwrap())
+}
+
+fn read_frame(data: &mut &[u8]) -> Result<RespFrame, SnapshotError> {
+    let value = rdb::read_string(data)?;
+    Ok(RespFrame::decode(&mut BytesMut::from(&value[..]))?)
+}
+
+// Write the file next to its final place first, so a crash never leaves a
+// half written snapshot behind.
+pub(crate) fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = path.with_file_name(format!("temp-{}.srdb", std::process::id()));
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    match result {
+        Ok(_) => fs::rename(&temp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+// SAVE: write the snapshot before returning, the caller holds the write side of the gate.
+pub(crate) fn save(backend: &Backend) -> Result<(), SnapshotError> {
+    let dirty = backend.save_state.dirty();
+    let data = Snapshot::capture(backend).encode_as(backend.config.snapshot_format);
+    write_file(&backend.config.snapshot_path(), &data)?;
+    backend.save_state.saved(dirty);
+    info!("DB saved on disk");
+    Ok(())
+}
+
+// BGSAVE: copy the dataset, the caller holds the write side of the gate,
+// then write it in the background. Returns false if a save is in progress.
+pub(crate) fn bgsave(backend: &Backend) -> bool {
+    let state = &backend.save_state;
+    if state.in_progress.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let dirty = state.dirty();
+    let snapshot = Snapshot::capture(backend);
+    let backend = backend.clone();
+    std::thread::spawn(move || {
+        let data = snapshot.encode_as(backend.config.snapshot_format);
+        let state = &backend.save_state;
+        match write_file(&backend.config.snapshot_path(), &data) {
+            Ok(_) => {
+                state.saved(dirty);
+                info!("Background saving terminated with success");
+            }
+            Err(e) => warn!("Background saving error: {}", e),
+        }
+        state.in_progress.store(false, Ordering::Release);
+    });
+    true
+}
+
+// Load the snapshot at boot, returns false when there is none.
+// The file may be a snapshot of simple-redis or an RDB file written by Redis.
+pub fn load_snapshot(backend: &Backend) -> Result<bool, SnapshotError> {
+    let data = match fs::read(backend.config.snapshot_path()) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let snapshot = if data.starts_with(rdb::RDB_MAGIC) {
+        rdb::read_rdb(&data)?
+    } else {
+        Snapshot::decode(&data)?
+    };
+    snapshot.restore(backend)?;
+    Ok(true)
+}
+
+// Start a background save whenever one of the `save <seconds> <changes>`
+// rules is met, runs as long as the server does.
+pub async fn run_save_rules(backend: Backend) {
+    let rules = backend.config.save.clone();
+    loop {
+        tokio::time::sleep(SAVE_RULES_INTERVAL).await;
+        if backend.save_state.should_save(&rules) {
+            let _guard = backend.gate.write().await;
+            bgsave(&backend);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BulkString, Config, SimpleString};
+
+    fn backend_in(dir: &Path) -> Backend {
+        Backend::with_config(Config {
+            dir: dir.to_path_buf(),
+            ..Default::default()
+        })
+    }
+
+    fn fill(backend: &Backend) {
+        backend.set(
+            Bytes::from_static(b"\xffkey"),
+            BulkString::new("value").into(),
+        );
+        backend.set(Bytes::from_static(b"num"), RespFrame::Integer(42));
+        backend.hset(
+            Bytes::from_static(b"hash"),
+            Bytes::from_static(b"field"),
+            SimpleString::new("v").into(),
+        );
+        backend.sadd(Bytes::from_static(b"set"), Bytes::from_static(b"a"));
+        backend.sadd(Bytes::from_static(b"set"), Bytes::from_static(b"b"));
+        backend
+            .functions
+            .load(
+                backend,
+                "#!lua name=lib\nredis.register_function('f', function() return 1 end)",
+                false,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<(), SnapshotError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path());
+        fill(&backend);
+        assert_eq!(backend.save_state.dirty(), 5);
+        save(&backend)?;
+        assert_eq!(backend.save_state.dirty(), 0);
+
+        let loaded = backend_in(dir.path());
+        assert!(load_snapshot(&loaded)?);
+        assert_eq!(
+            loaded.get(b"\xffkey"),
+            Some(BulkString::new("value").into())
+        );
+        assert_eq!(loaded.get(b"num"), Some(RespFrame::Integer(42)));
+        assert_eq!(
+            loaded.hget(b"hash", b"field"),
+            Some(SimpleString::new("v").into())
+        );
+        assert!(loaded.sismember(b"set", b"a") && loaded.sismember(b"set", b"b"));
+        assert_eq!(loaded.functions.list().len(), 1);
+        assert_eq!(loaded.save_state.dirty(), 0);
+
+        let empty = backend_in(&dir.path().join("missing"));
+        assert!(!load_snapshot(&empty)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rdb_format() -> Result<(), SnapshotError> {
+        let dir = tempfile::tempdir()?;
+        let backend = Backend::with_config(Config {
+            dir: dir.path().to_path_buf(),
+            snapshot_format: SnapshotFormat::Rdb,
+            ..Default::default()
+        });
+        fill(&backend);
+        backend.list.insert(
+            Bytes::from_static(b"list"),
+            ["a", "b"].map(Bytes::from).into(),
+        );
+        backend
+            .expires
+            .insert(Bytes::from_static(b"num"), unix_time_ms() + 60_000);
+        save(&backend)?;
+        let data = fs::read(backend.config.snapshot_path())?;
+        assert!(data.starts_with(b"REDIS0011"));
+
+        // loading doesn't depend on the configured format
+        let loaded = backend_in(dir.path());
+        assert!(load_snapshot(&loaded)?);
+        // Redis only has strings
+        assert_eq!(loaded.get(b"num"), Some(BulkString::new("42").into()));
+        assert!(loaded.expires.contains_key(&b"num"[..]));
+        assert_eq!(
+            loaded.hget(b"hash", b"field"),
+            Some(BulkString::new("v").into())
+        );
+        assert_eq!(loaded.list.get(&b"list"[..]).unwrap().len(), 2);
+        assert_eq!(loaded.functions.list().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_snapshot() {
+        let backend = Backend::new();
+        fill(&backend);
+        let data = Snapshot::capture(&backend).encode();
+        assert!(Snapshot::decode(&data).is_ok());
+
+        // flip a byte in the body of the first record
+        let mut broken = data.clone();
+        broken[MAGIC.len() + 4] ^= 0xff;
+        assert!(matches!(
+            Snapshot::decode(&broken),
+            Err(SnapshotError::BadChecksum(0))
+        ));
+        assert!(matches!(
+            Snapshot::decode(&data[..data.len() - 1]),
+            Err(SnapshotError::Truncated)
+        ));
+        assert!(matches!(
+            Snapshot::decode(b"REDIS0011"),
+            Err(SnapshotError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn test_save_rules() {
+        let state = SaveState::default();
+        let rules = [SaveRule {
+            seconds: 0,
+            changes: 2,
+        }];
+        state.incr_dirty();
+        assert!(!state.should_save(&rules));
+        state.incr_dirty();
+        assert!(state.should_save(&rules));
+        assert!(!state.should_save(&[SaveRule {
+            seconds: 3600,
+            changes: 1
+        }]));
+
+        let state = SaveState::default();
+        state.schedule();
+        assert!(state.should_save(&[]));
+        assert!(!state.should_save(&[]));
+    }
+
+    #[tokio::test]
+    async fn test_bgsave() -> Result<(), SnapshotError> {
+        let dir = tempfile::tempdir()?;
+        let backend = backend_in(dir.path());
+        fill(&backend);
+        {
+            let _guard = backend.gate.write().await;
+            assert!(bgsave(&backend));
+        }
+        while backend.save_state.in_progress() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(backend.save_state.dirty(), 0);
+        assert!(load_snapshot(&backend_in(dir.path()))?);
+        Ok(())
+    }
+}
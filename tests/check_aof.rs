@@ -0,0 +1,100 @@
+use std::fs;
+use std::process::{Command, Output};
+
+fn check_aof(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_simple-redis-check-aof"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn check_and_fix_aof() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+    let valid = "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+    // a transaction cut short by a crash
+    let data = format!("{}*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\nb", valid);
+    fs::write(&path, &data).unwrap();
+    let path = path.to_str().unwrap();
+
+    let output = check_aof(&[path]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains(&format!(
+        "size={}, ok_up_to={}, diff={}",
+        data.len(),
+        valid.len(),
+        data.len() - valid.len()
+    )));
+    assert!(stdout(&output).contains("Use the --fix option"));
+
+    let output = check_aof(&["--fix", path]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(path).unwrap(), valid);
+
+    let output = check_aof(&[path]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("AOF is valid"));
+
+    assert!(!check_aof(&[]).status.success());
+}
+
+#[test]
+fn check_multi_part_aof() {
+    let dir = tempfile::tempdir().unwrap();
+    let aof_dir = dir.path().join("appendonlydir");
+    fs::create_dir(&aof_dir).unwrap();
+    let manifest = aof_dir.join("appendonly.aof.manifest");
+    fs::write(
+        &manifest,
+        "file appendonly.aof.1.base.aof seq 1 type b\n\
+         file appendonly.aof.1.incr.aof seq 1 type i\n\
+         file appendonly.aof.2.incr.aof seq 2 type i\n",
+    )
+    .unwrap();
+    let valid = "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+    let truncated = format!("{}*2\r\n$3\r\nDEL", valid);
+    fs::write(aof_dir.join("appendonly.aof.1.base.aof"), valid).unwrap();
+    fs::write(aof_dir.join("appendonly.aof.1.incr.aof"), valid).unwrap();
+    let last = aof_dir.join("appendonly.aof.2.incr.aof");
+    fs::write(&last, &truncated).unwrap();
+    let (aof_dir, manifest) = (aof_dir.to_str().unwrap(), manifest.to_str().unwrap());
+
+    // the directory or its manifest
+    let output = check_aof(&[aof_dir]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Use the --fix option"));
+    let output = check_aof(&["--fix", manifest]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("All AOF files and manifest are valid"));
+    assert_eq!(fs::read_to_string(&last).unwrap(), valid);
+
+    // only the last file may be fixed
+    let first = dir.path().join("appendonlydir/appendonly.aof.1.incr.aof");
+    fs::write(&first, &truncated).unwrap();
+    let output = check_aof(&["--fix", aof_dir]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Only the last file"));
+    assert_eq!(fs::read_to_string(&first).unwrap(), truncated);
+
+    // a base in RDB format is checked as such
+    fs::write(&first, valid).unwrap();
+    fs::write(
+        manifest,
+        "file appendonly.aof.2.base.rdb seq 2 type b\n\
+         file appendonly.aof.1.incr.aof seq 1 type i\n",
+    )
+    .unwrap();
+    let base = dir.path().join("appendonlydir/appendonly.aof.2.base.rdb");
+    fs::write(&base, "REDIS0011").unwrap();
+    let output = check_aof(&[aof_dir]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("BASE AOF"));
+    assert!(stdout(&output).contains("is not valid"));
+    fs::copy("tests/fixtures/rdb11-hand-built.rdb", &base).unwrap();
+    assert!(check_aof(&[aof_dir]).status.success());
+}
@@ -0,0 +1,349 @@
+use bytes::BytesMut;
+use simple_redis::{
+    key_slot, run_cluster_bus, stream_handler, Backend, BulkString, Config, RespArray, RespDecode,
+    RespEncode, RespError, RespFrame, SimpleError, SimpleString,
+};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// A cluster node on ephemeral ports, for clients and for the cluster bus.
+struct Node {
+    port: u16,
+    bus_port: u16,
+}
+
+impl Node {
+    async fn start() -> Node {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bus_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let bus_port = bus_listener.local_addr().unwrap().port();
+        let backend = Backend::with_config(Config {
+            port,
+            cluster_enabled: true,
+            cluster_port: bus_port,
+            ..Default::default()
+        });
+        tokio::spawn(run_cluster_bus(bus_listener, backend.clone()));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(stream_handler(stream, backend.clone()));
+            }
+        });
+        Node { port, bus_port }
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    buf: BytesMut,
+}
+
+impl Client {
+    async fn connect(node: &Node) -> Client {
+        Client {
+            stream: TcpStream::connect(("127.0.0.1", node.port)).await.unwrap(),
+            buf: BytesMut::new(),
+        }
+    }
+
+    async fn call(&mut self, line: &str) -> RespFrame {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        self.call_args(&args).await
+    }
+
+    // the arguments as given, which may be empty
+    async fn call_args(&mut self, args: &[&str]) -> RespFrame {
+        let args: Vec<RespFrame> = args.iter().map(|a| BulkString::new(*a).into()).collect();
+        let request = RespFrame::from(RespArray::with_vec(args)).encode();
+        self.stream.write_all(&request).await.unwrap();
+        loop {
+            match RespFrame::decode(&mut self.buf) {
+                Ok(frame) => return frame,
+                Err(RespError::RespNotComplete) => {}
+                Err(e) => panic!("bad reply: {}", e),
+            }
+            assert!(self.stream.read_buf(&mut self.buf).await.unwrap() > 0);
+        }
+    }
+
+    async fn cluster_info(&mut self) -> String {
+        let info = self.call("cluster info").await;
+        String::from_utf8(info.as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    // the gossip is asynchronous, wait until the node knows the whole cluster
+    async fn wait_for_cluster(&mut self, nodes: usize) {
+        let start = tokio::time::Instant::now();
+        let expected = format!("cluster_known_nodes:{}\r\n", nodes);
+        loop {
+            let info = self.cluster_info().await;
+            if info.starts_with("cluster_state:ok\r\n") && info.contains(&expected) {
+                return;
+            }
+            assert!(start.elapsed() < TIMEOUT, "{}", info);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+// A client of the whole cluster, which follows MOVED and ASK redirections.
+struct ClusterClient {
+    // the connections by port
+    clients: HashMap<u16, Client>,
+    // the port of the node serving each slot, as last redirected
+    slots: HashMap<u16, u16>,
+    port: u16,
+}
+
+impl ClusterClient {
+    fn new(node: &Node) -> ClusterClient {
+        ClusterClient {
+            clients: HashMap::new(),
+            slots: HashMap::new(),
+            port: node.port,
+        }
+    }
+
+    async fn client(&mut self, port: u16) -> &mut Client {
+        match self.clients.entry(port) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(Client::connect(&Node { port, bus_port: 0 }).await),
+        }
+    }
+
+    async fn call(&mut self, key: &str, line: &str) -> RespFrame {
+        let slot = key_slot(key.as_bytes());
+        let mut port = *self.slots.get(&slot).unwrap_or(&self.port);
+        let mut asking = false;
+        for _ in 0..100 {
+            let client = self.client(port).await;
+            if std::mem::take(&mut asking) {
+                assert_eq!(client.call("asking").await, ok());
+            }
+            let reply = client.call(line).await;
+            let RespFrame::Error(e) = &reply else {
+                return reply;
+            };
+            let e = e.as_str();
+            let target = |e: &str| e.rsplit(':').next().unwrap().parse::<u16>().unwrap();
+            if e.starts_with("MOVED ") {
+                port = target(e);
+                self.slots.insert(slot, port);
+            } else if e.starts_with("ASK ") {
+                port = target(e);
+                asking = true;
+            } else if e.starts_with("TRYAGAIN ") {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            } else {
+                return reply;
+            }
+        }
+        panic!("too many redirections for {}", line);
+    }
+}
+
+fn ok() -> RespFrame {
+    SimpleString::new("OK").into()
+}
+
+fn error(s: &str) -> RespFrame {
+    SimpleError::new(s).into()
+}
+
+#[tokio::test]
+async fn three_nodes_redirect() {
+    let nodes = [
+        Node::start().await,
+        Node::start().await,
+        Node::start().await,
+    ];
+    let mut clients = Vec::new();
+    for node in &nodes {
+        clients.push(Client::connect(node).await);
+    }
+    let bounds = [(0, 5460), (5461, 10922), (10923, 16383)];
+    for (client, (start, end)) in clients.iter_mut().zip(bounds) {
+        let line = format!("cluster addslotsrange {} {}", start, end);
+        assert_eq!(client.call(&line).await, ok());
+    }
+
+    // the first node only meets the second, and learns about the third
+    // from the second one
+    let meet = |node: &Node| format!("cluster meet 127.0.0.1 {} {}", node.port, node.bus_port);
+    assert_eq!(clients[0].call(&meet(&nodes[1])).await, ok());
+    assert_eq!(clients[1].call(&meet(&nodes[2])).await, ok());
+    for client in clients.iter_mut() {
+        client.wait_for_cluster(3).await;
+    }
+
+    // foo is served by the third node, the others redirect to it
+    let slot = key_slot(b"foo");
+    assert_eq!(slot, 12182);
+    let moved = error(&format!("MOVED {} 127.0.0.1:{}", slot, nodes[2].port));
+    assert_eq!(clients[0].call("set foo bar").await, moved);
+    assert_eq!(clients[1].call("get foo").await, moved);
+    assert_eq!(clients[2].call("set foo bar").await, ok());
+    assert_eq!(clients[2].call("set {foo}2 baz").await, ok());
+    assert_eq!(
+        clients[2].call("get foo").await,
+        BulkString::new("bar").into()
+    );
+    assert_eq!(
+        clients[2].call("cluster countkeysinslot 12182").await,
+        RespFrame::Integer(2)
+    );
+    assert_eq!(
+        clients[2].call("watch foo bar").await,
+        error("CROSSSLOT Keys in request don't hash to the same slot")
+    );
+
+    // every node sees the same slots
+    let mut expected = Vec::new();
+    for ((client, node), (start, end)) in clients.iter_mut().zip(&nodes).zip(bounds) {
+        let id = client.call("cluster myid").await;
+        let node = RespArray::with_vec(vec![
+            BulkString::new("127.0.0.1").into(),
+            RespFrame::Integer(node.port as i64),
+            id,
+        ]);
+        let range = vec![
+            RespFrame::Integer(start),
+            RespFrame::Integer(end),
+            node.into(),
+        ];
+        expected.push(RespArray::with_vec(range).into());
+    }
+    let expected: RespFrame = RespArray::with_vec(expected).into();
+    for client in clients.iter_mut() {
+        assert_eq!(client.call("cluster slots").await, expected);
+    }
+    let nodes_info = clients[0].call("cluster nodes").await;
+    let nodes_info = String::from_utf8(nodes_info.as_bytes().unwrap().to_vec()).unwrap();
+    assert_eq!(nodes_info.lines().count(), 3);
+    assert!(nodes_info.contains(&format!(
+        "127.0.0.1:{}@{} master - ",
+        nodes[2].port, nodes[2].bus_port
+    )));
+}
+
+#[tokio::test]
+async fn reshard_under_load() {
+    let nodes = [Node::start().await, Node::start().await];
+    let mut a = Client::connect(&nodes[0]).await;
+    let mut b = Client::connect(&nodes[1]).await;
+    assert_eq!(a.call("cluster addslotsrange 0 8191").await, ok());
+    assert_eq!(b.call("cluster addslotsrange 8192 16383").await, ok());
+    let meet = format!(
+        "cluster meet 127.0.0.1 {} {}",
+        nodes[1].port, nodes[1].bus_port
+    );
+    assert_eq!(a.call(&meet).await, ok());
+    a.wait_for_cluster(2).await;
+    b.wait_for_cluster(2).await;
+    let id = |frame: RespFrame| String::from_utf8(frame.as_bytes().unwrap().to_vec()).unwrap();
+    let a_id = id(a.call("cluster myid").await);
+    let b_id = id(b.call("cluster myid").await);
+
+    // the keys of the slot of foo, which moves from the second node to the first
+    let slot = key_slot(b"foo");
+    let mut client = ClusterClient::new(&nodes[0]);
+    for i in 0..100 {
+        let line = format!("set {{foo}}k{} v{}", i, i);
+        assert_eq!(client.call("{foo}", &line).await, ok());
+    }
+
+    // keeps writing and reading back keys of the slot during the migration
+    let stop = Arc::new(AtomicBool::new(false));
+    let load = tokio::spawn({
+        let stop = stop.clone();
+        async move {
+            let mut i = 100;
+            while !stop.load(Ordering::Relaxed) {
+                let line = format!("set {{foo}}k{} v{}", i, i);
+                assert_eq!(client.call("{foo}", &line).await, ok());
+                // rewrite an older key, which may have been migrated already
+                let old = format!("set {{foo}}k{} w{}", i / 2, i / 2);
+                assert_eq!(client.call("{foo}", &old).await, ok());
+                let get = format!("get {{foo}}k{}", i);
+                let value = client.call("{foo}", &get).await;
+                assert_eq!(value, BulkString::new(format!("v{}", i)).into());
+                i += 1;
+            }
+            i
+        }
+    });
+
+    let importing = format!("cluster setslot {} importing {}", slot, b_id);
+    assert_eq!(a.call(&importing).await, ok());
+    let migrating = format!("cluster setslot {} migrating {}", slot, a_id);
+    assert_eq!(b.call(&migrating).await, ok());
+    let port = nodes[0].port.to_string();
+    loop {
+        let keys = b.call(&format!("cluster getkeysinslot {} 10", slot)).await;
+        let RespFrame::Array(keys) = keys else {
+            panic!("unexpected reply: {:?}", keys);
+        };
+        let keys: Vec<Vec<u8>> = keys
+            .iter()
+            .flatten()
+            .map(|k| k.as_bytes().unwrap().to_vec())
+            .collect();
+        if keys.is_empty() {
+            break;
+        }
+        let keys: Vec<String> = keys
+            .into_iter()
+            .map(|k| String::from_utf8(k).unwrap())
+            .collect();
+        let mut args = vec![
+            "migrate",
+            "127.0.0.1",
+            port.as_str(),
+            "",
+            "0",
+            "5000",
+            "keys",
+        ];
+        args.extend(keys.iter().map(String::as_str));
+        assert_eq!(b.call_args(&args).await, ok());
+        // leave room for the load between the batches
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let node = format!("cluster setslot {} node {}", slot, a_id);
+    assert_eq!(a.call(&node).await, ok());
+    assert_eq!(b.call(&node).await, ok());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.store(true, Ordering::Relaxed);
+    let written = load.await.unwrap();
+    assert!(written > 100);
+
+    // every key is on the first node, the second one redirects to it
+    let count = format!("cluster countkeysinslot {}", slot);
+    assert_eq!(a.call(&count).await, RespFrame::Integer(written as i64));
+    assert_eq!(b.call(&count).await, RespFrame::Integer(0));
+    let rewritten = 50..=(written - 1) / 2;
+    for i in 0..written {
+        let expected = match rewritten.contains(&i) {
+            true => format!("w{}", i),
+            false => format!("v{}", i),
+        };
+        let get = format!("get {{foo}}k{}", i);
+        assert_eq!(
+            a.call(&get).await,
+            BulkString::new(expected).into(),
+            "{}",
+            get
+        );
+    }
+    let moved = error(&format!("MOVED {} 127.0.0.1:{}", slot, nodes[0].port));
+    assert_eq!(b.call("get {foo}k0").await, moved);
+}
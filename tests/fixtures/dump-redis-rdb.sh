@@ -0,0 +1,46 @@
+#!/bin/sh
+# Write tests/fixtures/redis-6.2.rdb and redis-7.2.rdb with real Redis
+# servers, holding the dataset of the hand-built fixtures. Needs docker.
+#
+# Usage: tests/fixtures/dump-redis-rdb.sh
+set -eu
+
+fixtures=$(cd "$(dirname "$0")" && pwd)
+
+dump() {
+    version=$1
+    name=simple-redis-fixture-$version
+    docker run -d --rm --name "$name" "redis:$version" redis-server --save "" >/dev/null
+    trap 'docker stop "$name" >/dev/null' EXIT
+    until docker exec "$name" redis-cli ping >/dev/null 2>&1; do sleep 0.1; done
+    cli() { docker exec "$name" redis-cli "$@" >/dev/null; }
+
+    # small values keep the compact encodings, the big ones force the others
+    cli set greeting "hello world"
+    cli set counter 1234
+    cli set blob aaaaaaaaaaaaaaaaaaaa
+    cli pexpireat blob 4102444800000
+    cli -n 1 set other 1
+    cli rpush queue one two 3 -4000 100000 5000000000
+    cli rpush mixed "$(printf 'x%.0s' $(seq 70))" tail
+    cli sadd ids 1 2 300 5000000000
+    cli sadd tags red blue
+    cli sadd bigset $(seq -f m%g 0 200)
+    cli hset user:1 name alice age 30
+    cli hset plainhash f "$(printf 'v%.0s' $(seq 80))"
+    cli zadd board 1.5 alice 2 bob
+    cli zadd bigzset -0.5 a inf b $(seq 0 200 | sed 's/.*/& m&/')
+    if [ "$version" != 6.2 ]; then
+        printf '%s' "#!lua name=mylib
+redis.register_function('myfunc', function(keys, args) return 1 end)" |
+            docker exec -i "$name" redis-cli -x function load >/dev/null
+    fi
+
+    cli save
+    docker cp "$name:/data/dump.rdb" "$fixtures/redis-$version.rdb"
+    docker stop "$name" >/dev/null
+    trap - EXIT
+}
+
+dump 6.2
+dump 7.2
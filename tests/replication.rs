@@ -0,0 +1,230 @@
+use bytes::BytesMut;
+use simple_redis::{
+    load_aof, run_aof_cron, stream_handler, AppendFsync, Backend, BulkString, Config, RespArray,
+    RespDecode, RespEncode, RespError, RespFrame, SimpleError, SimpleString,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// A server on an ephemeral port, which keeps its connections to drop them.
+struct Server {
+    port: u16,
+    connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Server {
+    async fn start(config: Config) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let appendonly = config.appendonly;
+        let backend = Backend::with_config(Config { port, ..config });
+        if appendonly {
+            load_aof(&backend).unwrap();
+            tokio::spawn(run_aof_cron(backend.clone()));
+        }
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let handles = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let backend = backend.clone();
+                let handle = tokio::spawn(async move {
+                    let _ = stream_handler(stream, backend).await;
+                });
+                handles.lock().unwrap().push(handle);
+            }
+        });
+        Server { port, connections }
+    }
+
+    fn drop_connections(&self) {
+        for handle in self.connections.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    buf: BytesMut,
+}
+
+impl Client {
+    async fn connect(server: &Server) -> Client {
+        Client {
+            stream: TcpStream::connect(("127.0.0.1", server.port))
+                .await
+                .unwrap(),
+            buf: BytesMut::new(),
+        }
+    }
+
+    async fn call(&mut self, line: &str) -> RespFrame {
+        let args: Vec<RespFrame> = line
+            .split_whitespace()
+            .map(|a| BulkString::new(a).into())
+            .collect();
+        let request = RespFrame::from(RespArray::with_vec(args)).encode();
+        self.stream.write_all(&request).await.unwrap();
+        loop {
+            match RespFrame::decode(&mut self.buf) {
+                Ok(frame) => return frame,
+                Err(RespError::RespNotComplete) => {}
+                Err(e) => panic!("bad reply: {}", e),
+            }
+            assert!(self.stream.read_buf(&mut self.buf).await.unwrap() > 0);
+        }
+    }
+
+    async fn info(&mut self, section: &str) -> String {
+        let info = self.call(&format!("info {}", section)).await;
+        String::from_utf8(info.as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    async fn info_field(&mut self, section: &str, field: &str) -> String {
+        let info = self.info(section).await;
+        let prefix = format!("{}:", field);
+        info.lines()
+            .find_map(|l| l.strip_prefix(&prefix))
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    // the replication is asynchronous, wait until the reply is the expected one
+    async fn wait_for(&mut self, line: &str, expected: RespFrame) {
+        let start = tokio::time::Instant::now();
+        loop {
+            let res = self.call(line).await;
+            if res == expected {
+                return;
+            }
+            assert!(start.elapsed() < TIMEOUT, "{}: {:?}", line, res);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+async fn wait_for_same_offset(master: &mut Client, replica: &mut Client) -> String {
+    let start = tokio::time::Instant::now();
+    loop {
+        let offset = master.info_field("replication", "master_repl_offset").await;
+        if replica
+            .info_field("replication", "master_repl_offset")
+            .await
+            == offset
+        {
+            return offset;
+        }
+        assert!(start.elapsed() < TIMEOUT, "offsets differ");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+fn ok() -> RespFrame {
+    SimpleString::new("OK").into()
+}
+
+fn bulk(s: &str) -> RespFrame {
+    BulkString::new(s).into()
+}
+
+#[tokio::test]
+async fn replicate_and_resync() {
+    let master_server = Server::start(Config::default()).await;
+    let replica_server = Server::start(Config::default()).await;
+    let mut master = Client::connect(&master_server).await;
+    let mut replica = Client::connect(&replica_server).await;
+
+    // the dataset before REPLICAOF comes with the full sync
+    assert_eq!(master.call("set a 1").await, ok());
+    let line = format!("replicaof 127.0.0.1 {}", master_server.port);
+    assert_eq!(replica.call(&line).await, ok());
+    replica.wait_for("get a", bulk("1")).await;
+    assert_eq!(
+        replica.info_field("replication", "role").await,
+        "slave".to_string()
+    );
+
+    // then writes are streamed
+    assert_eq!(master.call("set b 2").await, ok());
+    assert_eq!(master.call("hset h f v").await, ok());
+    replica.wait_for("hget h f", bulk("v")).await;
+    replica.wait_for("get b", bulk("2")).await;
+    assert_eq!(
+        replica.call("set c 3").await,
+        SimpleError::new("READONLY You can't write against a read only replica.").into()
+    );
+    let offset = wait_for_same_offset(&mut master, &mut replica).await;
+    assert_ne!(offset, "0");
+    let slave0 = master.info_field("replication", "slave0").await;
+    let expected = format!("ip=127.0.0.1,port={},state=online,", replica_server.port);
+    assert!(slave0.starts_with(&expected), "{}", slave0);
+
+    // the replica resumes from the backlog after the link broke, with
+    // what was written meanwhile
+    master_server.drop_connections();
+    let mut master = Client::connect(&master_server).await;
+    assert_eq!(master.call("set c 3").await, ok());
+    replica.wait_for("get c", bulk("3")).await;
+    wait_for_same_offset(&mut master, &mut replica).await;
+    assert_eq!(master.info_field("stats", "sync_full").await, "1");
+    assert_eq!(master.info_field("stats", "sync_partial_ok").await, "1");
+
+    // a promoted replica takes writes again
+    assert_eq!(replica.call("replicaof no one").await, ok());
+    assert_eq!(replica.call("set c 4").await, ok());
+    assert_eq!(
+        replica.info_field("replication", "role").await,
+        "master".to_string()
+    );
+}
+
+#[tokio::test]
+async fn wait_for_acknowledgements() {
+    let master_dir = tempfile::tempdir().unwrap();
+    let replica_dir = tempfile::tempdir().unwrap();
+    let master_server = Server::start(Config {
+        dir: master_dir.path().to_path_buf(),
+        appendonly: true,
+        appendfsync: AppendFsync::Always,
+        ..Default::default()
+    })
+    .await;
+    let replica_server = Server::start(Config {
+        dir: replica_dir.path().to_path_buf(),
+        appendonly: true,
+        ..Default::default()
+    })
+    .await;
+    let mut master = Client::connect(&master_server).await;
+    let mut replica = Client::connect(&replica_server).await;
+    let line = format!("replicaof 127.0.0.1 {}", master_server.port);
+    assert_eq!(replica.call(&line).await, ok());
+
+    // the replica has to be online to count
+    master.wait_for("wait 1 10", RespFrame::Integer(1)).await;
+    assert_eq!(master.call("set a 1").await, ok());
+    assert_eq!(master.call("wait 1 5000").await, RespFrame::Integer(1));
+    // the replica fsyncs every second
+    let both: RespFrame =
+        RespArray::with_vec(vec![RespFrame::Integer(1), RespFrame::Integer(1)]).into();
+    assert_eq!(master.call("waitaof 1 1 5000").await, both);
+    assert_eq!(replica.call("get a").await, bulk("1"));
+
+    // there aren't enough replicas, it times out with those there are
+    assert_eq!(master.call("set a 2").await, ok());
+    assert_eq!(master.call("wait 2 100").await, RespFrame::Integer(1));
+
+    assert_eq!(
+        replica.call("wait 1 0").await,
+        SimpleError::new(
+            "ERR WAIT cannot be used with replica instances. Please also note that writes to \
+             replicas are just local and are not propagated."
+        )
+        .into()
+    );
+}
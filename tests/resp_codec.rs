@@ -0,0 +1,5 @@
+#[test]
+fn resp_codec_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/resp_*.rs");
+}
@@ -0,0 +1,9 @@
+use macro_definitions::RedisCommand;
+
+#[derive(RedisCommand)]
+#[command(name = "get")]
+enum Get {
+    Key,
+}
+
+fn main() {}
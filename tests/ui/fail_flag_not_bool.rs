@@ -0,0 +1,10 @@
+use macro_definitions::RedisCommand;
+
+#[derive(RedisCommand)]
+#[command(name = "set")]
+struct Set {
+    #[arg(flag = "NX")]
+    nx: i64,
+}
+
+fn main() {}
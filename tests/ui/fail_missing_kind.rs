@@ -0,0 +1,10 @@
+use bytes::Bytes;
+use macro_definitions::RedisCommand;
+
+#[derive(RedisCommand)]
+#[command(name = "get")]
+struct Get {
+    key: Bytes,
+}
+
+fn main() {}
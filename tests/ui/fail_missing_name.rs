@@ -0,0 +1,6 @@
+use macro_definitions::RedisCommand;
+
+#[derive(RedisCommand)]
+struct Multi;
+
+fn main() {}
@@ -0,0 +1,13 @@
+use bytes::Bytes;
+use macro_definitions::RedisCommand;
+
+#[derive(RedisCommand)]
+#[command(name = "set")]
+struct Set {
+    #[arg(key, optional)]
+    key: Option<Bytes>,
+    #[arg(bytes)]
+    value: Bytes,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use bytes::Bytes;
+use macro_definitions::RedisCommand;
+
+#[derive(RedisCommand)]
+#[command(name = "sadd")]
+struct Sadd {
+    #[arg(bytes, variadic)]
+    members: Vec<Bytes>,
+    #[arg(option = "EX", integer)]
+    ex: Option<i64>,
+}
+
+fn main() {}
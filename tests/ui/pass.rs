@@ -0,0 +1,25 @@
+use bytes::Bytes;
+use macro_definitions::RedisCommand;
+use simple_redis::RespFrame;
+
+#[derive(RedisCommand)]
+#[command(name = "set")]
+struct Set {
+    #[arg(key)]
+    key: Bytes,
+    #[arg(frame)]
+    value: RespFrame,
+    #[arg(flag = "NX")]
+    nx: bool,
+    #[arg(option = "EX", integer)]
+    ex: Option<i64>,
+}
+
+#[derive(RedisCommand)]
+#[command(name = "multi")]
+struct Multi;
+
+fn main() {
+    let _ = |v: simple_redis::RespArray| Set::try_from(v.clone()).map(|s| (s.key, s.value, s.nx, s.ex));
+    let _ = |v: simple_redis::RespArray| Multi::try_from(v);
+}
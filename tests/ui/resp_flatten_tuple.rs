@@ -0,0 +1,6 @@
+use simple_redis::RespEncode;
+
+#[derive(RespEncode)]
+struct Pair(#[resp(flatten)] Vec<i64>, i64);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use simple_redis::RespDecode;
+
+#[derive(RespDecode)]
+struct User {
+    #[resp(skip, rename = "n")]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use simple_redis::RespEncode;
+
+#[derive(RespEncode)]
+union Value {
+    int: i64,
+    float: f64,
+}
+
+fn main() {}